@@ -0,0 +1,49 @@
+//! Times the AI flying in traffic, where every racer is looking out for
+//! every other one. A pack of racers is raced with the real AI and
+//! physics on a few seeded maps. Run natively with:
+//!     cargo run --release --example ai_traffic
+use std::time::{Duration, Instant};
+
+use swoop_11_wingtip_trails_and_optimizations::ai::{AiDriver, CHAMPION, DEFENSIVE, PRO, ROOKIE};
+use swoop_11_wingtip_trails_and_optimizations::controller::ShipController;
+use swoop_11_wingtip_trails_and_optimizations::map::Map;
+use swoop_11_wingtip_trails_and_optimizations::simulation::{Simulation, TICK};
+
+const SEEDS: [u64; 3] = [1, 7, 42];
+const TICKS: u32 = 3600;
+
+fn main() {
+    let profiles = [CHAMPION, PRO, DEFENSIVE, PRO, ROOKIE];
+    for seed in SEEDS.iter() {
+        let mut map = Map::new();
+        map.randomize(*seed);
+        let controllers = profiles
+            .iter()
+            .enumerate()
+            .map(|(id, profile)| {
+                Box::new(AiDriver::new(*profile, seed + id as u64)) as Box<dyn ShipController>
+            })
+            .collect();
+        let mut simulation = Simulation::new(map, controllers, 1);
+
+        let mut deciding = Duration::default();
+        let start = Instant::now();
+        for _ in 0..TICKS {
+            let decide_start = Instant::now();
+            let controls = simulation
+                .calc_controls(TICK)
+                .expect("the built in AI doesn't fail");
+            deciding += decide_start.elapsed();
+            simulation.advance(&controls, TICK);
+        }
+        let per_tick = start.elapsed() / TICKS;
+
+        println!(
+            "seed {:>3}: {:>10?} per tick, {:>10?} of it deciding for {} ships",
+            seed,
+            per_tick,
+            deciding / TICKS,
+            profiles.len()
+        );
+    }
+}
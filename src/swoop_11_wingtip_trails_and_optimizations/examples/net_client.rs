@@ -0,0 +1,480 @@
+//! Stands in for a browser in an online race, so that relay_server can be
+//! tried out without one. It joins a lobby, readies up, flies its ship
+//! with an AI profile or a Rhai script, rolling back whenever the server
+//! says otherwise, and prints the results along with how often that
+//! happened. Run
+//! natively, with relay_server already running, with:
+//!     cargo run --release --example net_client -- [--server ws://127.0.0.1:9001] [--lobby NAME] [--name NAME] [--races N] [--wait N] DRIVER
+//! where DRIVER is the name of an AI profile or script:PATH. --wait holds
+//! off readying up until there are that many players in the lobby.
+//! For example, in two terminals:
+//!     cargo run --release --example net_client -- --name first --wait 2 champion
+//!     cargo run --release --example net_client -- --name second --wait 2 pro
+use std::env;
+use std::fs;
+use std::io::Write;
+use std::net::TcpStream;
+use std::process;
+use std::sync::mpsc::{channel, RecvTimeoutError, Sender};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use swoop_11_wingtip_trails_and_optimizations::ai::AiDriver;
+use swoop_11_wingtip_trails_and_optimizations::catch_up::CatchUp;
+use swoop_11_wingtip_trails_and_optimizations::controller::{
+    ControllerError, NearbyShip, Observation, ShipControl, ShipController,
+};
+use swoop_11_wingtip_trails_and_optimizations::game_options::DriverOption;
+use swoop_11_wingtip_trails_and_optimizations::map::Map;
+use swoop_11_wingtip_trails_and_optimizations::protocol::{ClientMessage, ServerMessage};
+use swoop_11_wingtip_trails_and_optimizations::racing_line::RacingLine;
+use swoop_11_wingtip_trails_and_optimizations::rng::Rng;
+use swoop_11_wingtip_trails_and_optimizations::rollback::{Rollback, INPUT_REDUNDANCY};
+use swoop_11_wingtip_trails_and_optimizations::script_controller::ScriptController;
+use swoop_11_wingtip_trails_and_optimizations::simulation::TICK;
+use swoop_11_wingtip_trails_and_optimizations::websocket::{
+    connect, encode_frame, read_message, FrameReader, Message, Opcode,
+};
+
+const DEFAULT_SERVER: &str = "ws://127.0.0.1:9001";
+const DEFAULT_LOBBY: &str = "swoop";
+
+/// Flies this client's ship if its controller fails
+struct Coast;
+
+impl ShipController for Coast {
+    fn control(&mut self, _observation: &Observation) -> Result<ShipControl, ControllerError> {
+        Ok(ShipControl::default())
+    }
+}
+
+struct Settings {
+    /// Host and port to connect to
+    address: String,
+    path: String,
+    lobby: String,
+    name: String,
+    races: u32,
+    wait: usize,
+    driver: DriverOption,
+    /// Script contents, loaded once up front
+    source: Option<String>,
+}
+
+impl Settings {
+    fn create_controller(&self, seed: u64) -> Result<Box<dyn ShipController>, String> {
+        match (&self.driver, &self.source) {
+            (DriverOption::Ai(profile), _) => Ok(Box::new(AiDriver::new(*profile, seed))),
+            (DriverOption::Script(_), Some(source)) => ScriptController::new(source)
+                .map(|controller| Box::new(controller) as Box<dyn ShipController>)
+                .map_err(|err| format!("{:?}", err)),
+            _ => Err(format!("{} can't race online", self.driver.name())),
+        }
+    }
+}
+
+fn parse_args() -> Result<Settings, String> {
+    let mut server = DEFAULT_SERVER.to_string();
+    let mut lobby = DEFAULT_LOBBY.to_string();
+    let mut name = None;
+    let mut races = 1;
+    let mut wait = 1;
+    let mut driver = None;
+
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        if !arg.starts_with("--") {
+            driver = Some(arg);
+            continue;
+        }
+        let value = args
+            .next()
+            .ok_or_else(|| format!("{} needs a value", arg))?;
+        match arg.as_str() {
+            "--server" => server = value,
+            "--lobby" => lobby = value,
+            "--name" => name = Some(value),
+            "--races" => races = value.parse().map_err(|_| "--races needs a number")?,
+            "--wait" => wait = value.parse().map_err(|_| "--wait needs a number")?,
+            _ => return Err(format!("unknown argument {}", arg)),
+        }
+    }
+
+    let driver_name = driver.ok_or("expected a driver")?;
+    let driver = match DriverOption::from_name(&driver_name) {
+        Some(driver @ DriverOption::Ai(_)) => driver,
+        Some(driver @ DriverOption::Script(_)) => driver,
+        _ => return Err(format!("unknown driver {}", driver_name)),
+    };
+    let source = match &driver {
+        DriverOption::Script(path) => Some(
+            fs::read_to_string(path).map_err(|err| format!("couldn't read {}: {}", path, err))?,
+        ),
+        _ => None,
+    };
+    let address = server
+        .strip_prefix("ws://")
+        .ok_or("--server has to start with ws://")?;
+    let (address, path) = match address.find('/') {
+        Some(slash) => (&address[..slash], &address[slash..]),
+        None => (address, "/"),
+    };
+    Ok(Settings {
+        address: address.to_string(),
+        path: path.to_string(),
+        lobby,
+        name: name.unwrap_or_else(|| driver.name().to_string()),
+        races,
+        wait,
+        driver,
+        source,
+    })
+}
+
+/// Passes on messages from the server until it hangs up
+fn receive(mut stream: TcpStream, received: Vec<u8>, messages: Sender<Option<ServerMessage>>) {
+    let mut reader = FrameReader::client(received);
+    loop {
+        match read_message(&mut stream, &mut reader) {
+            Ok(Some(Message::Binary(bytes))) => match ServerMessage::from_bytes(&bytes) {
+                Ok(message) => {
+                    if messages.send(Some(message)).is_err() {
+                        return;
+                    }
+                }
+                Err(err) => eprintln!("Couldn't read a message: {:?}", err),
+            },
+            Ok(Some(Message::Close)) | Ok(None) => break,
+            Ok(Some(_)) => {}
+            Err(err) => {
+                eprintln!("Connection failed: {:?}", err);
+                break;
+            }
+        }
+    }
+    let _ = messages.send(None);
+}
+
+/// The race as this client sees it. Its own ship is flown locally, and
+/// the rest with guesses that get fixed up as other players' inputs and
+/// the server's snapshots come in.
+struct ClientRace {
+    rollback: Rollback,
+    racing_line: RacingLine,
+    controller: Box<dyn ShipController>,
+    ship: usize,
+    drivers: Vec<String>,
+    countdown: u32,
+    /// The tick of the last snapshot
+    server_tick: u32,
+    /// How far the server moved this client's own ship, over all snapshots
+    corrections: u32,
+    total_error: f32,
+    max_error: f32,
+}
+
+struct Client {
+    settings: Settings,
+    stream: TcpStream,
+    rng: Rng,
+    players: usize,
+    ready: bool,
+    races_left: u32,
+    race: Option<ClientRace>,
+}
+
+impl Client {
+    fn send(&mut self, message: ClientMessage) {
+        // Clients have to mask what they send, with a new mask each time
+        let mask = self.rng.next_u32().to_le_bytes();
+        let frame = encode_frame(Opcode::Binary, &message.to_bytes(), Some(mask));
+        if let Err(err) = self.stream.write_all(&frame) {
+            eprintln!("Couldn't send: {}", err);
+            process::exit(1);
+        }
+    }
+
+    fn ready_up(&mut self) {
+        if !self.ready && self.race.is_none() && self.players >= self.settings.wait {
+            self.ready = true;
+            self.send(ClientMessage::Ready);
+        }
+    }
+
+    /// Gives false once there is nothing left to do
+    fn handle(&mut self, message: ServerMessage) -> bool {
+        match message {
+            ServerMessage::Welcome { player } => {
+                println!("Joined {} as player {}", self.settings.lobby, player + 1);
+            }
+            ServerMessage::Lobby { players } => {
+                let names: Vec<String> = players
+                    .iter()
+                    .map(|player| match player.ready {
+                        true => format!("{} (ready)", player.name),
+                        false => player.name.clone(),
+                    })
+                    .collect();
+                println!("In the lobby: {}", names.join(", "));
+                self.players = players.len();
+                self.ready_up();
+            }
+            ServerMessage::Start {
+                seed,
+                laps,
+                drivers,
+                ship,
+                countdown,
+            } => {
+                let ship = ship as usize;
+                let mut map = Map::new();
+                map.randomize(seed);
+                let controller = match self.settings.create_controller(seed + ship as u64) {
+                    Ok(controller) => controller,
+                    Err(err) => {
+                        eprintln!("{}", err);
+                        return false;
+                    }
+                };
+                println!(
+                    "Racing {} laps from slot {} against {}",
+                    laps,
+                    ship + 1,
+                    drivers.join(", ")
+                );
+                self.race = Some(ClientRace {
+                    racing_line: RacingLine::new(&map),
+                    rollback: Rollback::new(map, drivers.len(), laps),
+                    controller,
+                    ship,
+                    drivers,
+                    countdown,
+                    server_tick: 0,
+                    corrections: 0,
+                    total_error: 0.0,
+                    max_error: 0.0,
+                });
+                self.ready = false;
+            }
+            ServerMessage::Snapshot { tick, ships } => {
+                if let Some(race) = &mut self.race {
+                    // Snapshots come with the controls for the tick before,
+                    // which is the only way to hear about the AI's
+                    if tick > 0 {
+                        for (id, state) in ships.iter().enumerate() {
+                            if id != race.ship {
+                                race.rollback.add_input(id, tick - 1, state.control);
+                            }
+                        }
+                    }
+                    race.rollback.settle();
+                    let predicted = race
+                        .rollback
+                        .ships_at(tick)
+                        .and_then(|predicted| predicted.get(race.ship));
+                    if let (Some(ship), Some(state)) = (predicted, ships.get(race.ship)) {
+                        let dx = ship.position.x - state.position.0;
+                        let dy = ship.position.y - state.position.1;
+                        let error = (dx * dx + dy * dy).sqrt();
+                        race.corrections += 1;
+                        race.total_error += error;
+                        race.max_error = f32::max(race.max_error, error);
+                    }
+                    race.rollback.correct(tick, &ships);
+                    race.countdown = 0;
+                    race.server_tick = tick;
+                }
+            }
+            ServerMessage::Input {
+                ship,
+                tick,
+                controls,
+            } => {
+                if let Some(race) = &mut self.race {
+                    let first = (tick + 1).saturating_sub(controls.len() as u32);
+                    for (input_tick, control) in (first..=tick).zip(controls) {
+                        race.rollback.add_input(ship as usize, input_tick, control);
+                    }
+                }
+            }
+            ServerMessage::Results { standings } => {
+                if let Some(race) = self.race.take() {
+                    println!("Results:");
+                    for (position, ship) in standings.iter().enumerate() {
+                        let name = race.drivers.get(*ship as usize).map_or("?", String::as_str);
+                        let you = match *ship as usize == race.ship {
+                            true => " (you)",
+                            false => "",
+                        };
+                        println!("  {}. {}{}", position + 1, name, you);
+                    }
+                    if race.corrections > 0 {
+                        println!(
+                            "Corrected {} times by {:.3} on average and {:.3} at most",
+                            race.corrections,
+                            race.total_error / race.corrections as f32,
+                            race.max_error
+                        );
+                    }
+                    let stats = race.rollback.stats;
+                    println!(
+                        "Rolled back {} times, running {} ticks again and going back {} at most, \
+                         with {} too late to use",
+                        stats.rollbacks, stats.resimulated, stats.deepest, stats.too_late
+                    );
+                }
+                self.races_left = self.races_left.saturating_sub(1);
+                if self.races_left == 0 {
+                    self.send(ClientMessage::Leave);
+                    let mask = self.rng.next_u32().to_le_bytes();
+                    let _ = self
+                        .stream
+                        .write_all(&encode_frame(Opcode::Close, &[], Some(mask)));
+                    return false;
+                }
+                self.ready_up();
+            }
+            ServerMessage::Error { reason } => {
+                eprintln!("Server error: {}", reason);
+                return false;
+            }
+        }
+        true
+    }
+
+    /// Moves the race on a tick, or a few if it has fallen behind the
+    /// server, telling the server how this client's ship was flown
+    fn tick(&mut self) {
+        let race = match &mut self.race {
+            Some(race) => race,
+            None => return,
+        };
+        if race.countdown > 0 {
+            race.countdown -= 1;
+            return;
+        }
+        let mut inputs = vec![];
+        for _ in 0..race.rollback.ticks_to_run(race.server_tick) {
+            inputs.push(race.fly());
+        }
+        for input in inputs {
+            self.send(input);
+        }
+    }
+}
+
+impl ClientRace {
+    /// Runs one tick with this client's ship flown by its controller, and
+    /// gives the input to send for it
+    fn fly(&mut self) -> ClientMessage {
+        self.rollback.settle();
+        let others = NearbyShip::others(&self.rollback.ships, self.ship);
+        let observation = Observation {
+            ship: &self.rollback.ships[self.ship],
+            others: &others,
+            map: &self.rollback.map,
+            racing_line: &self.racing_line,
+            catch_up: CatchUp::NONE,
+            dt: TICK,
+        };
+        let control = match self.controller.control(&observation) {
+            Ok(control) => control,
+            Err(err) => {
+                eprintln!("Controller failed: {:?}", err);
+                self.controller = Box::new(Coast);
+                ShipControl::default()
+            }
+        };
+
+        let tick = self.rollback.tick();
+        self.rollback.add_input(self.ship, tick, control);
+        self.rollback.advance();
+        ClientMessage::Input {
+            tick,
+            controls: self
+                .rollback
+                .recent_controls(self.ship, tick, INPUT_REDUNDANCY),
+        }
+    }
+}
+
+fn main() {
+    let settings = match parse_args() {
+        Ok(settings) => settings,
+        Err(err) => {
+            eprintln!("{}", err);
+            eprintln!(
+                "usage: net_client [--server ws://HOST:PORT] [--lobby NAME] [--name NAME] \
+                 [--races N] [--wait N] DRIVER"
+            );
+            process::exit(1);
+        }
+    };
+
+    let mut stream = match TcpStream::connect(&settings.address) {
+        Ok(stream) => stream,
+        Err(err) => {
+            eprintln!("Couldn't connect to {}: {}", settings.address, err);
+            process::exit(1);
+        }
+    };
+    let _ = stream.set_nodelay(true);
+    let mut rng = Rng::new(
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |time| time.as_nanos() as u64),
+    );
+    let mut key = [0; 16];
+    for chunk in key.chunks_mut(4) {
+        chunk.copy_from_slice(&rng.next_u32().to_le_bytes());
+    }
+    let received = match connect(&mut stream, &settings.address, &settings.path, key) {
+        Ok(received) => received,
+        Err(err) => {
+            eprintln!("Handshake failed: {:?}", err);
+            process::exit(1);
+        }
+    };
+
+    let (messages, incoming) = channel();
+    let reader = stream.try_clone().expect("Couldn't clone the stream");
+    thread::spawn(move || receive(reader, received, messages));
+
+    let join = ClientMessage::Join {
+        lobby: settings.lobby.clone(),
+        name: settings.name.clone(),
+    };
+    let mut client = Client {
+        races_left: settings.races,
+        settings,
+        stream,
+        rng,
+        players: 0,
+        ready: false,
+        race: None,
+    };
+    client.send(join);
+
+    let tick = Duration::from_secs_f32(TICK);
+    let mut next_tick = Instant::now() + tick;
+    loop {
+        let now = Instant::now();
+        if now < next_tick {
+            match incoming.recv_timeout(next_tick - now) {
+                Ok(Some(message)) => {
+                    if !client.handle(message) {
+                        break;
+                    }
+                }
+                Ok(None) | Err(RecvTimeoutError::Disconnected) => {
+                    eprintln!("The server hung up");
+                    process::exit(1);
+                }
+                Err(RecvTimeoutError::Timeout) => {}
+            }
+            continue;
+        }
+        next_tick += tick;
+        client.tick();
+    }
+}
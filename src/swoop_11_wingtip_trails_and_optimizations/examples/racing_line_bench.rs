@@ -0,0 +1,45 @@
+//! Times the racing line solver on a handful of seeded maps and checks it
+//! is deterministic. Run natively with:
+//!     cargo run --release --example racing_line_bench
+use std::time::Instant;
+
+use swoop_11_wingtip_trails_and_optimizations::map::Map;
+use swoop_11_wingtip_trails_and_optimizations::racing_line::RacingLine;
+
+const SEEDS: [u64; 5] = [1, 2, 3, 42, 1234];
+const RUNS_PER_SEED: u32 = 10;
+
+fn main() {
+    for seed in SEEDS.iter() {
+        let mut map = Map::new();
+        map.randomize(*seed);
+
+        let start = Instant::now();
+        let mut racing_line = RacingLine::new(&map);
+        for _ in 1..RUNS_PER_SEED {
+            racing_line = RacingLine::new(&map);
+        }
+        let per_solve = start.elapsed() / RUNS_PER_SEED;
+
+        let again = RacingLine::new(&map);
+        let deterministic = racing_line
+            .points()
+            .iter()
+            .zip(again.points().iter())
+            .all(|(a, b)| {
+                a.offset.to_bits() == b.offset.to_bits() && a.speed.to_bits() == b.speed.to_bits()
+            });
+
+        let slowest = racing_line
+            .points()
+            .iter()
+            .map(|point| point.speed)
+            .fold(f32::INFINITY, f32::min);
+
+        println!(
+            "seed {:>5}: {:>10?} per solve, slowest corner {:.2}, deterministic: {}",
+            seed, per_solve, slowest, deterministic
+        );
+        assert!(deterministic, "racing line differs between runs");
+    }
+}
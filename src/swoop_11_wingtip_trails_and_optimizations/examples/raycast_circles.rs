@@ -0,0 +1,96 @@
+//! Times `Map::raycast` and the sensor fan on a perfectly circular track
+//! and on a few seeded ones, casting from random points on the track in
+//! random directions. Run natively with:
+//!     cargo run --release --example raycast_circles
+use std::time::Instant;
+
+use swoop_11_wingtip_trails_and_optimizations::map::Map;
+use swoop_11_wingtip_trails_and_optimizations::rng::Rng;
+use swoop_11_wingtip_trails_and_optimizations::sensors::SensorFan;
+use swoop_11_wingtip_trails_and_optimizations::ship::Ship;
+use swoop_11_wingtip_trails_and_optimizations::transform::Transform2d;
+
+const SEEDS: [u64; 3] = [1, 7, 42];
+const MAX_DISTANCE: f32 = 20.0;
+const RAYS: u32 = 100_000;
+const FAN: SensorFan = SensorFan {
+    rays: 9,
+    spread: std::f32::consts::PI / 2.0,
+    range: 5.0,
+};
+
+fn circular_map() -> Map {
+    let mut map = Map::new();
+    map.sin_consts = [0.0; 8];
+    map.cos_consts = [0.0; 8];
+    map.width_sin_consts = [0.0; 4];
+    map.width_cos_consts = [0.0; 4];
+    map
+}
+
+/// A random place on the track, away from the walls, and a random heading
+fn random_pose(map: &Map, rng: &mut Rng) -> Transform2d {
+    let angle = rng.next_f32() * std::f32::consts::PI * 2.0;
+    let centerline = map.centerline_position(angle);
+    let direction = map.centerline_direction(angle);
+    let offset = (rng.next_f32() * 2.0 - 1.0) * map.wall_distance(angle) * 0.9;
+    let heading = rng.next_f32() * std::f32::consts::PI * 2.0;
+    Transform2d::new(
+        centerline.0 - direction.1 * offset,
+        centerline.1 + direction.0 * offset,
+        heading,
+        1.0,
+    )
+}
+
+fn bench(name: &str, map: &Map) {
+    let mut rng = Rng::new(1);
+    let poses: Vec<Transform2d> = (0..RAYS).map(|_| random_pose(map, &mut rng)).collect();
+
+    let start = Instant::now();
+    let mut hits = 0;
+    for pose in poses.iter() {
+        let direction = (-f32::sin(pose.rot), f32::cos(pose.rot));
+        if map
+            .raycast((pose.x, pose.y), direction, MAX_DISTANCE)
+            .is_some()
+        {
+            hits += 1;
+        }
+    }
+    let per_ray = start.elapsed() / RAYS;
+
+    let fans = RAYS / FAN.rays as u32;
+    let start = Instant::now();
+    let mut fan_hits = 0;
+    for pose in poses.iter().take(fans as usize) {
+        let ship = Ship::new((1.0, 1.0, 1.0, 1.0), pose.clone());
+        let readings = FAN.sense(&ship, map);
+        fan_hits += readings
+            .iter()
+            .filter(|reading| reading.hit.is_some())
+            .count();
+    }
+    let per_fan = start.elapsed() / fans;
+
+    println!(
+        "{:>8}: {:>10?} per ray ({} of {} hit), {:>10?} per {} ray fan ({} of {} hit)",
+        name,
+        per_ray,
+        hits,
+        RAYS,
+        per_fan,
+        FAN.rays,
+        fan_hits,
+        fans * FAN.rays as u32
+    );
+}
+
+fn main() {
+    bench("circle", &circular_map());
+    for seed in SEEDS.iter() {
+        let mut map = Map::new();
+        map.randomize(*seed);
+        bench(&format!("seed {}", seed), &map);
+    }
+}
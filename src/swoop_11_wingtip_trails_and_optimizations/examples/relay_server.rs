@@ -0,0 +1,227 @@
+//! Hosts online races for the game and for net_client. Players join a
+//! lobby by name, and once everyone in it is ready the server runs the
+//! race itself and sends everyone snapshots of it. Run natively with:
+//!     cargo run --release --example relay_server -- [--address 127.0.0.1:9001] [--laps N] [--seed N]
+//! then open the game with `server=ws://127.0.0.1:9001` in its options.
+use std::collections::HashMap;
+use std::env;
+use std::io::Write;
+use std::net::{Shutdown, TcpListener, TcpStream};
+use std::process;
+use std::sync::mpsc::{channel, RecvTimeoutError, Sender};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use swoop_11_wingtip_trails_and_optimizations::server::{ClientId, Server};
+use swoop_11_wingtip_trails_and_optimizations::simulation::TICK;
+use swoop_11_wingtip_trails_and_optimizations::websocket::{
+    accept, encode_frame, read_message, FrameReader, Message, Opcode,
+};
+
+const DEFAULT_ADDRESS: &str = "127.0.0.1:9001";
+const DEFAULT_LAPS: u32 = 3;
+/// If the server falls further behind than this it skips ahead rather
+/// than running ticks back to back to catch up
+const MAX_LAG: Duration = Duration::from_secs(1);
+
+struct Settings {
+    address: String,
+    laps: u32,
+    seed: u64,
+}
+
+/// What the connection threads tell the main loop about
+enum Event {
+    /// A client finished the handshake. The stream is for writing to it.
+    Connected(ClientId, TcpStream),
+    Received(ClientId, Vec<u8>),
+    Ping(ClientId, Vec<u8>),
+    Closed(ClientId),
+}
+
+fn parse_args() -> Result<Settings, String> {
+    let mut settings = Settings {
+        address: DEFAULT_ADDRESS.to_string(),
+        laps: DEFAULT_LAPS,
+        seed: SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |time| time.as_secs()),
+    };
+
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        let value = args
+            .next()
+            .ok_or_else(|| format!("{} needs a value", arg))?;
+        match arg.as_str() {
+            "--address" => settings.address = value,
+            "--laps" => {
+                settings.laps = value
+                    .parse()
+                    .ok()
+                    .filter(|laps| *laps > 0)
+                    .ok_or("--laps needs a number")?
+            }
+            "--seed" => settings.seed = value.parse().map_err(|_| "--seed needs a number")?,
+            _ => return Err(format!("unknown argument {}", arg)),
+        }
+    }
+    Ok(settings)
+}
+
+/// Takes connections as they come, each on its own thread so that a slow
+/// handshake doesn't hold up anybody else
+fn listen(listener: TcpListener, events: Sender<Event>) {
+    for (id, stream) in listener.incoming().enumerate() {
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(err) => {
+                eprintln!("Couldn't accept a connection: {}", err);
+                continue;
+            }
+        };
+        let events = events.clone();
+        thread::spawn(move || serve(id as ClientId, stream, events));
+    }
+}
+
+/// Does the handshake and then reads messages until the client goes away
+fn serve(client: ClientId, mut stream: TcpStream, events: Sender<Event>) {
+    // Inputs are tiny and want to go out as soon as they are written
+    let _ = stream.set_nodelay(true);
+    let (path, received) = match accept(&mut stream) {
+        Ok(accepted) => accepted,
+        Err(err) => {
+            eprintln!("Handshake failed: {:?}", err);
+            return;
+        }
+    };
+    let writer = match stream.try_clone() {
+        Ok(writer) => writer,
+        Err(_) => return,
+    };
+    println!("Client {} connected to {}", client, path);
+    if events.send(Event::Connected(client, writer)).is_err() {
+        return;
+    }
+
+    let mut reader = FrameReader::server(received);
+    loop {
+        let event = match read_message(&mut stream, &mut reader) {
+            Ok(Some(Message::Binary(bytes))) => Event::Received(client, bytes),
+            Ok(Some(Message::Ping(payload))) => Event::Ping(client, payload),
+            Ok(Some(Message::Text(_))) | Ok(Some(Message::Pong(_))) => continue,
+            Ok(Some(Message::Close)) | Ok(None) => break,
+            Err(err) => {
+                eprintln!("Client {} sent something wrong: {:?}", client, err);
+                break;
+            }
+        };
+        if events.send(event).is_err() {
+            return;
+        }
+    }
+    let _ = events.send(Event::Closed(client));
+}
+
+/// The connections the main loop writes to, and the server they feed
+struct Relay {
+    server: Server,
+    clients: HashMap<ClientId, TcpStream>,
+}
+
+impl Relay {
+    fn handle(&mut self, event: Event) {
+        match event {
+            Event::Connected(client, stream) => {
+                self.clients.insert(client, stream);
+            }
+            Event::Received(client, bytes) => {
+                if !self.server.receive(client, &bytes) {
+                    // Let them know why before hanging up
+                    self.flush();
+                    self.close(client);
+                }
+            }
+            Event::Ping(client, payload) => {
+                if let Some(stream) = self.clients.get_mut(&client) {
+                    let _ = stream.write_all(&encode_frame(Opcode::Pong, &payload, None));
+                }
+            }
+            Event::Closed(client) => self.close(client),
+        }
+        self.flush();
+    }
+
+    /// Sends out whatever the server has to say, dropping anybody who
+    /// can't be written to
+    fn flush(&mut self) {
+        let mut failed = vec![];
+        for (client, bytes) in self.server.take_outgoing() {
+            if let Some(stream) = self.clients.get_mut(&client) {
+                let frame = encode_frame(Opcode::Binary, &bytes, None);
+                if stream.write_all(&frame).is_err() {
+                    failed.push(client);
+                }
+            }
+        }
+        for client in failed {
+            self.close(client);
+        }
+    }
+
+    fn close(&mut self, client: ClientId) {
+        if let Some(mut stream) = self.clients.remove(&client) {
+            let _ = stream.write_all(&encode_frame(Opcode::Close, &[], None));
+            let _ = stream.shutdown(Shutdown::Both);
+            println!("Client {} disconnected", client);
+        }
+        self.server.disconnect(client);
+    }
+}
+
+fn main() {
+    let settings = match parse_args() {
+        Ok(settings) => settings,
+        Err(err) => {
+            eprintln!("{}", err);
+            eprintln!("usage: relay_server [--address HOST:PORT] [--laps N] [--seed N]");
+            process::exit(1);
+        }
+    };
+    let listener = match TcpListener::bind(&settings.address) {
+        Ok(listener) => listener,
+        Err(err) => {
+            eprintln!("Couldn't listen on {}: {}", settings.address, err);
+            process::exit(1);
+        }
+    };
+    println!("Listening on ws://{}", settings.address);
+
+    let (events, received) = channel();
+    thread::spawn(move || listen(listener, events));
+
+    let mut relay = Relay {
+        server: Server::new(settings.seed, settings.laps),
+        clients: HashMap::new(),
+    };
+    let tick = Duration::from_secs_f32(TICK);
+    let mut next_tick = Instant::now() + tick;
+    loop {
+        let now = Instant::now();
+        if now < next_tick {
+            match received.recv_timeout(next_tick - now) {
+                Ok(event) => relay.handle(event),
+                Err(RecvTimeoutError::Timeout) => {}
+                Err(RecvTimeoutError::Disconnected) => break,
+            }
+            continue;
+        }
+        if now > next_tick + MAX_LAG {
+            next_tick = now;
+        }
+        next_tick += tick;
+        relay.server.tick();
+        relay.flush();
+    }
+}
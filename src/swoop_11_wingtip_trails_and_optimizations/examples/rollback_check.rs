@@ -0,0 +1,259 @@
+//! Times the rollback netcode without a real network. A race between the
+//! built in AI is run once for its controls, then again with each ship on
+//! its own peer, only hearing about the others through the online
+//! protocol over a simulated connection that delays, reorders and loses
+//! messages. Shows how long the peers took and how much they rolled back.
+//! Run natively with:
+//!     cargo run --release --example rollback_check -- [--peers N] [--latency TICKS] [--jitter TICKS] [--loss PERCENT] [--seed N] [--laps N]
+use std::env;
+use std::time::Instant;
+
+use swoop_11_wingtip_trails_and_optimizations::ai::{AiDriver, ALL_PROFILES};
+use swoop_11_wingtip_trails_and_optimizations::controller::{ShipControl, ShipController};
+use swoop_11_wingtip_trails_and_optimizations::map::Map;
+use swoop_11_wingtip_trails_and_optimizations::protocol::ClientMessage;
+use swoop_11_wingtip_trails_and_optimizations::replay::quantize;
+use swoop_11_wingtip_trails_and_optimizations::rollback::{Rollback, MAX_LEAD, MAX_ROLLBACK};
+use swoop_11_wingtip_trails_and_optimizations::simulation::{Simulation, TICK};
+use swoop_11_wingtip_trails_and_optimizations::transport::{LinkConditions, SimulatedLink};
+
+const DEFAULT_PEERS: usize = 4;
+const DEFAULT_LAPS: u32 = 1;
+/// The reference race gives up after this long per lap
+const TIME_LIMIT_PER_LAP: f32 = 60.0;
+/// Ticks of controls in every message. Peers never get more than MAX_LEAD
+/// ticks apart, so this covers everything another peer might be missing.
+const REDUNDANCY: u32 = MAX_ROLLBACK / 2;
+/// Gives up if the peers are still waiting on each other after this many
+/// ticks past the end of the race
+const DRAIN_LIMIT: u32 = 10_000;
+
+struct Settings {
+    peers: usize,
+    conditions: LinkConditions,
+    seed: u64,
+    laps: u32,
+}
+
+fn parse_args() -> Result<Settings, String> {
+    let mut settings = Settings {
+        peers: DEFAULT_PEERS,
+        conditions: LinkConditions {
+            latency: 6,
+            jitter: 4,
+            loss: 0.1,
+        },
+        seed: 1,
+        laps: DEFAULT_LAPS,
+    };
+
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        let value = args
+            .next()
+            .ok_or_else(|| format!("{} needs a value", arg))?;
+        let number = || {
+            value
+                .parse::<u32>()
+                .map_err(|_| format!("{} needs a number", arg))
+        };
+        match arg.as_str() {
+            "--peers" => {
+                settings.peers = number()? as usize;
+                if settings.peers < 2 || settings.peers > ALL_PROFILES.len() {
+                    return Err(format!("--peers has to be 2 to {}", ALL_PROFILES.len()));
+                }
+            }
+            "--latency" => settings.conditions.latency = number()?,
+            "--jitter" => settings.conditions.jitter = number()?,
+            "--loss" => settings.conditions.loss = u32::min(number()?, 100) as f32 / 100.0,
+            "--seed" => settings.seed = number()? as u64,
+            "--laps" => settings.laps = number()?.max(1),
+            _ => return Err(format!("unknown argument {}", arg)),
+        }
+    }
+    Ok(settings)
+}
+
+/// Races the AI with nothing in the way, giving every tick's controls
+fn reference_race(map: &Map, settings: &Settings) -> Vec<Vec<ShipControl>> {
+    let controllers = ALL_PROFILES[..settings.peers]
+        .iter()
+        .enumerate()
+        .map(|(id, profile)| {
+            Box::new(AiDriver::new(*profile, settings.seed + id as u64)) as Box<dyn ShipController>
+        })
+        .collect();
+    let mut simulation = Simulation::new(map.clone(), controllers, settings.laps);
+    let time_limit = TIME_LIMIT_PER_LAP * settings.laps as f32;
+
+    let mut ticks = vec![];
+    while !simulation.race.is_finished() && simulation.race.time < time_limit {
+        let controls: Vec<ShipControl> = simulation
+            .calc_controls(TICK)
+            .expect("the built in AI doesn't fail")
+            .into_iter()
+            .map(quantize)
+            .collect();
+        simulation.advance(&controls, TICK);
+        ticks.push(controls);
+    }
+    ticks
+}
+
+/// One player's view of the race. Its own ship is flown with the
+/// reference controls, and everyone else's with whatever has come in.
+struct Peer {
+    ship: usize,
+    rollback: Rollback,
+    /// For each ship, the first tick whose input hasn't been heard yet,
+    /// counting only unbroken runs from the start
+    heard_until: Vec<u32>,
+}
+
+impl Peer {
+    /// Ticks the other ships have all been heard up to
+    fn confirmed(&self) -> u32 {
+        self.heard_until
+            .iter()
+            .enumerate()
+            .filter(|(ship, _)| *ship != self.ship)
+            .map(|(_, heard)| *heard)
+            .min()
+            .unwrap_or(0)
+    }
+
+    fn receive(&mut self, sender: usize, bytes: &[u8]) {
+        let (tick, controls) = match ClientMessage::from_bytes(bytes) {
+            Ok(ClientMessage::Input { tick, controls }) => (tick, controls),
+            other => panic!("unexpected message {:?}", other),
+        };
+        let first = (tick + 1).saturating_sub(controls.len() as u32);
+        if first <= self.heard_until[sender] {
+            self.heard_until[sender] = u32::max(self.heard_until[sender], tick + 1);
+        }
+        for (input_tick, control) in (first..=tick).zip(controls) {
+            self.rollback.add_input(sender, input_tick, control);
+        }
+    }
+
+    /// The latest run of inputs for this peer's own ship, if it has any
+    fn latest_input(&self) -> Option<Vec<u8>> {
+        let tick = self.rollback.tick().checked_sub(1)?;
+        let controls = self.rollback.recent_controls(self.ship, tick, REDUNDANCY);
+        Some(ClientMessage::Input { tick, controls }.to_bytes())
+    }
+}
+
+fn main() {
+    let settings = match parse_args() {
+        Ok(settings) => settings,
+        Err(err) => {
+            eprintln!("{}", err);
+            eprintln!(
+                "usage: rollback_check [--peers N] [--latency TICKS] [--jitter TICKS] \
+                 [--loss PERCENT] [--seed N] [--laps N]"
+            );
+            return;
+        }
+    };
+
+    let mut map = Map::new();
+    map.randomize(settings.seed);
+    let reference = reference_race(&map, &settings);
+    let end = reference.len() as u32;
+    println!(
+        "Reference race of {} ticks between {} ships",
+        end, settings.peers
+    );
+
+    let mut peers: Vec<Peer> = (0..settings.peers)
+        .map(|ship| Peer {
+            ship,
+            rollback: Rollback::new(map.clone(), settings.peers, settings.laps),
+            heard_until: vec![0; settings.peers],
+        })
+        .collect();
+    // A link each way between every pair of peers
+    let mut links: Vec<Vec<SimulatedLink<Vec<u8>>>> = (0..settings.peers)
+        .map(|from| {
+            (0..settings.peers)
+                .map(|to| {
+                    let seed = settings.seed * 1000 + (from * settings.peers + to) as u64;
+                    SimulatedLink::new(settings.conditions, seed)
+                })
+                .collect()
+        })
+        .collect();
+
+    let start = Instant::now();
+    let mut now = 0;
+    let done = |peers: &[Peer]| {
+        peers
+            .iter()
+            .all(|peer| peer.rollback.tick() == end && peer.confirmed() >= end)
+    };
+    while !done(&peers) {
+        if now > end + DRAIN_LIMIT {
+            println!("The peers never caught up with each other");
+            break;
+        }
+        for (to, peer) in peers.iter_mut().enumerate() {
+            for (from, from_links) in links.iter_mut().enumerate() {
+                for bytes in from_links[to].receive(now) {
+                    peer.receive(from, &bytes);
+                }
+            }
+        }
+        for (from, peer) in peers.iter_mut().enumerate() {
+            // Guessing too far ahead of what has been heard would leave late
+            // inputs nowhere to be rolled back to
+            let tick = peer.rollback.tick();
+            if tick < end && tick < peer.confirmed() + MAX_LEAD {
+                let control = reference[tick as usize][peer.ship];
+                peer.rollback.add_input(peer.ship, tick, control);
+                peer.rollback.advance();
+            }
+            // Sent every tick, even when waiting, so lost inputs are sent again
+            if let Some(bytes) = peer.latest_input() {
+                for (to, link) in links[from].iter_mut().enumerate() {
+                    if to != from {
+                        link.send(now, bytes.clone());
+                    }
+                }
+            }
+        }
+        now += 1;
+    }
+
+    for peer in peers.iter_mut() {
+        peer.rollback.settle();
+    }
+    let elapsed = start.elapsed();
+
+    for peer in peers.iter() {
+        let stats = peer.rollback.stats;
+        println!(
+            "Peer {}: rolled back {} times running {} ticks again, \
+             {} deep at most, {} too late",
+            peer.ship + 1,
+            stats.rollbacks,
+            stats.resimulated,
+            stats.deepest,
+            stats.too_late
+        );
+    }
+    let sent: u32 = links.iter().flatten().map(|link| link.sent).sum();
+    let lost: u32 = links.iter().flatten().map(|link| link.lost).sum();
+    println!(
+        "Finished {} ticks after the reference, {} of {} messages lost",
+        now - end,
+        lost,
+        sent
+    );
+    println!(
+        "{:?} in all, {:?} per peer per tick",
+        elapsed,
+        elapsed / (settings.peers as u32 * end)
+    );
+}
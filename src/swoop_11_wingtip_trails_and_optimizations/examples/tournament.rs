@@ -0,0 +1,225 @@
+//! Races AI profiles and Rhai scripted bots against each other on a series
+//! of seeded maps and totals up the points. Run natively with:
+//!     cargo run --release --example tournament -- [--races N] [--laps N] ENTRANT...
+//! where each entrant is the name of an AI profile, script:PATH, policy
+//! for the built in trained policy or policy:PATH, for example:
+//!     cargo run --release --example tournament -- champion pro script:examples/bots/chase_centerline.rhai
+use std::env;
+use std::fs;
+use std::path::Path;
+use std::process;
+
+use swoop_11_wingtip_trails_and_optimizations::ai::AiDriver;
+use swoop_11_wingtip_trails_and_optimizations::championship::DEFAULT_POINTS;
+use swoop_11_wingtip_trails_and_optimizations::controller::{
+    ControllerError, Observation, ShipControl, ShipController,
+};
+use swoop_11_wingtip_trails_and_optimizations::game_options::DriverOption;
+use swoop_11_wingtip_trails_and_optimizations::map::Map;
+use swoop_11_wingtip_trails_and_optimizations::policy::{
+    Policy, PolicyController, BUILT_IN_POLICY,
+};
+use swoop_11_wingtip_trails_and_optimizations::script_controller::ScriptController;
+use swoop_11_wingtip_trails_and_optimizations::simulation::Simulation;
+
+const DT: f32 = 1.0 / 60.0;
+const DEFAULT_RACES: u64 = 5;
+const DEFAULT_LAPS: u32 = 3;
+/// Ships that haven't finished after this long per lap don't score
+const TIME_LIMIT_PER_LAP: f32 = 60.0;
+
+/// Takes over from a controller that has errored, so the ship just coasts
+struct Coast;
+
+impl ShipController for Coast {
+    fn control(&mut self, _observation: &Observation) -> Result<ShipControl, ControllerError> {
+        Ok(ShipControl::default())
+    }
+}
+
+struct Entrant {
+    name: String,
+    driver: DriverOption,
+    /// Script or policy file contents, loaded once up front
+    source: Option<String>,
+    points: u32,
+    wins: u32,
+    finishes: u32,
+    failures: u32,
+}
+
+impl Entrant {
+    fn create_controller(&self, seed: u64) -> Result<Box<dyn ShipController>, String> {
+        match (&self.driver, &self.source) {
+            (DriverOption::Ai(profile), _) => Ok(Box::new(AiDriver::new(*profile, seed))),
+            (DriverOption::Script(_), Some(source)) => ScriptController::new(source)
+                .map(|controller| Box::new(controller) as Box<dyn ShipController>)
+                .map_err(|err| format!("{:?}", err)),
+            (DriverOption::Policy(_), source) => source
+                .as_deref()
+                .unwrap_or(BUILT_IN_POLICY)
+                .parse::<Policy>()
+                .map(|policy| Box::new(PolicyController::new(policy)) as Box<dyn ShipController>)
+                .map_err(|err| format!("{:?}", err)),
+            _ => Err(format!("{} can't race in a tournament", self.name)),
+        }
+    }
+}
+
+struct Settings {
+    races: u64,
+    laps: u32,
+    entrants: Vec<Entrant>,
+}
+
+fn parse_args() -> Result<Settings, String> {
+    let mut settings = Settings {
+        races: DEFAULT_RACES,
+        laps: DEFAULT_LAPS,
+        entrants: vec![],
+    };
+
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--races" => {
+                settings.races = args
+                    .next()
+                    .and_then(|races| races.parse().ok())
+                    .ok_or("--races needs a number")?;
+            }
+            "--laps" => {
+                settings.laps = args
+                    .next()
+                    .and_then(|laps| laps.parse().ok())
+                    .filter(|laps| *laps > 0)
+                    .ok_or("--laps needs a number")?;
+            }
+            name => {
+                let driver = match DriverOption::from_name(name) {
+                    Some(DriverOption::Player) | None => {
+                        return Err(format!("unknown entrant {}", name))
+                    }
+                    Some(driver) => driver,
+                };
+                let (name, source) = match &driver {
+                    DriverOption::Script(path) | DriverOption::Policy(Some(path)) => (
+                        // Files go by their name to keep the tables narrow
+                        Path::new(path)
+                            .file_stem()
+                            .map_or(name.to_string(), |stem| stem.to_string_lossy().into()),
+                        Some(
+                            fs::read_to_string(path)
+                                .map_err(|err| format!("couldn't read {}: {}", path, err))?,
+                        ),
+                    ),
+                    _ => (name.to_string(), None),
+                };
+                settings.entrants.push(Entrant {
+                    name,
+                    driver,
+                    source,
+                    points: 0,
+                    wins: 0,
+                    finishes: 0,
+                    failures: 0,
+                });
+            }
+        }
+    }
+
+    if settings.entrants.len() < 2 {
+        return Err("a tournament needs at least two entrants".to_string());
+    }
+    Ok(settings)
+}
+
+/// Runs one race with the grid rotated so everyone gets a turn at the
+/// front, and adds the results onto the entrants
+fn run_race(race_number: u64, laps: u32, entrants: &mut [Entrant]) -> Result<(), String> {
+    let seed = race_number + 1;
+    let mut map = Map::new();
+    map.randomize(seed);
+
+    // grid[slot] is the entrant starting in that slot
+    let grid: Vec<usize> = (0..entrants.len())
+        .map(|slot| (slot + race_number as usize) % entrants.len())
+        .collect();
+    let controllers = grid
+        .iter()
+        .map(|entrant_id| entrants[*entrant_id].create_controller(seed * 100 + *entrant_id as u64))
+        .collect::<Result<Vec<_>, _>>()?;
+    let mut simulation = Simulation::new(map, controllers, laps);
+
+    println!("Race {} (seed {}):", race_number + 1, seed);
+    let time_limit = TIME_LIMIT_PER_LAP * laps as f32;
+    while !simulation.race.is_finished() && simulation.race.time < time_limit {
+        if let Err((slot, err)) = simulation.step(DT) {
+            let entrant = &mut entrants[grid[slot]];
+            println!("  {} failed: {:?}", entrant.name, err);
+            entrant.failures += 1;
+            simulation.controllers[slot] = Box::new(Coast);
+        }
+    }
+
+    for (position, slot) in simulation.race.standings().iter().enumerate() {
+        let entrant = &mut entrants[grid[*slot]];
+        match simulation.race.progress[*slot].finish_time {
+            Some(finish_time) => {
+                let points = DEFAULT_POINTS.get(position).copied().unwrap_or(0);
+                entrant.points += points;
+                entrant.finishes += 1;
+                if position == 0 {
+                    entrant.wins += 1;
+                }
+                println!(
+                    "  {}. {:<30} {:>7.2}s {:>3} points",
+                    position + 1,
+                    entrant.name,
+                    finish_time,
+                    points
+                );
+            }
+            None => println!("  {}. {:<30}     DNF", position + 1, entrant.name),
+        }
+    }
+    Ok(())
+}
+
+fn main() {
+    let mut settings = match parse_args() {
+        Ok(settings) => settings,
+        Err(err) => {
+            eprintln!("{}", err);
+            eprintln!("usage: tournament [--races N] [--laps N] ENTRANT...");
+            process::exit(1);
+        }
+    };
+
+    for race_number in 0..settings.races {
+        if let Err(err) = run_race(race_number, settings.laps, &mut settings.entrants) {
+            eprintln!("{}", err);
+            process::exit(1);
+        }
+    }
+
+    settings
+        .entrants
+        .sort_by(|a, b| b.points.cmp(&a.points).then(b.wins.cmp(&a.wins)));
+    println!("Standings after {} races:", settings.races);
+    println!(
+        "     {:<30} {:>6} {:>4} {:>8} {:>8}",
+        "entrant", "points", "wins", "finishes", "failures"
+    );
+    for (position, entrant) in settings.entrants.iter().enumerate() {
+        println!(
+            "  {}. {:<30} {:>6} {:>4} {:>8} {:>8}",
+            position + 1,
+            entrant.name,
+            entrant.points,
+            entrant.wins,
+            entrant.finishes,
+            entrant.failures
+        );
+    }
+}
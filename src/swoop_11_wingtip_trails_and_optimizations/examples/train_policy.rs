@@ -0,0 +1,222 @@
+//! Trains a policy to race using the gym environment and a simple
+//! evolution strategy, then compares it with the built in AI on maps it
+//! hasn't seen. Run natively with:
+//!     cargo run --release --example train_policy -- [--generations N] [--hidden N] [--out PATH]
+//! The saved policy can be dropped into src/policies/racer.policy to
+//! become the built in one.
+use std::env;
+use std::fs;
+use std::process;
+use std::time::Instant;
+
+use swoop_11_wingtip_trails_and_optimizations::ai::{AiDriver, PRO, ROOKIE};
+use swoop_11_wingtip_trails_and_optimizations::gym::{EnvConfig, RaceEnv};
+use swoop_11_wingtip_trails_and_optimizations::map::Map;
+use swoop_11_wingtip_trails_and_optimizations::policy::Policy;
+use swoop_11_wingtip_trails_and_optimizations::rng::Rng;
+use swoop_11_wingtip_trails_and_optimizations::simulation::Simulation;
+
+const DEFAULT_GENERATIONS: u32 = 100;
+const DEFAULT_HIDDEN: usize = 0;
+const DEFAULT_OUT: &str = "racer.policy";
+
+/// Pairs of mirrored perturbations tried each generation
+const HALF_POPULATION: usize = 12;
+const NOISE_SCALE: f32 = 0.1;
+const LEARNING_RATE: f32 = 0.05;
+const INITIAL_WEIGHT_SCALE: f32 = 0.2;
+/// Each candidate is scored on this many maps, picked fresh each
+/// generation from TRAINING_MAPS
+const MAPS_PER_GENERATION: usize = 2;
+const TRAINING_MAPS: u64 = 50;
+/// Maps for the final comparison, which are never trained on
+const TEST_SEEDS: [u64; 5] = [1001, 1002, 1003, 1004, 1005];
+const EPISODE_TIME: f32 = 30.0;
+
+struct Settings {
+    generations: u32,
+    hidden: usize,
+    out: String,
+}
+
+fn parse_args() -> Result<Settings, String> {
+    let mut settings = Settings {
+        generations: DEFAULT_GENERATIONS,
+        hidden: DEFAULT_HIDDEN,
+        out: DEFAULT_OUT.to_string(),
+    };
+
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        let value = args
+            .next()
+            .ok_or_else(|| format!("{} needs a value", arg))?;
+        match arg.as_str() {
+            "--generations" => {
+                settings.generations = value.parse().map_err(|_| "--generations needs a number")?
+            }
+            "--hidden" => settings.hidden = value.parse().map_err(|_| "--hidden needs a number")?,
+            "--out" => settings.out = value,
+            _ => return Err(format!("unknown argument {}", arg)),
+        }
+    }
+    Ok(settings)
+}
+
+/// Total reward for one lap on a map, along with how many steps it took
+/// and the finish time if it got round
+fn run_episode(env: &mut RaceEnv, policy: &Policy, seed: u64) -> (f32, u32, Option<f32>) {
+    let mut observations = env.reset(seed);
+    let mut total_reward = 0.0;
+    let mut steps = 0;
+    loop {
+        let result = env.step(&[policy.to_control(&observations[0])]);
+        total_reward += result.rewards[0];
+        steps += 1;
+        if result.done {
+            break;
+        }
+        observations = result.observations;
+    }
+    let finish_time = env.simulation().race.progress[0].finish_time;
+    (total_reward, steps, finish_time)
+}
+
+/// Lap time for a built in AI driving the same map alone
+fn ai_lap_time(driver: AiDriver, seed: u64) -> Option<f32> {
+    let mut map = Map::new();
+    map.randomize(seed);
+    let mut simulation = Simulation::new(map, vec![Box::new(driver)], 1);
+    while !simulation.race.is_finished() && simulation.race.time < EPISODE_TIME {
+        simulation
+            .step(1.0 / 60.0)
+            .expect("the built in AI doesn't fail");
+    }
+    simulation.race.progress[0].finish_time
+}
+
+/// Replaces scores with their rank, scaled from -0.5 to 0.5, so a single
+/// lucky run can't dominate the update
+fn centered_ranks(scores: &[f32]) -> Vec<f32> {
+    let mut order: Vec<usize> = (0..scores.len()).collect();
+    order.sort_by(|a, b| scores[*a].partial_cmp(&scores[*b]).unwrap());
+    let mut ranks = vec![0.0; scores.len()];
+    for (rank, id) in order.iter().enumerate() {
+        ranks[*id] = rank as f32 / (scores.len() - 1) as f32 - 0.5;
+    }
+    ranks
+}
+
+fn format_time(time: Option<f32>) -> String {
+    time.map_or("   DNF".to_string(), |time| format!("{:>5.2}s", time))
+}
+
+fn main() {
+    let settings = match parse_args() {
+        Ok(settings) => settings,
+        Err(err) => {
+            eprintln!("{}", err);
+            eprintln!("usage: train_policy [--generations N] [--hidden N] [--out PATH]");
+            process::exit(1);
+        }
+    };
+
+    let mut config = EnvConfig::new();
+    config.time_limit = EPISODE_TIME;
+    let dt = config.dt;
+    let mut env = RaceEnv::new(config);
+
+    let mut rng = Rng::new(1);
+    let hidden: Vec<usize> = if settings.hidden > 0 {
+        vec![settings.hidden]
+    } else {
+        vec![]
+    };
+    let mut policy = Policy::random(&hidden, INITIAL_WEIGHT_SCALE, &mut rng);
+    let mut parameters = policy.parameters();
+    let mut candidate = policy.clone();
+
+    let start = Instant::now();
+    let mut total_steps: u64 = 0;
+    for generation in 0..settings.generations {
+        let seeds: Vec<u64> = (0..MAPS_PER_GENERATION)
+            .map(|_| (rng.next_u32() as u64) % TRAINING_MAPS)
+            .collect();
+
+        let mut noises = vec![];
+        let mut scores = vec![];
+        for _ in 0..HALF_POPULATION {
+            let noise: Vec<f32> = parameters
+                .iter()
+                .map(|_| rng.next_f32() * 2.0 - 1.0)
+                .collect();
+            for sign in [1.0, -1.0].iter() {
+                let perturbed: Vec<f32> = parameters
+                    .iter()
+                    .zip(noise.iter())
+                    .map(|(p, n)| p + sign * NOISE_SCALE * n)
+                    .collect();
+                candidate.set_parameters(&perturbed);
+
+                let mut score = 0.0;
+                for seed in seeds.iter() {
+                    let (reward, steps, _) = run_episode(&mut env, &candidate, *seed);
+                    score += reward;
+                    total_steps += steps as u64;
+                }
+                scores.push(score / MAPS_PER_GENERATION as f32);
+            }
+            noises.push(noise);
+        }
+
+        let ranks = centered_ranks(&scores);
+        for (pair, noise) in noises.iter().enumerate() {
+            let weight = ranks[pair * 2] - ranks[pair * 2 + 1];
+            for (parameter, n) in parameters.iter_mut().zip(noise.iter()) {
+                *parameter += LEARNING_RATE / (HALF_POPULATION as f32 * NOISE_SCALE) * weight * n;
+            }
+        }
+
+        if generation % 10 == 0 || generation + 1 == settings.generations {
+            let mean = scores.iter().sum::<f32>() / scores.len() as f32;
+            let best = scores.iter().cloned().fold(f32::MIN, f32::max);
+            println!(
+                "generation {:>4}: mean reward {:>6.2}, best {:>6.2}",
+                generation, mean, best
+            );
+        }
+    }
+
+    let elapsed = start.elapsed().as_secs_f32();
+    println!(
+        "{} steps in {:.1}s, {:.0} steps per second, {:.0}x real time",
+        total_steps,
+        elapsed,
+        total_steps as f32 / elapsed,
+        total_steps as f32 * dt / elapsed
+    );
+
+    policy.set_parameters(&parameters);
+    println!("Lap times on unseen maps:");
+    println!("  seed   policy   rookie      pro");
+    for seed in TEST_SEEDS.iter() {
+        let (_, _, policy_time) = run_episode(&mut env, &policy, *seed);
+        println!(
+            "  {} {} {} {}",
+            seed,
+            format_time(policy_time),
+            format_time(ai_lap_time(AiDriver::new(ROOKIE, *seed), *seed)),
+            format_time(ai_lap_time(AiDriver::new(PRO, *seed), *seed))
+        );
+    }
+
+    let text = format!(
+        "# Trained for {} generations with train_policy\n{}",
+        settings.generations, policy
+    );
+    if let Err(err) = fs::write(&settings.out, text) {
+        eprintln!("couldn't write {}: {}", settings.out, err);
+        process::exit(1);
+    }
+    println!("Saved to {}", settings.out);
+}
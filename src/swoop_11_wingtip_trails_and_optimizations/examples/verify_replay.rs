@@ -0,0 +1,181 @@
+//! Re-runs a replay file downloaded from the game and checks it against
+//! the checksums recorded with it. Run natively with:
+//!     cargo run --release --example verify_replay -- FILE
+//! It can also record a race between the built in AI to try it out on:
+//!     cargo run --release --example verify_replay -- --record FILE [--seed N] [--laps N]
+//!
+//! Ships are simulated with the platform's own maths library, which can
+//! round sin and cos differently to the browser's. A desync on a replay
+//! recorded in the browser may be down to that rather than the replay.
+use std::env;
+use std::fs;
+use std::process;
+
+use swoop_11_wingtip_trails_and_optimizations::ai::{
+    AiDriver, AGGRESSIVE, CHAMPION, DEFENSIVE, PRO, ROOKIE,
+};
+use swoop_11_wingtip_trails_and_optimizations::controller::ShipController;
+use swoop_11_wingtip_trails_and_optimizations::map::Map;
+use swoop_11_wingtip_trails_and_optimizations::replay::{quantize, Playback, Recorder, Replay};
+use swoop_11_wingtip_trails_and_optimizations::simulation::{Simulation, TICK};
+
+const DEFAULT_SEED: u64 = 1;
+const DEFAULT_LAPS: u32 = 3;
+/// Recording gives up on a race that runs longer than this per lap
+const TIME_LIMIT_PER_LAP: f32 = 60.0;
+
+enum Command {
+    Verify(String),
+    Record { path: String, seed: u64, laps: u32 },
+}
+
+fn parse_args() -> Result<Command, String> {
+    let args: Vec<String> = env::args().skip(1).collect();
+    match args.first().map(String::as_str) {
+        Some("--record") => {
+            let path = args.get(1).ok_or("--record needs a file")?.clone();
+            let mut seed = DEFAULT_SEED;
+            let mut laps = DEFAULT_LAPS;
+            let mut rest = args[2..].iter();
+            while let Some(arg) = rest.next() {
+                let value = rest
+                    .next()
+                    .ok_or_else(|| format!("{} needs a value", arg))?;
+                match arg.as_str() {
+                    "--seed" => seed = value.parse().map_err(|_| "--seed needs a number")?,
+                    "--laps" => laps = value.parse().map_err(|_| "--laps needs a number")?,
+                    _ => return Err(format!("unknown argument {}", arg)),
+                }
+            }
+            Ok(Command::Record { path, seed, laps })
+        }
+        Some(path) if args.len() == 1 => Ok(Command::Verify(path.to_string())),
+        _ => Err("expected a replay file".to_string()),
+    }
+}
+
+/// Races the built in AI natively, recording it the same way the game does
+fn record(seed: u64, laps: u32) -> Replay {
+    let profiles = [CHAMPION, PRO, AGGRESSIVE, DEFENSIVE, ROOKIE];
+    let controllers = profiles
+        .iter()
+        .enumerate()
+        .map(|(id, profile)| {
+            Box::new(AiDriver::new(*profile, seed + id as u64)) as Box<dyn ShipController>
+        })
+        .collect();
+    let drivers = profiles
+        .iter()
+        .map(|profile| profile.name.to_string())
+        .collect();
+
+    let mut map = Map::new();
+    map.randomize(seed);
+    let mut simulation = Simulation::new(map, controllers, laps);
+    let mut recorder = Recorder::new(seed, laps, drivers);
+    while !simulation.race.is_finished() && simulation.race.time < TIME_LIMIT_PER_LAP * laps as f32
+    {
+        let controls: Vec<_> = simulation
+            .calc_controls(TICK)
+            .expect("the built in AI doesn't fail")
+            .into_iter()
+            .map(quantize)
+            .collect();
+        simulation.advance(&controls, TICK);
+        recorder.record(&controls, &simulation.ships);
+    }
+    recorder.finish()
+}
+
+/// Plays the replay through and reports how it went. Returns false if it
+/// didn't match the recording.
+fn verify(replay: Replay) -> bool {
+    println!(
+        "seed {}, {} laps, {} ships, {} ticks ({:.2}s)",
+        replay.seed,
+        replay.laps,
+        replay.drivers.len(),
+        replay.ticks(),
+        replay.duration()
+    );
+
+    let mut playback = Playback::new(replay);
+    while playback.step() {}
+
+    for (position, ship_id) in playback.race.standings().iter().enumerate() {
+        let finish_time = playback.race.progress[*ship_id]
+            .finish_time
+            .map_or("DNF".to_string(), |time| format!("{:.2}s", time));
+        println!(
+            "  {}. {:<30} {}",
+            position + 1,
+            playback.replay.drivers[*ship_id],
+            finish_time
+        );
+    }
+
+    match playback.desync {
+        None => {
+            println!(
+                "ok: all {} checksums match",
+                playback.replay.checksums.len()
+            );
+            true
+        }
+        Some(tick) => {
+            println!(
+                "desync: ships stopped matching the recording at {:.2}s",
+                tick as f32 * TICK
+            );
+            false
+        }
+    }
+}
+
+fn main() {
+    let command = match parse_args() {
+        Ok(command) => command,
+        Err(err) => {
+            eprintln!("{}", err);
+            eprintln!("usage: verify_replay FILE");
+            eprintln!("       verify_replay --record FILE [--seed N] [--laps N]");
+            process::exit(1);
+        }
+    };
+
+    match command {
+        Command::Record { path, seed, laps } => {
+            let replay = record(seed, laps);
+            let bytes = replay.to_bytes();
+            if let Err(err) = fs::write(&path, &bytes) {
+                eprintln!("couldn't write {}: {}", path, err);
+                process::exit(1);
+            }
+            println!(
+                "Recorded {:.2}s of racing into {} bytes at {}",
+                replay.duration(),
+                bytes.len(),
+                path
+            );
+        }
+        Command::Verify(path) => {
+            let bytes = match fs::read(&path) {
+                Ok(bytes) => bytes,
+                Err(err) => {
+                    eprintln!("couldn't read {}: {}", path, err);
+                    process::exit(1);
+                }
+            };
+            let replay = match Replay::from_bytes(&bytes) {
+                Ok(replay) => replay,
+                Err(err) => {
+                    eprintln!("{} isn't a valid replay: {:?}", path, err);
+                    process::exit(1);
+                }
+            };
+            if !verify(replay) {
+                process::exit(1);
+            }
+        }
+    }
+}
@@ -1,52 +1,631 @@
+use super::catch_up::CatchUp;
+use super::controller::{ControllerError, NearbyShip, Observation, ShipControl, ShipController};
 use super::map::Map;
+use super::racing_line::{RacingLine, MAX_BRAKING, MAX_SPEED};
+use super::rng::Rng;
 use super::ship::Ship;
-use super::transform::{PolarCoordinate, Vec2};
+use super::transform::{length, Vec2};
+use std::collections::VecDeque;
 use std::f32::consts::PI;
 use wasm_bindgen::prelude::wasm_bindgen;
 
+/// How far along the track the most skilled racers look for corners
+const BRAKING_LOOKAHEAD: f32 = 4.0;
+/// Step size when marching ahead along the track
+const LOOKAHEAD_STEP_ANGLE: f32 = 0.05;
+/// How strongly the thrust reacts to being off the target speed
+const SPEED_GAIN: f32 = 2.0;
+
+/// How far towards the racing line the most skilled racers aim. Steering
+/// at predicted positions already cuts in a little on its own, so aiming
+/// for the whole line puts ships into the inside wall.
+const LINE_COMMITMENT: f32 = 0.6;
+/// How far ahead the racing line is sampled to find which way it is heading
+const LINE_SLOPE_STEP_ANGLE: f32 = 0.02;
+
+/// How long an AI keeps running wide after making a mistake
+const MISTAKE_DURATION: f32 = 0.6;
+/// How hard an AI steers off its line while making a mistake
+const MISTAKE_STEERING: f32 = 0.5;
+/// A mistake is cut short if the ship is heading for a wall it would
+/// reach within this long
+const MISTAKE_WALL_WARNING_TIME: f32 = 0.3;
+
+/// How far ahead along the track the most observant racers watch for
+/// ships to get past
+const TRAFFIC_LOOKAHEAD: f32 = 2.0;
+/// How far back a racer watches for ships trying to get past it
+const TRAFFIC_LOOKBEHIND: f32 = 1.5;
+/// Sideways gap left when passing another ship, center to center
+const PASSING_CLEARANCE: f32 = 0.35;
+/// Roughly how long a ship is nose to tail. A pass is finished once this
+/// far ahead, and a ship any closer than this is alongside rather than
+/// behind.
+const SHIP_LENGTH: f32 = 0.6;
+/// Closest a racer will follow behind a ship it can't get past
+const FOLLOWING_DISTANCE: f32 = 0.5;
+/// How strongly the speed reacts to being too close behind another ship
+const FOLLOWING_GAIN: f32 = 3.0;
+/// How close to the walls a racer will go to get past or to defend
+const TRAFFIC_WALL_MARGIN: f32 = 0.15;
+
+/// Describes how a particular AI racer drives
+#[derive(Clone, Copy, Debug)]
+pub struct AiProfile {
+    pub name: &'static str,
+    /// How well the racing line is followed and corner speeds judged, from
+    /// zero to one
+    pub skill: f32,
+    /// Seconds between the AI deciding to do something and the ship
+    /// doing it
+    pub reaction_delay: f32,
+    /// Size of the random wobble added to the steering each frame
+    pub steering_noise: f32,
+    /// Multiplier on how far ahead the AI predicts when steering
+    pub lookahead: f32,
+    /// How much of the boost meter the AI is willing to spend, from zero
+    /// to one
+    pub boost_usage: f32,
+    /// How keen the AI is to move across and defend its position, from
+    /// zero to one
+    pub blocking_tendency: f32,
+    /// Chance per second of the AI running wide
+    pub mistake_probability: f32,
+}
+
+pub const ROOKIE: AiProfile = AiProfile {
+    name: "rookie",
+    skill: 0.4,
+    reaction_delay: 0.25,
+    steering_noise: 0.3,
+    lookahead: 0.5,
+    boost_usage: 0.9,
+    blocking_tendency: 0.0,
+    mistake_probability: 0.05,
+};
+
+pub const PRO: AiProfile = AiProfile {
+    name: "pro",
+    skill: 0.75,
+    reaction_delay: 0.12,
+    steering_noise: 0.1,
+    lookahead: 0.8,
+    boost_usage: 0.5,
+    blocking_tendency: 0.3,
+    mistake_probability: 0.02,
+};
+
+pub const CHAMPION: AiProfile = AiProfile {
+    name: "champion",
+    skill: 1.0,
+    reaction_delay: 0.05,
+    steering_noise: 0.02,
+    lookahead: 1.0,
+    boost_usage: 0.6,
+    blocking_tendency: 0.4,
+    mistake_probability: 0.005,
+};
+
+pub const AGGRESSIVE: AiProfile = AiProfile {
+    name: "aggressive",
+    skill: 0.85,
+    reaction_delay: 0.08,
+    steering_noise: 0.15,
+    lookahead: 0.9,
+    boost_usage: 1.0,
+    blocking_tendency: 0.1,
+    mistake_probability: 0.03,
+};
+
+pub const DEFENSIVE: AiProfile = AiProfile {
+    name: "defensive",
+    skill: 0.8,
+    reaction_delay: 0.1,
+    steering_noise: 0.05,
+    lookahead: 0.9,
+    boost_usage: 0.3,
+    blocking_tendency: 0.9,
+    mistake_probability: 0.01,
+};
+
+pub const ALL_PROFILES: [AiProfile; 5] = [ROOKIE, PRO, CHAMPION, AGGRESSIVE, DEFENSIVE];
+
+impl AiProfile {
+    pub fn from_name(name: &str) -> Option<AiProfile> {
+        ALL_PROFILES
+            .iter()
+            .find(|profile| profile.name == name)
+            .copied()
+    }
+}
+
+/// Where across the track a racer wants to be because of other ships,
+/// instead of on the racing line
+#[derive(Clone, Copy, Debug)]
+struct Lane {
+    /// Target distance from the centerline, positive is to the left
+    offset: f32,
+    /// How much this overrides the racing line, from zero to one
+    weight: f32,
+}
+
+/// How a racer reacts to the ships around it
+struct TrafficPlan {
+    lane: Option<Lane>,
+    speed_limit: f32,
+}
+
+/// Everything an AI racer remembers between frames
+pub struct AiDriver {
+    pub profile: AiProfile,
+    rng: Rng,
+    time: f32,
+    /// Decisions that have been made but not acted on yet because of the
+    /// reaction delay
+    pending: VecDeque<(f32, ShipControl)>,
+    current: ShipControl,
+    mistake_time_left: f32,
+    mistake_direction: f32,
+    /// Which side the ship ahead is being passed on, positive is to the
+    /// left. This is kept until the pass is done so the AI doesn't dither
+    /// behind the other ship.
+    passing_side: Option<f32>,
+}
+
+impl AiDriver {
+    pub fn new(profile: AiProfile, seed: u64) -> Self {
+        Self {
+            profile,
+            rng: Rng::new(seed),
+            time: 0.0,
+            pending: VecDeque::new(),
+            current: ShipControl::default(),
+            mistake_time_left: 0.0,
+            mistake_direction: 1.0,
+            passing_side: None,
+        }
+    }
+
+    /// Flies a copy of the ship forward through the decisions that have
+    /// been made but not acted on yet
+    fn predict_ship(&self, ship: &Ship) -> Ship {
+        let mut predicted = ship.clone();
+        let mut time = self.time;
+        let mut control = self.current;
+
+        for (decided_at, next_control) in self.pending.iter() {
+            let acted_at = decided_at + self.profile.reaction_delay;
+            control.apply(&mut predicted);
+            predicted.update(acted_at - time);
+            time = acted_at;
+            control = *next_control;
+        }
+        control.apply(&mut predicted);
+        predicted.update(self.time + self.profile.reaction_delay - time);
+
+        predicted
+    }
+}
+
+impl ShipController for AiDriver {
+    fn control(&mut self, observation: &Observation) -> Result<ShipControl, ControllerError> {
+        let dt = observation.dt;
+        self.time += dt;
+
+        while let Some((decided_at, _)) = self.pending.front() {
+            if *decided_at > self.time - self.profile.reaction_delay {
+                break;
+            }
+            self.current = self.pending.pop_front().expect("pending empty").1;
+        }
+
+        // Anything decided now only happens after the reaction delay, so
+        // decide based on where the ship will be by then
+        let predicted_ship = self.predict_ship(observation.ship);
+        let predicted_others: Vec<NearbyShip> = observation
+            .others
+            .iter()
+            .map(|other| other.predict(self.profile.reaction_delay))
+            .collect();
+        let traffic = calc_traffic_plan(
+            &predicted_ship,
+            &predicted_others,
+            &self.profile,
+            observation.map,
+            &mut self.passing_side,
+        );
+        let mut control = calc_ai_control(
+            &predicted_ship,
+            &self.profile,
+            observation.map,
+            observation.racing_line,
+            &traffic,
+            &observation.catch_up,
+        );
+
+        control.steering += (self.rng.next_f32() - 0.5) * 2.0 * self.profile.steering_noise;
+
+        if self.mistake_time_left > 0.0 && is_heading_for_wall(&predicted_ship, observation.map) {
+            self.mistake_time_left = 0.0;
+        }
+        if self.mistake_time_left > 0.0 {
+            self.mistake_time_left -= dt;
+            control.steering += MISTAKE_STEERING * self.mistake_direction;
+            control.thrust = 1.0;
+        } else if self.rng.next_f32() < self.profile.mistake_probability * dt {
+            self.mistake_time_left = MISTAKE_DURATION;
+            self.mistake_direction = if self.rng.next_f32() < 0.5 { -1.0 } else { 1.0 };
+        }
+
+        if self.profile.reaction_delay > 0.0 {
+            self.pending.push_back((self.time, control));
+        } else {
+            self.current = control;
+        }
+
+        Ok(self.current)
+    }
+}
+
 #[wasm_bindgen]
 extern "C" {
     #[wasm_bindgen(js_namespace = console)]
     fn log(s: &str);
 }
 
-pub fn calc_ai_control(ship: &mut Ship, skill: f32, map: &Map) {
+fn calc_ai_control(
+    ship: &Ship,
+    profile: &AiProfile,
+    map: &Map,
+    racing_line: &RacingLine,
+    traffic: &TrafficPlan,
+    catch_up: &CatchUp,
+) -> ShipControl {
     let mut steering = 0.0;
     let mut thrust = 0.0;
 
-    let lookahead_mul = skill;
+    let skill = profile.skill;
+    // Looking further ahead than the most skilled racers do doesn't help
+    let lookahead_mul = f32::min(profile.lookahead * catch_up.lookahead_scale, 1.0);
+    let max_thrust = catch_up.thrust_scale;
+    let max_speed = MAX_SPEED * max_thrust;
 
-    steering += calc_steering_input(&ship, &map, 1.0 * lookahead_mul) * 0.15;
-    steering += calc_steering_input(&ship, &map, 0.5 * lookahead_mul) * 0.45;
-    steering += calc_steering_input(&ship, &map, 0.2 * lookahead_mul) * 0.4;
+    let steering_input = |lookahead_time: f32| {
+        let position = predict_position(ship, lookahead_time);
+        calc_steering_input(
+            position,
+            ship.position.rot,
+            map,
+            racing_line,
+            skill,
+            traffic.lane,
+        )
+    };
+    steering += steering_input(1.0 * lookahead_mul) * 0.15;
+    steering += steering_input(0.5 * lookahead_mul) * 0.45;
+    steering += steering_input(0.2 * lookahead_mul) * 0.4;
 
-    thrust += 1.0;
+    let target_speed = f32::min(
+        calc_target_speed(ship, map, racing_line, skill, max_thrust),
+        traffic.speed_limit,
+    );
+    thrust += calc_thrust_input(ship, target_speed, max_thrust);
 
-    ship.angular_thrust = f32::max(f32::min(steering, 1.0), -1.0);
-    ship.linear_thrust = f32::max(f32::min(thrust, 3.0), -1.0);
+    // Only boost to get up to speed on the straights, and keep some in
+    // reserve depending on how boost-happy this racer is
+    let speed = length(&(ship.velocity.x, ship.velocity.y));
+    let on_straight = target_speed >= max_speed && speed < max_speed;
+    let boost = on_straight && ship.boost > 1.0 - profile.boost_usage;
+    if boost {
+        thrust = max_thrust;
+    }
+
+    ShipControl {
+        steering,
+        thrust,
+        boost,
+    }
 }
 
-fn calc_steering_input(ship: &Ship, map: &Map, lookahead_time: f32) -> f32 {
-    let polar_position = PolarCoordinate::from_cartesian(predict_position(ship, lookahead_time));
+/// Works out how to steer to get back on the racing line, or into the
+/// lane picked because of traffic, for a ship heading in the direction
+/// `rotation` that will be at `position`
+fn calc_steering_input(
+    position: Vec2,
+    rotation: f32,
+    map: &Map,
+    racing_line: &RacingLine,
+    skill: f32,
+    lane: Option<Lane>,
+) -> f32 {
+    let track_point = map.closest_point(position);
+
+    let track_angle_here = f32::atan2(-track_point.direction.0, track_point.direction.1);
+    let track_width_here = map.track_width(track_point.angle);
+
+    // Less skilled racers stay closer to the centerline rather than
+    // following the racing line into the apex
+    let line_following = skill * LINE_COMMITMENT;
+    let target_offset = racing_line.sample(track_point.angle).offset * line_following;
 
-    let track_angle_here = map.get_track_direction(polar_position.angle);
-    let track_radius_here = map.track_radius(polar_position.angle);
+    // The racing line crosses the track, so it doesn't point quite the
+    // same way as the centerline
+    let angle_ahead = track_point.angle - LINE_SLOPE_STEP_ANGLE;
+    let offset_ahead = racing_line.sample(angle_ahead).offset * line_following;
+    let step_length = map.arc_length(angle_ahead, track_point.angle);
+    let mut line_angle = f32::atan((offset_ahead - target_offset) / step_length);
+
+    let mut target_offset = target_offset;
+    if let Some(lane) = lane {
+        target_offset += (lane.offset - target_offset) * lane.weight;
+        line_angle *= 1.0 - lane.weight;
+    }
 
     let mut steering = 0.0;
 
-    let radius_error = track_radius_here - polar_position.radius;
-    let radius_steering_input = f32::max(f32::min(radius_error, PI / 2.0), -PI / 2.0);
+    // Being off the line matters more when the track is narrow
+    let lateral_error =
+        (target_offset - track_point.lateral_offset) * map.track_base_width / track_width_here;
+    let lateral_steering_input = lateral_error.clamp(-PI / 2.0, PI / 2.0);
 
     let mut target_angle = 0.0;
     target_angle += track_angle_here;
-    target_angle += radius_steering_input;
+    target_angle += line_angle;
+    target_angle += lateral_steering_input;
 
-    let angular_error = wrap_angle(target_angle - ship.position.rot);
+    let angular_error = wrap_angle(target_angle - rotation);
     steering += angular_error;
 
     steering
 }
 
+/// Another ship as seen from this one, measured along and across the
+/// track at the other ship's own place on the track. Projecting onto this
+/// ship's heading instead is way off in corners.
+struct RelativeShip {
+    /// Distance ahead along the track, negative if behind
+    ahead: f32,
+    /// Offset from the centerline, positive is to the left
+    offset: f32,
+    /// Speed along the track
+    speed: f32,
+}
+
+/// Looks at the ships nearby to decide whether to move across to pass the
+/// ship ahead, hold back behind it, give room to a ship alongside, or
+/// move across to defend against a ship coming from behind
+fn calc_traffic_plan(
+    ship: &Ship,
+    others: &[NearbyShip],
+    profile: &AiProfile,
+    map: &Map,
+    passing_side: &mut Option<f32>,
+) -> TrafficPlan {
+    let position = (ship.position.x, ship.position.y);
+    let track_point = map.closest_point(position);
+    let our_offset = track_point.lateral_offset;
+    let limit = f32::max(
+        map.wall_distance(track_point.angle) - TRAFFIC_WALL_MARGIN,
+        0.0,
+    );
+    let speed = dot((ship.velocity.x, ship.velocity.y), track_point.direction);
+    let lookahead = TRAFFIC_LOOKAHEAD * profile.lookahead;
+
+    let watch_distance = f32::max(lookahead, TRAFFIC_LOOKBEHIND);
+    let nearby: Vec<RelativeShip> = others
+        .iter()
+        .filter(|other| {
+            let delta = (other.position.0 - position.0, other.position.1 - position.1);
+            length(&delta) < watch_distance
+        })
+        .map(|other| {
+            let other_point = map.closest_point(other.position);
+            // Ships race clockwise, so ahead is towards smaller angles
+            let angle_ahead = wrap_angle(track_point.angle - other_point.angle);
+            let ahead = f32::signum(angle_ahead)
+                * map.arc_length(track_point.angle - angle_ahead, track_point.angle);
+            RelativeShip {
+                ahead,
+                offset: other_point.lateral_offset,
+                speed: dot(other.velocity, other_point.direction),
+            }
+        })
+        .collect();
+    let closest = |a: &&RelativeShip, b: &&RelativeShip| {
+        f32::abs(a.ahead)
+            .partial_cmp(&f32::abs(b.ahead))
+            .unwrap_or(std::cmp::Ordering::Equal)
+    };
+    let clamp_to_track = |offset: f32| f32::max(f32::min(offset, limit), -limit);
+
+    let mut plan = TrafficPlan {
+        lane: None,
+        speed_limit: f32::INFINITY,
+    };
+
+    // Never run into the back of a ship that is in the way, whether or
+    // not there's a plan to get past it
+    for other in nearby.iter() {
+        let in_the_way = f32::abs(other.offset - our_offset) < PASSING_CLEARANCE * 0.5;
+        if other.ahead > 0.0 && other.ahead < lookahead && in_the_way {
+            let following_speed = other.speed + (other.ahead - FOLLOWING_DISTANCE) * FOLLOWING_GAIN;
+            plan.speed_limit = f32::min(plan.speed_limit, following_speed);
+        }
+    }
+
+    // The closest ship ahead that this one is catching and is in the way.
+    // Once a pass has started it carries on until this ship is past, even
+    // though the other ship is no longer directly ahead.
+    let passing = passing_side.is_some();
+    let blocker = nearby
+        .iter()
+        .filter(|other| {
+            let overlap = f32::abs(other.offset - our_offset);
+            if passing {
+                other.ahead > -SHIP_LENGTH
+                    && other.ahead < lookahead
+                    && overlap < PASSING_CLEARANCE * 2.0
+            } else {
+                other.ahead > 0.0
+                    && other.ahead < lookahead
+                    && other.speed < speed
+                    && overlap < PASSING_CLEARANCE
+            }
+        })
+        .min_by(closest);
+
+    if let Some(other) = blocker {
+        let room_left = limit - other.offset;
+        let room_right = limit + other.offset;
+        let room_on = |side: f32| if side > 0.0 { room_left } else { room_right };
+
+        // Stick with the side already picked while there's still room
+        // there, otherwise go round the side this ship is already on,
+        // unless there's no space there either
+        let side = match *passing_side {
+            Some(side) if room_on(side) >= PASSING_CLEARANCE => side,
+            _ => {
+                let preferred = if our_offset >= other.offset {
+                    1.0
+                } else {
+                    -1.0
+                };
+                if room_on(preferred) >= PASSING_CLEARANCE {
+                    preferred
+                } else if room_left > room_right {
+                    1.0
+                } else {
+                    -1.0
+                }
+            }
+        };
+        *passing_side = Some(side);
+
+        plan.lane = Some(Lane {
+            offset: clamp_to_track(other.offset + side * PASSING_CLEARANCE),
+            weight: 1.0,
+        });
+
+        // With no way past, sit behind rather than squeeze into the wall
+        if other.ahead > 0.0 && room_on(side) < PASSING_CLEARANCE {
+            let following_speed = other.speed + (other.ahead - FOLLOWING_DISTANCE) * FOLLOWING_GAIN;
+            plan.speed_limit = f32::min(plan.speed_limit, following_speed);
+        }
+        return plan;
+    }
+    *passing_side = None;
+
+    // Leave room for a ship alongside rather than turning into it
+    let alongside = nearby
+        .iter()
+        .filter(|other| {
+            f32::abs(other.ahead) < SHIP_LENGTH
+                && f32::abs(other.offset - our_offset) < PASSING_CLEARANCE
+        })
+        .min_by(closest);
+
+    if let Some(other) = alongside {
+        let side = if our_offset >= other.offset {
+            1.0
+        } else {
+            -1.0
+        };
+        plan.lane = Some(Lane {
+            offset: clamp_to_track(other.offset + side * PASSING_CLEARANCE),
+            weight: 1.0,
+        });
+        return plan;
+    }
+
+    // Nothing to pass, so see if there's anyone to defend against. Move
+    // across in front of the closest ship that is catching up.
+    let attacker = nearby
+        .iter()
+        .filter(|other| {
+            other.ahead < -SHIP_LENGTH && other.ahead > -TRAFFIC_LOOKBEHIND && other.speed > speed
+        })
+        .min_by(closest);
+
+    if let Some(other) = attacker {
+        if profile.blocking_tendency > 0.0 {
+            plan.lane = Some(Lane {
+                offset: clamp_to_track(other.offset),
+                weight: profile.blocking_tendency,
+            });
+        }
+    }
+
+    plan
+}
+
+fn calc_thrust_input(ship: &Ship, target_speed: f32, max_thrust: f32) -> f32 {
+    // Only count speed in the direction the ship is facing, otherwise a
+    // ship knocked backwards would keep reversing to slow down
+    let facing = (-f32::sin(ship.position.rot), f32::cos(ship.position.rot));
+    let speed = dot((ship.velocity.x, ship.velocity.y), facing);
+
+    let thrust = Ship::thrust_to_hold_speed(target_speed) + (target_speed - speed) * SPEED_GAIN;
+    f32::max(f32::min(thrust, max_thrust), -1.0)
+}
+
+/// The fastest the ship should be going right now so that it can still
+/// slow down in time for the corners ahead, going by how fast the racing
+/// line can take each of them. Less skilled racers look less far ahead
+/// and overestimate their brakes, so they arrive at corners too fast.
+/// They also don't trust their grip so take the corners themselves
+/// slower, but go just as fast on the straights.
+fn calc_target_speed(
+    ship: &Ship,
+    map: &Map,
+    racing_line: &RacingLine,
+    skill: f32,
+    max_thrust: f32,
+) -> f32 {
+    let lookahead_distance = BRAKING_LOOKAHEAD * skill;
+    let believed_braking = MAX_BRAKING / skill;
+    // Cornering speed goes with the square root of the grip
+    let corner_judgement = f32::sqrt(0.5 + 0.5 * skill);
+
+    let mut angle = map.closest_point((ship.position.x, ship.position.y)).angle;
+    let mut position = map.centerline_position(angle);
+    let mut distance = 0.0;
+    let mut target_speed = MAX_SPEED;
+
+    while distance <= lookahead_distance {
+        let corner_speed = racing_line.sample(angle).corner_speed;
+        let straightness = corner_speed / MAX_SPEED;
+        let judgement = 1.0 - (1.0 - corner_judgement) * (1.0 - straightness);
+        let believed_corner_speed = corner_speed * judgement;
+        let allowed_speed = f32::sqrt(
+            believed_corner_speed * believed_corner_speed + 2.0 * believed_braking * distance,
+        );
+        target_speed = f32::min(target_speed, allowed_speed);
+
+        // Ships race clockwise, so ahead is towards smaller angles
+        angle -= LOOKAHEAD_STEP_ANGLE;
+        let next_position = map.centerline_position(angle);
+        distance += length(&(next_position.0 - position.0, next_position.1 - position.1));
+        position = next_position;
+    }
+
+    target_speed * max_thrust
+}
+
+/// Looks along the direction of travel to see whether the ship will hit
+/// a wall soon if nothing changes
+fn is_heading_for_wall(ship: &Ship, map: &Map) -> bool {
+    let velocity = (ship.velocity.x, ship.velocity.y);
+    let speed = length(&velocity);
+    if speed < 0.01 {
+        return false;
+    }
+
+    let direction = (velocity.0 / speed, velocity.1 / speed);
+    let range = speed * MISTAKE_WALL_WARNING_TIME;
+    map.raycast((ship.position.x, ship.position.y), direction, range)
+        .is_some()
+}
+
 fn predict_position(ship: &Ship, time: f32) -> Vec2 {
     (
         ship.position.x + ship.velocity.x * time,
@@ -54,6 +633,10 @@ fn predict_position(ship: &Ship, time: f32) -> Vec2 {
     )
 }
 
+fn dot(a: Vec2, b: Vec2) -> f32 {
+    a.0 * b.0 + a.1 * b.1
+}
+
 fn wrap_angle(angle: f32) -> f32 {
     let angle = angle + PI;
     let sig = f32::signum(angle);
@@ -61,3 +644,306 @@ fn wrap_angle(angle: f32) -> f32 {
 
     sig * (mag - PI)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::physics::calc_racing_ship_physics;
+    use crate::race::Race;
+    use crate::racing_line::MAX_ACCELERATION;
+    use crate::transform::Transform2d;
+
+    const DT: f32 = 1.0 / 60.0;
+    const SEEDS: [u64; 3] = [1, 7, 42];
+    /// Ships closer than this, center to center, have run into each other
+    const CONTACT_DISTANCE: f32 = 0.2;
+    /// The speed the ship being overtaken is held to
+    const SLOW_SPEED: f32 = 2.0;
+    /// How long the faster ship gets to make the pass
+    const OVERTAKE_TIME: f32 = 8.0;
+
+    /// Flies like the AI but can't go faster than SLOW_SPEED
+    struct SlowDriver(AiDriver);
+
+    impl ShipController for SlowDriver {
+        fn control(&mut self, observation: &Observation) -> Result<ShipControl, ControllerError> {
+            let mut control = self.0.control(observation)?;
+            control.thrust = f32::min(control.thrust, Ship::thrust_to_hold_speed(SLOW_SPEED));
+            control.boost = false;
+            Ok(control)
+        }
+    }
+
+    /// Ships flown by the real AI and physics, counting how often any of
+    /// those still racing run into each other
+    struct Scenario {
+        map: Map,
+        racing_line: RacingLine,
+        ships: Vec<Ship>,
+        controllers: Vec<Box<dyn ShipController>>,
+        race: Race,
+        /// Whether each ship is still in the race, as in elimination races
+        racing: Vec<bool>,
+        contacts: u32,
+    }
+
+    impl Scenario {
+        /// Places one ship per profile along the track, `gap` radians apart
+        /// starting from the start line and going forwards, all at `speed`
+        fn new(seed: u64, profiles: &[AiProfile], slow: &[bool], gap: f32, speed: f32) -> Self {
+            let mut map = Map::new();
+            map.randomize(seed);
+            let racing_line = RacingLine::new(&map);
+
+            let start_angle = map.get_start_position().angle;
+            let ships: Vec<Ship> = (0..profiles.len())
+                .map(|id| {
+                    // Ships race clockwise, so ahead is towards smaller angles
+                    let angle = start_angle - gap * id as f32;
+                    let position = map.centerline_position(angle);
+                    let direction = map.centerline_direction(angle);
+                    let mut ship = Ship::new(
+                        (1.0, 1.0, 1.0, 1.0),
+                        Transform2d::new(
+                            position.0,
+                            position.1,
+                            map.get_track_direction(angle),
+                            1.0,
+                        ),
+                    );
+                    ship.velocity.x = direction.0 * speed;
+                    ship.velocity.y = direction.1 * speed;
+                    ship
+                })
+                .collect();
+
+            let controllers = profiles
+                .iter()
+                .zip(slow.iter())
+                .enumerate()
+                .map(|(id, (profile, slow))| {
+                    let driver = AiDriver::new(*profile, seed + id as u64);
+                    if *slow {
+                        Box::new(SlowDriver(driver)) as Box<dyn ShipController>
+                    } else {
+                        Box::new(driver)
+                    }
+                })
+                .collect();
+            let race = Race::new(&ships, &map, 1);
+
+            Self {
+                map,
+                racing_line,
+                ships,
+                controllers,
+                race,
+                racing: vec![true; profiles.len()],
+                contacts: 0,
+            }
+        }
+
+        fn step(&mut self) {
+            let mut controls = Vec::with_capacity(self.ships.len());
+            for (id, controller) in self.controllers.iter_mut().enumerate() {
+                if !self.racing[id] {
+                    controls.push(ShipControl::default());
+                    continue;
+                }
+                let others = NearbyShip::racing_others(&self.ships, &self.racing, id);
+                let observation = Observation {
+                    ship: &self.ships[id],
+                    others: &others,
+                    map: &self.map,
+                    racing_line: &self.racing_line,
+                    catch_up: CatchUp::NONE,
+                    dt: DT,
+                };
+                controls.push(controller.control(&observation).expect("AI can't fail"));
+            }
+            for (ship, control) in self.ships.iter_mut().zip(controls.iter()) {
+                control.apply(ship);
+            }
+
+            calc_racing_ship_physics(&mut self.ships, &self.racing, &self.map, DT);
+            self.race.update(&self.ships, &self.map, DT);
+
+            let racing: Vec<&Ship> = self
+                .ships
+                .iter()
+                .zip(self.racing.iter())
+                .filter(|(_, racing)| **racing)
+                .map(|(ship, _)| ship)
+                .collect();
+            for (id, ship) in racing.iter().enumerate() {
+                for other in racing[id + 1..].iter() {
+                    let delta = (
+                        other.position.x - ship.position.x,
+                        other.position.y - ship.position.y,
+                    );
+                    if length(&delta) < CONTACT_DISTANCE {
+                        self.contacts += 1;
+                    }
+                }
+            }
+        }
+    }
+
+    /// A fast AI starts right behind a slow ship and has to get past it
+    /// without running into it
+    #[test]
+    fn overtakes_a_slower_ship() {
+        for seed in SEEDS.iter() {
+            let mut scenario =
+                Scenario::new(*seed, &[CHAMPION, PRO], &[false, true], 0.15, SLOW_SPEED);
+
+            let mut time = 0.0;
+            let mut passed = false;
+            while time < OVERTAKE_TIME {
+                scenario.step();
+                time += DT;
+                passed |= scenario.race.distance(0) > scenario.race.distance(1);
+            }
+
+            assert!(
+                passed,
+                "seed {}: didn't get past within {}s",
+                seed, OVERTAKE_TIME
+            );
+            assert!(
+                scenario.contacts == 0,
+                "seed {}: ran into the ship it was passing",
+                seed
+            );
+        }
+    }
+
+    /// A pack of racers starting close together shouldn't pile into each
+    /// other
+    #[test]
+    fn pack_keeps_apart() {
+        let profiles = [DEFENSIVE, CHAMPION, PRO, CHAMPION, PRO];
+        for seed in SEEDS.iter() {
+            let mut scenario = Scenario::new(*seed, &profiles, &[false; 5], 0.1, 2.0);
+
+            let frames = (20.0 / DT) as u32;
+            for _ in 0..frames {
+                scenario.step();
+            }
+
+            assert!(
+                scenario.contacts < frames / 20,
+                "seed {}: ships spent {} of {} frames running into each other",
+                seed,
+                scenario.contacts,
+                frames
+            );
+        }
+    }
+
+    /// A ship knocked out of an elimination race is left parked on the
+    /// track. Nothing can hit it, so the AI should drive straight through
+    /// it as if it wasn't there.
+    #[test]
+    fn ignores_eliminated_ships() {
+        const PARKED_AT: f32 = 2.0;
+        let steps = (4.0 / DT) as u32;
+        for seed in SEEDS.iter() {
+            let mut alone = Scenario::new(*seed, &[CHAMPION], &[false], 0.0, 2.0);
+            let mut path = vec![];
+            for _ in 0..steps {
+                alone.step();
+                let ship = &alone.ships[0];
+                path.push((ship.position.x, ship.position.y, ship.position.rot));
+            }
+
+            // Park a ship right where the AI goes when racing alone
+            let mut scenario =
+                Scenario::new(*seed, &[CHAMPION, CHAMPION], &[false, false], 0.0, 2.0);
+            let parked = path[(PARKED_AT / DT) as usize];
+            let ship = &mut scenario.ships[1];
+            ship.position = Transform2d::new(parked.0, parked.1, parked.2, 1.0);
+            ship.velocity = Transform2d::new(0.0, 0.0, 0.0, 1.0);
+            scenario.racing[1] = false;
+
+            for position in path.iter() {
+                scenario.step();
+                let ship = &scenario.ships[0];
+                let flown = (ship.position.x, ship.position.y, ship.position.rot);
+                assert_eq!(flown, *position, "seed {}", seed);
+            }
+            let ship = &scenario.ships[1];
+            assert_eq!(
+                (ship.position.x, ship.position.y),
+                (parked.0, parked.1),
+                "seed {}",
+                seed
+            );
+        }
+    }
+
+    /// The most a racer goes over the speed the racing line can take
+    /// anywhere on a lap, as a ratio. The ship follows the centerline,
+    /// speeding up and slowing down towards its target speed no harder
+    /// than ships really can.
+    fn worst_overshoot(seed: u64, skill: f32) -> f32 {
+        const STEP_ANGLE: f32 = 0.001;
+        let steps_per_lap = (PI * 2.0 / STEP_ANGLE) as usize;
+
+        let mut map = Map::new();
+        map.randomize(seed);
+        let racing_line = RacingLine::new(&map);
+
+        let mut angle = 0.0;
+        let mut position = map.centerline_position(angle);
+        let mut speed = MAX_SPEED;
+        let mut worst: f32 = 0.0;
+        // The first lap gets the ship up to racing speed
+        for step in 0..steps_per_lap * 2 {
+            // Ships race clockwise, so ahead is towards smaller angles
+            angle -= STEP_ANGLE;
+            let next_position = map.centerline_position(angle);
+            let distance = length(&(next_position.0 - position.0, next_position.1 - position.1));
+            position = next_position;
+
+            let ship = Ship::new(
+                (1.0, 1.0, 1.0, 1.0),
+                Transform2d::new(position.0, position.1, 0.0, 1.0),
+            );
+            let target_speed = calc_target_speed(&ship, &map, &racing_line, skill, 1.0);
+            let accelerated = f32::sqrt(speed * speed + 2.0 * MAX_ACCELERATION * distance);
+            let braked = f32::sqrt(f32::max(speed * speed - 2.0 * MAX_BRAKING * distance, 0.0));
+            speed = f32::max(f32::min(accelerated, target_speed), braked);
+
+            if step >= steps_per_lap {
+                worst = f32::max(worst, speed / racing_line.sample(angle).corner_speed);
+            }
+        }
+        worst
+    }
+
+    /// Less skilled racers look less far ahead and think they can brake
+    /// harder than they can, so they come into hairpins faster than they
+    /// can be taken. The best racers only run wide by as much as they
+    /// misjudge between looking ahead.
+    #[test]
+    fn rookie_arrives_at_a_hairpin_too_fast() {
+        // Tracks with a hairpin in them
+        for seed in [1, 2, 17].iter() {
+            let rookie = worst_overshoot(*seed, ROOKIE.skill);
+            let champion = worst_overshoot(*seed, CHAMPION.skill);
+            assert!(
+                rookie > 1.2,
+                "seed {}: rookie only went {} times the corner speed",
+                seed,
+                rookie
+            );
+            assert!(
+                champion < 1.1,
+                "seed {}: champion went {} times the corner speed",
+                seed,
+                champion
+            );
+        }
+    }
+}
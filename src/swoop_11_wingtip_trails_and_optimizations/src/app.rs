@@ -1,31 +1,168 @@
 use wasm_bindgen::prelude::wasm_bindgen;
 use wasm_bindgen::{JsCast, JsValue};
-use web_sys::{window, HtmlCanvasElement, KeyboardEvent, MouseEvent, WebGl2RenderingContext};
+use web_sys::{
+    window, Blob, BlobPropertyBag, Gamepad, GamepadButton, HtmlAnchorElement, HtmlCanvasElement,
+    KeyboardEvent, MouseEvent, Storage, Url, WebGl2RenderingContext, WheelEvent,
+};
 
-use super::ai::calc_ai_control;
-use super::camera::Camera;
-use super::engine_trail::EngineTrail;
+use super::ai::AiDriver;
+use super::camera::{Camera, CameraMode};
+use super::catch_up::{calc_catch_up, calc_racing_catch_up, CatchUp};
+use super::championship::{Championship, Track};
+use super::clock::GameClock;
+use super::controller::{NearbyShip, Observation, ShipControl, ShipController};
+use super::director::{Director, Shot, Transition};
+use super::elimination::Elimination;
+use super::engine_trail::ShipTrails;
 use super::engine_trail_sprite::EngineTrailSprite;
-use super::keymap::{KeyMap, KeyState};
+use super::entity::{Components, Entities, EntityId};
+use super::game_options::{DriverOption, GameMode, GameOptions, TrackOption};
+use super::ghost::{Ghost, GhostRecorder, GHOST_STORAGE_PREFIX};
+use super::gym::SENSORS;
+use super::hud::{
+    championship_lines, countdown_text, leaderboard_lines, lobby_lines, position_text,
+    results_lines, speed_text, timing_lines, Anchor, HudBatch, RaceResult,
+};
+use super::hud_sprite::HudSprite;
+use super::input::{GamepadState, PlayerInput};
+use super::keymap::{KeyMap, KeyState, PlayerKeys};
+use super::line_sprite::LineSprite;
 use super::map::Map;
 use super::map_sprite::MapSprite;
-use super::physics::calc_ship_physics;
-use super::ship::Ship;
+use super::minimap::Minimap;
+use super::minimap_sprite::MinimapSprite;
+use super::net::Connection;
+use super::physics::{calc_racing_ship_physics, calc_ship_physics};
+use super::policy::{Policy, PolicyController, BUILT_IN_POLICY};
+use super::profile::{Profile, RaceOutcome, RaceTally, PROFILE_KEY};
+use super::protocol::{ClientMessage, LobbyPlayer, ServerMessage, ShipState};
+use super::race::{place_on_grid, Race};
+use super::racing_line::RacingLine;
+use super::replay::{quantize, Playback, Recorder, Replay};
+use super::rollback::{Rollback, INPUT_REDUNDANCY};
+use super::script_controller::ScriptController;
+use super::ship::{Color, Ship, SHIP_COLORS};
 use super::ship_sprite::ShipSprite;
+use super::simulation::TICK;
+use super::storage::{KeyValueStore, StorageError};
+use super::timing::format_time;
 use super::transform::Transform2d;
+use super::viewport::{split, Viewport};
 
-const CYAN_SHIP: (f32, f32, f32, f32) = (0.0, 0.5, 1.0, 1.0);
-const YELLOW_SHIP: (f32, f32, f32, f32) = (1.0, 0.5, 0.0, 1.0);
-const PINK_SHIP: (f32, f32, f32, f32) = (1.0, 0.0, 0.5, 1.0);
-const PURPLE_SHIP: (f32, f32, f32, f32) = (0.5, 0.0, 1.0, 1.0);
-const WHITE_SHIP: (f32, f32, f32, f32) = (1.0, 1.0, 1.0, 1.0);
+/// Most simulation steps run in one frame. Any more time than this is
+/// dropped so that a slow frame doesn't snowball.
+const MAX_TICKS_PER_FRAME: u32 = 10;
+/// How far the arrow keys jump through a replay, in seconds
+const SCRUB_STEP: f32 = 5.0;
+/// How long the countdown before each race lasts, in seconds
+const COUNTDOWN_TIME: f32 = 3.0;
+const MIN_PLAYBACK_SPEED: f32 = 0.25;
+const MAX_PLAYBACK_SPEED: f32 = 4.0;
+
+/// Ghosts are drawn dimmer than the ships actually racing
+const GHOST_COLOR: (f32, f32, f32, f32) = (1.0, 1.0, 1.0, 0.3);
+const HUD_COLOR: (f32, f32, f32, f32) = (1.0, 1.0, 1.0, 1.0);
+/// The championship in progress is kept in localStorage under this
+const CHAMPIONSHIP_STORAGE_KEY: &str = "swoop-championship";
+
+/// Whatever is in control of a ship
+enum ShipDriver {
+    /// Flown by the player with this index, with their controls
+    Player(usize),
+    Controller {
+        controller: Box<dyn ShipController>,
+        /// Set once the controller has errored, after which the ship
+        /// coasts for the rest of the race
+        failed: bool,
+    },
+    /// The controller couldn't be created, so nobody is flying
+    Idle,
+    /// Flown by someone else in an online race. The rollback works out
+    /// where it goes from what they send.
+    Remote,
+}
+
+/// A replay being watched in place of the race
+struct Viewer {
+    playback: Playback,
+    /// Multiplier on how quickly the replay runs
+    speed: f32,
+    /// Which ship the camera follows
+    camera_ship: usize,
+    unsimulated_time: f32,
+    /// Entities standing in for the replay's ships, which only have a
+    /// race slot, so trails can follow them like they do live ships
+    ships: Vec<EntityId>,
+}
+
+/// Racing online through a relay server
+struct Online {
+    connection: Connection,
+    /// Set once the lobby has been asked to be joined
+    joined: bool,
+    /// Set once the player has said they are ready for the next race
+    ready: bool,
+    /// Who is in the lobby, as the server last said
+    lobby: Vec<LobbyPlayer>,
+    /// Names in the order the last race finished
+    results: Vec<String>,
+    /// The ship this player flies in the race under way, if there is one
+    ship: Option<usize>,
+    /// The race under way as this player sees it, run on ahead of the
+    /// server and wound back whenever it turns out to be wrong
+    rollback: Option<Rollback>,
+    /// The tick of the last snapshot
+    server_tick: u32,
+}
+
+impl Online {
+    fn new(connection: Connection) -> Self {
+        Self {
+            connection,
+            joined: false,
+            ready: false,
+            lobby: vec![],
+            results: vec![],
+            ship: None,
+            rollback: None,
+            server_tick: 0,
+        }
+    }
+
+    fn send(&self, message: &ClientMessage) {
+        if let Err(err) = self.connection.send(message) {
+            log(&format!("Couldn't send to the server {:?}", err));
+        }
+    }
+
+    /// Tells the server this player is ready to race, if it is listening
+    fn ready_up(&mut self) {
+        if self.joined && !self.ready && !self.connection.is_closed() {
+            self.ready = true;
+            self.send(&ClientMessage::Ready);
+        }
+    }
+
+    /// What the player is waiting on between races
+    fn status(&self) -> &'static str {
+        if self.connection.is_closed() {
+            "Lost the connection to the server"
+        } else if !self.joined {
+            "Connecting to the server"
+        } else if self.ready {
+            "Waiting for everyone to be ready"
+        } else {
+            "Press Enter when ready"
+        }
+    }
+}
 
 #[wasm_bindgen]
 extern "C" {
     #[wasm_bindgen(js_namespace = console)]
     fn log(s: &str);
       #[wasm_bindgen(js_namespace = Math)]
-    fn random() -> f32;
+    fn random() -> f64;
 }
 
 pub struct App {
@@ -34,20 +171,95 @@ pub struct App {
     ship_sprite: ShipSprite,
     map_sprite: MapSprite,
     engine_trail_sprite: EngineTrailSprite,
+    line_sprite: LineSprite,
+    hud_sprite: HudSprite,
+    minimap_sprite: MinimapSprite,
     key_map: KeyMap,
     map: Map,
+    racing_line: RacingLine,
+    options: GameOptions,
+    race: Race,
+    /// Per ship help or handicap from the catch-up assist
+    catch_up: Vec<CatchUp>,
+    /// The standings when the catch-up was last logged in debug mode
+    logged_standings: Vec<usize>,
 
     prev_time: f64,
+    clock: GameClock,
+    /// Time that has passed but isn't yet enough for a whole tick
+    unsimulated_time: f64,
+    /// Time left before the race starts. Keeps counting down past zero
+    /// so the HUD can show the start for a moment.
+    countdown: f32,
+
+    /// Seed for the current map, so the race can be replayed
+    seed: u64,
+    recorder: Recorder,
+    /// The most recently finished race
+    last_replay: Option<Replay>,
+    viewer: Option<Viewer>,
 
-    ship_entities: Vec<Ship>,
-    engine_trails: Vec<(EngineTrail,EngineTrail,EngineTrail)>,
-    camera: Camera,
+    /// The best lap on this track, in time trials
+    ghost: Option<Ghost>,
+    ghost_recorder: GhostRecorder,
+    /// Start of the lap being recorded, as timed by the race
+    ghost_lap_start: Option<f32>,
+    /// Laps the first ship had completed as of the last tick
+    laps_completed: u32,
+
+    /// The championship being run, in championship mode
+    championship: Option<Championship>,
+    /// Set between championship rounds while the results are up
+    intermission: bool,
+    /// How the last race finished, shown during the countdown of the next
+    results: Vec<RaceResult>,
+    /// Who has been knocked out, in elimination races
+    elimination: Option<Elimination>,
+    /// Picks which ship to watch, when nobody is playing
+    director: Option<Director>,
+    /// The connection to the server, when racing online
+    online: Option<Online>,
+    /// The player's name, color, records and stats, kept between visits
+    profile: Profile,
+    /// How the first player's ship has flown this race, for their profile
+    tally: RaceTally,
+    /// What each ship is painted and called, in starting order. The same
+    /// as SHIP_COLORS unless the player has picked a color of their own.
+    ship_colors: Vec<(&'static str, Color)>,
+
+    entities: Entities,
+    /// Ships in starting order
+    ship_entities: Components<Ship>,
+    /// Each ship's id in the race, replays and standings. Unlike where it
+    /// is in `ship_entities`, this stays put when other ships are removed.
+    race_slots: Components<usize>,
+    ship_drivers: Components<ShipDriver>,
+    /// Trails are entities of their own, so they can be left behind by
+    /// the ship that made them
+    engine_trails: Components<ShipTrails>,
+    /// One camera for each view on the screen
+    cameras: Vec<Camera>,
+    /// Where each view is on the canvas
+    viewports: Vec<Viewport>,
+    /// The gamepads plugged in as of this frame, by their index in the
+    /// browser. Only read when a player is using one.
+    gamepads: Vec<Option<GamepadState>>,
 
     canvas_resolution: (u32, u32),
+    /// Device pixels to each CSS pixel the canvas was last sized with
+    pixel_ratio: f32,
 }
 
 impl App {
-    pub fn new(canvas: HtmlCanvasElement, _options: String) -> Self {
+    pub fn new(canvas: HtmlCanvasElement, options: String) -> Self {
+        let options = match options.parse::<GameOptions>() {
+            Ok(options) => options,
+            Err(err) => {
+                log(&format!("options error {:?}", err));
+                GameOptions::new()
+            }
+        };
+
         let gl = get_gl_context(&canvas).expect("No GL Canvas");
 
         gl.clear_color(0.0, 0.0, 0.0, 1.0);
@@ -85,41 +297,52 @@ impl App {
             }
         };
 
-        let ship_entities = vec![
-            Ship::new(CYAN_SHIP, Transform2d::new(0.0, 0.0, 0.0, 0.1)),
-            Ship::new(YELLOW_SHIP, Transform2d::new(0.0, 0.1, 0.0, 0.1)),
-            Ship::new(PINK_SHIP, Transform2d::new(0.0, 0.2, 0.0, 0.1)),
-            Ship::new(PURPLE_SHIP, Transform2d::new(0.0, 0.3, 0.0, 0.1)),
-            Ship::new(WHITE_SHIP, Transform2d::new(0.0, 0.4, 0.0, 0.1)),
-        ];
-
-        let mut engine_trails = vec![];
-        for ship in ship_entities.iter() {
-              const MAIN_TRAIL_WIDTH: f32 = 0.10;
-            const WINGTIP_TRAIL_WIDTH: f32 = 0.02;
-            const MAIN_TRAIL_BRIGHTNESS: f32 = 0.3;
-            const WINGTIP_TRAIL_BRIGHTNESS: f32 = 1.0;
-
-            engine_trails.push(
-                (
-                    EngineTrail::new(ship.color.clone(), MAIN_TRAIL_WIDTH, MAIN_TRAIL_BRIGHTNESS),
-                    EngineTrail::new(ship.color.clone(), WINGTIP_TRAIL_WIDTH, WINGTIP_TRAIL_BRIGHTNESS),
-                    EngineTrail::new(ship.color.clone(),  WINGTIP_TRAIL_WIDTH, WINGTIP_TRAIL_BRIGHTNESS),
-                )
-            );
-        }
+        let line_sprite = match LineSprite::new(&gl) {
+            Ok(g) => g,
+            Err(err) => {
+                log(&format!("line error {:?}", err));
+                panic!("line error");
+            }
+        };
+
+        let hud_sprite = match HudSprite::new(&gl) {
+            Ok(g) => g,
+            Err(err) => {
+                log(&format!("hud error {:?}", err));
+                panic!("hud error");
+            }
+        };
 
-        let map = Map {
-            sin_consts: [2.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0],
-            cos_consts: [0.0, -2.0, 0.0, 1.0, 0.0, 0.0, 0.5, 0.0],
-            track_base_radius: 8.0,
-            track_width: 0.7,
+        let minimap_sprite = match MinimapSprite::new(&gl) {
+            Ok(g) => g,
+            Err(err) => {
+                log(&format!("minimap error {:?}", err));
+                panic!("minimap error");
+            }
         };
 
-        let camera = Camera::new();
+        let map = Map::new();
+        let racing_line = RacingLine::new(&map);
+        // Ships are spawned when the race starts
+        let race = Race::new(&[], &map, options.laps);
+
+        let mut camera = Camera::new();
+        camera.set_mode(options.camera);
 
         let now = window().unwrap().performance().unwrap().now();
         let prev_time = now / 1000.0;
+        let seed = options.seed.unwrap_or_else(random_seed);
+
+        let mut profile = load_profile();
+        if let Some(name) = &options.name {
+            profile.set_name(name);
+        }
+        if let Some(color) = &options.color {
+            profile.set_color(color);
+        }
+        // Saved straight away so that anything brought over from an older
+        // version is only brought over once
+        save_profile(&profile);
 
         let mut game = Self {
             canvas,
@@ -127,69 +350,811 @@ impl App {
             ship_sprite,
             map_sprite,
             engine_trail_sprite,
+            line_sprite,
+            hud_sprite,
+            minimap_sprite,
             map,
+            racing_line,
+            options,
+            race,
+            catch_up: vec![],
+            logged_standings: vec![],
             key_map: KeyMap::new(),
             canvas_resolution: (0, 0),
-            ship_entities,
-            engine_trails,
-            camera,
+            pixel_ratio: 1.0,
+            entities: Entities::new(),
+            ship_entities: Components::new(),
+            race_slots: Components::new(),
+            ship_drivers: Components::new(),
+            engine_trails: Components::new(),
+            cameras: vec![camera],
+            viewports: vec![Viewport::full((0, 0))],
+            gamepads: vec![],
             prev_time,
+            clock: GameClock::new(),
+            unsimulated_time: 0.0,
+            countdown: COUNTDOWN_TIME,
+            seed,
+            recorder: Recorder::new(0, 0, vec![]),
+            last_replay: None,
+            viewer: None,
+            ghost: None,
+            ghost_recorder: GhostRecorder::new(seed),
+            ghost_lap_start: None,
+            laps_completed: 0,
+            championship: None,
+            intermission: false,
+            results: vec![],
+            elimination: None,
+            director: None,
+            online: None,
+            profile,
+            tally: RaceTally::default(),
+            ship_colors: SHIP_COLORS.to_vec(),
         };
-        game.start_game();
+
+        if let Some(server) = game.options.server.clone() {
+            game.connect(&server);
+        } else if game.options.mode == GameMode::Championship {
+            // Carry on from where the last visit got to
+            let resumed = match load_championship().filter(|saved| !saved.is_finished()) {
+                Some(saved) => match game.resume_championship(saved) {
+                    Ok(()) => true,
+                    Err(err) => {
+                        log(&err);
+                        false
+                    }
+                },
+                None => false,
+            };
+            if !resumed {
+                game.start_championship();
+            }
+        } else {
+            game.start_game();
+        }
         game
     }
 
     fn start_game(&mut self) {
-        self.camera.reset();
-        self.map.randomize();
+        for camera in self.cameras.iter_mut() {
+            camera.reset();
+        }
+        if let Some(track) = self
+            .championship
+            .as_ref()
+            .and_then(Championship::current_track)
+        {
+            if let Track::Seed(seed) = track {
+                self.seed = *seed;
+            }
+            self.map = track.map();
+        } else {
+            // Time trials stay on one track so there is a ghost to chase
+            if self.options.mode == GameMode::Race && self.options.seed.is_none() {
+                self.seed = random_seed();
+            }
+            self.map.randomize(self.seed);
+        }
+        if let Err(err) = self.map.validate() {
+            log(&format!("map error {:?}", err));
+        }
+        self.racing_line = RacingLine::new(&self.map);
         self.map_sprite.set_to_map(&self.gl, &self.map);
+        self.minimap_sprite.set_to_map(&self.gl, &self.map);
+        self.minimap_sprite.view_radius = Minimap::view_radius(&self.map);
+        let track_radius = self.map.bounding_radius();
+        for camera in self.cameras.iter_mut() {
+            camera.track_radius = track_radius;
+        }
 
-        {
-            const SHIP_SPACING: f32 = 0.12;
-            let start_position = self.map.get_start_position();
-            let startline_angle = self.map.get_track_direction(start_position.angle);
+        // Every race starts with a fresh set of ships
+        for id in self.ship_entities.ids().to_vec() {
+            self.despawn_ship(id);
+        }
+        let players = self.options.players();
+        let drivers: Vec<ShipDriver> = self
+            .options
+            .drivers
+            .iter()
+            .enumerate()
+            .map(|(ship_id, option)| {
+                let player = players.iter().position(|id| *id == ship_id);
+                create_driver(option, player.unwrap_or(0))
+            })
+            .collect();
+        self.ship_colors = SHIP_COLORS.to_vec();
+        if let (Some(player), Some(color)) = (players.first(), self.profile.color) {
+            // Whoever would have been painted that color gets the player's
+            self.ship_colors.swap(*player, color);
+        }
+        let ships = drivers.into_iter().zip(self.ship_colors.clone());
+        for (slot, (driver, (_, color))) in ships.enumerate() {
+            let ship = Ship::new(color, Transform2d::new(0.0, 0.0, 0.0, 0.1));
+            self.spawn_ship(slot, ship, driver);
+        }
+        place_on_grid(self.ship_entities.values_mut(), &self.map);
 
-            let startline_tangent = (f32::cos(startline_angle), f32::sin(startline_angle));
-            let startline_normal = (-f32::sin(startline_angle), f32::cos(startline_angle));
+        self.race = Race::new(self.ship_entities.values(), &self.map, self.options.laps);
+        self.elimination = match self.options.mode {
+            GameMode::Elimination => Some(Elimination::new(self.ship_entities.len())),
+            _ => None,
+        };
+        self.director = match self.options.director {
+            true => Some(Director::new(self.ship_entities.len())),
+            false => None,
+        };
+        self.catch_up = vec![CatchUp::NONE; self.ship_entities.len()];
+        self.logged_standings = vec![];
+        self.reset_trails();
+
+        let drivers = driver_names(&self.options.drivers);
+        self.recorder = Recorder::new(self.seed, self.options.laps, drivers);
 
-            let num_ships = self.ship_entities.len();
+        if self.options.mode == GameMode::TimeTrial {
+            self.ghost = load_ghost(self.seed);
+            if let Some(ghost) = &self.ghost {
+                log(&format!("Chasing a ghost lap of {:.2}s", ghost.lap_time));
+            }
+        }
+        self.ghost_recorder = GhostRecorder::new(self.seed);
+        self.ghost_lap_start = None;
+        self.laps_completed = 0;
+        self.tally = RaceTally::default();
+        self.countdown = COUNTDOWN_TIME;
+    }
 
-            for (id, ship) in self.ship_entities.iter_mut().enumerate() {
-                let offset = (id as f32) - ((num_ships - 1) as f32) * 0.5;
+    /// Connects to a relay server, then waits with the lobby up until a
+    /// race starts there
+    fn connect(&mut self, server: &str) {
+        match Connection::open(server) {
+            Ok(connection) => {
+                log(&format!("Connecting to {}", server));
+                self.online = Some(Online::new(connection));
+            }
+            Err(err) => log(&format!("Couldn't connect to {} {:?}", server, err)),
+        }
+        self.start_game();
+        self.intermission = self.online.is_some();
+    }
 
-                let offset_vec = (
-                    (startline_tangent.0 * offset - startline_normal.0) * SHIP_SPACING,
-                    (startline_tangent.1 * offset - startline_normal.1) * SHIP_SPACING,
-                );
+    /// Joins the lobby once connected, and handles whatever the server
+    /// has sent since the last frame
+    fn update_online(&mut self) {
+        let online = match &mut self.online {
+            Some(online) => online,
+            None => return,
+        };
+        if !online.joined && online.connection.is_open() {
+            online.joined = true;
+            online.send(&ClientMessage::Join {
+                lobby: self.options.lobby.clone(),
+                name: self.profile.name.clone(),
+            });
+        }
+        if online.connection.is_closed() && online.ship.take().is_some() {
+            log("Lost the connection to the server");
+            self.intermission = true;
+        }
+
+        for message in online.connection.receive() {
+            match message {
+                Ok(ServerMessage::Welcome { player }) => log(&format!(
+                    "Joined lobby {} as player {}",
+                    self.options.lobby,
+                    player + 1
+                )),
+                Ok(ServerMessage::Lobby { players }) => {
+                    if let Some(online) = &mut self.online {
+                        online.lobby = players;
+                    }
+                }
+                Ok(ServerMessage::Start {
+                    seed,
+                    laps,
+                    drivers,
+                    ship,
+                    countdown,
+                }) => self.start_online_race(seed, laps, drivers, ship as usize, countdown),
+                Ok(ServerMessage::Snapshot { tick, ships }) => self.apply_snapshot(tick, &ships),
+                Ok(ServerMessage::Input {
+                    ship,
+                    tick,
+                    controls,
+                }) => {
+                    if let Some(rollback) = self.online.as_mut().and_then(|o| o.rollback.as_mut()) {
+                        let first = (tick + 1).saturating_sub(controls.len() as u32);
+                        for (input_tick, control) in (first..=tick).zip(controls) {
+                            rollback.add_input(ship as usize, input_tick, control);
+                        }
+                    }
+                }
+                Ok(ServerMessage::Results { standings }) => self.finish_online_race(&standings),
+                Ok(ServerMessage::Error { reason }) => log(&format!("Server error: {}", reason)),
+                Err(err) => log(&format!("Server message error {:?}", err)),
+            }
+        }
+        self.sync_online_ships();
+    }
 
-                let ship_start_position = start_position.to_cartesian();
-                ship.position.x = ship_start_position.0 + offset_vec.0;
-                ship.position.y = ship_start_position.1 + offset_vec.1;
-                ship.position.rot = startline_angle;
+    /// Sets up a race the server has started. This player flies their own
+    /// ship and everyone else's is flown with what they send.
+    fn start_online_race(
+        &mut self,
+        seed: u64,
+        laps: u32,
+        drivers: Vec<String>,
+        ship: usize,
+        countdown: u32,
+    ) {
+        if drivers.len() > SHIP_COLORS.len() || ship >= drivers.len() {
+            log(&format!("Can't race {} ships online", drivers.len()));
+            return;
+        }
+        let ships = drivers.len();
+        self.options.drivers = drivers
+            .into_iter()
+            .enumerate()
+            .map(|(id, name)| match id == ship {
+                true => DriverOption::Player,
+                false => DriverOption::Remote(name),
+            })
+            .collect();
+        self.options.controls.truncate(1);
+        if self.options.controls.is_empty() {
+            self.options.controls.push(PlayerInput::default_for(0));
+        }
+        self.options.mode = GameMode::Race;
+        self.options.laps = laps;
+        self.options.seed = Some(seed);
+        self.options.director = false;
+        self.seed = seed;
+        self.championship = None;
+        self.stop_watching();
+        self.start_game();
+        self.countdown = countdown as f32 * TICK;
+        self.intermission = false;
 
-                ship.velocity.x = 0.0;
-                ship.velocity.y = 0.0;
-                ship.velocity.rot = 0.0;
+        if let Some(online) = &mut self.online {
+            online.ready = false;
+            online.ship = Some(ship);
+            online.rollback = Some(Rollback::new(self.map.clone(), ships, laps));
+            online.server_tick = 0;
+        }
+    }
+
+    /// Winds the race back to where the server says every ship was, and
+    /// takes the controls the AI were flying with
+    fn apply_snapshot(&mut self, tick: u32, states: &[ShipState]) {
+        let online = match &mut self.online {
+            Some(online) if online.ship.is_some() => online,
+            _ => return,
+        };
+        let rollback = match &mut online.rollback {
+            Some(rollback) => rollback,
+            None => return,
+        };
+        if tick > 0 {
+            for (id, state) in states.iter().enumerate() {
+                if Some(id) != online.ship {
+                    rollback.add_input(id, tick - 1, state.control);
+                }
             }
         }
+        rollback.correct(tick, states);
+        online.server_tick = tick;
+        // The race is under way on the server, even if the countdown here
+        // hasn't quite finished
+        self.countdown = f32::min(self.countdown, 0.0);
+    }
+
+    /// Shows the ships where the rollback has them, after running again
+    /// whatever has changed
+    fn sync_online_ships(&mut self) {
+        let rollback = match self.online.as_mut().and_then(|o| o.rollback.as_mut()) {
+            Some(rollback) => rollback,
+            None => return,
+        };
+        rollback.settle();
+        let ships = self.ship_entities.values_mut();
+        for (ship, rolled) in ships.iter_mut().zip(rollback.ships.iter()) {
+            // Colors are only known here
+            let color = ship.color;
+            *ship = rolled.clone();
+            ship.color = color;
+        }
+        self.race = rollback.race.clone();
+    }
+
+    /// Runs the online race on by a tick, or by a few to catch up with
+    /// the server, with the player flying their own ship
+    fn tick_online(&mut self, control: ShipControl) {
+        let online = match &mut self.online {
+            Some(online) => online,
+            None => return,
+        };
+        let (ship, rollback) = match (online.ship, &mut online.rollback) {
+            (Some(ship), Some(rollback)) => (ship, rollback),
+            _ => return,
+        };
+        let mut inputs = vec![];
+        for _ in 0..rollback.ticks_to_run(online.server_tick) {
+            let tick = rollback.tick();
+            rollback.add_input(ship, tick, control);
+            rollback.advance();
+            inputs.push(ClientMessage::Input {
+                tick,
+                controls: rollback.recent_controls(ship, tick, INPUT_REDUNDANCY),
+            });
+        }
+        for input in inputs.iter() {
+            online.send(input);
+        }
+        self.sync_online_ships();
+        self.update_tally();
+        self.update_catch_up();
+    }
+
+    /// Shows the finishing order the server sent, then waits in the
+    /// lobby for the next race
+    fn finish_online_race(&mut self, standings: &[u8]) {
+        let names: Vec<String> = standings
+            .iter()
+            .filter_map(|ship_id| self.options.drivers.get(*ship_id as usize))
+            .map(|driver| match driver {
+                DriverOption::Player => self.profile.name.clone(),
+                driver => driver.name().to_string(),
+            })
+            .collect();
+        log("Race results:");
+        for (position, name) in names.iter().enumerate() {
+            log(&format!("{}. {}", position + 1, name));
+        }
+        let standings: Vec<usize> = standings.iter().map(|ship_id| *ship_id as usize).collect();
+        self.record_race(&standings);
+        if let Some(online) = &mut self.online {
+            online.results = names;
+            online.ship = None;
+            online.rollback = None;
+        }
+        self.intermission = true;
+    }
+
+    /// Spawns a ship into a race slot along with whatever flies it
+    fn spawn_ship(&mut self, slot: usize, ship: Ship, driver: ShipDriver) -> EntityId {
+        let id = self.entities.spawn();
+        self.ship_entities.insert(id, ship);
+        self.race_slots.insert(id, slot);
+        self.ship_drivers.insert(id, driver);
+        id
+    }
+
+    /// Removes a ship and everything that belongs to it, apart from its
+    /// trails which fade out on their own
+    fn despawn_ship(&mut self, id: EntityId) {
+        self.ship_entities.remove(id);
+        self.race_slots.remove(id);
+        self.ship_drivers.remove(id);
+        self.entities.despawn(id);
+    }
+
+    /// The ship entity racing in a slot. Slots can't be used to index
+    /// `ship_entities`, as removing a ship moves those after it.
+    fn ship_entity(&self, slot: usize) -> Option<EntityId> {
+        self.race_slots
+            .iter()
+            .find(|(id, ship_slot)| **ship_slot == slot && self.ship_entities.contains(*id))
+            .map(|(id, _)| id)
+    }
+
+    /// The ship in a race slot, from the replay if one is being watched
+    fn shown_ship(&self, slot: usize) -> Option<&Ship> {
+        match &self.viewer {
+            Some(viewer) => viewer.playback.ships.get(slot),
+            None => self.ship_entities.get(self.ship_entity(slot)?),
+        }
     }
 
+    /// Stops watching any replay, dropping the stand-ins for its ships
+    fn stop_watching(&mut self) {
+        if let Some(viewer) = self.viewer.take() {
+            for id in viewer.ships {
+                self.race_slots.remove(id);
+                self.entities.despawn(id);
+            }
+        }
+    }
+
+    /// Replaces all the trails with fresh ones behind the ships being
+    /// shown
+    fn reset_trails(&mut self) {
+        for id in self.engine_trails.ids().to_vec() {
+            self.entities.despawn(id);
+        }
+        self.engine_trails = Components::new();
+
+        let shown = match &self.viewer {
+            Some(viewer) => viewer.ships.clone(),
+            None => self.ship_entities.ids().to_vec(),
+        };
+        for ship in shown {
+            let slot = match self.race_slots.get(ship) {
+                Some(slot) => *slot,
+                None => continue,
+            };
+            let trails = match self.shown_ship(slot) {
+                Some(shown_ship) => ShipTrails::new(ship, shown_ship),
+                None => continue,
+            };
+            let id = self.entities.spawn();
+            self.engine_trails.insert(id, trails);
+        }
+    }
+
+    /// Times the first ship's laps in time trials, and keeps the fastest
+    /// as a ghost
+    fn update_time_trial(&mut self) {
+        if self.options.mode != GameMode::TimeTrial {
+            return;
+        }
+        let ship = match self.ship_entity(0) {
+            Some(id) => self.ship_entities.get(id),
+            None => None,
+        };
+        let ship = match ship {
+            Some(ship) => ship,
+            None => return,
+        };
+        let timer = &self.race.timing[0];
+
+        if timer.laps_completed > self.laps_completed {
+            if let Some(lap) = &timer.last_lap {
+                let ghost = self.ghost_recorder.finish(lap.total, &ship.position);
+                let best = self
+                    .ghost
+                    .as_ref()
+                    .is_none_or(|ghost| lap.total < ghost.lap_time);
+                if best {
+                    log(&format!("New best lap {}", format_time(lap.total)));
+                    save_ghost(&ghost);
+                    self.ghost = Some(ghost);
+                }
+            }
+            self.laps_completed = timer.laps_completed;
+        }
+        // A new lap, or going backwards over the line which doesn't count
+        if timer.lap_start != self.ghost_lap_start {
+            self.ghost_lap_start = timer.lap_start;
+            self.ghost_recorder.restart();
+        }
+
+        if let Some(lap_start) = self.ghost_lap_start {
+            self.ghost_recorder
+                .sample(self.race.time - lap_start, &ship.position);
+        }
+    }
+
+    /// Starts a new championship on the tracks from the options, or on
+    /// random ones if none were given
+    fn start_championship(&mut self) {
+        let mut rounds: Vec<Track> = self
+            .options
+            .tracks
+            .iter()
+            .filter_map(create_track)
+            .collect();
+        if rounds.is_empty() {
+            rounds = (0..self.options.rounds)
+                .map(|_| Track::Seed(random_seed()))
+                .collect();
+        }
+        let championship = Championship::new(
+            rounds,
+            driver_names(&self.options.drivers),
+            self.options.laps,
+        );
+        save_championship(&championship);
+        self.championship = Some(championship);
+        self.intermission = false;
+        self.start_game();
+    }
+
+    /// Picks a championship back up, showing the standings until the
+    /// next round is started. It has to be between the same drivers.
+    fn resume_championship(&mut self, championship: Championship) -> Result<(), String> {
+        let drivers = driver_names(&self.options.drivers);
+        if championship.drivers != drivers {
+            return Err(format!(
+                "The championship is between {}, not {}",
+                championship.drivers.join(","),
+                drivers.join(",")
+            ));
+        }
+        log(&format!(
+            "Resuming championship at round {}/{}",
+            usize::min(championship.round() + 1, championship.rounds.len()),
+            championship.rounds.len()
+        ));
+        self.options.mode = GameMode::Championship;
+        self.options.laps = championship.laps;
+        self.championship = Some(championship);
+        self.stop_watching();
+        self.start_game();
+        self.intermission = true;
+        Ok(())
+    }
+
+    /// Scores the finished race in the championship and saves where it
+    /// has got to, then holds the results up until the next round
+    fn finish_round(&mut self) {
+        let championship = match &mut self.championship {
+            Some(championship) => championship,
+            None => return,
+        };
+        if let Some(Track::Saved(_)) = championship.current_track() {
+            // Replays only hold the seed of the track they were raced on
+            self.last_replay = None;
+        }
+        championship.record_result(self.race.standings());
+        save_championship(championship);
+        for line in championship_lines(championship) {
+            log(&line);
+        }
+        self.intermission = true;
+    }
+
+    /// Leaves the results and races the next round, or starts a new
+    /// championship once the last one is over
+    fn next_round(&mut self) {
+        self.intermission = false;
+        match &self.championship {
+            Some(championship) if !championship.is_finished() => self.start_game(),
+            _ => self.start_championship(),
+        }
+    }
+
+    /// Knocks out the ship in last place whenever the leader starts a new
+    /// lap, in elimination races
+    fn update_elimination(&mut self) {
+        let elimination = match &mut self.elimination {
+            Some(elimination) => elimination,
+            None => return,
+        };
+        if let Some(ship_id) = elimination.update(&self.race) {
+            log(&format!(
+                "{} ({}) is eliminated, {} left",
+                self.ship_colors[ship_id].0,
+                self.options.drivers[ship_id].name(),
+                elimination.ships_left()
+            ));
+        }
+    }
+
+    /// Keeps count of how the first player's ship is flying, for their
+    /// profile
+    fn update_tally(&mut self) {
+        let ship = match self.options.players().first() {
+            Some(ship_id) => self.ship_entity(*ship_id),
+            None => None,
+        };
+        if let Some(ship) = ship {
+            if let Some(ship) = self.ship_entities.get(ship) {
+                self.tally.update(ship, &self.map);
+            }
+        }
+    }
+
+    /// Adds the race just finished to the first player's profile and
+    /// saves it, logging any new records
+    fn record_race(&mut self, standings: &[usize]) {
+        let ship_id = match self.options.players().first() {
+            Some(ship_id) => *ship_id,
+            None => return,
+        };
+        let outcome = RaceOutcome {
+            track: self.current_track(),
+            laps: self.race.laps_to_win,
+            // Nobody wins a time trial on their own
+            won: self.ship_entities.len() > 1 && standings.first() == Some(&ship_id),
+            // Elimination races are about lasting, not going fast
+            race_time: self.race.progress[ship_id]
+                .finish_time
+                .filter(|_| self.elimination.is_none()),
+            best_lap: self.race.timing[ship_id]
+                .best_lap
+                .as_ref()
+                .map(|lap| lap.total),
+            tally: std::mem::take(&mut self.tally),
+        };
+        let records = self.profile.record_race(&outcome);
+        if records.best_lap {
+            log("New best lap on this track");
+        }
+        if let Some(place) = records.race_place {
+            log(&format!("Race time is number {} on this track", place + 1));
+        }
+        save_profile(&self.profile);
+    }
+
+    /// The track being raced, as the player's records know it
+    fn current_track(&self) -> Track {
+        self.championship
+            .as_ref()
+            .and_then(Championship::current_track)
+            .cloned()
+            .unwrap_or(Track::Seed(self.seed))
+    }
+
+    /// Finishing order of the race, which in elimination races goes by
+    /// who lasted longest
+    fn standings(&self) -> Vec<usize> {
+        match &self.elimination {
+            Some(elimination) => elimination.standings(&self.race),
+            None => self.race.standings(),
+        }
+    }
+
+    /// Which of the ships being shown are still in the race
+    fn racing_ships(&self) -> Vec<bool> {
+        match (&self.viewer, &self.elimination) {
+            (Some(viewer), _) => vec![true; viewer.playback.ships.len()],
+            (None, Some(elimination)) => elimination.racing().to_vec(),
+            (None, None) => vec![true; self.ship_entities.len()],
+        }
+    }
+
+    /// The ship the camera and HUD follow: whichever the director picked,
+    /// or else the player's, or the first one with nobody playing, or
+    /// whoever is leading once it has been knocked out
+    fn followed_ship(&self) -> usize {
+        if let Some(director) = &self.director {
+            return director.target();
+        }
+        let ship_id = self.options.players().first().copied().unwrap_or(0);
+        match &self.elimination {
+            Some(elimination) if !elimination.is_racing(ship_id) => {
+                elimination.standings(&self.race)[0]
+            }
+            _ => ship_id,
+        }
+    }
+
+    /// Which ship each view on the screen follows. Players racing each
+    /// other get a view each, following their own ship until it is
+    /// knocked out. Otherwise there is one view of the followed ship.
+    fn view_ships(&self) -> Vec<usize> {
+        if let Some(viewer) = &self.viewer {
+            return vec![viewer.camera_ship];
+        }
+        let players = self.options.players();
+        if players.len() < 2 {
+            return vec![self.followed_ship()];
+        }
+        players
+            .iter()
+            .map(|ship_id| match &self.elimination {
+                Some(elimination) if !elimination.is_racing(*ship_id) => {
+                    elimination.standings(&self.race)[0]
+                }
+                _ => *ship_id,
+            })
+            .collect()
+    }
+
+    /// Splits the canvas between the views, with a camera for each.
+    /// Cameras added for new views jump straight to their ship.
+    fn layout_views(&mut self, views: usize) {
+        if self.cameras.len() != views {
+            let mode = self.cameras[0].mode();
+            let track_radius = self.cameras[0].track_radius;
+            self.cameras = (0..views)
+                .map(|_| {
+                    let mut camera = Camera::new();
+                    camera.set_mode(mode);
+                    camera.track_radius = track_radius;
+                    camera.cut();
+                    camera
+                })
+                .collect();
+        }
+        self.viewports = split(self.canvas_resolution, views, self.pixel_ratio);
+        for (camera, viewport) in self.cameras.iter_mut().zip(self.viewports.iter()) {
+            camera.aspect_ratio = viewport.aspect_ratio();
+        }
+    }
+
+    /// The view under a point on the canvas, in CSS pixels from the top
+    /// left corner
+    fn view_at(&self, x: i32, y: i32) -> Option<usize> {
+        let point = (x as f32 * self.pixel_ratio, y as f32 * self.pixel_ratio);
+        self.viewports
+            .iter()
+            .position(|viewport| viewport.contains(point))
+    }
+
+    /// Hands out help to the trailing AI and handicaps to the leaders
+    /// based on the standings. In debug mode the adjustments are logged
+    /// whenever the order changes.
+    fn update_catch_up(&mut self) {
+        self.catch_up = match &self.elimination {
+            Some(elimination) => {
+                calc_racing_catch_up(&self.race, elimination.racing(), self.options.catch_up)
+            }
+            None => calc_catch_up(&self.race, self.options.catch_up),
+        };
+        let catch_up = &self.catch_up;
+
+        if !self.options.debug {
+            return;
+        }
+        let standings = self.race.standings();
+        if standings == self.logged_standings {
+            return;
+        }
+        log(&format!("Catch-up ({:?}):", self.options.catch_up));
+        for (position, ship_id) in standings.iter().enumerate() {
+            let driver = self
+                .ship_entity(*ship_id)
+                .and_then(|id| self.ship_drivers.get(id));
+            let adjustment = match driver {
+                Some(ShipDriver::Controller { .. }) if catch_up[*ship_id].is_active() => format!(
+                    "thrust x{:.2} lookahead x{:.2}",
+                    catch_up[*ship_id].thrust_scale, catch_up[*ship_id].lookahead_scale
+                ),
+                _ => "none".to_string(),
+            };
+            log(&format!(
+                "{}. {} ({}) {}",
+                position + 1,
+                self.ship_colors[*ship_id].0,
+                self.options.drivers[*ship_id].name(),
+                adjustment
+            ));
+        }
+        self.logged_standings = standings;
+    }
+
+    /// Keeps the finishing order along with who was driving each ship, to
+    /// show before the next race, and logs it
+    fn show_results(&mut self) {
+        let players = self.options.players();
+        self.results = self
+            .standings()
+            .iter()
+            .map(|ship_id| RaceResult {
+                ship: self.ship_colors[*ship_id].0,
+                driver: match players.iter().position(|player| player == ship_id) {
+                    Some(0) => self.profile.name.clone(),
+                    Some(player) => format!("Player {}", player + 1),
+                    None => self.options.drivers[*ship_id].name().to_string(),
+                },
+                finish_time: self.race.progress[*ship_id].finish_time,
+            })
+            .collect();
+        for line in results_lines(&self.results) {
+            log(&line);
+        }
+    }
+
+    /// Matches the canvas resolution to its size on the page in device
+    /// pixels, so it stays sharp on high density screens
     fn check_resize(&mut self) {
-        let client_width = self.canvas.client_width();
-        let client_height = self.canvas.client_height();
+        let pixel_ratio = window().map_or(1.0, |window| window.device_pixel_ratio());
+        let display_width = (self.canvas.client_width() as f64 * pixel_ratio).round() as i32;
+        let display_height = (self.canvas.client_height() as f64 * pixel_ratio).round() as i32;
+        self.pixel_ratio = pixel_ratio as f32;
         let canvas_width = self.canvas.width() as i32;
         let canvas_height = self.canvas.height() as i32;
 
-        if client_width != canvas_width || client_height != canvas_height {
-            self.gl.viewport(0, 0, client_width, client_height);
-            let client_width = client_width as u32;
-            let client_height = client_height as u32;
+        if display_width != canvas_width || display_height != canvas_height {
+            let display_width = display_width as u32;
+            let display_height = display_height as u32;
 
-            self.canvas.set_width(client_width);
-            self.canvas.set_height(client_height);
-            self.canvas_resolution = (client_width, client_height);
+            self.canvas.set_width(display_width);
+            self.canvas.set_height(display_height);
+            self.canvas_resolution = (display_width, display_height);
 
-            log(&format!("Resized to {}:{}", client_width, client_height));
+            log(&format!("Resized to {}:{}", display_width, display_height));
         }
     }
 
@@ -197,140 +1162,822 @@ impl App {
         let now = window().unwrap().performance().unwrap().now();
         let time = now / 1000.0;
 
-        let dt = time - self.prev_time;
+        let real_dt = time - self.prev_time;
         self.prev_time = time;
 
+        self.handle_clock_keys();
+        self.handle_camera_keys();
+        self.read_gamepads();
+        let dt = self.clock.advance(real_dt as f32) as f64;
+
         {
-            // Logic uncomment it to play with computer
-            /*   let player_ship = &mut self.ship_entities[0];
-            player_ship.linear_thrust = 0.0;
-            player_ship.angular_thrust = 0.0;
-            if self.key_map.forwards.active() {
-                player_ship.linear_thrust = 1.0;
+            // Logic
+            self.handle_replay_keys();
+            self.update_online();
+            if self.intermission && self.key_map.next_round.just_pressed() {
+                match &mut self.online {
+                    Some(online) => online.ready_up(),
+                    None => self.next_round(),
+                }
             }
-            if self.key_map.backwards.active() {
-                player_ship.linear_thrust = -1.0;
+            self.key_map.update();
+
+            if self.viewer.is_some() {
+                self.update_viewer(dt as f32);
+            } else if self.intermission {
+                // The race is held where it finished while the results
+                // are up
+                self.unsimulated_time = 0.0;
+            } else if self.countdown > 0.0 {
+                // Ships wait on the grid until the countdown is over
+                self.countdown -= dt as f32;
+                self.unsimulated_time = 0.0;
+            } else {
+                self.countdown -= dt as f32;
+                // The race runs in fixed ticks so that it can be replayed
+                self.unsimulated_time = f64::min(
+                    self.unsimulated_time + dt,
+                    (MAX_TICKS_PER_FRAME as f32 * TICK) as f64,
+                );
+                // Stops early if the race finishes and the next one is
+                // counting down
+                while self.unsimulated_time >= TICK as f64
+                    && !self.intermission
+                    && self.countdown <= 0.0
+                {
+                    self.unsimulated_time -= TICK as f64;
+                    self.tick();
+                }
             }
+        }
 
-            if self.key_map.turn_left.active() {
-                player_ship.angular_thrust += 1.0;
+        self.update_director(dt as f32);
+
+        let racing = self.racing_ships();
+        let view_ships = self.view_ships();
+        self.check_resize();
+        self.layout_views(view_ships.len());
+
+        // Cameras
+        let targets: Vec<Option<Ship>> = view_ships
+            .iter()
+            .map(|ship_id| self.shown_ship(*ship_id).cloned())
+            .collect();
+        for (camera, ship) in self.cameras.iter_mut().zip(targets.iter()) {
+            let ship = match ship {
+                Some(ship) => ship,
+                None => continue,
+            };
+            camera.target_posiion.0 = ship.position.x;
+            camera.target_posiion.1 = ship.position.y;
+            camera.target_velocity.0 = ship.velocity.x;
+            camera.target_velocity.1 = ship.velocity.y;
+            camera.target_rotation = ship.position.rot;
+            // The free camera can be moved around while the game is paused
+            let camera_dt = match camera.mode() {
+                CameraMode::Free => real_dt as f32,
+                _ => dt as f32,
+            };
+            camera.update(camera_dt);
+        }
+
+        // Trails are frozen while the game is paused
+        if dt > 0.0 {
+            // Trails. Those left behind by ships that are out of the race
+            // fade away and are then dropped.
+            let mut faded = vec![];
+            for (id, trails) in self.engine_trails.iter_mut() {
+                let ship = match (&self.viewer, self.race_slots.get(trails.ship)) {
+                    (_, Some(slot)) if !racing[*slot] => None,
+                    (Some(viewer), Some(slot)) => viewer.playback.ships.get(*slot),
+                    (None, Some(_)) => self.ship_entities.get(trails.ship),
+                    (_, None) => None,
+                };
+                match ship {
+                    Some(ship) => trails.update(dt as f32, ship),
+                    None => {
+                        trails.fade(dt as f32);
+                        if trails.is_faded() {
+                            faded.push(id);
+                        }
+                    }
+                }
             }
-            if self.key_map.turn_right.active() {
-                player_ship.angular_thrust -= 1.0;
+            for id in faded {
+                self.engine_trails.remove(id);
+                self.entities.despawn(id);
             }
+        }
 
-            if self.key_map.turn_right.active() || self.key_map.turn_left.active() {
-                if player_ship.linear_thrust < 0.0 {
-                    player_ship.linear_thrust = -0.5;
-                } else if 0.0 < player_ship.linear_thrust {
-                    player_ship.linear_thrust = 0.5;
-                }
-            }*/
+        {
+            // Rendering
+            self.gl.clear(
+                WebGl2RenderingContext::COLOR_BUFFER_BIT | WebGl2RenderingContext::DEPTH_BUFFER_BIT,
+            );
 
-            self.key_map.update();
+            // Each view only draws inside its own part of the canvas
+            self.gl.enable(WebGl2RenderingContext::SCISSOR_TEST);
+            for view in 0..self.viewports.len() {
+                self.render_view(view, &racing);
+            }
+            self.gl.disable(WebGl2RenderingContext::SCISSOR_TEST);
 
-            //let num_ships = self.ship_entities.len() - 2;
-            for (_, ship) in self.ship_entities[0..].iter_mut().enumerate() {
-                let skill = f32::max(random(), 0.33);
-                calc_ai_control(ship, skill, &self.map);
+            // The minimap and HUD are placed on the canvas as a whole
+            let (width, height) = self.canvas_resolution;
+            self.gl.viewport(0, 0, width as i32, height as i32);
+
+            // The minimap only shows the ships still in the race. With
+            // more than one view it isn't following anyone in particular.
+            if let Some(minimap) = &self.options.minimap {
+                let shown: Vec<(usize, &Ship)> =
+                    shown_ships(&self.viewer, &self.ship_entities, &self.race_slots)
+                        .into_iter()
+                        .filter(|(slot, _)| racing[*slot])
+                        .collect();
+                let (followed, view_rotation) = match view_ships[..] {
+                    [ship_id] => (
+                        shown.iter().position(|(id, _)| *id == ship_id),
+                        shown
+                            .iter()
+                            .find(|(id, _)| *id == ship_id)
+                            .map_or(0.0, |(_, ship)| minimap.view_rotation(ship)),
+                    ),
+                    _ => (None, 0.0),
+                };
+                let shown: Vec<&Ship> = shown.into_iter().map(|(_, ship)| ship).collect();
+
+                self.minimap_sprite.rect = minimap.rect(self.canvas_resolution, self.pixel_ratio);
+                self.minimap_sprite.canvas_resolution = self.canvas_resolution;
+                self.minimap_sprite.view_rotation = view_rotation;
+                self.minimap_sprite.render(&self.gl, &shown, followed);
             }
+
+            // The HUD goes over everything else
+            self.render_hud(&view_ships);
         }
+    }
+
+    /// Draws the world as one view's camera sees it, into that view's
+    /// part of the canvas
+    fn render_view(&mut self, view: usize, racing: &[bool]) {
+        let (x, y, width, height) = self.viewports[view].gl_rect(self.canvas_resolution.1);
+        self.gl.viewport(x, y, width, height);
+        self.gl.scissor(x, y, width, height);
+
+        let world_to_camera = self.cameras[view].get_camera_matrix();
+        let camera_to_clipspace = self.viewports[view].camera_to_clipspace();
 
+        self.ship_sprite.world_to_camera = world_to_camera;
+        self.ship_sprite.camera_to_clipspace = camera_to_clipspace;
+        self.ship_sprite.setup(&self.gl);
+        let map = match &self.viewer {
+            Some(viewer) => &viewer.playback.map,
+            None => &self.map,
+        };
+        for (slot, ship) in shown_ships(&self.viewer, &self.ship_entities, &self.race_slots) {
+            if racing[slot] {
+                self.ship_sprite.render(&self.gl, ship);
+            }
+        }
+        if let (None, Some(ghost), Some(lap_start)) =
+            (&self.viewer, &self.ghost, self.ghost_lap_start)
         {
-            // physics
-            calc_ship_physics(&mut self.ship_entities, &self.map, dt as f32);
+            if let Some(position) = ghost.position_at(self.race.time - lap_start) {
+                self.ship_sprite
+                    .render(&self.gl, &Ship::new(GHOST_COLOR, position));
+            }
         }
 
-        {
-            // camera
-            self.camera.target_posiion.0 = self.ship_entities[0].position.x;
-            self.camera.target_posiion.1 = self.ship_entities[0].position.y;
-            self.camera.target_velocity.0 = self.ship_entities[0].velocity.x;
-            self.camera.target_velocity.1 = self.ship_entities[0].velocity.y;
-            self.camera.update(dt as f32);
+        let map_sprite_transform = Transform2d::new(0.0, 0.0, 0.0, 1.0);
+
+        // Render the map
+        self.map_sprite.world_to_camera = world_to_camera;
+        self.map_sprite.camera_to_clipspace = camera_to_clipspace;
+        self.map_sprite.world_to_sprite = map_sprite_transform.to_mat3_array();
+        self.map_sprite.render(&self.gl);
+
+        // Render the trails
+        self.engine_trail_sprite.world_to_camera = world_to_camera;
+        self.engine_trail_sprite.camera_to_clipspace = camera_to_clipspace;
+        self.engine_trail_sprite.world_to_sprite = map_sprite_transform.to_mat3_array();
+        self.engine_trail_sprite.setup(&self.gl);
+        for trails in self.engine_trails.values() {
+            for engine_trail in trails.trails().iter() {
+                self.engine_trail_sprite.render(&self.gl, engine_trail);
+            }
         }
 
-        {
-            // Trails
-            for (ship, trail) in self.ship_entities.iter().zip(self.engine_trails.iter_mut()) {
-                trail.0.update(
-                    dt as f32,
-                    ship.get_engine_position(),
-                    f32::abs(ship.linear_thrust),
-                );
+        // Show what the AI and policies can feel of the walls
+        if self.options.debug {
+            self.line_sprite.world_to_camera = world_to_camera;
+            self.line_sprite.camera_to_clipspace = camera_to_clipspace;
+            self.line_sprite.setup(&self.gl);
+            for (slot, ship) in shown_ships(&self.viewer, &self.ship_entities, &self.race_slots) {
+                if !racing[slot] {
+                    continue;
+                }
+                let origin = (ship.position.x, ship.position.y);
+                let rays: Vec<_> = SENSORS
+                    .sense(ship, map)
+                    .iter()
+                    .map(|reading| {
+                        let end = (
+                            origin.0 + reading.direction.0 * reading.distance,
+                            origin.1 + reading.direction.1 * reading.distance,
+                        );
+                        (origin, end)
+                    })
+                    .collect();
+                self.line_sprite.render(&self.gl, &rays, ship.color);
+            }
+        }
+    }
 
-                let wingtip_positions = ship.get_wingtip_positions();
+    /// Runs one fixed step of the race, recording it as it goes
+    fn tick(&mut self) {
+        let mut controls = vec![ShipControl::default(); self.ship_entities.len()];
+        let racing = self.racing_ships();
+        let race_slots = &self.race_slots;
+        for (entity, driver) in self.ship_drivers.iter_mut() {
+            let id = match self.race_slots.get(entity) {
+                Some(slot) => *slot,
+                None => continue,
+            };
+            let control = match driver {
+                _ if !racing[id] => Default::default(),
+                ShipDriver::Player(player) => calc_player_control(
+                    self.options.controls[*player],
+                    &self.key_map,
+                    &self.gamepads,
+                ),
+                ShipDriver::Controller { failed: true, .. } | ShipDriver::Idle => {
+                    Default::default()
+                }
+                ShipDriver::Remote => Default::default(),
+                ShipDriver::Controller { controller, failed } => {
+                    let ship = match self.ship_entities.get(entity) {
+                        Some(ship) => ship,
+                        None => continue,
+                    };
+                    // Ships knocked out of the race can't be run into
+                    let others: Vec<NearbyShip> = self
+                        .ship_entities
+                        .iter()
+                        .filter(|(other, _)| *other != entity)
+                        .filter(|(other, _)| {
+                            race_slots.get(*other).is_some_and(|slot| racing[*slot])
+                        })
+                        .map(|(_, other)| NearbyShip::from_ship(other))
+                        .collect();
+                    let observation = Observation {
+                        ship,
+                        others: &others,
+                        map: &self.map,
+                        racing_line: &self.racing_line,
+                        catch_up: self.catch_up[id],
+                        dt: TICK,
+                    };
+                    match controller.control(&observation) {
+                        Ok(control) => control,
+                        Err(err) => {
+                            log(&format!("Ship {} controller error {:?}", id, err));
+                            *failed = true;
+                            Default::default()
+                        }
+                    }
+                }
+            };
+            // Fly with exactly what the replay will hold
+            controls[id] = quantize(control);
+        }
+        if let Some(ship) = self.online.as_ref().and_then(|online| online.ship) {
+            self.tick_online(controls[ship]);
+            return;
+        }
+        let ships = self.ship_entities.values_mut();
+        for (ship, control) in ships.iter_mut().zip(controls.iter()) {
+            control.apply(ship);
+        }
 
-                let raw_slip = ship.calc_slip() / 2.5;
-                let base_slip = f32::abs(raw_slip);
-                let left_slip = base_slip + raw_slip / 8.0;
-                let right_slip = base_slip - raw_slip / 8.0;
+        match &self.elimination {
+            Some(elimination) => calc_racing_ship_physics(
+                self.ship_entities.values_mut(),
+                elimination.racing(),
+                &self.map,
+                TICK,
+            ),
+            None => calc_ship_physics(self.ship_entities.values_mut(), &self.map, TICK),
+        }
+        self.recorder.record(&controls, self.ship_entities.values());
 
-                trail.1.update(
-                    dt as f32,
-                    wingtip_positions.0,
-                    f32::max(f32::min(left_slip, 1.0), 0.0),
-                );
-                trail.2.update(
-                    dt as f32,
-                    wingtip_positions.1,
-                    f32::max(f32::min(right_slip, 1.0), 0.0),
+        self.race
+            .update(self.ship_entities.values(), &self.map, TICK);
+        self.update_tally();
+        self.update_time_trial();
+        self.update_elimination();
+        let finished = match &self.elimination {
+            Some(elimination) => elimination.is_finished(),
+            None => self.race.is_finished(),
+        };
+        if finished {
+            self.show_results();
+            self.record_race(&self.standings());
+            // Playback doesn't know about ships being knocked out, so
+            // elimination races can't be replayed
+            if self.elimination.is_none() {
+                self.last_replay = Some(self.recorder.replay().clone());
+            }
+            if self.championship.is_some() {
+                self.finish_round();
+            } else {
+                self.start_game();
+            }
+        }
+        self.update_catch_up();
+    }
+
+    /// Starts watching a replay instead of racing
+    fn watch_replay(&mut self, replay: Replay) {
+        if replay.drivers.is_empty() || replay.drivers.len() > SHIP_COLORS.len() {
+            log(&format!(
+                "Can't show a replay with {} ships",
+                replay.drivers.len()
+            ));
+            return;
+        }
+        log(&format!(
+            "Watching replay of seed {}, {} laps, {:.2}s. P pauses, left and right skip, up and down change speed, C changes ship, Escape leaves",
+            replay.seed,
+            replay.laps,
+            replay.duration()
+        ));
+
+        let mut playback = Playback::new(replay);
+        for (ship, (_, color)) in playback.ships.iter_mut().zip(SHIP_COLORS.iter()) {
+            ship.color = *color;
+        }
+        self.map_sprite.set_to_map(&self.gl, &playback.map);
+        self.minimap_sprite.set_to_map(&self.gl, &playback.map);
+        self.minimap_sprite.view_radius = Minimap::view_radius(&playback.map);
+        let track_radius = playback.map.bounding_radius();
+        for camera in self.cameras.iter_mut() {
+            camera.track_radius = track_radius;
+            camera.reset();
+        }
+        self.stop_watching();
+        let mut ships = vec![];
+        for slot in 0..playback.ships.len() {
+            let id = self.entities.spawn();
+            self.race_slots.insert(id, slot);
+            ships.push(id);
+        }
+        self.viewer = Some(Viewer {
+            playback,
+            speed: 1.0,
+            camera_ship: 0,
+            unsimulated_time: 0.0,
+            ships,
+        });
+        self.reset_trails();
+    }
+
+    /// Moves the replay on at its playback speed
+    fn update_viewer(&mut self, dt: f32) {
+        let viewer = match &mut self.viewer {
+            Some(viewer) => viewer,
+            None => return,
+        };
+        if viewer.playback.is_finished() {
+            return;
+        }
+
+        let had_desync = viewer.playback.desync.is_some();
+        viewer.unsimulated_time = f32::min(
+            viewer.unsimulated_time + dt * viewer.speed,
+            MAX_TICKS_PER_FRAME as f32 * MAX_PLAYBACK_SPEED * TICK,
+        );
+        while viewer.unsimulated_time >= TICK {
+            viewer.unsimulated_time -= TICK;
+            viewer.playback.step();
+        }
+
+        if let (false, Some(tick)) = (had_desync, viewer.playback.desync) {
+            log(&format!(
+                "Replay desynced at {:.2}s, the ships no longer match the recording",
+                tick as f32 * TICK
+            ));
+        }
+        if viewer.playback.is_finished() {
+            log("Replay finished");
+        }
+    }
+
+    /// The browser only hands out snapshots of the gamepads, so they are
+    /// read once a frame, and only if somebody is using one
+    fn read_gamepads(&mut self) {
+        let using_gamepads = self
+            .options
+            .controls
+            .iter()
+            .any(|input| matches!(input, PlayerInput::Gamepad(_)));
+        if using_gamepads {
+            self.gamepads = read_gamepads();
+        }
+    }
+
+    /// Keys for pausing, stepping and slowing down the game. These act on
+    /// key presses, so have to be checked before the key map is updated.
+    fn handle_clock_keys(&mut self) {
+        if self.key_map.pause.just_pressed() {
+            self.clock.toggle_pause();
+        }
+        if self.key_map.frame_step.just_pressed() {
+            self.clock.step();
+        }
+        if self.key_map.slow_motion.just_pressed() {
+            self.clock.next_time_scale();
+        }
+    }
+
+    fn handle_camera_keys(&mut self) {
+        if self.key_map.camera_mode.just_pressed() {
+            // Every view changes together
+            let mode = self.cameras[0].mode().next();
+            for camera in self.cameras.iter_mut() {
+                camera.set_mode(mode);
+            }
+            log(&format!("Camera: {}", mode.name()));
+        }
+        // Replays have their own way of changing ship
+        if self.viewer.is_none() && self.key_map.next_camera.just_pressed() {
+            let racing = self.racing_ships();
+            let shot = match &mut self.director {
+                Some(director) => director.next_ship(self.ship_entities.values(), &racing),
+                None => None,
+            };
+            if let Some(shot) = shot {
+                self.take_shot(shot);
+            }
+        }
+    }
+
+    /// Lets the director look for something more interesting to watch
+    fn update_director(&mut self, dt: f32) {
+        if self.viewer.is_some() {
+            return;
+        }
+        let racing = self.racing_ships();
+        let shot = match &mut self.director {
+            Some(director) => director.update(
+                &self.race,
+                self.ship_entities.values(),
+                &racing,
+                &self.map,
+                dt,
+            ),
+            None => None,
+        };
+        if let Some(shot) = shot {
+            self.take_shot(shot);
+        }
+    }
+
+    fn take_shot(&mut self, shot: Shot) {
+        // The director only has a say when there is one view
+        if let (Transition::Cut, [camera]) = (shot.transition, &mut self.cameras[..]) {
+            camera.cut();
+        }
+        if self.options.debug {
+            log(&format!(
+                "Director: {:?} to {}",
+                shot.transition, self.ship_colors[shot.ship_id].0
+            ));
+        }
+    }
+
+    /// Keys for starting, leaving and moving around a replay. These act on
+    /// key presses, so have to be checked before the key map is updated.
+    fn handle_replay_keys(&mut self) {
+        let viewer = match &mut self.viewer {
+            Some(viewer) => viewer,
+            None => {
+                if self.key_map.watch_replay.just_pressed() {
+                    match self.last_replay.clone() {
+                        Some(replay) => self.watch_replay(replay),
+                        None => log("No race has finished yet"),
+                    }
+                }
+                return;
+            }
+        };
+
+        if self.key_map.faster.just_pressed() {
+            viewer.speed = f32::min(viewer.speed * 2.0, MAX_PLAYBACK_SPEED);
+        }
+        if self.key_map.slower.just_pressed() {
+            viewer.speed = f32::max(viewer.speed / 2.0, MIN_PLAYBACK_SPEED);
+        }
+        if self.key_map.next_camera.just_pressed() {
+            viewer.camera_ship = (viewer.camera_ship + 1) % viewer.playback.ships.len();
+        }
+
+        let scrub_ticks = (SCRUB_STEP / TICK) as u32;
+        let tick = viewer.playback.tick();
+        let target = if self.key_map.scrub_back.just_pressed() {
+            Some(tick.saturating_sub(scrub_ticks))
+        } else if self.key_map.scrub_forward.just_pressed() {
+            Some(tick + scrub_ticks)
+        } else {
+            None
+        };
+        if let Some(target) = target {
+            viewer.playback.seek(target);
+            log(&format!(
+                "Replay at {:.2}s of {:.2}s",
+                viewer.playback.time(),
+                viewer.playback.replay.duration()
+            ));
+            // Trails would otherwise streak across the map
+            self.reset_trails();
+        }
+
+        if self.key_map.leave_replay.just_pressed() {
+            self.stop_watching();
+            self.start_game();
+        }
+    }
+
+    /// Saves the last finished race as a file through the browser
+    pub fn download_replay(&self) {
+        let replay = match &self.last_replay {
+            Some(replay) => replay,
+            None => {
+                log("No race has finished yet");
+                return;
+            }
+        };
+        let filename = format!("swoop-{}.replay", replay.seed);
+        if let Err(err) = download_bytes(&replay.to_bytes(), &filename) {
+            log(&format!("Replay download error {:?}", err));
+        }
+    }
+
+    pub fn load_replay(&mut self, bytes: &[u8]) {
+        match Replay::from_bytes(bytes) {
+            Ok(replay) => self.watch_replay(replay),
+            Err(err) => log(&format!("Replay error {:?}", err)),
+        }
+    }
+
+    /// Lines of text about the race for the ship a view follows: its
+    /// timing, and in the first view anything affecting the game as a
+    /// whole. Between championship rounds it is the results and standings
+    /// instead.
+    fn hud_lines(&self, view: usize, ship_id: usize) -> Vec<String> {
+        let race = match &self.viewer {
+            Some(viewer) => &viewer.playback.race,
+            None => &self.race,
+        };
+        let mut lines = vec![];
+        if view == 0 {
+            if self.clock.is_paused() {
+                lines.push("Paused".to_string());
+            } else if self.clock.time_scale != 1.0 {
+                lines.push(format!("Slow motion x{}", self.clock.time_scale));
+            }
+            if self.cameras[0].mode() != CameraMode::Follow {
+                lines.push(format!("Camera: {}", self.cameras[0].mode().name()));
+            }
+            if let (None, Some(director)) = (&self.viewer, &self.director) {
+                let ship_id = director.target();
+                lines.push(format!(
+                    "Watching {} ({})",
+                    self.ship_colors[ship_id].0,
+                    self.options.drivers[ship_id].name()
+                ));
+            }
+        }
+        if let (None, Some(elimination)) = (&self.viewer, &self.elimination) {
+            // On a split screen each view belongs to a player's ship
+            let own_ship = match self.cameras.len() {
+                1 => 0,
+                _ => self.options.players()[view],
+            };
+            lines.push(match elimination.is_racing(own_ship) {
+                true => format!("{} ships left", elimination.ships_left()),
+                false => format!("Eliminated, {} ships left", elimination.ships_left()),
+            });
+        }
+        if let (Some(online), true, None) = (&self.online, self.intermission, &self.viewer) {
+            lines = lobby_lines(&self.options.lobby, &online.lobby, &online.results);
+            lines.push(String::new());
+            lines.push(online.status().to_string());
+            return lines;
+        }
+        match (&self.championship, self.intermission, &self.viewer) {
+            (Some(championship), true, None) => {
+                lines = championship_lines(championship);
+                lines.push(String::new());
+                lines.push(match championship.is_finished() {
+                    true => "Press Enter for a new championship".to_string(),
+                    false => "Press Enter for the next round".to_string(),
+                });
+            }
+            (Some(championship), false, None) if view == 0 => {
+                lines.push(format!(
+                    "Round {}/{}",
+                    championship.round() + 1,
+                    championship.rounds.len()
+                ));
+                lines.extend(timing_lines(race, ship_id));
+            }
+            _ => {
+                // The last race's results and the player's records for the
+                // track are up until the start
+                let waiting = self.viewer.is_none() && self.countdown > 0.0;
+                if view == 0 && waiting && !self.results.is_empty() {
+                    lines.extend(results_lines(&self.results));
+                    lines.push(String::new());
+                }
+                if view == 0 && waiting && !self.options.players().is_empty() {
+                    let track = self.current_track();
+                    lines.extend(leaderboard_lines(&self.profile, &track, race.laps_to_win));
+                    lines.push(String::new());
+                }
+                lines.extend(timing_lines(race, ship_id));
+            }
+        }
+        lines
+    }
+
+    /// Draws the HUD in screen space. Each view gets timing in its top
+    /// left and position and speed in its top right, and the countdown
+    /// goes in the middle of the canvas.
+    fn render_hud(&mut self, view_ships: &[usize]) {
+        let mut batch = HudBatch::new(self.canvas_resolution, self.pixel_ratio);
+
+        if self.intermission && self.viewer.is_none() {
+            let lines: Vec<(String, f32)> = self
+                .hud_lines(0, view_ships[0])
+                .into_iter()
+                .map(|line| (line, 1.0))
+                .collect();
+            batch.block(Anchor::Center, 0.0, &lines, HUD_COLOR);
+        } else {
+            let (ships, standings) = match &self.viewer {
+                Some(viewer) => (&viewer.playback.ships[..], viewer.playback.race.standings()),
+                None => (self.ship_entities.values(), self.standings()),
+            };
+            for (view, (ship_id, viewport)) in
+                view_ships.iter().zip(self.viewports.iter()).enumerate()
+            {
+                batch.area = *viewport;
+                let lines: Vec<(String, f32)> = self
+                    .hud_lines(view, *ship_id)
+                    .into_iter()
+                    .map(|line| (line, 1.0))
+                    .collect();
+                batch.block(Anchor::TopLeft, 0.0, &lines, HUD_COLOR);
+
+                let ship = match self.shown_ship(*ship_id) {
+                    Some(ship) => ship,
+                    None => continue,
+                };
+                let position = standings.iter().position(|id| id == ship_id).unwrap_or(0);
+                let speed = f32::hypot(ship.velocity.x, ship.velocity.y);
+                batch.block(
+                    Anchor::TopRight,
+                    0.0,
+                    &[
+                        (position_text(position, ships.len()), 3.0),
+                        (speed_text(speed), 2.0),
+                    ],
+                    ship.color,
                 );
             }
+
+            batch.area = Viewport::full(self.canvas_resolution);
+            if self.viewer.is_none() {
+                if let Some(countdown) = countdown_text(self.countdown) {
+                    batch.block(Anchor::Center, 0.0, &[(countdown, 6.0)], HUD_COLOR);
+                }
+            }
         }
+        self.hud_sprite.render(&self.gl, &batch);
+    }
 
-        {
-            // Rendering
-            self.check_resize();
-            self.gl.clear(
-                WebGl2RenderingContext::COLOR_BUFFER_BIT | WebGl2RenderingContext::DEPTH_BUFFER_BIT,
-            );
+    /// Saves the ghost for this track as a file through the browser
+    pub fn export_ghost(&self) {
+        let ghost = match &self.ghost {
+            Some(ghost) => ghost,
+            None => {
+                log("No ghost lap on this track yet");
+                return;
+            }
+        };
+        let filename = format!("swoop-ghost-{}.ghost", ghost.seed);
+        if let Err(err) = download_bytes(ghost.to_string().as_bytes(), &filename) {
+            log(&format!("Ghost download error {:?}", err));
+        }
+    }
+
+    /// Stores a ghost from a file. In time trials its track is loaded so
+    /// it can be raced straight away.
+    pub fn import_ghost(&mut self, text: &str) {
+        let ghost = match text.parse::<Ghost>() {
+            Ok(ghost) => ghost,
+            Err(err) => {
+                log(&format!("Ghost error {:?}", err));
+                return;
+            }
+        };
+        save_ghost(&ghost);
+        if self.options.mode == GameMode::TimeTrial {
+            self.seed = ghost.seed;
+            self.stop_watching();
+            self.start_game();
+        } else {
+            log(&format!(
+                "Saved a ghost for seed {}, race it with mode=timetrial;seed={}",
+                ghost.seed, ghost.seed
+            ));
+        }
+    }
+
+    /// Saves the championship so far as a file through the browser
+    pub fn export_championship(&self) {
+        let championship = match &self.championship {
+            Some(championship) => championship,
+            None => {
+                log("No championship is being run");
+                return;
+            }
+        };
+        let text = championship.to_string();
+        if let Err(err) = download_bytes(text.as_bytes(), "swoop.championship") {
+            log(&format!("Championship download error {:?}", err));
+        }
+    }
 
-            let world_to_camera = self
-                .camera
-                .get_camera_matrix();
-            let camera_to_clipspace = [
-                1.0,
-                0.0,
-                0.0,
-                0.0,
-                (self.canvas_resolution.1 as f32 / self.canvas_resolution.0 as f32),
-                0.0,
-                0.0,
-                0.0,
-                1.0,
-            ];
-
-            self.ship_sprite.world_to_camera = world_to_camera;
-            self.ship_sprite.camera_to_clipspace = camera_to_clipspace;
-            self.ship_sprite.setup(&self.gl);
-            for ship in &self.ship_entities {
-                self.ship_sprite.render(&self.gl, &ship);
-            }
-
-            let map_sprite_transform = Transform2d::new(0.0, 0.0, 0.0, 1.0);
-
-            // Render the map
-            self.map_sprite.world_to_camera = world_to_camera;
-            self.map_sprite.camera_to_clipspace = camera_to_clipspace;
-            self.map_sprite.world_to_sprite = map_sprite_transform.to_mat3_array();
-            self.map_sprite.render(&self.gl);
-
-            // Render the trails
-            self.engine_trail_sprite.world_to_camera = world_to_camera;
-            self.engine_trail_sprite.camera_to_clipspace = camera_to_clipspace;
-            self.engine_trail_sprite.world_to_sprite = map_sprite_transform.to_mat3_array();
-            self.engine_trail_sprite.setup(&self.gl);
-            for engine_trail in &self.engine_trails {
-                self.engine_trail_sprite.render(&self.gl, &engine_trail.0);
-                self.engine_trail_sprite.render(&self.gl, &engine_trail.1);
-                self.engine_trail_sprite.render(&self.gl, &engine_trail.2);
+    /// Carries on a championship from a file in place of whatever is
+    /// being raced
+    pub fn import_championship(&mut self, text: &str) {
+        let championship = match text.parse::<Championship>() {
+            Ok(championship) => championship,
+            Err(err) => {
+                log(&format!("Championship error {:?}", err));
+                return;
+            }
+        };
+        match self.resume_championship(championship) {
+            Ok(()) => {
+                if let Some(championship) = &self.championship {
+                    save_championship(championship);
+                }
             }
+            Err(err) => log(&err),
+        }
+    }
+
+    /// Pauses while the page is hidden, such as in a background tab, and
+    /// carries on when it is shown again
+    pub fn visibility_changed(&mut self, hidden: bool) {
+        self.clock.set_hidden(hidden);
+        if !hidden {
+            // No frames run while hidden, so don't count that time
+            self.prev_time = window().unwrap().performance().unwrap().now() / 1000.0;
         }
     }
 
+    /// Dragging with the left button held moves the free camera of the
+    /// view under the mouse
     pub fn mouse_event(&mut self, event: MouseEvent) {
-        log(&format!("Mouse Event {:?}", event))
+        let dragging = event.type_() == "mousemove" && event.buttons() & 1 != 0;
+        if !dragging {
+            return;
+        }
+        if let Some(view) = self.view_at(event.offset_x(), event.offset_y()) {
+            // In CSS pixels, like the mouse movement
+            let width = self.viewports[view].width as f32 / self.pixel_ratio;
+            let height = self.viewports[view].height as f32 / self.pixel_ratio;
+            if width > 0.0 && height > 0.0 {
+                self.cameras[view].pan((
+                    event.movement_x() as f32 / width,
+                    event.movement_y() as f32 / height,
+                ));
+            }
+        }
+    }
+
+    /// The mouse wheel zooms the free camera of the view under the mouse
+    pub fn wheel_event(&mut self, event: WheelEvent) {
+        let delta = event.delta_y();
+        if delta == 0.0 {
+            return;
+        }
+        if let Some(view) = self.view_at(event.offset_x(), event.offset_y()) {
+            self.cameras[view].wheel(delta.signum() as f32);
+        }
     }
 
     pub fn keydown_event(&mut self, event: KeyboardEvent) {
@@ -346,6 +1993,310 @@ impl App {
     }
 }
 
+/// Creates whatever is going to fly a ship. Scripts and policies are read
+/// from the element on the page with a matching id. Players are told
+/// which player they are.
+fn create_driver(option: &DriverOption, player: usize) -> ShipDriver {
+    let controller: Result<Box<dyn ShipController>, String> = match option {
+        DriverOption::Player => return ShipDriver::Player(player),
+        DriverOption::Remote(_) => return ShipDriver::Remote,
+        DriverOption::Ai(profile) => Ok(Box::new(AiDriver::new(*profile, random_seed()))),
+        DriverOption::Script(id) => read_element_text(id).and_then(|source| {
+            ScriptController::new(&source)
+                .map(|controller| Box::new(controller) as Box<dyn ShipController>)
+                .map_err(|err| format!("Script {} error {:?}", id, err))
+        }),
+        DriverOption::Policy(id) => {
+            let text = match id {
+                Some(id) => read_element_text(id),
+                None => Ok(BUILT_IN_POLICY.to_string()),
+            };
+            text.and_then(|text| {
+                text.parse::<Policy>()
+                    .map(|policy| {
+                        Box::new(PolicyController::new(policy)) as Box<dyn ShipController>
+                    })
+                    .map_err(|err| format!("Policy {} error {:?}", option.name(), err))
+            })
+        }
+    };
+    match controller {
+        Ok(controller) => ShipDriver::Controller {
+            controller,
+            failed: false,
+        },
+        Err(err) => {
+            log(&err);
+            ShipDriver::Idle
+        }
+    }
+}
+
+/// Finds a championship track. Saved tracks are read from the element on
+/// the page with a matching id.
+fn create_track(option: &TrackOption) -> Option<Track> {
+    let map = match option {
+        TrackOption::Seed(seed) => return Some(Track::Seed(*seed)),
+        TrackOption::Saved(id) => read_element_text(id).and_then(|text| {
+            text.parse::<Map>()
+                .map_err(|err| format!("Track {} error {:?}", id, err))
+        }),
+    };
+    match map {
+        Ok(map) => Some(Track::Saved(map)),
+        Err(err) => {
+            log(&err);
+            None
+        }
+    }
+}
+
+fn driver_names(drivers: &[DriverOption]) -> Vec<String> {
+    drivers
+        .iter()
+        .map(|driver| driver.name().to_string())
+        .collect()
+}
+
+fn read_element_text(id: &str) -> Result<String, String> {
+    window()
+        .and_then(|window| window.document())
+        .and_then(|document| document.get_element_by_id(id))
+        .and_then(|element| element.text_content())
+        .ok_or_else(|| format!("No element with id {}", id))
+}
+
+fn calc_player_control(
+    input: PlayerInput,
+    key_map: &KeyMap,
+    gamepads: &[Option<GamepadState>],
+) -> ShipControl {
+    match input {
+        PlayerInput::Keys(block) => calc_key_control(&key_map.players[block]),
+        // A gamepad that isn't plugged in leaves the ship coasting
+        PlayerInput::Gamepad(index) => gamepads
+            .get(index)
+            .and_then(Option::as_ref)
+            .map_or_else(ShipControl::default, GamepadState::control),
+    }
+}
+
+fn calc_key_control(keys: &PlayerKeys) -> ShipControl {
+    let mut control = ShipControl::default();
+    if keys.forwards.active() {
+        control.thrust = 1.0;
+    }
+    if keys.backwards.active() {
+        control.thrust = -1.0;
+    }
+
+    if keys.turn_left.active() {
+        control.steering += 1.0;
+    }
+    if keys.turn_right.active() {
+        control.steering -= 1.0;
+    }
+
+    if keys.turn_right.active() || keys.turn_left.active() {
+        if control.thrust < 0.0 {
+            control.thrust = -0.5;
+        } else if 0.0 < control.thrust {
+            control.thrust = 0.5;
+        }
+    }
+
+    control.boost = keys.boost.active();
+    control
+}
+
+/// Everything about the gamepads plugged in, by their index in the
+/// browser. Slots without a gamepad in them are None.
+fn read_gamepads() -> Vec<Option<GamepadState>> {
+    let gamepads = match window().map(|window| window.navigator().get_gamepads()) {
+        Some(Ok(gamepads)) => gamepads,
+        _ => return vec![],
+    };
+    gamepads
+        .iter()
+        .map(|gamepad| {
+            let gamepad: Gamepad = gamepad.dyn_into().ok()?;
+            if !gamepad.connected() {
+                return None;
+            }
+            Some(GamepadState {
+                buttons: gamepad
+                    .buttons()
+                    .iter()
+                    .map(|button| {
+                        button
+                            .dyn_into::<GamepadButton>()
+                            .map_or(0.0, |button| button.value() as f32)
+                    })
+                    .collect(),
+                axes: gamepad
+                    .axes()
+                    .iter()
+                    .map(|axis| axis.as_f64().unwrap_or(0.0) as f32)
+                    .collect(),
+            })
+        })
+        .collect()
+}
+
+/// A single draw from random() has at most 53 bits in it, so a seed is
+/// put together from the top 32 bits of two
+fn random_seed() -> u64 {
+    let random_u32 = || (random() * (1u64 << 32) as f64) as u32;
+    (random_u32() as u64) << 32 | random_u32() as u64
+}
+
+/// Every ship being shown along with its race slot, from the replay if
+/// one is being watched. Takes just the parts of the app it reads, so the
+/// sprites can still be drawn with while the ships are borrowed.
+fn shown_ships<'a>(
+    viewer: &'a Option<Viewer>,
+    ship_entities: &'a Components<Ship>,
+    race_slots: &'a Components<usize>,
+) -> Vec<(usize, &'a Ship)> {
+    match viewer {
+        Some(viewer) => viewer.playback.ships.iter().enumerate().collect(),
+        None => ship_entities
+            .iter()
+            .filter_map(|(id, ship)| Some((*race_slots.get(id)?, ship)))
+            .collect(),
+    }
+}
+
+fn local_storage() -> Option<Storage> {
+    window()?.local_storage().ok()?
+}
+
+fn load_ghost(seed: u64) -> Option<Ghost> {
+    let key = format!("{}{}", GHOST_STORAGE_PREFIX, seed);
+    let text = local_storage()?.get_item(&key).ok()??;
+    match text.parse::<Ghost>() {
+        Ok(ghost) => Some(ghost),
+        Err(err) => {
+            log(&format!("Stored ghost error {:?}", err));
+            None
+        }
+    }
+}
+
+fn save_ghost(ghost: &Ghost) {
+    let key = format!("{}{}", GHOST_STORAGE_PREFIX, ghost.seed);
+    match local_storage() {
+        Some(storage) => {
+            if let Err(err) = storage.set_item(&key, &ghost.to_string()) {
+                log(&format!("Couldn't save ghost {:?}", err));
+            }
+        }
+        None => log("No localStorage to save the ghost in"),
+    }
+}
+
+fn load_championship() -> Option<Championship> {
+    let text = local_storage()?.get_item(CHAMPIONSHIP_STORAGE_KEY).ok()??;
+    match text.parse::<Championship>() {
+        Ok(championship) => Some(championship),
+        Err(err) => {
+            log(&format!("Stored championship error {:?}", err));
+            None
+        }
+    }
+}
+
+fn save_championship(championship: &Championship) {
+    match local_storage() {
+        Some(storage) => {
+            let text = championship.to_string();
+            if let Err(err) = storage.set_item(CHAMPIONSHIP_STORAGE_KEY, &text) {
+                log(&format!("Couldn't save championship {:?}", err));
+            }
+        }
+        None => log("No localStorage to save the championship in"),
+    }
+}
+
+/// localStorage, for the profile to be kept in
+struct BrowserStore(Storage);
+
+impl KeyValueStore for BrowserStore {
+    fn get(&self, key: &str) -> Option<String> {
+        self.0.get_item(key).ok()?
+    }
+
+    fn set(&mut self, key: &str, value: &str) -> Result<(), StorageError> {
+        self.0
+            .set_item(key, value)
+            .map_err(|err| StorageError::Refused(format!("{:?}", err)))
+    }
+
+    fn keys(&self) -> Vec<String> {
+        let length = self.0.length().unwrap_or(0);
+        (0..length)
+            .filter_map(|index| self.0.key(index).ok().flatten())
+            .collect()
+    }
+}
+
+/// The player's profile, or a new one if there isn't one that can be read
+fn load_profile() -> Profile {
+    let mut store = match local_storage() {
+        Some(storage) => BrowserStore(storage),
+        None => {
+            log("No localStorage to keep the profile in");
+            return Profile::new();
+        }
+    };
+    match Profile::load(&store) {
+        Ok(profile) => profile,
+        Err(err) => {
+            log(&format!("Stored profile error {:?}", err));
+            // Put aside rather than saved over, in case a newer version of
+            // the game can still read it
+            if let Some(text) = store.get(PROFILE_KEY) {
+                let key = format!("{}-unreadable", PROFILE_KEY);
+                if let Err(err) = store.set(&key, &text) {
+                    log(&format!("Couldn't put the profile aside {:?}", err));
+                }
+            }
+            Profile::new()
+        }
+    }
+}
+
+fn save_profile(profile: &Profile) {
+    match local_storage() {
+        Some(storage) => {
+            if let Err(err) = profile.save(&mut BrowserStore(storage)) {
+                log(&format!("Couldn't save profile {:?}", err));
+            }
+        }
+        None => log("No localStorage to save the profile in"),
+    }
+}
+
+/// Hands some bytes to the browser as a file download
+fn download_bytes(bytes: &[u8], filename: &str) -> Result<(), JsValue> {
+    let parts = js_sys::Array::new();
+    parts.push(&js_sys::Uint8Array::from(bytes));
+    let properties = BlobPropertyBag::new();
+    properties.set_type("application/octet-stream");
+    let blob = Blob::new_with_u8_array_sequence_and_options(&parts, &properties)?;
+    let url = Url::create_object_url_with_blob(&blob)?;
+
+    let document = window()
+        .and_then(|window| window.document())
+        .ok_or_else(|| JsValue::from_str("No document"))?;
+    let link: HtmlAnchorElement = document.create_element("a")?.dyn_into()?;
+    link.set_href(&url);
+    link.set_download(filename);
+    link.click();
+
+    Url::revoke_object_url(&url)
+}
+
 fn get_gl_context(canvas: &HtmlCanvasElement) -> Result<WebGl2RenderingContext, JsValue> {
     Ok(canvas.get_context("webgl2")?.unwrap().dyn_into()?)
 }
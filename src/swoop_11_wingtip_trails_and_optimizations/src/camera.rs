@@ -1,3 +1,5 @@
+use std::f32::consts::PI;
+
 use super::transform::{length, Transform2d, Vec2};
 
 const PREDICT_FACTOR: f32 = 0.6;
@@ -5,12 +7,86 @@ const ZOOM_FACTOR: f32 = 0.825;
 const SMOOTHING: f32 = 0.4;
 /// What the zoom is set to after calling "reset"
 const RESET_ZOOM: f32 = 10.0;
+/// The free camera keeps up with the mouse more closely than the other
+/// cameras keep up with ships
+const FREE_SMOOTHING: f32 = 0.05;
+/// Room left around the track in the overview
+const OVERVIEW_MARGIN: f32 = 1.1;
+/// How far the free camera can zoom in and out
+const MIN_FREE_ZOOM: f32 = 0.5;
+const MAX_FREE_ZOOM: f32 = 50.0;
+/// How much each click of the mouse wheel zooms the free camera
+const WHEEL_ZOOM_STEP: f32 = 1.1;
+
+/// What the camera is looking at
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CameraMode {
+    /// Follows a ship from a little ahead of it, zooming out as it speeds
+    /// up
+    Follow,
+    /// Like follow, but turns so the ship is always pointing up the screen
+    Chase,
+    /// Shows the whole track at once
+    Overview,
+    /// Moved around by dragging with the mouse and zoomed with the wheel,
+    /// for debugging
+    Free,
+}
+
+impl CameraMode {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "follow" => Some(CameraMode::Follow),
+            "chase" => Some(CameraMode::Chase),
+            "overview" => Some(CameraMode::Overview),
+            "free" => Some(CameraMode::Free),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            CameraMode::Follow => "follow",
+            CameraMode::Chase => "chase",
+            CameraMode::Overview => "overview",
+            CameraMode::Free => "free",
+        }
+    }
+
+    /// The mode after this one, for cycling through them all
+    pub fn next(&self) -> Self {
+        match self {
+            CameraMode::Follow => CameraMode::Chase,
+            CameraMode::Chase => CameraMode::Overview,
+            CameraMode::Overview => CameraMode::Free,
+            CameraMode::Free => CameraMode::Follow,
+        }
+    }
+}
 
+/// The view of the world. Every mode works out where the camera ought to
+/// be, and the camera eases its way there, so changing mode is smooth.
 pub struct Camera {
     position: Vec2,
+    /// Half the width of the view, in world units
     zoom: f32,
+    rotation: f32,
+    mode: CameraMode,
     pub target_posiion: Vec2,
     pub target_velocity: Vec2,
+    /// Which way the followed ship is pointing, for the chase camera
+    pub target_rotation: f32,
+    /// Radius of a circle around the origin that the whole track fits
+    /// inside, for the overview
+    pub track_radius: f32,
+    /// Width of the view over its height
+    pub aspect_ratio: f32,
+    /// Where the free camera has been moved to
+    free_position: Vec2,
+    free_zoom: f32,
+    free_rotation: f32,
+    /// Set to jump straight to the target on the next update
+    cutting: bool,
 }
 
 impl Camera {
@@ -18,46 +94,140 @@ impl Camera {
         Self {
             position: (0.0, 0.0),
             zoom: RESET_ZOOM,
+            rotation: 0.0,
+            mode: CameraMode::Follow,
             target_posiion: (0.0, 0.0),
             target_velocity: (0.0, 0.0),
+            target_rotation: 0.0,
+            track_radius: RESET_ZOOM,
+            aspect_ratio: 1.0,
+            free_position: (0.0, 0.0),
+            free_zoom: RESET_ZOOM,
+            free_rotation: 0.0,
+            cutting: false,
         }
     }
 
     pub fn reset(&mut self) {
         self.position = (0.0, 0.0);
         self.zoom = 10.0;
+        self.rotation = 0.0;
         self.target_posiion = (0.0, 0.0);
         self.target_velocity = (0.0, 0.0);
+        self.target_rotation = 0.0;
+    }
+
+    pub fn mode(&self) -> CameraMode {
+        self.mode
+    }
+
+    /// Switches mode. The free camera starts off wherever the camera
+    /// already is, turned the same way.
+    pub fn set_mode(&mut self, mode: CameraMode) {
+        if mode == CameraMode::Free && self.mode != CameraMode::Free {
+            self.free_position = self.position;
+            self.free_zoom = self.zoom;
+            self.free_rotation = self.rotation;
+        }
+        self.mode = mode;
+    }
+
+    /// Jumps straight to where the camera is heading on the next update,
+    /// rather than easing its way there
+    pub fn cut(&mut self) {
+        self.cutting = true;
+    }
+
+    /// Moves the free camera as the mouse drags the world across the
+    /// screen. The movement is a fraction of the view's width and height,
+    /// with y down the screen.
+    pub fn pan(&mut self, movement: Vec2) {
+        if self.mode != CameraMode::Free {
+            return;
+        }
+        let across = -movement.0 * self.zoom * 2.0;
+        let up = movement.1 * self.zoom * 2.0 / self.aspect_ratio;
+        let c = f32::cos(self.rotation);
+        let s = f32::sin(self.rotation);
+        self.free_position.0 += c * across - s * up;
+        self.free_position.1 += s * across + c * up;
+    }
+
+    /// Zooms the free camera by a number of mouse wheel clicks, out for
+    /// clicks down and in for clicks up
+    pub fn wheel(&mut self, clicks: f32) {
+        if self.mode != CameraMode::Free {
+            return;
+        }
+        self.free_zoom = (self.free_zoom * f32::powf(WHEEL_ZOOM_STEP, clicks))
+            .clamp(MIN_FREE_ZOOM, MAX_FREE_ZOOM);
     }
 
     pub fn update(&mut self, dt: f32) {
-        let ideal_position = (
+        let predicted_position = (
             self.target_posiion.0 + self.target_velocity.0 * PREDICT_FACTOR,
             self.target_posiion.1 + self.target_velocity.1 * PREDICT_FACTOR,
         );
-
         let velocity = length(&self.target_velocity);
-        let ideal_zoom = 1.0 + velocity * ZOOM_FACTOR;
+        let speed_zoom = 1.0 + velocity * ZOOM_FACTOR;
+
+        let (ideal_position, ideal_zoom, ideal_rotation, smoothing) = match self.mode {
+            CameraMode::Follow => (predicted_position, speed_zoom, 0.0, SMOOTHING),
+            CameraMode::Chase => (
+                predicted_position,
+                speed_zoom,
+                self.target_rotation,
+                SMOOTHING,
+            ),
+            CameraMode::Overview => {
+                // The track has to fit both across and up the view
+                let zoom = self.track_radius * OVERVIEW_MARGIN * f32::max(self.aspect_ratio, 1.0);
+                ((0.0, 0.0), zoom, 0.0, SMOOTHING)
+            }
+            CameraMode::Free => (
+                self.free_position,
+                self.free_zoom,
+                self.free_rotation,
+                FREE_SMOOTHING,
+            ),
+        };
 
         let zoom_err = self.zoom - ideal_zoom;
         let pos_err = (
             self.position.0 - ideal_position.0,
             self.position.1 - ideal_position.1,
         );
+        // The short way round
+        let rotation_err = (self.rotation - ideal_rotation + PI).rem_euclid(PI * 2.0) - PI;
 
-        self.zoom -= zoom_err * dt / SMOOTHING;
+        // Never goes past where it is heading, however long the frame
+        let blend = match self.cutting {
+            true => 1.0,
+            false => f32::min(dt / smoothing, 1.0),
+        };
+        self.cutting = false;
+        self.zoom -= zoom_err * blend;
 
-        self.position.0 -= pos_err.0 * dt / SMOOTHING;
-        self.position.1 -= pos_err.1 * dt / SMOOTHING;
+        self.position.0 -= pos_err.0 * blend;
+        self.position.1 -= pos_err.1 * blend;
+
+        // Kept between -pi and pi so it doesn't wind up lap after lap
+        self.rotation = (self.rotation - rotation_err * blend + PI).rem_euclid(PI * 2.0) - PI;
     }
 
     pub fn get_camera_matrix(&self) -> [f32; 9] {
         Transform2d::new(
             self.position.0,
             self.position.1,
-            0.0,
+            self.rotation,
            self.zoom,
         )
         .to_mat3_array()
     }
 }
+
+impl Default for Camera {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,116 @@
+use super::race::Race;
+
+/// How hard the catch-up assist pulls the field back together
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CatchUpStrength {
+    None,
+    Weak,
+    Medium,
+    Strong,
+}
+
+impl CatchUpStrength {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "none" => Some(CatchUpStrength::None),
+            "weak" => Some(CatchUpStrength::Weak),
+            "medium" => Some(CatchUpStrength::Medium),
+            "strong" => Some(CatchUpStrength::Strong),
+            _ => None,
+        }
+    }
+
+    /// The most the engine thrust is scaled up for the last ship, or down
+    /// for the leader
+    fn max_thrust_change(&self) -> f32 {
+        match self {
+            CatchUpStrength::None => 0.0,
+            CatchUpStrength::Weak => 0.03,
+            CatchUpStrength::Medium => 0.06,
+            CatchUpStrength::Strong => 0.1,
+        }
+    }
+
+    /// The most the steering lookahead is scaled up for the last ship, or
+    /// down for the leader
+    fn max_lookahead_change(&self) -> f32 {
+        match self {
+            CatchUpStrength::None => 0.0,
+            CatchUpStrength::Weak => 0.05,
+            CatchUpStrength::Medium => 0.1,
+            CatchUpStrength::Strong => 0.2,
+        }
+    }
+}
+
+/// Nudges given to one AI racer depending on where it is in the race
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct CatchUp {
+    /// Multiplier on how hard the AI can thrust, which also raises or
+    /// lowers its top speed
+    pub thrust_scale: f32,
+    /// Multiplier on how far ahead the AI predicts when steering
+    pub lookahead_scale: f32,
+}
+
+impl CatchUp {
+    /// No help and no handicap
+    pub const NONE: CatchUp = CatchUp {
+        thrust_scale: 1.0,
+        lookahead_scale: 1.0,
+    };
+
+    pub fn is_active(&self) -> bool {
+        *self != Self::NONE
+    }
+}
+
+/// Works out the catch-up for every ship from the current standings. The
+/// leader gets the biggest handicap and the last ship the biggest bonus,
+/// with everyone else spread evenly in between. Ships that have finished
+/// are left alone.
+pub fn calc_catch_up(race: &Race, strength: CatchUpStrength) -> Vec<CatchUp> {
+    calc_standings_catch_up(race, race.standings(), strength)
+}
+
+/// Like `calc_catch_up`, but only for the ships still in the race. The
+/// others get none and don't count towards anyone's place.
+pub fn calc_racing_catch_up(
+    race: &Race,
+    racing: &[bool],
+    strength: CatchUpStrength,
+) -> Vec<CatchUp> {
+    let standings = race
+        .standings()
+        .into_iter()
+        .filter(|ship_id| racing[*ship_id])
+        .collect();
+    calc_standings_catch_up(race, standings, strength)
+}
+
+fn calc_standings_catch_up(
+    race: &Race,
+    standings: Vec<usize>,
+    strength: CatchUpStrength,
+) -> Vec<CatchUp> {
+    let mut catch_up = vec![CatchUp::NONE; race.progress.len()];
+    if strength == CatchUpStrength::None || standings.len() < 2 {
+        return catch_up;
+    }
+
+    let last_place = (standings.len() - 1) as f32;
+    for (place, ship_id) in standings.iter().enumerate() {
+        if race.progress[*ship_id].finish_time.is_some() {
+            continue;
+        }
+
+        // From -1 for the leader to 1 for the last ship
+        let behind = place as f32 / last_place * 2.0 - 1.0;
+        catch_up[*ship_id] = CatchUp {
+            thrust_scale: 1.0 + behind * strength.max_thrust_change(),
+            lookahead_scale: 1.0 + behind * strength.max_lookahead_change(),
+        };
+    }
+
+    catch_up
+}
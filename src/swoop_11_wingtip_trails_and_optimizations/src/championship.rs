@@ -0,0 +1,344 @@
+use std::fmt;
+use std::str::FromStr;
+
+use super::map::{Map, MapError};
+
+/// Points for each finishing position, first place first. Anyone further
+/// back scores nothing.
+pub const DEFAULT_POINTS: [u32; 8] = [10, 8, 6, 5, 4, 3, 2, 1];
+const VERSION: u32 = 1;
+
+/// An error to represent a saved championship that couldn't be read
+#[derive(Debug)]
+pub enum ChampionshipError {
+    /// Championships start with a `championship VERSION LAPS` line
+    InvalidHeader(String),
+    UnsupportedVersion(u32),
+    /// Other lines start with points, driver, round or result
+    UnknownLine(String),
+    InvalidPoints(String),
+    /// Rounds are either a seed or a saved track
+    InvalidRound(String),
+    InvalidTrack(MapError),
+    /// Results list every driver's id once, winner first
+    InvalidResult(String),
+    /// There are results for more rounds than the championship has
+    TooManyResults,
+    NoRounds,
+    NoDrivers,
+}
+
+/// Where one round is raced
+#[derive(Clone, Debug)]
+pub enum Track {
+    /// Generated by `Map::randomize`
+    Seed(u64),
+    /// Read in with `Map::from_str`
+    Saved(Map),
+}
+
+impl Track {
+    pub fn map(&self) -> Map {
+        match self {
+            Track::Seed(seed) => {
+                let mut map = Map::new();
+                map.randomize(*seed);
+                map
+            }
+            Track::Saved(map) => map.clone(),
+        }
+    }
+}
+
+impl FromStr for Track {
+    type Err = ChampionshipError;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        let text = text.trim();
+        if text.starts_with("track") {
+            return text
+                .parse::<Map>()
+                .map(Track::Saved)
+                .map_err(ChampionshipError::InvalidTrack);
+        }
+        text.parse()
+            .map(Track::Seed)
+            .map_err(|_| ChampionshipError::InvalidRound(text.to_string()))
+    }
+}
+
+impl fmt::Display for Track {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Track::Seed(seed) => write!(f, "{}", seed),
+            Track::Saved(map) => write!(f, "{}", map),
+        }
+    }
+}
+
+/// A series of races on different tracks between the same drivers, who
+/// score points for where they finish in each.
+///
+/// The whole championship saves as plain text so it can be resumed:
+///     championship VERSION LAPS
+///     points 10 8 6 5 4 3 2 1
+///     driver NAME            (one per ship, in starting order)
+///     round SEED             (or round track ... for a saved track)
+///     result 2 0 1           (finishing order of each round raced)
+#[derive(Clone, Debug)]
+pub struct Championship {
+    pub laps: u32,
+    /// Points for each finishing position, first place first
+    pub points: Vec<u32>,
+    pub drivers: Vec<String>,
+    pub rounds: Vec<Track>,
+    /// Finishing order of each round raced so far, as driver ids
+    pub results: Vec<Vec<usize>>,
+}
+
+impl Championship {
+    pub fn new(rounds: Vec<Track>, drivers: Vec<String>, laps: u32) -> Self {
+        Self {
+            laps,
+            points: DEFAULT_POINTS.to_vec(),
+            drivers,
+            rounds,
+            results: vec![],
+        }
+    }
+
+    /// The round being raced, counting from zero. Equal to the number of
+    /// rounds once they have all been raced.
+    pub fn round(&self) -> usize {
+        self.results.len()
+    }
+
+    pub fn current_track(&self) -> Option<&Track> {
+        self.rounds.get(self.round())
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.round() >= self.rounds.len()
+    }
+
+    pub fn points_for(&self, position: usize) -> u32 {
+        self.points.get(position).copied().unwrap_or(0)
+    }
+
+    /// Scores the current round from its finishing order and moves on to
+    /// the next
+    pub fn record_result(&mut self, finishing_order: Vec<usize>) {
+        self.results.push(finishing_order);
+    }
+
+    /// Points each driver has scored so far
+    pub fn totals(&self) -> Vec<u32> {
+        let mut totals = vec![0; self.drivers.len()];
+        for result in self.results.iter() {
+            for (position, driver) in result.iter().enumerate() {
+                totals[*driver] += self.points_for(position);
+            }
+        }
+        totals
+    }
+
+    /// Drivers in championship order. Drivers level on points are split
+    /// on countback: whoever has more wins, then more second places and
+    /// so on.
+    pub fn standings(&self) -> Vec<usize> {
+        let totals = self.totals();
+        let mut finishes = vec![vec![0; self.drivers.len()]; self.drivers.len()];
+        for result in self.results.iter() {
+            for (position, driver) in result.iter().enumerate() {
+                finishes[*driver][position] += 1;
+            }
+        }
+
+        let mut order: Vec<usize> = (0..self.drivers.len()).collect();
+        order.sort_by(|a, b| {
+            totals[*b]
+                .cmp(&totals[*a])
+                .then_with(|| finishes[*b].cmp(&finishes[*a]))
+        });
+        order
+    }
+}
+
+impl FromStr for Championship {
+    type Err = ChampionshipError;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        let mut lines = text
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'));
+
+        let header = lines.next().ok_or(ChampionshipError::NoRounds)?;
+        let invalid_header = || ChampionshipError::InvalidHeader(header.to_string());
+        let fields: Vec<&str> = header.split_whitespace().collect();
+        if fields.len() != 3 || fields[0] != "championship" {
+            return Err(invalid_header());
+        }
+        let version: u32 = fields[1].parse().map_err(|_| invalid_header())?;
+        if version != VERSION {
+            return Err(ChampionshipError::UnsupportedVersion(version));
+        }
+        let laps = fields[2]
+            .parse()
+            .ok()
+            .filter(|laps| *laps > 0)
+            .ok_or_else(invalid_header)?;
+
+        let mut championship = Self::new(vec![], vec![], laps);
+        let mut results = vec![];
+        for line in lines {
+            let mut parts = line.splitn(2, char::is_whitespace);
+            let kind = parts.next().unwrap_or("");
+            let value = parts.next().unwrap_or("").trim();
+            match kind {
+                "points" => {
+                    championship.points = value
+                        .split_whitespace()
+                        .map(str::parse)
+                        .collect::<Result<Vec<_>, _>>()
+                        .map_err(|_| ChampionshipError::InvalidPoints(line.to_string()))?;
+                }
+                "driver" => championship.drivers.push(value.to_string()),
+                "round" => championship.rounds.push(value.parse::<Track>()?),
+                "result" => results.push(line),
+                _ => return Err(ChampionshipError::UnknownLine(line.to_string())),
+            }
+        }
+
+        if championship.drivers.is_empty() {
+            return Err(ChampionshipError::NoDrivers);
+        }
+        if championship.rounds.is_empty() {
+            return Err(ChampionshipError::NoRounds);
+        }
+        if results.len() > championship.rounds.len() {
+            return Err(ChampionshipError::TooManyResults);
+        }
+        // Results are read last so they can be checked against the drivers
+        for line in results {
+            let invalid_result = || ChampionshipError::InvalidResult(line.to_string());
+            let order = line
+                .split_whitespace()
+                .skip(1)
+                .map(str::parse::<usize>)
+                .collect::<Result<Vec<_>, _>>()
+                .map_err(|_| invalid_result())?;
+            let mut sorted = order.clone();
+            sorted.sort_unstable();
+            if !sorted.iter().copied().eq(0..championship.drivers.len()) {
+                return Err(invalid_result());
+            }
+            championship.record_result(order);
+        }
+        Ok(championship)
+    }
+}
+
+impl fmt::Display for Championship {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "championship {} {}", VERSION, self.laps)?;
+        write!(f, "points")?;
+        for points in self.points.iter() {
+            write!(f, " {}", points)?;
+        }
+        writeln!(f)?;
+        for driver in self.drivers.iter() {
+            writeln!(f, "driver {}", driver)?;
+        }
+        for track in self.rounds.iter() {
+            writeln!(f, "round {}", track)?;
+        }
+        for result in self.results.iter() {
+            write!(f, "result")?;
+            for driver in result.iter() {
+                write!(f, " {}", driver)?;
+            }
+            writeln!(f)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn drivers(count: usize) -> Vec<String> {
+        (0..count)
+            .map(|driver| format!("driver {}", driver))
+            .collect()
+    }
+
+    #[test]
+    fn saved_championships_read_back() {
+        let mut saved_map = Map::new();
+        saved_map.randomize(42);
+        let rounds = vec![Track::Seed(12), Track::Saved(saved_map), Track::Seed(34)];
+        let mut championship = Championship::new(rounds, drivers(3), 4);
+        championship.points = vec![25, 18, 15];
+        championship.record_result(vec![2, 0, 1]);
+        championship.record_result(vec![0, 1, 2]);
+
+        let text = championship.to_string();
+        let read: Championship = text.parse().expect("saved championship reads back");
+        assert_eq!(read.to_string(), text);
+        assert_eq!(read.laps, 4);
+        assert_eq!(read.points, championship.points);
+        assert_eq!(read.drivers, championship.drivers);
+        assert_eq!(read.results, championship.results);
+        assert_eq!(read.round(), 2);
+        assert!(matches!(read.rounds[1], Track::Saved(_)));
+        assert!(matches!(read.current_track(), Some(Track::Seed(34))));
+    }
+
+    #[test]
+    fn rejects_broken_championships() {
+        let parse = |text: &str| text.parse::<Championship>();
+        assert!(matches!(
+            parse("championship 1\ndriver a\nround 1"),
+            Err(ChampionshipError::InvalidHeader(_))
+        ));
+        assert!(matches!(
+            parse("championship 2 3\ndriver a\nround 1"),
+            Err(ChampionshipError::UnsupportedVersion(2))
+        ));
+        assert!(matches!(
+            parse("championship 1 3\nround 1"),
+            Err(ChampionshipError::NoDrivers)
+        ));
+        assert!(matches!(
+            parse("championship 1 3\ndriver a\ndriver b\nround 1\nresult 0 0"),
+            Err(ChampionshipError::InvalidResult(_))
+        ));
+        assert!(matches!(
+            parse("championship 1 3\ndriver a\nround 1\nresult 0\nresult 0"),
+            Err(ChampionshipError::TooManyResults)
+        ));
+    }
+
+    #[test]
+    fn ties_are_split_on_countback() {
+        let rounds = vec![Track::Seed(1), Track::Seed(2)];
+        let mut championship = Championship::new(rounds, drivers(3), 1);
+        championship.points = vec![10, 6, 2];
+        // Everyone ends up on 12, but two seconds don't beat a win
+        championship.record_result(vec![0, 1, 2]);
+        championship.record_result(vec![2, 1, 0]);
+        assert_eq!(championship.totals(), vec![12, 12, 12]);
+        assert_eq!(championship.standings()[2], 1);
+
+        // Level on points and wins, so second places decide it
+        let rounds = vec![Track::Seed(1), Track::Seed(2)];
+        let mut championship = Championship::new(rounds, drivers(3), 1);
+        championship.points = vec![3, 2, 2];
+        championship.record_result(vec![2, 1, 0]);
+        championship.record_result(vec![0, 2, 1]);
+        assert_eq!(championship.totals(), vec![5, 4, 5]);
+        assert_eq!(championship.standings(), vec![2, 0, 1]);
+    }
+}
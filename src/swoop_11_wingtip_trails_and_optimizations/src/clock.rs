@@ -0,0 +1,90 @@
+use super::simulation::TICK;
+
+/// Frames longer than this, such as after the browser has stalled, only
+/// move the game on by this much
+const MAX_FRAME_TIME: f32 = 0.1;
+/// Slow motion cycles through these time scales
+const TIME_SCALES: [f32; 3] = [1.0, 0.5, 0.25];
+
+/// Game time, as opposed to the wall clock time between frames. It can be
+/// paused, stepped one tick at a time and slowed down, and everything that
+/// moves should go by it.
+pub struct GameClock {
+    paused: bool,
+    /// Set when the clock was paused because the page was hidden, so that
+    /// it only starts again by itself if it was running before
+    paused_while_hidden: bool,
+    /// Ticks to run while paused
+    pending_steps: u32,
+    /// Multiplier on how quickly game time passes
+    pub time_scale: f32,
+}
+
+impl GameClock {
+    pub fn new() -> Self {
+        Self {
+            paused: false,
+            paused_while_hidden: false,
+            pending_steps: 0,
+            time_scale: 1.0,
+        }
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused
+    }
+
+    pub fn toggle_pause(&mut self) {
+        self.paused = !self.paused;
+        self.paused_while_hidden = false;
+        self.pending_steps = 0;
+    }
+
+    /// Pauses the game if it isn't already and moves it on by one tick
+    pub fn step(&mut self) {
+        self.paused = true;
+        self.pending_steps += 1;
+    }
+
+    /// Moves on to the next slower time scale, going back to full speed
+    /// after the slowest
+    pub fn next_time_scale(&mut self) {
+        let current = TIME_SCALES
+            .iter()
+            .position(|scale| *scale == self.time_scale)
+            .unwrap_or(0);
+        self.time_scale = TIME_SCALES[(current + 1) % TIME_SCALES.len()];
+    }
+
+    /// Pauses while the page can't be seen, and carries on again when it
+    /// comes back if it was running before
+    pub fn set_hidden(&mut self, hidden: bool) {
+        if hidden && !self.paused {
+            self.paused = true;
+            self.paused_while_hidden = true;
+        } else if !hidden && self.paused_while_hidden {
+            self.paused = false;
+            self.paused_while_hidden = false;
+        }
+    }
+
+    /// Turns the real time since the last frame into how far the game
+    /// should move on this frame. This is zero while paused, apart from
+    /// exactly one tick for each step asked for.
+    pub fn advance(&mut self, real_dt: f32) -> f32 {
+        if self.paused {
+            if self.pending_steps > 0 {
+                self.pending_steps -= 1;
+                return TICK;
+            }
+            return 0.0;
+        }
+        real_dt.clamp(0.0, MAX_FRAME_TIME) * self.time_scale
+    }
+}
+
+impl Default for GameClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,100 @@
+use super::catch_up::CatchUp;
+use super::map::Map;
+use super::racing_line::RacingLine;
+use super::ship::Ship;
+use super::transform::Vec2;
+
+/// What a controller can see of another ship on the track
+#[derive(Clone, Copy, Debug)]
+pub struct NearbyShip {
+    pub position: Vec2,
+    pub velocity: Vec2,
+}
+
+impl NearbyShip {
+    pub fn from_ship(ship: &Ship) -> Self {
+        Self {
+            position: (ship.position.x, ship.position.y),
+            velocity: (ship.velocity.x, ship.velocity.y),
+        }
+    }
+
+    /// What the ship at `ship_id` can see of all the other ships
+    pub fn others(ships: &[Ship], ship_id: usize) -> Vec<Self> {
+        ships
+            .iter()
+            .enumerate()
+            .filter(|(other_id, _)| *other_id != ship_id)
+            .map(|(_, other)| Self::from_ship(other))
+            .collect()
+    }
+
+    /// Like `others`, but leaving out ships no longer in the race, which
+    /// can't be run into
+    pub fn racing_others(ships: &[Ship], racing: &[bool], ship_id: usize) -> Vec<Self> {
+        ships
+            .iter()
+            .zip(racing.iter())
+            .enumerate()
+            .filter(|(other_id, (_, racing))| *other_id != ship_id && **racing)
+            .map(|(_, (other, _))| Self::from_ship(other))
+            .collect()
+    }
+
+    pub fn predict(&self, time: f32) -> Self {
+        Self {
+            position: (
+                self.position.0 + self.velocity.0 * time,
+                self.position.1 + self.velocity.1 * time,
+            ),
+            velocity: self.velocity,
+        }
+    }
+}
+
+/// Everything a controller gets to see when deciding how to fly its ship
+pub struct Observation<'a> {
+    /// The ship being controlled
+    pub ship: &'a Ship,
+    /// The rest of the ships in the race, not including this one
+    pub others: &'a [NearbyShip],
+    /// For track queries such as the closest point on the centerline
+    pub map: &'a Map,
+    pub racing_line: &'a RacingLine,
+    /// Help or handicap from the catch-up assist
+    pub catch_up: CatchUp,
+    /// Time since the last observation
+    pub dt: f32,
+}
+
+/// How a controller wants its ship to be flown
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct ShipControl {
+    /// Positive turns left
+    pub steering: f32,
+    /// Positive is forwards, negative is reverse
+    pub thrust: f32,
+    pub boost: bool,
+}
+
+impl ShipControl {
+    pub fn apply(&self, ship: &mut Ship) {
+        ship.angular_thrust = self.steering.clamp(-1.0, 1.0);
+        ship.linear_thrust = self.thrust.clamp(-1.0, 3.0);
+        ship.boosting = self.boost;
+    }
+}
+
+/// An error to represent a controller that couldn't decide what to do
+#[derive(Debug)]
+pub enum ControllerError {
+    /// A script failed while running, or gave back something that isn't
+    /// a control
+    Script(String),
+}
+
+/// Anything that can fly a ship, given what it can see of the race
+pub trait ShipController {
+    /// Called once per frame to decide the controls for the next frame
+    fn control(&mut self, observation: &Observation) -> Result<ShipControl, ControllerError>;
+}
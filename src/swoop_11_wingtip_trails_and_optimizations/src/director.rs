@@ -0,0 +1,225 @@
+use super::map::Map;
+use super::physics::SHIP_RADIUS;
+use super::race::Race;
+use super::ship::Ship;
+use super::transform::{length, vect_between};
+
+/// Shortest time a shot is held before the director looks elsewhere
+const MIN_SHOT_TIME: f32 = 4.0;
+/// How much more interesting another ship has to be to cut away to it,
+/// so the director doesn't flick between two ships that are much the same
+const SWITCH_MARGIN: f32 = 0.3;
+/// How long the viewer's choice of ship is kept before the director
+/// takes over again
+const MANUAL_HOLD_TIME: f32 = 10.0;
+/// Ships closer than this together are having a battle
+const BATTLE_DISTANCE: f32 = 1.0;
+/// The camera pans to ships closer than this to the one being watched,
+/// and cuts to those further away
+const PAN_DISTANCE: f32 = 3.0;
+/// Extra room on top of the ship radius for something to count as a hit
+const CONTACT_MARGIN: f32 = 0.01;
+
+/// Excitement added for getting past another ship
+const OVERTAKE_EXCITEMENT: f32 = 1.0;
+/// Excitement added for being got past
+const OVERTAKEN_EXCITEMENT: f32 = 0.5;
+/// Excitement added for hitting a wall or another ship
+const COLLISION_EXCITEMENT: f32 = 0.5;
+/// Seconds for excitement to fade to about a third
+const EXCITEMENT_FADE: f32 = 3.0;
+/// The leader is a little more interesting when nothing else is going on
+const LEADER_INTEREST: f32 = 0.2;
+
+/// How the camera gets to a new ship
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Transition {
+    /// Jumps straight there
+    Cut,
+    /// Moves smoothly across
+    Pan,
+}
+
+/// A change of which ship is being watched
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Shot {
+    pub ship_id: usize,
+    pub transition: Transition,
+}
+
+/// Picks which ship the camera follows, for watching races between AI.
+/// Ships are more interesting when they are close to each other, have
+/// just overtaken or been overtaken, or have just hit something. Every
+/// shot is held for a while before the director looks elsewhere.
+pub struct Director {
+    target: usize,
+    /// How long the current shot has been held
+    shot_time: f32,
+    /// Time left before the director takes over from the viewer again
+    manual_time: f32,
+    /// Where each ship was in the race at the last update
+    positions: Vec<usize>,
+    /// How much has been happening around each ship lately
+    excitement: Vec<f32>,
+    /// Whether each ship was touching a wall or another ship at the last
+    /// update
+    in_contact: Vec<bool>,
+}
+
+impl Director {
+    pub fn new(ships: usize) -> Self {
+        Self {
+            target: 0,
+            shot_time: 0.0,
+            manual_time: 0.0,
+            positions: (0..ships).collect(),
+            excitement: vec![0.0; ships],
+            in_contact: vec![false; ships],
+        }
+    }
+
+    /// The ship being watched
+    pub fn target(&self) -> usize {
+        self.target
+    }
+
+    /// Whether the viewer picked the ship being watched
+    pub fn is_manual(&self) -> bool {
+        self.manual_time > 0.0
+    }
+
+    /// How interesting a ship is to watch right now. Ships out of the
+    /// race aren't interesting at all.
+    pub fn interest(&self, ship_id: usize, ships: &[Ship], racing: &[bool]) -> f32 {
+        if !racing[ship_id] {
+            return f32::NEG_INFINITY;
+        }
+        let nearest = (0..ships.len())
+            .filter(|other| *other != ship_id && racing[*other])
+            .map(|other| {
+                length(&vect_between(
+                    &ships[ship_id].position,
+                    &ships[other].position,
+                ))
+            })
+            .fold(f32::INFINITY, f32::min);
+        let battle = f32::max(1.0 - nearest / BATTLE_DISTANCE, 0.0);
+        let leader = match self.positions.first() {
+            Some(leader) if *leader == ship_id => LEADER_INTEREST,
+            _ => 0.0,
+        };
+        battle + self.excitement[ship_id] + leader
+    }
+
+    /// Keeps track of what is happening in the race and decides whether
+    /// to move on to another ship. Gives the new shot if there is one.
+    pub fn update(
+        &mut self,
+        race: &Race,
+        ships: &[Ship],
+        racing: &[bool],
+        map: &Map,
+        dt: f32,
+    ) -> Option<Shot> {
+        let fade = f32::exp(-dt / EXCITEMENT_FADE);
+        for excitement in self.excitement.iter_mut() {
+            *excitement *= fade;
+        }
+        self.update_overtakes(race, racing);
+        self.update_contacts(ships, racing, map);
+
+        self.shot_time += dt;
+        self.manual_time = f32::max(self.manual_time - dt, 0.0);
+
+        // Ships knocked out of the race are never worth staying on
+        let target_gone = !racing[self.target];
+        if !target_gone && (self.is_manual() || self.shot_time < MIN_SHOT_TIME) {
+            return None;
+        }
+
+        let interest: Vec<f32> = (0..ships.len())
+            .map(|ship_id| self.interest(ship_id, ships, racing))
+            .collect();
+        let best = (0..ships.len()).max_by(|a, b| {
+            interest[*a]
+                .partial_cmp(&interest[*b])
+                .unwrap_or(std::cmp::Ordering::Equal)
+        })?;
+        if !racing[best] || best == self.target {
+            return None;
+        }
+        if !target_gone && interest[best] < interest[self.target] + SWITCH_MARGIN {
+            return None;
+        }
+        Some(self.cut_to(best, ships))
+    }
+
+    /// Moves on to the next ship still racing, as picked by the viewer
+    pub fn next_ship(&mut self, ships: &[Ship], racing: &[bool]) -> Option<Shot> {
+        let next = (1..=ships.len())
+            .map(|offset| (self.target + offset) % ships.len())
+            .find(|ship_id| racing[*ship_id])?;
+        self.manual_time = MANUAL_HOLD_TIME;
+        Some(self.cut_to(next, ships))
+    }
+
+    fn cut_to(&mut self, ship_id: usize, ships: &[Ship]) -> Shot {
+        let distance = length(&vect_between(
+            &ships[self.target].position,
+            &ships[ship_id].position,
+        ));
+        self.target = ship_id;
+        self.shot_time = 0.0;
+        Shot {
+            ship_id,
+            transition: match distance < PAN_DISTANCE {
+                true => Transition::Pan,
+                false => Transition::Cut,
+            },
+        }
+    }
+
+    /// Gets excited about ships that have just got past one another.
+    /// Ships are compared in pairs, so nobody moving up a place because a
+    /// ship ahead was knocked out counts.
+    fn update_overtakes(&mut self, race: &Race, racing: &[bool]) {
+        let positions: Vec<usize> = race
+            .standings()
+            .into_iter()
+            .filter(|ship_id| racing[*ship_id])
+            .collect();
+        let previous = std::mem::take(&mut self.positions);
+        let place = |ship_id: usize| previous.iter().position(|id| *id == ship_id);
+        for (index, ahead) in positions.iter().enumerate() {
+            for behind in positions[index + 1..].iter() {
+                if let (Some(was_ahead), Some(was_behind)) = (place(*ahead), place(*behind)) {
+                    if was_ahead > was_behind {
+                        self.excitement[*ahead] += OVERTAKE_EXCITEMENT;
+                        self.excitement[*behind] += OVERTAKEN_EXCITEMENT;
+                    }
+                }
+            }
+        }
+        self.positions = positions;
+    }
+
+    /// Gets excited about ships that have just hit a wall or each other
+    fn update_contacts(&mut self, ships: &[Ship], racing: &[bool], map: &Map) {
+        for ship_id in 0..ships.len() {
+            let ship = &ships[ship_id];
+            let hitting_wall = map.distance_field((ship.position.x, ship.position.y))
+                > -(SHIP_RADIUS + CONTACT_MARGIN);
+            let hitting_ship = (0..ships.len())
+                .filter(|other| *other != ship_id && racing[*other])
+                .any(|other| {
+                    length(&vect_between(&ship.position, &ships[other].position))
+                        < SHIP_RADIUS * 2.0 + CONTACT_MARGIN
+                });
+            let in_contact = racing[ship_id] && (hitting_wall || hitting_ship);
+            if in_contact && !self.in_contact[ship_id] {
+                self.excitement[ship_id] += COLLISION_EXCITEMENT;
+            }
+            self.in_contact[ship_id] = in_contact;
+        }
+    }
+}
@@ -0,0 +1,85 @@
+use super::race::Race;
+
+/// Knocks the ship in last place out of the race every time the leader
+/// completes a lap, until only one ship is left.
+///
+/// Ships keep their ids when they are knocked out, so they still line up
+/// with the race progress and timing. They just stop taking part.
+pub struct Elimination {
+    /// Whether each ship is still in the race
+    racing: Vec<bool>,
+    /// Ships in the order they were knocked out
+    eliminated: Vec<usize>,
+    /// Laps the leader had completed the last time a ship went out
+    leader_laps: i32,
+}
+
+impl Elimination {
+    pub fn new(ships: usize) -> Self {
+        Self {
+            racing: vec![true; ships],
+            eliminated: vec![],
+            leader_laps: 0,
+        }
+    }
+
+    /// The number of laps it takes to knock out all but one of a number of
+    /// ships
+    pub fn laps_for(ships: usize) -> u32 {
+        usize::max(ships, 2) as u32 - 1
+    }
+
+    pub fn racing(&self) -> &[bool] {
+        &self.racing
+    }
+
+    pub fn is_racing(&self, ship_id: usize) -> bool {
+        self.racing[ship_id]
+    }
+
+    pub fn ships_left(&self) -> usize {
+        self.racing.iter().filter(|racing| **racing).count()
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.ships_left() <= 1
+    }
+
+    /// Checks for the leader starting a new lap once the race has been
+    /// updated. Gives the ship that was knocked out, if one was.
+    pub fn update(&mut self, race: &Race) -> Option<usize> {
+        if self.is_finished() {
+            return None;
+        }
+        let leader_laps = (0..self.racing.len())
+            .filter(|ship_id| self.racing[*ship_id])
+            .map(|ship_id| race.progress[ship_id].laps)
+            .max()?;
+        if leader_laps <= self.leader_laps {
+            return None;
+        }
+        self.leader_laps = leader_laps;
+
+        let last = race
+            .standings()
+            .into_iter()
+            .rev()
+            .find(|ship_id| self.racing[*ship_id])?;
+        self.racing[last] = false;
+        self.eliminated.push(last);
+        Some(last)
+    }
+
+    /// Where each ship ended up, first place first. Ships still racing
+    /// are in race order, ahead of those knocked out, who are placed by
+    /// how long they lasted.
+    pub fn standings(&self, race: &Race) -> Vec<usize> {
+        let mut order: Vec<usize> = race
+            .standings()
+            .into_iter()
+            .filter(|ship_id| self.racing[*ship_id])
+            .collect();
+        order.extend(self.eliminated.iter().rev());
+        order
+    }
+}
@@ -1,9 +1,18 @@
+use super::entity::EntityId;
+use super::ship::Ship;
 use super::transform::Vec2;
 use std::collections::VecDeque;
 
 const NUM_SEGEMENTS: usize = 20;
 const TIME_PER_SEGMENT: f32 = 0.25;
 
+const MAIN_TRAIL_WIDTH: f32 = 0.10;
+const WINGTIP_TRAIL_WIDTH: f32 = 0.02;
+const MAIN_TRAIL_BRIGHTNESS: f32 = 0.3;
+const WINGTIP_TRAIL_BRIGHTNESS: f32 = 1.0;
+/// Seconds the trails of a ship that has left the race take to fade out
+const FADE_TIME: f32 = 1.5;
+
 pub struct PathPoint {
     pub position: Vec2,
     pub tangent: Vec2,
@@ -76,6 +85,18 @@ impl EngineTrail {
         }
     }
 
+    /// Dims the whole trail where it is by a fraction of its full
+    /// brightness, for once nothing is flying along it any more
+    pub fn fade(&mut self, amount: f32) {
+        for point in self.path.iter_mut() {
+            point.brightness = f32::max(point.brightness - self.brightness * amount, 0.0);
+        }
+    }
+
+    pub fn is_faded(&self) -> bool {
+        self.path.iter().all(|point| point.brightness <= 0.0)
+    }
+
     pub fn length(&self) -> i32 {
         self.path.len() as i32
     }
@@ -102,3 +123,62 @@ impl EngineTrail {
         point_buffer
     }
 }
+
+/// The engine trail and two wingtip trails behind one ship. They are
+/// matched to their ship by its entity rather than by position in a list,
+/// so they can stay behind and fade out once the ship has gone.
+pub struct ShipTrails {
+    pub ship: EntityId,
+    pub main: EngineTrail,
+    pub left: EngineTrail,
+    pub right: EngineTrail,
+}
+
+impl ShipTrails {
+    pub fn new(id: EntityId, ship: &Ship) -> Self {
+        Self {
+            ship: id,
+            main: EngineTrail::new(ship.color, MAIN_TRAIL_WIDTH, MAIN_TRAIL_BRIGHTNESS),
+            left: EngineTrail::new(ship.color, WINGTIP_TRAIL_WIDTH, WINGTIP_TRAIL_BRIGHTNESS),
+            right: EngineTrail::new(ship.color, WINGTIP_TRAIL_WIDTH, WINGTIP_TRAIL_BRIGHTNESS),
+        }
+    }
+
+    /// Extends the trails behind the ship. The engine trail shows the
+    /// thrust and the wingtips show how much the ship is sliding.
+    pub fn update(&mut self, dt: f32, ship: &Ship) {
+        self.main
+            .update(dt, ship.get_engine_position(), f32::abs(ship.linear_thrust));
+
+        let wingtip_positions = ship.get_wingtip_positions();
+
+        let raw_slip = ship.calc_slip() / 2.5;
+        let base_slip = f32::abs(raw_slip);
+        let left_slip = base_slip + raw_slip / 8.0;
+        let right_slip = base_slip - raw_slip / 8.0;
+
+        self.left
+            .update(dt, wingtip_positions.0, left_slip.clamp(0.0, 1.0));
+        self.right
+            .update(dt, wingtip_positions.1, right_slip.clamp(0.0, 1.0));
+    }
+
+    /// Fades the trails out where they were left
+    pub fn fade(&mut self, dt: f32) {
+        for trail in self.trails_mut().iter_mut() {
+            trail.fade(dt / FADE_TIME);
+        }
+    }
+
+    pub fn is_faded(&self) -> bool {
+        self.trails().iter().all(|trail| trail.is_faded())
+    }
+
+    pub fn trails(&self) -> [&EngineTrail; 3] {
+        [&self.main, &self.left, &self.right]
+    }
+
+    fn trails_mut(&mut self) -> [&mut EngineTrail; 3] {
+        [&mut self.main, &mut self.left, &mut self.right]
+    }
+}
@@ -0,0 +1,259 @@
+/// A handle to something in the game. Slots are reused once an entity is
+/// despawned, so the generation tells apart the entities that have had
+/// the same slot. An id kept after its entity is gone never reaches
+/// whatever is spawned in its place.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct EntityId {
+    index: u32,
+    generation: u32,
+}
+
+/// Hands out entity ids and keeps track of which are still alive
+pub struct Entities {
+    /// The current generation of each slot
+    generations: Vec<u32>,
+    alive: Vec<bool>,
+    /// Slots free to be reused
+    free: Vec<u32>,
+}
+
+impl Entities {
+    pub fn new() -> Self {
+        Self {
+            generations: vec![],
+            alive: vec![],
+            free: vec![],
+        }
+    }
+
+    pub fn spawn(&mut self) -> EntityId {
+        let index = match self.free.pop() {
+            Some(index) => index,
+            None => {
+                self.generations.push(0);
+                self.alive.push(false);
+                self.generations.len() as u32 - 1
+            }
+        };
+        self.alive[index as usize] = true;
+        EntityId {
+            index,
+            generation: self.generations[index as usize],
+        }
+    }
+
+    /// Frees up the entity's slot. Returns false if it had already gone.
+    /// Its components have to be removed from their stores separately.
+    pub fn despawn(&mut self, id: EntityId) -> bool {
+        if !self.is_alive(id) {
+            return false;
+        }
+        let index = id.index as usize;
+        self.alive[index] = false;
+        self.generations[index] = self.generations[index].wrapping_add(1);
+        self.free.push(id.index);
+        true
+    }
+
+    pub fn is_alive(&self, id: EntityId) -> bool {
+        let index = id.index as usize;
+        index < self.alive.len() && self.alive[index] && self.generations[index] == id.generation
+    }
+
+    /// The number of entities alive
+    pub fn len(&self) -> usize {
+        self.alive.len() - self.free.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl Default for Entities {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// One kind of component, such as ships or trails, for the entities that
+/// have it. Components are packed together in the order they were added
+/// so systems can run over just the ones they need.
+pub struct Components<T> {
+    /// Where each entity's component is in `values`, by entity slot
+    sparse: Vec<Option<usize>>,
+    ids: Vec<EntityId>,
+    values: Vec<T>,
+}
+
+impl<T> Components<T> {
+    pub fn new() -> Self {
+        Self {
+            sparse: vec![],
+            ids: vec![],
+            values: vec![],
+        }
+    }
+
+    /// Gives an entity this component, replacing any it already had
+    pub fn insert(&mut self, id: EntityId, value: T) {
+        let index = id.index as usize;
+        if index >= self.sparse.len() {
+            self.sparse.resize(index + 1, None);
+        }
+        match self.sparse[index] {
+            // Also replaces one left over from an earlier generation
+            Some(position) => {
+                self.ids[position] = id;
+                self.values[position] = value;
+            }
+            None => {
+                self.sparse[index] = Some(self.values.len());
+                self.ids.push(id);
+                self.values.push(value);
+            }
+        }
+    }
+
+    /// Takes the component away from an entity. Everything after it moves
+    /// up one so the rest stay in the order they were added.
+    pub fn remove(&mut self, id: EntityId) -> Option<T> {
+        let position = self.position(id)?;
+        self.sparse[id.index as usize] = None;
+        self.ids.remove(position);
+        for later in self.ids[position..].iter() {
+            if let Some(later) = &mut self.sparse[later.index as usize] {
+                *later -= 1;
+            }
+        }
+        Some(self.values.remove(position))
+    }
+
+    /// Where the entity's component is in `values`, if it has one
+    pub fn position(&self, id: EntityId) -> Option<usize> {
+        let position = (*self.sparse.get(id.index as usize)?)?;
+        match self.ids[position] == id {
+            true => Some(position),
+            false => None,
+        }
+    }
+
+    pub fn get(&self, id: EntityId) -> Option<&T> {
+        self.position(id).map(|position| &self.values[position])
+    }
+
+    pub fn get_mut(&mut self, id: EntityId) -> Option<&mut T> {
+        self.position(id)
+            .map(move |position| &mut self.values[position])
+    }
+
+    pub fn contains(&self, id: EntityId) -> bool {
+        self.position(id).is_some()
+    }
+
+    pub fn ids(&self) -> &[EntityId] {
+        &self.ids
+    }
+
+    pub fn values(&self) -> &[T] {
+        &self.values
+    }
+
+    pub fn values_mut(&mut self) -> &mut [T] {
+        &mut self.values
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (EntityId, &T)> {
+        self.ids.iter().copied().zip(self.values.iter())
+    }
+
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = (EntityId, &mut T)> {
+        self.ids.iter().copied().zip(self.values.iter_mut())
+    }
+
+    pub fn len(&self) -> usize {
+        self.values.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.values.is_empty()
+    }
+}
+
+impl<T> Default for Components<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn despawned_slots_are_reused_with_a_new_generation() {
+        let mut entities = Entities::new();
+        let first = entities.spawn();
+        let second = entities.spawn();
+        assert!(entities.despawn(first));
+        assert!(!entities.despawn(first));
+
+        let reused = entities.spawn();
+        assert_eq!(reused.index, first.index);
+        assert_ne!(reused, first);
+        assert!(!entities.is_alive(first));
+        assert!(entities.is_alive(reused));
+        assert!(entities.is_alive(second));
+        assert_eq!(entities.len(), 2);
+    }
+
+    #[test]
+    fn stale_ids_miss_components() {
+        let mut entities = Entities::new();
+        let mut names = Components::new();
+        let stale = entities.spawn();
+        names.insert(stale, "stale");
+        entities.despawn(stale);
+
+        // The stale component is left in the slot until something
+        // replaces it, but the new entity there can't see it
+        let fresh = entities.spawn();
+        assert_eq!(names.get(fresh), None);
+        names.insert(fresh, "fresh");
+        assert_eq!(names.len(), 1);
+        assert_eq!(names.get(stale), None);
+        assert_eq!(names.get(fresh), Some(&"fresh"));
+        assert_eq!(names.remove(stale), None);
+        assert!(!names.contains(stale));
+        assert!(names.contains(fresh));
+    }
+
+    #[test]
+    fn removing_keeps_the_rest_in_order() {
+        let mut entities = Entities::new();
+        let mut names = Components::new();
+        let ids: Vec<EntityId> = (0..5).map(|_| entities.spawn()).collect();
+        for (id, name) in ids.iter().zip(["a", "b", "c", "d", "e"].iter()) {
+            names.insert(*id, *name);
+        }
+
+        assert_eq!(names.remove(ids[1]), Some("b"));
+        assert_eq!(names.remove(ids[3]), Some("d"));
+        assert_eq!(names.values(), &["a", "c", "e"]);
+        assert_eq!(names.ids(), &[ids[0], ids[2], ids[4]]);
+        for (position, id) in names.ids().iter().enumerate() {
+            assert_eq!(names.position(*id), Some(position));
+        }
+        assert_eq!(names.get(ids[4]), Some(&"e"));
+        *names.get_mut(ids[2]).unwrap() = "C";
+        assert_eq!(names.get(ids[2]), Some(&"C"));
+        assert_eq!(names.get(ids[1]), None);
+
+        // A slot freed up in the middle goes on the end when reused
+        entities.despawn(ids[1]);
+        let reused = entities.spawn();
+        names.insert(reused, "f");
+        assert_eq!(names.values(), &["a", "C", "e", "f"]);
+        assert_eq!(names.position(reused), Some(3));
+    }
+}
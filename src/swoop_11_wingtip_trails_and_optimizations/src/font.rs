@@ -0,0 +1,71 @@
+/// The bitmap font in `resources/font.png`. Printable ASCII from space to
+/// underscore is laid out in order in a grid of 8x8 cells, with each glyph
+/// drawn 5x7 from the top left corner of its cell. Lower case letters use
+/// the capitals, and anything else missing is drawn as a space.
+pub const GLYPH_WIDTH: f32 = 5.0;
+pub const GLYPH_HEIGHT: f32 = 7.0;
+/// How far along the pen moves after each character, in font pixels
+pub const ADVANCE: f32 = 6.0;
+/// How far down each line of text is from the last, in font pixels
+pub const LINE_HEIGHT: f32 = 10.0;
+
+const ATLAS_COLUMNS: u32 = 16;
+const ATLAS_CELL: f32 = 8.0;
+const ATLAS_WIDTH: f32 = 128.0;
+const ATLAS_HEIGHT: f32 = 48.0;
+const FIRST_CHAR: u32 = ' ' as u32;
+const LAST_CHAR: u32 = '_' as u32;
+/// The cell after the last glyph is filled in solid, for drawing panels
+const SOLID_CELL: u32 = 127;
+
+/// Where something is in the atlas, as texture coordinates of its top
+/// left and bottom right corners
+pub type AtlasRegion = [f32; 4];
+
+/// The region of the atlas to draw a character with, or None for blank
+/// characters that only move the pen along
+pub fn glyph(c: char) -> Option<AtlasRegion> {
+    let code = c.to_ascii_uppercase() as u32;
+    if code <= FIRST_CHAR || code > LAST_CHAR {
+        return None;
+    }
+    Some(cell_region(code, GLYPH_WIDTH, GLYPH_HEIGHT))
+}
+
+/// A region of the atlas that is solid all over, so quads drawn with it
+/// are filled in with their color
+pub fn solid() -> AtlasRegion {
+    // Only the middle of the cell is used so nothing bleeds in from the
+    // glyphs around it
+    let [left, top, right, bottom] = cell_region(SOLID_CELL, ATLAS_CELL, ATLAS_CELL);
+    let inset_x = (right - left) / 4.0;
+    let inset_y = (bottom - top) / 4.0;
+    [
+        left + inset_x,
+        top + inset_y,
+        right - inset_x,
+        bottom - inset_y,
+    ]
+}
+
+/// How wide a line of text is, in font pixels
+pub fn text_width(text: &str) -> f32 {
+    match text.chars().count() {
+        0 => 0.0,
+        chars => (chars - 1) as f32 * ADVANCE + GLYPH_WIDTH,
+    }
+}
+
+fn cell_region(code: u32, width: f32, height: f32) -> AtlasRegion {
+    let index = code - FIRST_CHAR;
+    let left = (index % ATLAS_COLUMNS) as f32 * ATLAS_CELL;
+    let top = (index / ATLAS_COLUMNS) as f32 * ATLAS_CELL;
+    // Textures are flipped as they are loaded, so v counts up from the
+    // bottom of the image
+    [
+        left / ATLAS_WIDTH,
+        1.0 - top / ATLAS_HEIGHT,
+        (left + width) / ATLAS_WIDTH,
+        1.0 - (top + height) / ATLAS_HEIGHT,
+    ]
+}
@@ -0,0 +1,561 @@
+use std::str::FromStr;
+
+use super::ai::{AiProfile, AGGRESSIVE, CHAMPION, DEFENSIVE, PRO, ROOKIE};
+use super::camera::CameraMode;
+use super::catch_up::CatchUpStrength;
+use super::elimination::Elimination;
+use super::input::PlayerInput;
+use super::minimap::{Minimap, MinimapCorner, MinimapRotation};
+use super::ship::SHIP_COLORS;
+
+/// The most ships there are colors for
+pub const MAX_SHIPS: usize = 5;
+/// The most players that can share one canvas
+pub const MAX_PLAYERS: usize = 4;
+const DEFAULT_LAPS: u32 = 3;
+/// Championships without a list of tracks run this many random ones
+const DEFAULT_ROUNDS: u32 = 4;
+/// Online races are found in this lobby unless another is given
+const DEFAULT_LOBBY: &str = "swoop";
+
+/// Who is flying a ship
+#[derive(Clone, Debug)]
+pub enum DriverOption {
+    /// Controlled from the keyboard or a gamepad
+    Player,
+    Ai(AiProfile),
+    /// Flown by a Rhai script, written as `script:NAME`. In the browser
+    /// NAME is the id of the element holding the script.
+    Script(String),
+    /// Flown by a trained policy, written as `policy` for the built in one
+    /// or `policy:NAME` to load one the same way as a script
+    Policy(Option<String>),
+    /// Flown by someone else in an online race, going by their name. The
+    /// server says who is racing, so this is never parsed.
+    Remote(String),
+}
+
+impl DriverOption {
+    pub fn from_name(name: &str) -> Option<Self> {
+        if name == "player" {
+            return Some(DriverOption::Player);
+        }
+        if let Some(script) = name.strip_prefix("script:") {
+            return Some(DriverOption::Script(script.to_string()));
+        }
+        if name == "policy" {
+            return Some(DriverOption::Policy(None));
+        }
+        if let Some(policy) = name.strip_prefix("policy:") {
+            return Some(DriverOption::Policy(Some(policy.to_string())));
+        }
+        AiProfile::from_name(name).map(DriverOption::Ai)
+    }
+
+    pub fn name(&self) -> &str {
+        match self {
+            DriverOption::Player => "player",
+            DriverOption::Ai(profile) => profile.name,
+            DriverOption::Script(script) => script,
+            DriverOption::Policy(None) => "policy",
+            DriverOption::Policy(Some(policy)) => policy,
+            DriverOption::Remote(name) => name,
+        }
+    }
+}
+
+/// What kind of session to run
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum GameMode {
+    /// Every ship races to the finish
+    Race,
+    /// Only the first ship goes out, chasing a ghost of its best lap on
+    /// the same track each time
+    TimeTrial,
+    /// A race on each of a series of tracks, scoring points for where
+    /// each ship finishes
+    Championship,
+    /// The ship in last place is knocked out every lap until only one is
+    /// left
+    Elimination,
+}
+
+impl GameMode {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "race" => Some(GameMode::Race),
+            "timetrial" => Some(GameMode::TimeTrial),
+            "championship" => Some(GameMode::Championship),
+            "elimination" => Some(GameMode::Elimination),
+            _ => None,
+        }
+    }
+}
+
+/// Where a championship round is raced
+#[derive(Clone, Debug)]
+pub enum TrackOption {
+    /// The track `Map::randomize` makes from this seed
+    Seed(u64),
+    /// A saved track, written as `track:NAME`. In the browser NAME is the
+    /// id of the element holding the track.
+    Saved(String),
+}
+
+impl TrackOption {
+    pub fn from_name(name: &str) -> Option<Self> {
+        if let Some(track) = name.strip_prefix("track:") {
+            return Some(TrackOption::Saved(track.to_string()));
+        }
+        name.parse().ok().map(TrackOption::Seed)
+    }
+}
+
+/// An error to represent an options string that couldn't be understood
+#[derive(Debug)]
+pub enum OptionsError {
+    /// Options are written as key=value
+    MissingValue(String),
+    UnknownKey(String),
+    /// Drivers are "player", the name of an AI profile, script:NAME,
+    /// policy or policy:NAME
+    UnknownDriver(String),
+    InvalidLaps(String),
+    /// Catch-up is one of none, weak, medium or strong
+    UnknownCatchUp(String),
+    /// Debug is either true or false
+    InvalidDebug(String),
+    /// Mode is one of race, timetrial, championship or elimination
+    UnknownMode(String),
+    InvalidSeed(String),
+    /// Tracks are seeds or track:NAME
+    UnknownTrack(String),
+    InvalidRounds(String),
+    /// The minimap is off or in one of the corners: top-left, top-right,
+    /// bottom-left or bottom-right
+    UnknownMinimap(String),
+    InvalidMinimapSize(String),
+    /// The minimap is either north or player up
+    UnknownMinimapRotation(String),
+    /// Camera is one of follow, chase, overview or free
+    UnknownCamera(String),
+    /// Director is either true or false
+    InvalidDirector(String),
+    /// Controls are wasd, arrows, ijkl, numpad or gamepad1 to gamepad4
+    UnknownControls(String),
+    /// Colors are cyan, yellow, pink, purple or white
+    UnknownColor(String),
+    NoShips,
+    TooManyShips(usize),
+    /// There is only room on the screen for MAX_PLAYERS
+    TooManyPlayers(usize),
+    /// Elimination races need at least two ships
+    TooFewShips(usize),
+}
+
+/// Settings for a race, parsed from the `options` attribute on the canvas.
+/// The options are semicolon separated key=value pairs, for example:
+///     ships=player,champion,pro,script:my-bot;laps=5;catchup=weak;debug=true
+/// or for chasing a ghost around one particular track:
+///     ships=player;mode=timetrial;seed=1234
+/// or for a championship over three tracks, the last of them saved:
+///     mode=championship;tracks=12,34,track:my-track
+/// or for a bigger minimap that turns with the player:
+///     minimap=top-left;minimapsize=240;minimaprotation=player
+/// or for a camera that turns with the ship:
+///     camera=chase
+/// or for always following the first ship, even with nobody playing:
+///     ships=champion,pro,aggressive;director=false
+/// or for three players on a split screen, two sharing the keyboard and
+/// one on a gamepad:
+///     ships=player,player,player,pro;controls=wasd,arrows,gamepad1
+/// or for racing online against whoever else is in a lobby:
+///     server=ws://127.0.0.1:9001;lobby=friday;name=sam
+/// or for changing the name and color kept in the player's profile:
+///     name=sam;color=pink
+#[derive(Debug)]
+pub struct GameOptions {
+    /// One entry per ship, in starting order
+    pub drivers: Vec<DriverOption>,
+    pub laps: u32,
+    /// How much trailing AI are helped and leading AI held back
+    pub catch_up: CatchUpStrength,
+    /// Log extra information to the console for balance testing
+    pub debug: bool,
+    pub mode: GameMode,
+    /// Track to race on. A new one is picked at random each race if this
+    /// isn't set, except in time trials which stay on the first one.
+    pub seed: Option<u64>,
+    /// Tracks for a championship, in order. Random ones are used if this
+    /// is empty.
+    pub tracks: Vec<TrackOption>,
+    /// How many random tracks a championship has when none are listed
+    pub rounds: u32,
+    /// The map of the whole track in a corner, if it is shown
+    pub minimap: Option<Minimap>,
+    /// What the camera starts off looking at
+    pub camera: CameraMode,
+    /// Let a director pick which ship to watch rather than always
+    /// following the first. On by default when nobody is playing.
+    pub director: bool,
+    /// What each player flies with, in the order their ships start
+    pub controls: Vec<PlayerInput>,
+    /// Address of a relay server to race online through. The server
+    /// picks the tracks and who races, so ships and mode are ignored.
+    pub server: Option<String>,
+    /// The lobby to race in on the server
+    pub lobby: String,
+    /// Renames the player in their profile, which is the name other
+    /// players online see
+    pub name: Option<String>,
+    /// Repaints the player's ship in their profile, going by the name of
+    /// one of SHIP_COLORS
+    pub color: Option<String>,
+}
+
+impl GameOptions {
+    pub fn new() -> Self {
+        Self {
+            drivers: vec![
+                DriverOption::Ai(CHAMPION),
+                DriverOption::Ai(PRO),
+                DriverOption::Ai(AGGRESSIVE),
+                DriverOption::Ai(DEFENSIVE),
+                DriverOption::Ai(ROOKIE),
+            ],
+            laps: DEFAULT_LAPS,
+            catch_up: CatchUpStrength::None,
+            debug: false,
+            mode: GameMode::Race,
+            seed: None,
+            tracks: vec![],
+            rounds: DEFAULT_ROUNDS,
+            minimap: Some(Minimap::new()),
+            camera: CameraMode::Follow,
+            director: true,
+            controls: vec![],
+            server: None,
+            lobby: DEFAULT_LOBBY.to_string(),
+            name: None,
+            color: None,
+        }
+    }
+
+    /// Ids of the ships flown by players, in starting order. The
+    /// player's index in this list picks their controls and their view
+    /// on a split screen.
+    pub fn players(&self) -> Vec<usize> {
+        self.drivers
+            .iter()
+            .enumerate()
+            .filter(|(_, driver)| matches!(driver, DriverOption::Player))
+            .map(|(ship_id, _)| ship_id)
+            .collect()
+    }
+}
+
+impl Default for GameOptions {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl FromStr for GameOptions {
+    type Err = OptionsError;
+
+    fn from_str(options: &str) -> Result<Self, Self::Err> {
+        let mut game_options = Self::new();
+        // The minimap is put together once all its options are known
+        let mut minimap = Minimap::new();
+        let mut show_minimap = true;
+        let mut director = None;
+        let mut controls = vec![];
+
+        for pair in options.split(';').map(str::trim).filter(|p| !p.is_empty()) {
+            let mut parts = pair.splitn(2, '=');
+            let key = parts.next().unwrap_or("").trim();
+            let value = parts
+                .next()
+                .ok_or_else(|| OptionsError::MissingValue(key.to_string()))?
+                .trim();
+
+            match key {
+                "ships" => {
+                    game_options.drivers = value
+                        .split(',')
+                        .map(str::trim)
+                        .map(|name| {
+                            DriverOption::from_name(name)
+                                .ok_or_else(|| OptionsError::UnknownDriver(name.to_string()))
+                        })
+                        .collect::<Result<Vec<_>, _>>()?;
+                    if game_options.drivers.is_empty() {
+                        return Err(OptionsError::NoShips);
+                    }
+                    if game_options.drivers.len() > MAX_SHIPS {
+                        return Err(OptionsError::TooManyShips(game_options.drivers.len()));
+                    }
+                }
+                "laps" => {
+                    game_options.laps = value
+                        .parse()
+                        .ok()
+                        .filter(|laps| *laps > 0)
+                        .ok_or_else(|| OptionsError::InvalidLaps(value.to_string()))?;
+                }
+                "catchup" => {
+                    game_options.catch_up = CatchUpStrength::from_name(value)
+                        .ok_or_else(|| OptionsError::UnknownCatchUp(value.to_string()))?;
+                }
+                "debug" => {
+                    game_options.debug = value
+                        .parse()
+                        .map_err(|_| OptionsError::InvalidDebug(value.to_string()))?;
+                }
+                "mode" => {
+                    game_options.mode = GameMode::from_name(value)
+                        .ok_or_else(|| OptionsError::UnknownMode(value.to_string()))?;
+                }
+                "seed" => {
+                    game_options.seed = Some(
+                        value
+                            .parse()
+                            .map_err(|_| OptionsError::InvalidSeed(value.to_string()))?,
+                    );
+                }
+                "tracks" => {
+                    game_options.tracks = value
+                        .split(',')
+                        .map(str::trim)
+                        .map(|name| {
+                            TrackOption::from_name(name)
+                                .ok_or_else(|| OptionsError::UnknownTrack(name.to_string()))
+                        })
+                        .collect::<Result<Vec<_>, _>>()?;
+                }
+                "rounds" => {
+                    game_options.rounds = value
+                        .parse()
+                        .ok()
+                        .filter(|rounds| *rounds > 0)
+                        .ok_or_else(|| OptionsError::InvalidRounds(value.to_string()))?;
+                }
+                "minimap" => match value {
+                    "off" => show_minimap = false,
+                    corner => {
+                        minimap.corner = MinimapCorner::from_name(corner)
+                            .ok_or_else(|| OptionsError::UnknownMinimap(value.to_string()))?;
+                        show_minimap = true;
+                    }
+                },
+                "minimapsize" => {
+                    minimap.size = value
+                        .parse()
+                        .ok()
+                        .filter(|size| *size > 0)
+                        .ok_or_else(|| OptionsError::InvalidMinimapSize(value.to_string()))?;
+                }
+                "minimaprotation" => {
+                    minimap.rotation = MinimapRotation::from_name(value)
+                        .ok_or_else(|| OptionsError::UnknownMinimapRotation(value.to_string()))?;
+                }
+                "camera" => {
+                    game_options.camera = CameraMode::from_name(value)
+                        .ok_or_else(|| OptionsError::UnknownCamera(value.to_string()))?;
+                }
+                "director" => {
+                    director = Some(
+                        value
+                            .parse()
+                            .map_err(|_| OptionsError::InvalidDirector(value.to_string()))?,
+                    );
+                }
+                "controls" => {
+                    controls = value
+                        .split(',')
+                        .map(str::trim)
+                        .map(|name| {
+                            PlayerInput::from_name(name)
+                                .ok_or_else(|| OptionsError::UnknownControls(name.to_string()))
+                        })
+                        .collect::<Result<Vec<_>, _>>()?;
+                }
+                "server" => game_options.server = Some(value.to_string()),
+                "lobby" => game_options.lobby = value.to_string(),
+                "name" => game_options.name = Some(value.to_string()),
+                "color" => {
+                    if !SHIP_COLORS
+                        .iter()
+                        .any(|(color, _)| color.eq_ignore_ascii_case(value))
+                    {
+                        return Err(OptionsError::UnknownColor(value.to_string()));
+                    }
+                    game_options.color = Some(value.to_string());
+                }
+                _ => return Err(OptionsError::UnknownKey(key.to_string())),
+            }
+        }
+        game_options.director = director.unwrap_or_else(|| game_options.players().is_empty());
+        game_options.minimap = match show_minimap {
+            true => Some(minimap),
+            false => None,
+        };
+
+        if game_options.mode == GameMode::TimeTrial {
+            game_options.drivers.truncate(1);
+        }
+        if game_options.mode == GameMode::Elimination {
+            if game_options.drivers.len() < 2 {
+                return Err(OptionsError::TooFewShips(game_options.drivers.len()));
+            }
+            // The race lasts exactly as long as it takes to knock out
+            // everyone but the winner
+            game_options.laps = Elimination::laps_for(game_options.drivers.len());
+        }
+
+        let players = game_options.players().len();
+        if players > MAX_PLAYERS {
+            return Err(OptionsError::TooManyPlayers(players));
+        }
+        game_options.controls = (0..players)
+            .map(|player| {
+                controls
+                    .get(player)
+                    .copied()
+                    .unwrap_or_else(|| PlayerInput::default_for(player))
+            })
+            .collect();
+
+        Ok(game_options)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(options: &str) -> Result<GameOptions, OptionsError> {
+        options.parse()
+    }
+
+    #[test]
+    fn reads_every_key() {
+        let options = parse(
+            "ships=player,champion,script:bot;laps=5;catchup=weak;debug=true;seed=1234;\
+             tracks=12,track:mine;rounds=6;minimap=top-left;minimapsize=240;\
+             minimaprotation=player;camera=chase;director=false;controls=arrows;\
+             server=ws://127.0.0.1:9001;lobby=friday;name=sam;color=pink",
+        )
+        .expect("options are read");
+        assert_eq!(options.drivers.len(), 3);
+        assert_eq!(options.laps, 5);
+        assert!(options.debug);
+        assert_eq!(options.seed, Some(1234));
+        assert_eq!(options.tracks.len(), 2);
+        assert_eq!(options.rounds, 6);
+        assert_eq!(options.minimap.map(|minimap| minimap.size), Some(240));
+        assert!(!options.director);
+        assert_eq!(options.controls.len(), 1);
+        assert_eq!(options.lobby, "friday");
+        assert_eq!(options.name.as_deref(), Some("sam"));
+        assert_eq!(options.color.as_deref(), Some("pink"));
+    }
+
+    #[test]
+    fn rejects_bad_values() {
+        assert!(matches!(parse("laps"), Err(OptionsError::MissingValue(_))));
+        assert!(matches!(
+            parse("speed=11"),
+            Err(OptionsError::UnknownKey(_))
+        ));
+        assert!(matches!(
+            parse("ships=player,nobody"),
+            Err(OptionsError::UnknownDriver(_))
+        ));
+        assert!(matches!(
+            parse("laps=three"),
+            Err(OptionsError::InvalidLaps(_))
+        ));
+        assert!(matches!(
+            parse("catchup=huge"),
+            Err(OptionsError::UnknownCatchUp(_))
+        ));
+        assert!(matches!(
+            parse("debug=yes"),
+            Err(OptionsError::InvalidDebug(_))
+        ));
+        assert!(matches!(
+            parse("mode=drift"),
+            Err(OptionsError::UnknownMode(_))
+        ));
+        assert!(matches!(
+            parse("seed=abc"),
+            Err(OptionsError::InvalidSeed(_))
+        ));
+        assert!(matches!(
+            parse("tracks=12,map:mine"),
+            Err(OptionsError::UnknownTrack(_))
+        ));
+        assert!(matches!(
+            parse("minimap=middle"),
+            Err(OptionsError::UnknownMinimap(_))
+        ));
+        assert!(matches!(
+            parse("minimaprotation=south"),
+            Err(OptionsError::UnknownMinimapRotation(_))
+        ));
+        assert!(matches!(
+            parse("camera=drone"),
+            Err(OptionsError::UnknownCamera(_))
+        ));
+        assert!(matches!(
+            parse("director=maybe"),
+            Err(OptionsError::InvalidDirector(_))
+        ));
+        assert!(matches!(
+            parse("controls=mouse"),
+            Err(OptionsError::UnknownControls(_))
+        ));
+        assert!(matches!(
+            parse("color=orange"),
+            Err(OptionsError::UnknownColor(_))
+        ));
+    }
+
+    #[test]
+    fn rejects_out_of_range_numbers() {
+        assert!(matches!(parse("laps=0"), Err(OptionsError::InvalidLaps(_))));
+        assert!(matches!(
+            parse("laps=-1"),
+            Err(OptionsError::InvalidLaps(_))
+        ));
+        assert!(matches!(
+            parse("laps=4294967296"),
+            Err(OptionsError::InvalidLaps(_))
+        ));
+        assert!(matches!(
+            parse("rounds=0"),
+            Err(OptionsError::InvalidRounds(_))
+        ));
+        assert!(matches!(
+            parse("minimapsize=0"),
+            Err(OptionsError::InvalidMinimapSize(_))
+        ));
+        assert!(matches!(
+            parse("seed=18446744073709551616"),
+            Err(OptionsError::InvalidSeed(_))
+        ));
+        assert!(matches!(
+            parse("ships=pro,pro,pro,pro,pro,pro"),
+            Err(OptionsError::TooManyShips(6))
+        ));
+        assert!(matches!(
+            parse("ships=player,player,player,player,player"),
+            Err(OptionsError::TooManyPlayers(5))
+        ));
+        assert!(matches!(
+            parse("ships=pro;mode=elimination"),
+            Err(OptionsError::TooFewShips(1))
+        ));
+    }
+}
@@ -0,0 +1,193 @@
+use std::fmt;
+use std::str::FromStr;
+
+use super::transform::Transform2d;
+
+/// Seconds between samples of a ghost's path
+const SAMPLE_INTERVAL: f32 = 0.05;
+const VERSION: u32 = 1;
+/// Ships are always drawn at this scale
+const SHIP_SCALE: f32 = 0.1;
+/// Ghosts are kept in localStorage under this followed by the track seed
+pub const GHOST_STORAGE_PREFIX: &str = "swoop-ghost-";
+
+/// An error to represent a ghost file that couldn't be read
+#[derive(Debug)]
+pub enum GhostError {
+    /// Ghosts start with a `ghost VERSION SEED LAP_TIME` line
+    InvalidHeader(String),
+    UnsupportedVersion(u32),
+    /// Samples are written as `TIME X Y ROTATION`
+    InvalidSample(String),
+    /// Samples have to go forwards in time
+    SamplesOutOfOrder,
+    NoSamples,
+}
+
+/// Where a ship was at a time since the start of its lap
+#[derive(Clone, Debug)]
+pub struct GhostSample {
+    pub time: f32,
+    pub position: Transform2d,
+}
+
+/// A recorded lap that can be raced against. Ghosts only ever follow
+/// their path: they are never handed to `calc_ship_physics`, so nothing
+/// can collide with them.
+///
+/// Ghosts are stored as plain text so they fit in localStorage:
+///     ghost VERSION SEED LAP_TIME
+///     TIME X Y ROTATION      (one line per sample)
+#[derive(Clone, Debug)]
+pub struct Ghost {
+    /// The track the lap was set on
+    pub seed: u64,
+    pub lap_time: f32,
+    pub samples: Vec<GhostSample>,
+}
+
+impl Ghost {
+    /// Where the ghost is at a time into its lap, interpolated between
+    /// samples. Gives None before the lap starts and after it ends.
+    pub fn position_at(&self, time: f32) -> Option<Transform2d> {
+        if time < 0.0 || time > self.lap_time {
+            return None;
+        }
+        let next = self.samples.iter().position(|sample| sample.time >= time);
+        let (before, after) = match next {
+            Some(0) => return self.samples.first().map(|sample| sample.position.clone()),
+            Some(next) => (&self.samples[next - 1], &self.samples[next]),
+            None => return self.samples.last().map(|sample| sample.position.clone()),
+        };
+
+        let fraction = (time - before.time) / (after.time - before.time);
+        let lerp = |a: f32, b: f32| a + (b - a) * fraction;
+        // Rotations aren't wrapped while flying, so they can be
+        // interpolated directly
+        Some(Transform2d::new(
+            lerp(before.position.x, after.position.x),
+            lerp(before.position.y, after.position.y),
+            lerp(before.position.rot, after.position.rot),
+            before.position.scale,
+        ))
+    }
+}
+
+impl FromStr for Ghost {
+    type Err = GhostError;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        let mut lines = text
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'));
+
+        let header = lines.next().ok_or(GhostError::NoSamples)?;
+        let invalid_header = || GhostError::InvalidHeader(header.to_string());
+        let fields: Vec<&str> = header.split_whitespace().collect();
+        if fields.len() != 4 || fields[0] != "ghost" {
+            return Err(invalid_header());
+        }
+        let version: u32 = fields[1].parse().map_err(|_| invalid_header())?;
+        if version != VERSION {
+            return Err(GhostError::UnsupportedVersion(version));
+        }
+        let seed = fields[2].parse().map_err(|_| invalid_header())?;
+        let lap_time = fields[3].parse().map_err(|_| invalid_header())?;
+
+        let mut samples: Vec<GhostSample> = vec![];
+        for line in lines {
+            let values = line
+                .split_whitespace()
+                .map(str::parse::<f32>)
+                .collect::<Result<Vec<_>, _>>()
+                .ok()
+                .filter(|values| values.len() == 4)
+                .ok_or_else(|| GhostError::InvalidSample(line.to_string()))?;
+            if let Some(last) = samples.last() {
+                if values[0] <= last.time {
+                    return Err(GhostError::SamplesOutOfOrder);
+                }
+            }
+            samples.push(GhostSample {
+                time: values[0],
+                position: Transform2d::new(values[1], values[2], values[3], SHIP_SCALE),
+            });
+        }
+
+        if samples.is_empty() {
+            return Err(GhostError::NoSamples);
+        }
+        Ok(Self {
+            seed,
+            lap_time,
+            samples,
+        })
+    }
+}
+
+impl fmt::Display for Ghost {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "ghost {} {} {}", VERSION, self.seed, self.lap_time)?;
+        for sample in self.samples.iter() {
+            writeln!(
+                f,
+                "{} {} {} {}",
+                sample.time, sample.position.x, sample.position.y, sample.position.rot
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Samples a ship's path over a lap
+pub struct GhostRecorder {
+    seed: u64,
+    samples: Vec<GhostSample>,
+}
+
+impl GhostRecorder {
+    pub fn new(seed: u64) -> Self {
+        Self {
+            seed,
+            samples: vec![],
+        }
+    }
+
+    /// Throws away what has been recorded, for when a new lap starts or
+    /// the current one no longer counts
+    pub fn restart(&mut self) {
+        self.samples.clear();
+    }
+
+    /// Offers the ship's position at a time into the lap. Only one sample
+    /// is kept every SAMPLE_INTERVAL.
+    pub fn sample(&mut self, time: f32, position: &Transform2d) {
+        let due = self
+            .samples
+            .last()
+            .is_none_or(|last| time - last.time >= SAMPLE_INTERVAL);
+        if due {
+            self.samples.push(GhostSample {
+                time,
+                position: position.clone(),
+            });
+        }
+    }
+
+    /// Turns the lap so far into a ghost, ending at the given time
+    pub fn finish(&mut self, lap_time: f32, position: &Transform2d) -> Ghost {
+        let mut samples = std::mem::take(&mut self.samples);
+        if samples.last().is_none_or(|last| last.time < lap_time) {
+            samples.push(GhostSample {
+                time: lap_time,
+                position: position.clone(),
+            });
+        }
+        Ghost {
+            seed: self.seed,
+            lap_time,
+            samples,
+        }
+    }
+}
@@ -0,0 +1,245 @@
+use super::ai::{AiDriver, AiProfile};
+use super::controller::{ControllerError, Observation, ShipControl, ShipController};
+use super::map::Map;
+use super::racing_line::RacingLine;
+use super::sensors::SensorFan;
+use super::ship::Ship;
+use super::simulation::{Simulation, TICK};
+use super::transform::Vec2;
+
+/// The wall distance rays in an observation
+pub const SENSORS: SensorFan = SensorFan {
+    rays: 7,
+    spread: std::f32::consts::PI / 2.0,
+    range: 4.0,
+};
+/// How far around the track, in radians, the upcoming bends are sampled
+const BEND_SAMPLES: [f32; 3] = [0.1, 0.2, 0.4];
+/// Number of values in an observation
+pub const OBSERVATION_SIZE: usize = 7 + BEND_SAMPLES.len() + SENSORS.rays;
+
+/// Rough top speed, so velocities in observations are around -1 to 1
+const SPEED_SCALE: f32 = 5.0;
+const ANGULAR_SPEED_SCALE: f32 = 5.0;
+
+/// Reward for finishing, on top of the reward for distance covered
+const FINISH_REWARD: f32 = 10.0;
+
+/// Settings for a training environment
+#[derive(Clone, Debug)]
+pub struct EnvConfig {
+    /// Ships flown by the actions passed to `step`. These take the first
+    /// places on the grid.
+    pub agents: usize,
+    /// AI ships racing against the agents, lined up behind them
+    pub opponents: Vec<AiProfile>,
+    pub laps: u32,
+    /// Simulated time per step
+    pub dt: f32,
+    /// The episode ends after this long even if the race isn't over
+    pub time_limit: f32,
+    /// Taken off the reward every second until an agent finishes, so that
+    /// getting round faster scores more
+    pub time_penalty: f32,
+}
+
+impl EnvConfig {
+    /// One agent racing alone for a lap
+    pub fn new() -> Self {
+        Self {
+            agents: 1,
+            opponents: vec![],
+            laps: 1,
+            dt: TICK,
+            time_limit: 60.0,
+            time_penalty: 1.0,
+        }
+    }
+}
+
+impl Default for EnvConfig {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// What happened in one step, with one observation and reward per agent
+pub struct StepResult {
+    pub observations: Vec<Vec<f32>>,
+    /// Distance gained around the track this step less the time penalty,
+    /// plus a bonus on the step an agent finishes
+    pub rewards: Vec<f32>,
+    /// Set once every agent has finished or the time limit is reached
+    pub done: bool,
+}
+
+/// Agents are flown from outside, so their controller does nothing and its
+/// control gets replaced by the agent's action
+struct External;
+
+impl ShipController for External {
+    fn control(&mut self, _observation: &Observation) -> Result<ShipControl, ControllerError> {
+        Ok(ShipControl::default())
+    }
+}
+
+/// A gym style wrapper around the race simulation for training bots. It
+/// runs headless and as fast as the simulation allows:
+///     let mut env = RaceEnv::new(EnvConfig::new());
+///     let mut observations = env.reset(seed);
+///     loop {
+///         let result = env.step(&actions_for(&observations));
+///         ...
+///     }
+pub struct RaceEnv {
+    config: EnvConfig,
+    simulation: Simulation,
+    /// How far each agent had got at the last step
+    distances: Vec<f32>,
+}
+
+impl RaceEnv {
+    /// Creates the environment with a race on the map for seed 0 ready
+    /// to go
+    pub fn new(config: EnvConfig) -> Self {
+        let simulation = create_simulation(&config, 0);
+        let distances = (0..config.agents)
+            .map(|id| simulation.race.distance(id))
+            .collect();
+        Self {
+            config,
+            simulation,
+            distances,
+        }
+    }
+
+    /// Starts a new race on the map generated from `seed` and returns the
+    /// first observation for every agent
+    pub fn reset(&mut self, seed: u64) -> Vec<Vec<f32>> {
+        self.simulation = create_simulation(&self.config, seed);
+        self.distances = (0..self.config.agents)
+            .map(|id| self.simulation.race.distance(id))
+            .collect();
+        self.observe()
+    }
+
+    /// Moves the race on by one step with one action per agent
+    pub fn step(&mut self, actions: &[ShipControl]) -> StepResult {
+        assert_eq!(actions.len(), self.config.agents, "one action per agent");
+        let dt = self.config.dt;
+
+        let mut controls = self
+            .simulation
+            .calc_controls(dt)
+            .expect("the built in AI doesn't fail");
+        controls[..actions.len()].copy_from_slice(actions);
+
+        let was_finished: Vec<bool> = (0..self.config.agents)
+            .map(|id| self.simulation.race.progress[id].finish_time.is_some())
+            .collect();
+        self.simulation.advance(&controls, dt);
+
+        let mut rewards = Vec::with_capacity(self.config.agents);
+        for (id, was_finished) in was_finished.into_iter().enumerate() {
+            let distance = self.simulation.race.distance(id);
+            let mut reward = distance - self.distances[id];
+            self.distances[id] = distance;
+
+            if !was_finished {
+                reward -= self.config.time_penalty * dt;
+                if self.simulation.race.progress[id].finish_time.is_some() {
+                    reward += FINISH_REWARD;
+                }
+            }
+            rewards.push(reward);
+        }
+
+        let all_finished = (0..self.config.agents)
+            .all(|id| self.simulation.race.progress[id].finish_time.is_some());
+        StepResult {
+            observations: self.observe(),
+            rewards,
+            done: all_finished || self.simulation.race.time >= self.config.time_limit,
+        }
+    }
+
+    /// The race being run, for looking at results or drawing it
+    pub fn simulation(&self) -> &Simulation {
+        &self.simulation
+    }
+
+    fn observe(&self) -> Vec<Vec<f32>> {
+        let simulation = &self.simulation;
+        simulation.ships[..self.config.agents]
+            .iter()
+            .map(|ship| observe_ship(ship, &simulation.map, &simulation.racing_line))
+            .collect()
+    }
+}
+
+fn create_simulation(config: &EnvConfig, seed: u64) -> Simulation {
+    let mut map = Map::new();
+    map.randomize(seed);
+
+    let mut controllers: Vec<Box<dyn ShipController>> = vec![];
+    for _ in 0..config.agents {
+        controllers.push(Box::new(External));
+    }
+    for (id, profile) in config.opponents.iter().enumerate() {
+        controllers.push(Box::new(AiDriver::new(*profile, seed + id as u64)));
+    }
+    Simulation::new(map, controllers, config.laps)
+}
+
+/// Builds the observation vector for the ship a controller is flying.
+/// This is what trained policies see, so changing it means retraining.
+pub fn observe(observation: &Observation) -> Vec<f32> {
+    observe_ship(observation.ship, observation.map, observation.racing_line)
+}
+
+fn observe_ship(ship: &Ship, map: &Map, racing_line: &RacingLine) -> Vec<f32> {
+    let position = (ship.position.x, ship.position.y);
+    let track_point = map.closest_point(position);
+    let wall_distance = map.wall_distance(track_point.angle);
+    let line_offset = racing_line.sample(track_point.angle).offset;
+
+    let facing = (-f32::sin(ship.position.rot), f32::cos(ship.position.rot));
+    let right = (facing.1, -facing.0);
+    let velocity = (ship.velocity.x, ship.velocity.y);
+    let track_direction = map.get_track_direction(track_point.angle);
+    let heading_error = wrap_angle(track_direction - ship.position.rot);
+
+    let mut values = Vec::with_capacity(OBSERVATION_SIZE);
+    values.push(dot(velocity, facing) / SPEED_SCALE);
+    values.push(dot(velocity, right) / SPEED_SCALE);
+    values.push(ship.velocity.rot / ANGULAR_SPEED_SCALE);
+    values.push(ship.boost);
+    values.push(f32::sin(heading_error));
+    values.push(track_point.lateral_offset / wall_distance);
+    values.push((line_offset - track_point.lateral_offset) / wall_distance);
+
+    // How much the track turns between here and a little way ahead.
+    // Positive is a left hand bend.
+    for ahead in BEND_SAMPLES.iter() {
+        let ahead_direction = map.get_track_direction(track_point.angle - ahead);
+        values.push(wrap_angle(ahead_direction - track_direction));
+    }
+
+    for reading in SENSORS.sense(ship, map) {
+        values.push(reading.distance / SENSORS.range);
+    }
+
+    values
+}
+
+fn dot(a: Vec2, b: Vec2) -> f32 {
+    a.0 * b.0 + a.1 * b.1
+}
+
+fn wrap_angle(angle: f32) -> f32 {
+    let angle = angle + std::f32::consts::PI;
+    let sig = f32::signum(angle);
+    let mag = f32::abs(angle) % (2.0 * std::f32::consts::PI);
+
+    sig * (mag - std::f32::consts::PI)
+}
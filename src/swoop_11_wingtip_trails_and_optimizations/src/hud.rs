@@ -0,0 +1,371 @@
+use super::championship::{Championship, Track};
+use super::font::{self, AtlasRegion, GLYPH_HEIGHT, LINE_HEIGHT};
+use super::profile::Profile;
+use super::protocol::LobbyPlayer;
+use super::race::Race;
+use super::timing::{format_delta, format_time};
+use super::viewport::Viewport;
+
+/// Shown in place of a time that isn't known yet
+const NO_TIME: &str = "-:--.---";
+/// Ship speeds are shown multiplied by this, so they read like km/h
+const SPEED_DISPLAY_SCALE: f32 = 100.0;
+/// Distances are shown multiplied by this, so they read as km at the
+/// same scale as the speeds
+const DISTANCE_DISPLAY_SCALE: f32 = SPEED_DISPLAY_SCALE / 3600.0;
+/// How long "Go" stays up once the countdown is over, in seconds
+const GO_TIME: f32 = 1.0;
+
+/// The font is drawn one size larger for every this many CSS pixels of
+/// canvas height
+const SCREEN_HEIGHT_PER_SCALE: f32 = 360.0;
+/// Gap between the HUD and the edge of the screen, in font pixels
+const MARGIN: f32 = 8.0;
+/// Space around text inside its panel, in font pixels
+const PANEL_PADDING: f32 = 3.0;
+const PANEL_COLOR: (f32, f32, f32, f32) = (0.0, 0.0, 0.0, 0.5);
+
+/// Lines of text describing one ship's timing, for showing over the race.
+/// Sectors done this lap are compared with the best for that sector, and
+/// the delta is against the best lap at the same point on the track.
+pub fn timing_lines(race: &Race, ship_id: usize) -> Vec<String> {
+    let progress = &race.progress[ship_id];
+    let timer = &race.timing[ship_id];
+    let mut lines = vec![];
+
+    match progress.finish_time {
+        Some(finish_time) => lines.push(format!("Finished {}", format_time(finish_time))),
+        None => {
+            let lap = i32::min(i32::max(progress.laps + 1, 1), race.laps_to_win as i32);
+            let lap_time = timer
+                .lap_time(race.time)
+                .map_or(NO_TIME.to_string(), format_time);
+            lines.push(format!("Lap {}/{}  {}", lap, race.laps_to_win, lap_time));
+        }
+    }
+
+    let last = timer.last_lap.as_ref().map(|lap| format_time(lap.total));
+    let best = timer.best_lap.as_ref().map(|lap| format_time(lap.total));
+    lines.push(format!(
+        "Last {}  Best {}",
+        last.as_deref().unwrap_or(NO_TIME),
+        best.as_deref().unwrap_or(NO_TIME)
+    ));
+    if progress.finish_time.is_some() {
+        return lines;
+    }
+
+    let sectors: Vec<String> = timer
+        .current_sectors()
+        .iter()
+        .zip(timer.best_sectors.iter())
+        .enumerate()
+        .map(|(sector, (time, best))| {
+            let delta = best.map_or(String::new(), |best| {
+                format!(" {}", format_delta(time - best))
+            });
+            format!("S{} {:.3}{}", sector + 1, time, delta)
+        })
+        .collect();
+    if !sectors.is_empty() {
+        lines.push(sectors.join("  "));
+    }
+
+    if let Some(delta) = timer.delta(progress.lap_progress, race.time) {
+        lines.push(format!("Delta {}", format_delta(delta)));
+    }
+    lines
+}
+
+/// Lines of text for the break between championship rounds: the order
+/// the last round finished in with the points each driver picked up, then
+/// the standings so far
+pub fn championship_lines(championship: &Championship) -> Vec<String> {
+    let mut lines = vec![];
+    let rounds = championship.rounds.len();
+    if let Some(result) = championship.results.last() {
+        lines.push(format!("Round {}/{} results", championship.round(), rounds));
+        for (position, driver) in result.iter().enumerate() {
+            lines.push(format!(
+                "{}. {}  +{}",
+                position + 1,
+                championship.drivers[*driver],
+                championship.points_for(position)
+            ));
+        }
+        lines.push(String::new());
+    }
+
+    lines.push(match (championship.is_finished(), championship.round()) {
+        (true, _) => "Final standings".to_string(),
+        (false, 0) => format!("Championship of {} rounds", rounds),
+        (false, round) => format!("Standings after round {}/{}", round, rounds),
+    });
+    let totals = championship.totals();
+    for (position, driver) in championship.standings().iter().enumerate() {
+        lines.push(format!(
+            "{}. {}  {}",
+            position + 1,
+            championship.drivers[*driver],
+            totals[*driver]
+        ));
+    }
+    lines
+}
+
+/// Lines of text for waiting in an online lobby: the order the last race
+/// finished in, if there was one, then who is in the lobby and whether
+/// they are ready
+pub fn lobby_lines(lobby: &str, players: &[LobbyPlayer], results: &[String]) -> Vec<String> {
+    let mut lines = vec![];
+    if !results.is_empty() {
+        lines.push("Race results".to_string());
+        for (position, driver) in results.iter().enumerate() {
+            lines.push(format!("{}. {}", position + 1, driver));
+        }
+        lines.push(String::new());
+    }
+    lines.push(format!("Lobby {}", lobby));
+    for player in players.iter() {
+        lines.push(match player.ready {
+            true => format!("{}  ready", player.name),
+            false => player.name.clone(),
+        });
+    }
+    lines
+}
+
+/// How one ship got on in a race
+pub struct RaceResult {
+    /// The name of the ship's color
+    pub ship: &'static str,
+    /// Who was flying it: the player's profile name, or the name of what
+    /// flew it for anything else
+    pub driver: String,
+    /// None for ships that were knocked out
+    pub finish_time: Option<f32>,
+}
+
+/// Lines of text for the order the last race finished in, with who flew
+/// each ship and when they finished
+pub fn results_lines(results: &[RaceResult]) -> Vec<String> {
+    let mut lines = vec!["Race results".to_string()];
+    for (position, result) in results.iter().enumerate() {
+        let finish_time = result
+            .finish_time
+            .map_or("eliminated".to_string(), format_time);
+        lines.push(format!(
+            "{}. {} ({})  {}",
+            position + 1,
+            result.ship,
+            result.driver,
+            finish_time
+        ));
+    }
+    lines
+}
+
+/// Lines of text for before a race: the player's best lap on the track,
+/// their fastest races over the same number of laps, then their totals
+/// over every race
+pub fn leaderboard_lines(profile: &Profile, track: &Track, laps: u32) -> Vec<String> {
+    let record = profile.record(track);
+    let mut lines = vec![profile.name.clone()];
+
+    let best_lap = record.and_then(|record| record.best_lap).map(format_time);
+    lines.push(format!(
+        "Best lap {}",
+        best_lap.as_deref().unwrap_or(NO_TIME)
+    ));
+    match record.and_then(|record| record.races.get(&laps)) {
+        Some(times) => {
+            lines.push(format!("Fastest {} lap races", laps));
+            for (place, time) in times.iter().enumerate() {
+                lines.push(format!("{}. {}", place + 1, format_time(*time)));
+            }
+        }
+        None => lines.push(format!("No {} lap races here yet", laps)),
+    }
+
+    let stats = &profile.stats;
+    lines.push(String::new());
+    lines.push(format!("{} races  {} wins", stats.races, stats.wins));
+    lines.push(format!(
+        "{:.1}km flown  {} wall hits",
+        stats.distance * DISTANCE_DISPLAY_SCALE,
+        stats.wall_hits
+    ));
+    lines
+}
+
+/// The speed readout for a ship going at a speed in world units per
+/// second
+pub fn speed_text(speed: f32) -> String {
+    format!("{:.0} km/h", speed * SPEED_DISPLAY_SCALE)
+}
+
+/// The race position readout, such as P2/5
+pub fn position_text(position: usize, ships: usize) -> String {
+    format!("P{}/{}", position + 1, ships)
+}
+
+/// What to show for the start countdown with this long left to go:
+/// whole seconds counting down, then "Go" for a moment after the start
+pub fn countdown_text(countdown: f32) -> Option<String> {
+    if countdown > 0.0 {
+        Some(format!("{}", countdown.ceil()))
+    } else if countdown > -GO_TIME {
+        Some("Go".to_string())
+    } else {
+        None
+    }
+}
+
+/// Where on the screen a block of text goes
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Anchor {
+    TopLeft,
+    /// Lines are lined up on their right hand side
+    TopRight,
+    /// Lines are centered on each other
+    Center,
+}
+
+/// One rectangle of the HUD in screen space. Positions and sizes are in
+/// canvas pixels from the top left corner, whatever the camera is doing.
+#[derive(Clone, Copy, Debug)]
+pub struct HudQuad {
+    pub position: (f32, f32),
+    pub size: (f32, f32),
+    /// The part of the font atlas shown on the quad
+    pub region: AtlasRegion,
+    pub color: (f32, f32, f32, f32),
+}
+
+/// Everything to draw on the HUD in one frame. Text and panels are both
+/// built up as quads on the font atlas so they can be drawn in one go.
+pub struct HudBatch {
+    pub quads: Vec<HudQuad>,
+    pub resolution: (f32, f32),
+    /// The part of the canvas that blocks are placed in
+    pub area: Viewport,
+    /// Canvas pixels to each font pixel at size one. Always a whole
+    /// number so the font stays crisp.
+    pub scale: f32,
+}
+
+impl HudBatch {
+    /// An empty HUD for a canvas of this resolution in device pixels.
+    /// Text keeps the same size on screen whatever the device pixel
+    /// ratio, and gets bigger on bigger screens.
+    pub fn new(resolution: (u32, u32), pixel_ratio: f32) -> Self {
+        let pixel_ratio = if pixel_ratio > 0.0 { pixel_ratio } else { 1.0 };
+        let css_height = resolution.1 as f32 / pixel_ratio;
+        let css_scale = f32::max((css_height / SCREEN_HEIGHT_PER_SCALE).floor(), 1.0);
+        Self {
+            quads: vec![],
+            resolution: (resolution.0 as f32, resolution.1 as f32),
+            area: Viewport::full(resolution),
+            scale: f32::max((css_scale * pixel_ratio).round(), 1.0),
+        }
+    }
+
+    pub fn rect(&mut self, position: (f32, f32), size: (f32, f32), color: (f32, f32, f32, f32)) {
+        self.quads.push(HudQuad {
+            position,
+            size,
+            region: font::solid(),
+            color,
+        });
+    }
+
+    /// Writes one line of text with its top left corner at a position.
+    /// Size multiplies the scale of the font.
+    pub fn text(
+        &mut self,
+        position: (f32, f32),
+        size: f32,
+        color: (f32, f32, f32, f32),
+        text: &str,
+    ) {
+        let pixel = self.scale * size;
+        for (index, c) in text.chars().enumerate() {
+            if let Some(region) = font::glyph(c) {
+                self.quads.push(HudQuad {
+                    position: (
+                        position.0 + index as f32 * font::ADVANCE * pixel,
+                        position.1,
+                    ),
+                    size: (font::GLYPH_WIDTH * pixel, GLYPH_HEIGHT * pixel),
+                    region,
+                    color,
+                });
+            }
+        }
+    }
+
+    /// Writes lines of text, each with its own size, on a panel placed by
+    /// the anchor within the area. An offset moves the whole block down,
+    /// so blocks can be stacked. Gives the offset for the next block to go
+    /// underneath.
+    pub fn block(
+        &mut self,
+        anchor: Anchor,
+        offset: f32,
+        lines: &[(String, f32)],
+        color: (f32, f32, f32, f32),
+    ) -> f32 {
+        if lines.is_empty() {
+            return offset;
+        }
+        let widths: Vec<f32> = lines
+            .iter()
+            .map(|(text, size)| font::text_width(text) * size * self.scale)
+            .collect();
+        let width = widths.iter().copied().fold(0.0, f32::max);
+        let line_heights: Vec<f32> = lines
+            .iter()
+            .map(|(_, size)| LINE_HEIGHT * size * self.scale)
+            .collect();
+        // The gap under the last line isn't part of the block
+        let last_size = lines[lines.len() - 1].1;
+        let height = line_heights.iter().sum::<f32>()
+            - (LINE_HEIGHT - GLYPH_HEIGHT) * last_size * self.scale;
+
+        let margin = MARGIN * self.scale;
+        let padding = PANEL_PADDING * self.scale;
+        let area_left = self.area.left as f32;
+        let area_top = self.area.top as f32;
+        let area_width = self.area.width as f32;
+        let area_height = self.area.height as f32;
+        let left = match anchor {
+            Anchor::TopLeft => area_left + margin + padding,
+            Anchor::TopRight => area_left + area_width - margin - padding - width,
+            Anchor::Center => area_left + ((area_width - width) / 2.0).floor(),
+        };
+        let top = match anchor {
+            Anchor::TopLeft | Anchor::TopRight => area_top + margin + padding + offset,
+            Anchor::Center => area_top + ((area_height - height) / 2.0).floor() + offset,
+        };
+
+        self.rect(
+            (left - padding, top - padding),
+            (width + padding * 2.0, height + padding * 2.0),
+            PANEL_COLOR,
+        );
+        let mut y = top;
+        for (((text, size), line_width), line_height) in
+            lines.iter().zip(widths.iter()).zip(line_heights.iter())
+        {
+            let x = match anchor {
+                Anchor::TopLeft => left,
+                Anchor::TopRight => left + width - line_width,
+                Anchor::Center => left + ((width - line_width) / 2.0).floor(),
+            };
+            self.text((x, y), *size, color, text);
+            y += line_height;
+        }
+        // Leave a padding's gap between this panel and the next
+        offset + height + padding * 3.0
+    }
+}
@@ -0,0 +1,159 @@
+use web_sys::{
+    WebGl2RenderingContext, WebGlBuffer, WebGlProgram, WebGlTexture, WebGlUniformLocation,
+};
+
+use super::hud::HudBatch;
+use super::shader::{init_shader_program, ShaderError};
+use super::texture::{bind_2d_texture_to_uniform, load_texture, TextureUnit};
+
+/// Floats in each vertex: position, texture coordinate and color
+const VERTEX_SIZE: i32 = 8;
+const BYTES_PER_FLOAT: i32 = 4;
+
+/// Draws the HUD in screen space over everything else. The whole batch
+/// of text and panels goes up as one buffer and is drawn in one call.
+pub struct HudSprite {
+    vertex_buffer: WebGlBuffer,
+    program: WebGlProgram,
+    attrib_vertex_positions: u32,
+    attrib_texture_coords: u32,
+    attrib_vertex_colors: u32,
+
+    uniform_screen_size: Option<WebGlUniformLocation>,
+    uniform_font_texture: Option<WebGlUniformLocation>,
+
+    pub font_texture: WebGlTexture,
+}
+
+impl HudSprite {
+    pub fn new(gl: &WebGl2RenderingContext) -> Result<Self, ShaderError> {
+        let vertex_buffer = gl
+            .create_buffer()
+            .ok_or(ShaderError::BufferCreationFailed)?;
+
+        let program = init_shader_program(
+            gl,
+            include_str!("resources/hud.vert"),
+            include_str!("resources/hud.frag"),
+        )?;
+
+        let attrib_vertex_positions = gl.get_attrib_location(&program, "aVertexPosition") as u32;
+        let attrib_texture_coords = gl.get_attrib_location(&program, "aTextureCoord") as u32;
+        let attrib_vertex_colors = gl.get_attrib_location(&program, "aVertexColor") as u32;
+
+        let uniform_screen_size = gl.get_uniform_location(&program, "screen_size");
+        let uniform_font_texture = gl.get_uniform_location(&program, "font_texture");
+
+        let font_texture = load_texture(gl, include_bytes!("resources/font.png")).expect("");
+
+        Ok(Self {
+            vertex_buffer,
+            program,
+            attrib_vertex_positions,
+            attrib_texture_coords,
+            attrib_vertex_colors,
+
+            uniform_screen_size,
+            uniform_font_texture,
+
+            font_texture,
+        })
+    }
+
+    pub fn render(&mut self, gl: &WebGl2RenderingContext, batch: &HudBatch) {
+        if batch.quads.is_empty() {
+            return;
+        }
+        gl.use_program(Some(&self.program));
+        // Panels are see-through rather than glowing, so the HUD blends
+        // normally instead of adding up like the rest of the game
+        gl.blend_func(
+            WebGl2RenderingContext::SRC_ALPHA,
+            WebGl2RenderingContext::ONE_MINUS_SRC_ALPHA,
+        );
+
+        gl.uniform2f(
+            self.uniform_screen_size.as_ref(),
+            batch.resolution.0,
+            batch.resolution.1,
+        );
+        bind_2d_texture_to_uniform(
+            gl,
+            &self.uniform_font_texture,
+            &self.font_texture,
+            TextureUnit::Unit0,
+        );
+
+        // Two triangles per quad
+        let mut vertices: Vec<f32> =
+            Vec::with_capacity(batch.quads.len() * 6 * VERTEX_SIZE as usize);
+        for quad in batch.quads.iter() {
+            let (left, top) = quad.position;
+            let (right, bottom) = (left + quad.size.0, top + quad.size.1);
+            let [u_left, v_top, u_right, v_bottom] = quad.region;
+            let (r, g, b, a) = quad.color;
+            for (x, y, u, v) in [
+                (left, top, u_left, v_top),
+                (right, top, u_right, v_top),
+                (left, bottom, u_left, v_bottom),
+                (right, top, u_right, v_top),
+                (right, bottom, u_right, v_bottom),
+                (left, bottom, u_left, v_bottom),
+            ]
+            .iter()
+            {
+                vertices.extend_from_slice(&[*x, *y, *u, *v, r, g, b, a]);
+            }
+        }
+
+        gl.bind_buffer(
+            WebGl2RenderingContext::ARRAY_BUFFER,
+            Some(&self.vertex_buffer),
+        );
+        gl.buffer_data_with_array_buffer_view(
+            WebGl2RenderingContext::ARRAY_BUFFER,
+            &js_sys::Float32Array::from(vertices.as_slice()),
+            WebGl2RenderingContext::DYNAMIC_DRAW,
+        );
+
+        let stride = VERTEX_SIZE * BYTES_PER_FLOAT;
+        gl.vertex_attrib_pointer_with_i32(
+            self.attrib_vertex_positions,
+            2, // num components
+            WebGl2RenderingContext::FLOAT,
+            false, // normalize
+            stride,
+            0, // offset
+        );
+        gl.enable_vertex_attrib_array(self.attrib_vertex_positions);
+        gl.vertex_attrib_pointer_with_i32(
+            self.attrib_texture_coords,
+            2, // num components
+            WebGl2RenderingContext::FLOAT,
+            false, // normalize
+            stride,
+            2 * BYTES_PER_FLOAT, // offset
+        );
+        gl.enable_vertex_attrib_array(self.attrib_texture_coords);
+        gl.vertex_attrib_pointer_with_i32(
+            self.attrib_vertex_colors,
+            4, // num components
+            WebGl2RenderingContext::FLOAT,
+            false, // normalize
+            stride,
+            4 * BYTES_PER_FLOAT, // offset
+        );
+        gl.enable_vertex_attrib_array(self.attrib_vertex_colors);
+
+        gl.draw_arrays(
+            WebGl2RenderingContext::TRIANGLES,
+            0,                                     // offset
+            (vertices.len() as i32) / VERTEX_SIZE, // vertex count
+        );
+
+        // The other sprites only use the first attribute, so leave the
+        // rest switched off for them
+        gl.disable_vertex_attrib_array(self.attrib_texture_coords);
+        gl.disable_vertex_attrib_array(self.attrib_vertex_colors);
+    }
+}
@@ -0,0 +1,120 @@
+use super::controller::ShipControl;
+use super::game_options::MAX_PLAYERS;
+
+/// Blocks of keys that players can share a keyboard with, by name. Each
+/// gives the key codes for forwards, backwards, turning left, turning
+/// right and boosting.
+pub const KEY_BLOCKS: [(&str, [&str; 5]); MAX_PLAYERS] = [
+    ("wasd", ["KeyW", "KeyS", "KeyA", "KeyD", "Space"]),
+    (
+        "arrows",
+        [
+            "ArrowUp",
+            "ArrowDown",
+            "ArrowLeft",
+            "ArrowRight",
+            "ShiftRight",
+        ],
+    ),
+    ("ijkl", ["KeyI", "KeyK", "KeyJ", "KeyL", "KeyH"]),
+    (
+        "numpad",
+        ["Numpad8", "Numpad5", "Numpad4", "Numpad6", "Numpad0"],
+    ),
+];
+
+/// How far a stick has to be pushed before it counts, so that sticks
+/// which don't quite center don't steer on their own
+const DEAD_ZONE: f32 = 0.15;
+/// How far a trigger or button has to be pressed to count as held
+const PRESSED: f32 = 0.5;
+
+// Buttons and axes in the browser's standard gamepad layout
+const BUTTON_A: usize = 0;
+const BUTTON_RIGHT_BUMPER: usize = 5;
+const BUTTON_LEFT_TRIGGER: usize = 6;
+const BUTTON_RIGHT_TRIGGER: usize = 7;
+const BUTTON_DPAD_UP: usize = 12;
+const BUTTON_DPAD_DOWN: usize = 13;
+const BUTTON_DPAD_LEFT: usize = 14;
+const BUTTON_DPAD_RIGHT: usize = 15;
+const AXIS_LEFT_STICK_X: usize = 0;
+
+/// What a player flies their ship with
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PlayerInput {
+    /// One of the KEY_BLOCKS on the keyboard, by index
+    Keys(usize),
+    /// A gamepad, by its index in the browser counting from zero.
+    /// Written counting from one, as gamepad1 to gamepad4.
+    Gamepad(usize),
+}
+
+impl PlayerInput {
+    pub fn from_name(name: &str) -> Option<Self> {
+        if let Some(index) = name.strip_prefix("gamepad") {
+            return index
+                .parse::<usize>()
+                .ok()
+                .filter(|index| (1..=MAX_PLAYERS).contains(index))
+                .map(|index| PlayerInput::Gamepad(index - 1));
+        }
+        KEY_BLOCKS
+            .iter()
+            .position(|(block, _)| *block == name)
+            .map(PlayerInput::Keys)
+    }
+
+    /// What each player uses unless the options say otherwise: the next
+    /// block of keys along
+    pub fn default_for(player: usize) -> Self {
+        PlayerInput::Keys(player % KEY_BLOCKS.len())
+    }
+}
+
+/// How a gamepad's buttons and sticks were sitting when it was last
+/// read, in the browser's standard layout. Buttons go from 0 to 1 and
+/// axes from -1 to 1.
+#[derive(Clone, Debug, Default)]
+pub struct GamepadState {
+    pub buttons: Vec<f32>,
+    pub axes: Vec<f32>,
+}
+
+impl GamepadState {
+    /// The right trigger thrusts and the left one reverses, the left
+    /// stick or the d-pad steers and A or the right bumper boosts
+    pub fn control(&self) -> ShipControl {
+        let button = |index: usize| self.buttons.get(index).copied().unwrap_or(0.0);
+        let held = |index: usize| button(index) > PRESSED;
+
+        let stick = self.axes.get(AXIS_LEFT_STICK_X).copied().unwrap_or(0.0);
+        // What is left past the dead zone is stretched back out to the
+        // full range, so the ship can still be steered gently
+        let stick = match stick.abs() > DEAD_ZONE {
+            true => stick.signum() * (stick.abs() - DEAD_ZONE) / (1.0 - DEAD_ZONE),
+            false => 0.0,
+        };
+        let mut steering = -stick;
+        if held(BUTTON_DPAD_LEFT) {
+            steering += 1.0;
+        }
+        if held(BUTTON_DPAD_RIGHT) {
+            steering -= 1.0;
+        }
+
+        let mut thrust = button(BUTTON_RIGHT_TRIGGER) - button(BUTTON_LEFT_TRIGGER);
+        if held(BUTTON_DPAD_UP) {
+            thrust += 1.0;
+        }
+        if held(BUTTON_DPAD_DOWN) {
+            thrust -= 1.0;
+        }
+
+        ShipControl {
+            steering: steering.clamp(-1.0, 1.0),
+            thrust: thrust.clamp(-1.0, 1.0),
+            boost: held(BUTTON_A) || held(BUTTON_RIGHT_BUMPER),
+        }
+    }
+}
@@ -1,4 +1,6 @@
-#[derive(Debug)]
+use super::input::KEY_BLOCKS;
+
+#[derive(Clone, Copy, Debug)]
 pub enum KeyState {
     /// Represents that the key has been pressed since the last
     /// call to update()
@@ -33,23 +35,32 @@ impl KeyState {
             KeyState::Up => false,
         }
     }
+
+    /// If the key went down since the last call to update(). Used for
+    /// things that should happen once per press.
+    pub fn just_pressed(&self) -> bool {
+        matches!(self, KeyState::JustPressed)
+    }
 }
 
+/// The keys one player flies with
 #[derive(Debug)]
-pub struct KeyMap {
+pub struct PlayerKeys {
     pub forwards: KeyState,
     pub backwards: KeyState,
     pub turn_left: KeyState,
     pub turn_right: KeyState,
+    pub boost: KeyState,
 }
 
-impl KeyMap {
+impl PlayerKeys {
     pub fn new() -> Self {
         Self {
             forwards: KeyState::Up,
             backwards: KeyState::Up,
             turn_left: KeyState::Up,
             turn_right: KeyState::Up,
+            boost: KeyState::Up,
         }
     }
 
@@ -58,14 +69,110 @@ impl KeyMap {
         self.backwards = self.backwards.update();
         self.turn_left = self.turn_left.update();
         self.turn_right = self.turn_right.update();
+        self.boost = self.boost.update();
+    }
+}
+
+#[derive(Debug)]
+pub struct KeyMap {
+    /// One set of flying keys for each block in KEY_BLOCKS, so players
+    /// can share the keyboard
+    pub players: Vec<PlayerKeys>,
+
+    // Game clock
+    pub pause: KeyState,
+    pub frame_step: KeyState,
+    pub slow_motion: KeyState,
+
+    // Camera
+    pub camera_mode: KeyState,
+
+    // Replays
+    pub watch_replay: KeyState,
+    pub leave_replay: KeyState,
+    pub faster: KeyState,
+    pub slower: KeyState,
+    pub scrub_back: KeyState,
+    pub scrub_forward: KeyState,
+    pub next_camera: KeyState,
+
+    // Championships
+    pub next_round: KeyState,
+}
+
+impl KeyMap {
+    pub fn new() -> Self {
+        Self {
+            players: KEY_BLOCKS.iter().map(|_| PlayerKeys::new()).collect(),
+
+            pause: KeyState::Up,
+            frame_step: KeyState::Up,
+            slow_motion: KeyState::Up,
+
+            camera_mode: KeyState::Up,
+
+            watch_replay: KeyState::Up,
+            leave_replay: KeyState::Up,
+            faster: KeyState::Up,
+            slower: KeyState::Up,
+            scrub_back: KeyState::Up,
+            scrub_forward: KeyState::Up,
+            next_camera: KeyState::Up,
+
+            next_round: KeyState::Up,
+        }
+    }
+
+    pub fn update(&mut self) {
+        for player in self.players.iter_mut() {
+            player.update();
+        }
+
+        self.pause = self.pause.update();
+        self.frame_step = self.frame_step.update();
+        self.slow_motion = self.slow_motion.update();
+
+        self.camera_mode = self.camera_mode.update();
+
+        self.watch_replay = self.watch_replay.update();
+        self.leave_replay = self.leave_replay.update();
+        self.faster = self.faster.update();
+        self.slower = self.slower.update();
+        self.scrub_back = self.scrub_back.update();
+        self.scrub_forward = self.scrub_forward.update();
+        self.next_camera = self.next_camera.update();
+
+        self.next_round = self.next_round.update();
     }
 
     pub fn set_state_from_str(&mut self, code: &str, new_state: KeyState) {
+        // Flying keys can do other things too, such as the arrows moving
+        // through replays
+        for (player, (_, codes)) in self.players.iter_mut().zip(KEY_BLOCKS.iter()) {
+            let key = match codes.iter().position(|key| *key == code) {
+                Some(0) => &mut player.forwards,
+                Some(1) => &mut player.backwards,
+                Some(2) => &mut player.turn_left,
+                Some(3) => &mut player.turn_right,
+                Some(4) => &mut player.boost,
+                _ => continue,
+            };
+            *key = new_state;
+        }
+
         match code {
-            "KeyW" => self.forwards = new_state,
-            "KeyS" => self.backwards = new_state,
-            "KeyA" => self.turn_left = new_state,
-            "KeyD" => self.turn_right = new_state,
+            "KeyR" => self.watch_replay = new_state,
+            "Escape" => self.leave_replay = new_state,
+            "KeyP" => self.pause = new_state,
+            "Period" => self.frame_step = new_state,
+            "KeyM" => self.slow_motion = new_state,
+            "ArrowUp" => self.faster = new_state,
+            "ArrowDown" => self.slower = new_state,
+            "ArrowLeft" => self.scrub_back = new_state,
+            "ArrowRight" => self.scrub_forward = new_state,
+            "KeyC" => self.next_camera = new_state,
+            "KeyV" => self.camera_mode = new_state,
+            "Enter" => self.next_round = new_state,
             _ => (),
         }
     }
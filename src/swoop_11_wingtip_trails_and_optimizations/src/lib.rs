@@ -4,22 +4,57 @@ use std::rc::Rc;
 
 use wasm_bindgen::prelude::{wasm_bindgen, Closure};
 use wasm_bindgen::JsCast;
-use web_sys::{window, Event, HtmlCanvasElement, KeyboardEvent, MouseEvent};
+use web_sys::{window, Event, HtmlCanvasElement, KeyboardEvent, MouseEvent, WheelEvent};
 
-mod ai;
+pub mod ai;
 mod app;
-mod camera;
+pub mod camera;
+pub mod catch_up;
+pub mod championship;
+pub mod clock;
+pub mod controller;
+pub mod director;
+pub mod elimination;
 mod engine_trail;
 mod engine_trail_sprite;
+pub mod entity;
+pub mod font;
+pub mod game_options;
+pub mod ghost;
+pub mod gym;
+pub mod hud;
+mod hud_sprite;
+pub mod input;
 mod keymap;
-mod map;
+mod line_sprite;
+pub mod map;
 mod map_sprite;
-mod physics;
+pub mod minimap;
+mod minimap_sprite;
+mod net;
+pub mod physics;
+pub mod policy;
+pub mod profile;
+pub mod protocol;
+pub mod race;
+pub mod racing_line;
+pub mod replay;
+pub mod rng;
+pub mod rollback;
+pub mod script_controller;
+pub mod sensors;
+pub mod server;
 mod shader;
-mod ship;
+pub mod ship;
 mod ship_sprite;
+pub mod simulation;
+pub mod storage;
 mod texture;
-mod transform;
+pub mod timing;
+pub mod transform;
+pub mod transport;
+pub mod viewport;
+pub mod websocket;
 
 // Pull in the console.log function so we can debug things more easily
 #[wasm_bindgen]
@@ -116,6 +151,17 @@ impl Core {
                 .unwrap();
 
             callback.forget();
+
+            // The wheel zooms the free camera rather than scrolling the page
+            let anim_app = self.app.clone();
+            let wheel_callback = Closure::wrap(Box::new(move |event: WheelEvent| {
+                event.prevent_default();
+                anim_app.borrow_mut().wheel_event(event);
+            }) as Box<dyn FnMut(_)>);
+            self.canvas
+                .add_event_listener_with_callback("wheel", wheel_callback.as_ref().unchecked_ref())
+                .unwrap();
+            wheel_callback.forget();
         }
 
         {
@@ -154,6 +200,63 @@ impl Core {
             keydown_callback.forget();
             keyup_callback.forget();
         }
+
+        {
+            // Page visibility, so the game doesn't run on while hidden
+            let anim_app = self.app.clone();
+            let document = window.document().unwrap();
+            let callback_document = document.clone();
+
+            let callback = Closure::wrap(Box::new(move || {
+                anim_app
+                    .borrow_mut()
+                    .visibility_changed(callback_document.hidden());
+            }) as Box<dyn FnMut()>);
+
+            document
+                .add_event_listener_with_callback(
+                    "visibilitychange",
+                    callback.as_ref().unchecked_ref(),
+                )
+                .unwrap();
+            callback.forget();
+        }
+    }
+
+    /// Saves the last finished race as a replay file
+    #[wasm_bindgen]
+    pub fn download_replay(&self) {
+        self.app.borrow().download_replay();
+    }
+
+    /// Starts watching a replay, such as one picked with a file input
+    #[wasm_bindgen]
+    pub fn load_replay(&self, bytes: &[u8]) {
+        self.app.borrow_mut().load_replay(bytes);
+    }
+
+    /// Saves the ghost lap for the current track
+    #[wasm_bindgen]
+    pub fn export_ghost(&self) {
+        self.app.borrow().export_ghost();
+    }
+
+    /// Stores a ghost lap read from a file
+    #[wasm_bindgen]
+    pub fn import_ghost(&self, text: String) {
+        self.app.borrow_mut().import_ghost(&text);
+    }
+
+    /// Saves the championship so far, so it can be carried on later
+    #[wasm_bindgen]
+    pub fn export_championship(&self) {
+        self.app.borrow().export_championship();
+    }
+
+    /// Carries on a championship read from a file
+    #[wasm_bindgen]
+    pub fn import_championship(&self, text: String) {
+        self.app.borrow_mut().import_championship(&text);
     }
 }
 
@@ -0,0 +1,120 @@
+use web_sys::{WebGl2RenderingContext, WebGlBuffer, WebGlProgram, WebGlUniformLocation};
+
+use super::shader::{init_shader_program, ShaderError};
+use super::transform::Vec2;
+
+/// Draws straight lines between points in world space. Used for debug
+/// overlays, so the lines are re-uploaded every time they are drawn.
+pub struct LineSprite {
+    position_buffer: WebGlBuffer,
+    program: WebGlProgram,
+    attrib_vertex_positions: u32,
+
+    uniform_world_to_camera: Option<WebGlUniformLocation>,
+    uniform_camera_to_clipspace: Option<WebGlUniformLocation>,
+    uniform_line_color: Option<WebGlUniformLocation>,
+
+    pub world_to_camera: [f32; 9],
+    pub camera_to_clipspace: [f32; 9],
+}
+
+impl LineSprite {
+    pub fn new(gl: &WebGl2RenderingContext) -> Result<Self, ShaderError> {
+        let position_buffer = gl
+            .create_buffer()
+            .ok_or(ShaderError::BufferCreationFailed)?;
+
+        let program = init_shader_program(
+            gl,
+            include_str!("resources/line.vert"),
+            include_str!("resources/line.frag"),
+        )?;
+
+        let attrib_vertex_positions = gl.get_attrib_location(&program, "aVertexPosition") as u32;
+
+        let uniform_world_to_camera = gl.get_uniform_location(&program, "world_to_camera");
+        let uniform_camera_to_clipspace = gl.get_uniform_location(&program, "camera_to_clipspace");
+        let uniform_line_color = gl.get_uniform_location(&program, "line_color");
+
+        Ok(Self {
+            position_buffer,
+            program,
+            attrib_vertex_positions,
+
+            uniform_world_to_camera,
+            uniform_camera_to_clipspace,
+            uniform_line_color,
+
+            world_to_camera: [0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0],
+            camera_to_clipspace: [0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0],
+        })
+    }
+
+    pub fn setup(&mut self, gl: &WebGl2RenderingContext) {
+        gl.use_program(Some(&self.program));
+        gl.blend_func(WebGl2RenderingContext::ONE, WebGl2RenderingContext::ONE);
+
+        gl.uniform_matrix3fv_with_f32_array(
+            self.uniform_world_to_camera.as_ref(),
+            true,
+            &self.world_to_camera,
+        );
+        gl.uniform_matrix3fv_with_f32_array(
+            self.uniform_camera_to_clipspace.as_ref(),
+            true,
+            &self.camera_to_clipspace,
+        );
+
+        gl.bind_buffer(
+            WebGl2RenderingContext::ARRAY_BUFFER,
+            Some(&self.position_buffer),
+        );
+
+        gl.vertex_attrib_pointer_with_i32(
+            self.attrib_vertex_positions,
+            2, // num components
+            WebGl2RenderingContext::FLOAT,
+            false, // normalize
+            0,     // stride
+            0,     // offset
+        );
+        gl.enable_vertex_attrib_array(self.attrib_vertex_positions);
+    }
+
+    /// Draws a line for each pair of start and end points
+    pub fn render(
+        &mut self,
+        gl: &WebGl2RenderingContext,
+        lines: &[(Vec2, Vec2)],
+        color: (f32, f32, f32, f32),
+    ) {
+        let vertices: Vec<f32> = lines
+            .iter()
+            .flat_map(|(start, end)| vec![start.0, start.1, end.0, end.1])
+            .collect();
+
+        gl.bind_buffer(
+            WebGl2RenderingContext::ARRAY_BUFFER,
+            Some(&self.position_buffer),
+        );
+        gl.buffer_data_with_array_buffer_view(
+            WebGl2RenderingContext::ARRAY_BUFFER,
+            &js_sys::Float32Array::from(vertices.as_slice()),
+            WebGl2RenderingContext::DYNAMIC_DRAW,
+        );
+
+        gl.uniform4f(
+            self.uniform_line_color.as_ref(),
+            color.0,
+            color.1,
+            color.2,
+            color.3,
+        );
+
+        gl.draw_arrays(
+            WebGl2RenderingContext::LINES,
+            0,                      // offset
+            lines.len() as i32 * 2, // vertex count
+        );
+    }
+}
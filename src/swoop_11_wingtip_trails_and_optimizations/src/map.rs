@@ -1,24 +1,88 @@
-use wasm_bindgen::prelude::wasm_bindgen;
-
-#[wasm_bindgen]
-extern "C" {
-    #[wasm_bindgen(js_namespace = console)]
-    fn log(s: &str);
-    #[wasm_bindgen(js_namespace = Math)]
-    fn random() -> f32;
-}
+use std::fmt;
+use std::str::FromStr;
 
+use super::rng::Rng;
 use super::transform::{length, normalize, PolarCoordinate, Vec2};
 // TODO: rewrite map to be easily portable
 
+/// The narrowest the track is allowed to get anywhere along the lap
+pub const MIN_TRACK_WIDTH: f32 = 0.4;
+/// How wide the track gets on a dead straight
+const MAX_TRACK_WIDTH: f32 = 1.0;
+/// Curvature at which the track is squeezed down to its narrowest
+const TIGHT_CORNER_CURVATURE: f32 = 0.5;
+/// Number of points along the lap used to fit and validate the width
+const WIDTH_SAMPLES: usize = 64;
+
+/// An error to represent a map that can't be raced on
+#[derive(Debug)]
+pub enum MapError {
+    /// The track is narrower than MIN_TRACK_WIDTH at this angle
+    TrackTooNarrow { angle: f32, width: f32 },
+    /// Saved tracks are written as `track` followed by the eight sine
+    /// constants, the eight cosine constants and the base radius
+    InvalidTrack(String),
+    /// One of the constants is NaN or infinite
+    NotFinite,
+}
+
+/// Sphere tracing steps a little less than the distance field says is
+/// safe, because the field is only exact when measured radially
+const RAY_STEP_SCALE: f32 = 0.8;
+/// How close a ray has to get to a wall to count as touching it
+const RAY_HIT_DISTANCE: f32 = 0.001;
+/// Gives up on rays that creep along a wall without ever touching it
+const RAY_MAX_STEPS: usize = 512;
+
+/// Where a ray met a wall
+#[derive(Clone, Copy, Debug)]
+pub struct RayHit {
+    /// Distance from the start of the ray to the wall
+    pub distance: f32,
+    pub position: Vec2,
+    /// Unit vector out of the wall, pointing back onto the track
+    pub normal: Vec2,
+}
+
+/// The point on the centerline nearest to some position
+#[derive(Debug)]
+pub struct TrackPoint {
+    /// Polar angle of the point on the centerline
+    pub angle: f32,
+    /// The point on the centerline
+    pub position: Vec2,
+    /// Unit vector along the track in the direction of racing
+    pub direction: Vec2,
+    /// Signed distance from the centerline. Positive is to the left when
+    /// facing the direction of racing.
+    pub lateral_offset: f32,
+}
+
+#[derive(Clone, Debug)]
 pub struct Map {
     pub sin_consts: [f32; 8],
     pub cos_consts: [f32; 8],
     pub track_base_radius: f32,
-    pub track_width: f32,
+
+    /// The width is a second fourier series over the same angle as the
+    /// radius, but with only the first four harmonics so it varies slowly
+    pub width_sin_consts: [f32; 4],
+    pub width_cos_consts: [f32; 4],
+    pub track_base_width: f32,
 }
 
 impl Map {
+    pub fn new() -> Self {
+        Self {
+            sin_consts: [2.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0],
+            cos_consts: [0.0, -2.0, 0.0, 1.0, 0.0, 0.0, 0.5, 0.0],
+            track_base_radius: 8.0,
+            width_sin_consts: [0.0, 0.0, 0.0, 0.0],
+            width_cos_consts: [0.0, 0.0, 0.0, 0.0],
+            track_base_width: 0.7,
+        }
+    }
+
     pub fn track_radius(&self, angle: f32) -> f32 {
         let mut track_radius = self.track_base_radius;
         for i in 0..8 {
@@ -29,14 +93,61 @@ impl Map {
         track_radius
     }
 
+    /// Returns the radius of the track along with its first and second
+    /// derivatives with respect to the angle
+    pub fn track_radius_derivatives(&self, angle: f32) -> (f32, f32, f32) {
+        let mut radius = self.track_base_radius;
+        let mut d_radius = 0.0;
+        let mut dd_radius = 0.0;
+        for i in 0..8 {
+            let omega = (i + 1) as f32;
+            let s = f32::sin(angle * omega);
+            let c = f32::cos(angle * omega);
+            radius += s * self.sin_consts[i] + c * self.cos_consts[i];
+            d_radius += omega * (c * self.sin_consts[i] - s * self.cos_consts[i]);
+            dd_radius -= omega * omega * (s * self.sin_consts[i] + c * self.cos_consts[i]);
+        }
+        (radius, d_radius, dd_radius)
+    }
+
+    /// How sharply the centerline bends at a particular angle. This is
+    /// one over the radius of the turn.
+    pub fn track_curvature(&self, angle: f32) -> f32 {
+        let (r, dr, ddr) = self.track_radius_derivatives(angle);
+        let numerator = f32::abs(r * r + 2.0 * dr * dr - r * ddr);
+        let denominator = f32::powf(r * r + dr * dr, 1.5);
+        numerator / denominator
+    }
+
+    /// The half-width of the track (centerline to wall) at a particular angle
+    pub fn track_width(&self, angle: f32) -> f32 {
+        let mut track_width = self.track_base_width;
+        for i in 0..4 {
+            let omega = (i + 1) as f32;
+            track_width += f32::sin(angle * omega) * self.width_sin_consts[i];
+            track_width += f32::cos(angle * omega) * self.width_cos_consts[i];
+        }
+        track_width
+    }
+
+    /// How far the walls are from the centerline, measured perpendicular
+    /// to the track. The distance field measures the width radially, so
+    /// where the track isn't running around the origin the walls are
+    /// closer than track_width.
+    pub fn wall_distance(&self, angle: f32) -> f32 {
+        let (r, dr, _) = self.track_radius_derivatives(angle);
+        self.track_width(angle) * r / f32::sqrt(r * r + dr * dr)
+    }
+
     pub fn distance_field(&self, position: Vec2) -> f32 {
         let course = length(&position);
         let angle = position.1.atan2(position.0);
 
         let track_radius = self.track_radius(angle);
+        let track_width = self.track_width(angle);
 
         let mut track_sdf = course - track_radius;
-        track_sdf = f32::abs(track_sdf) - self.track_width;
+        track_sdf = f32::abs(track_sdf) - track_width;
         track_sdf
     }
 
@@ -55,6 +166,36 @@ impl Map {
         normalize((dx, dy))
     }
 
+    /// Finds the first wall along a ray by sphere tracing the distance
+    /// field. `direction` has to be a unit vector. Rays starting off the
+    /// track hit straight away, and rays that don't reach a wall within
+    /// `max_distance` return None.
+    pub fn raycast(&self, origin: Vec2, direction: Vec2, max_distance: f32) -> Option<RayHit> {
+        let mut distance = 0.0;
+        for _ in 0..RAY_MAX_STEPS {
+            let position = (
+                origin.0 + direction.0 * distance,
+                origin.1 + direction.1 * distance,
+            );
+            // The field is negative on the track
+            let clearance = -self.distance_field(position);
+            if clearance < RAY_HIT_DISTANCE {
+                let normal = self.calc_normal(position);
+                return Some(RayHit {
+                    distance,
+                    position,
+                    normal: (-normal.0, -normal.1),
+                });
+            }
+
+            distance += clearance * RAY_STEP_SCALE;
+            if distance > max_distance {
+                return None;
+            }
+        }
+        None
+    }
+
     pub fn get_start_position(&self) -> PolarCoordinate {
         const ANGLE: f32 = std::f32::consts::PI / 2.0;
         PolarCoordinate {
@@ -64,43 +205,745 @@ impl Map {
     }
 
     /// Returns the angle pointing along the track at a particular
-    /// polar/angular coordinate along the track
+    /// polar/angular coordinate along the track. This is in the same
+    /// convention as a ship's rotation, so a ship with this rotation is
+    /// facing the direction of racing.
     pub fn get_track_direction(&self, angle: f32) -> f32 {
-        const DELTA_ANGLE: f32 = 0.01;
-        let radius_here = self.track_radius(angle);
-        let radius_a_bit_further = self.track_radius(angle + DELTA_ANGLE);
-        let delta_radius = radius_a_bit_further - radius_here;
-
-        // Use cosine rule to find the length of the line joining the
-        // two radius' (chord)
-        let joining_side_length = cosine_rule(radius_here, radius_a_bit_further, DELTA_ANGLE);
-
-        // Use sin rule to find the angle of the chord and radius_here
-        let ratio = radius_here / joining_side_length * f32::sin(DELTA_ANGLE);
-        let ratio = f32::max(f32::min(ratio, 1.0), -1.0); // Floating point precision
-        let extra_angle = f32::asin(ratio);
-
-        if delta_radius.is_sign_negative() {
-            angle - extra_angle - std::f32::consts::PI / 2.0
-        } else {
-            angle + extra_angle + std::f32::consts::PI / 2.0
+        let direction = self.centerline_direction(angle);
+        f32::atan2(-direction.0, direction.1)
+    }
+
+    /// The point on the centerline of the track at a polar angle
+    pub fn centerline_position(&self, angle: f32) -> Vec2 {
+        let radius = self.track_radius(angle);
+        (f32::cos(angle) * radius, f32::sin(angle) * radius)
+    }
+
+    /// Unit vector along the centerline in the direction of racing. Ships
+    /// race clockwise, so this is the negative of the derivative of the
+    /// centerline with respect to the angle.
+    pub fn centerline_direction(&self, angle: f32) -> Vec2 {
+        let (radius, d_radius, _) = self.track_radius_derivatives(angle);
+        let c = f32::cos(angle);
+        let s = f32::sin(angle);
+        normalize((radius * s - d_radius * c, -d_radius * s - radius * c))
+    }
+
+    /// Finds the point on the centerline closest to a position. A coarse
+    /// search around the whole lap finds the right stretch of track, and
+    /// then Newton's method on the analytic derivatives refines it.
+    pub fn closest_point(&self, position: Vec2) -> TrackPoint {
+        const COARSE_SAMPLES: usize = 64;
+        const NEWTON_STEPS: usize = 5;
+        let coarse_step = std::f32::consts::PI * 2.0 / COARSE_SAMPLES as f32;
+
+        let distance_squared = |angle: f32| {
+            let point = self.centerline_position(angle);
+            let delta = (point.0 - position.0, point.1 - position.1);
+            delta.0 * delta.0 + delta.1 * delta.1
+        };
+
+        let mut angle = PolarCoordinate::from_cartesian(position).angle;
+        let mut best = distance_squared(angle);
+        for sample in 0..COARSE_SAMPLES {
+            let sample_angle = sample as f32 * coarse_step;
+            let dist = distance_squared(sample_angle);
+            if dist < best {
+                best = dist;
+                angle = sample_angle;
+            }
+        }
+
+        for _ in 0..NEWTON_STEPS {
+            // Minimize |P - q|^2, so find the root of (P - q).P'
+            let (r, dr, ddr) = self.track_radius_derivatives(angle);
+            let c = f32::cos(angle);
+            let s = f32::sin(angle);
+            let delta = (r * c - position.0, r * s - position.1);
+            let d_point = (dr * c - r * s, dr * s + r * c);
+            let dd_point = ((ddr - r) * c - 2.0 * dr * s, (ddr - r) * s + 2.0 * dr * c);
+
+            let slope = dot(delta, d_point);
+            let slope_derivative = dot(d_point, d_point) + dot(delta, dd_point);
+            if slope_derivative <= 0.0 {
+                break;
+            }
+            let step = slope / slope_derivative;
+            angle -= f32::max(f32::min(step, coarse_step), -coarse_step);
+        }
+
+        let centerline = self.centerline_position(angle);
+        let direction = self.centerline_direction(angle);
+        let left = (-direction.1, direction.0);
+        let delta = (position.0 - centerline.0, position.1 - centerline.1);
+
+        TrackPoint {
+            angle,
+            position: centerline,
+            direction,
+            lateral_offset: dot(delta, left),
+        }
+    }
+
+    /// Length of the centerline between two angles. Integrated using
+    /// Simpson's rule on the analytic derivative.
+    pub fn arc_length(&self, from_angle: f32, to_angle: f32) -> f32 {
+        const INTERVALS_PER_LAP: f32 = 256.0;
+        let span = to_angle - from_angle;
+        let intervals =
+            f32::ceil(f32::abs(span) / (std::f32::consts::PI * 2.0) * INTERVALS_PER_LAP) as usize;
+        let intervals = usize::max(intervals + intervals % 2, 2);
+        let step = span / intervals as f32;
+
+        let speed = |angle: f32| {
+            let (r, dr, _) = self.track_radius_derivatives(angle);
+            f32::sqrt(r * r + dr * dr)
+        };
+
+        let mut total = speed(from_angle) + speed(to_angle);
+        for i in 1..intervals {
+            let weight = if i % 2 == 1 { 4.0 } else { 2.0 };
+            total += weight * speed(from_angle + step * i as f32);
         }
+        f32::abs(total * step / 3.0)
     }
 
-    /// Change the sin and cosine constants to change the map course
-    pub fn randomize(&mut self) {
+    /// The radius of a circle around the origin that the whole track,
+    /// walls included, fits inside
+    pub fn bounding_radius(&self) -> f32 {
+        (0..WIDTH_SAMPLES)
+            .map(sample_angle)
+            .map(|angle| self.track_radius(angle) + self.track_width(angle))
+            .fold(0.0, f32::max)
+    }
+
+    /// Length of the centerline for a whole lap
+    pub fn lap_length(&self) -> f32 {
+        self.arc_length(0.0, std::f32::consts::PI * 2.0)
+    }
+
+    /// How far along the centerline a position is from the start line,
+    /// measured in the direction of racing. Goes from zero up to lap_length.
+    pub fn track_progress(&self, position: Vec2) -> f32 {
+        let start_angle = self.get_start_position().angle;
+        let closest = self.closest_point(position);
+        let swept = (start_angle - closest.angle).rem_euclid(std::f32::consts::PI * 2.0);
+        self.arc_length(start_angle - swept, start_angle)
+    }
+
+    /// The curvature of the centerline nearest to a position
+    pub fn curvature_at(&self, position: Vec2) -> f32 {
+        self.track_curvature(self.closest_point(position).angle)
+    }
+
+    /// Change the sin and cosine constants to change the map course. The
+    /// same seed always generates the same course.
+    pub fn randomize(&mut self, seed: u64) {
         const WAVINESS: f32 = 3.0;
+        let mut rng = Rng::new(seed);
         for i in 0..8 {
-            let rand1 = (random() - 0.5) * 2.0;
-            let rand2 = (random() - 0.5) * 2.0;
+            let rand1 = (rng.next_f32() - 0.5) * 2.0;
+            let rand2 = (rng.next_f32() - 0.5) * 2.0;
             let amplitude = WAVINESS / f32::powf((i + 1) as f32, 1.3);
 
             self.sin_consts[i] = rand1 * amplitude;
             self.cos_consts[i] = rand2 * amplitude;
         }
+        self.fit_width_to_curvature();
+    }
+
+    /// Sets the width constants so the track is wide on the straights and
+    /// narrow through the corners. The ideal width is sampled around the
+    /// lap and projected onto the width fourier series.
+    fn fit_width_to_curvature(&mut self) {
+        let mut base_width = 0.0;
+        let mut sin_consts = [0.0; 4];
+        let mut cos_consts = [0.0; 4];
+
+        for sample in 0..WIDTH_SAMPLES {
+            let angle = sample_angle(sample);
+            let tightness = f32::min(self.track_curvature(angle) / TIGHT_CORNER_CURVATURE, 1.0);
+            let ideal_width = MAX_TRACK_WIDTH - (MAX_TRACK_WIDTH - MIN_TRACK_WIDTH) * tightness;
+
+            base_width += ideal_width / WIDTH_SAMPLES as f32;
+            for i in 0..4 {
+                let omega = (i + 1) as f32;
+                let weight = 2.0 / WIDTH_SAMPLES as f32;
+                sin_consts[i] += ideal_width * f32::sin(angle * omega) * weight;
+                cos_consts[i] += ideal_width * f32::cos(angle * omega) * weight;
+            }
+        }
+
+        self.track_base_width = base_width;
+        self.width_sin_consts = sin_consts;
+        self.width_cos_consts = cos_consts;
+
+        // Truncating the series can ring below the floor, so push the
+        // whole track out until the narrowest point is wide enough.
+        let narrowest = (0..WIDTH_SAMPLES)
+            .map(|sample| self.track_width(sample_angle(sample)))
+            .fold(f32::INFINITY, f32::min);
+        if narrowest < MIN_TRACK_WIDTH {
+            self.track_base_width += MIN_TRACK_WIDTH - narrowest;
+        }
+    }
+
+    /// Checks that the map is raceable
+    pub fn validate(&self) -> Result<(), MapError> {
+        let bases = [self.track_base_radius, self.track_base_width];
+        let constants = self
+            .sin_consts
+            .iter()
+            .chain(self.cos_consts.iter())
+            .chain(self.width_sin_consts.iter())
+            .chain(self.width_cos_consts.iter())
+            .chain(bases.iter());
+        if !constants.copied().all(f32::is_finite) {
+            return Err(MapError::NotFinite);
+        }
+        for sample in 0..WIDTH_SAMPLES {
+            let angle = sample_angle(sample);
+            let width = self.track_width(angle);
+            if width < MIN_TRACK_WIDTH - 0.001 {
+                return Err(MapError::TrackTooNarrow { angle, width });
+            }
+        }
+        Ok(())
     }
 }
 
-pub fn cosine_rule(a: f32, b: f32, angle: f32) -> f32 {
-    f32::sqrt(a * a + b * b - 2.0 * a * b * f32::cos(angle))
+impl FromStr for Map {
+    type Err = MapError;
+
+    /// Reads a track saved with the `Display` impl. Only the course is
+    /// saved, the width is fitted to it again the same as a random track.
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        let invalid_track = || MapError::InvalidTrack(text.trim().to_string());
+        let mut fields = text.split_whitespace();
+        if fields.next() != Some("track") {
+            return Err(invalid_track());
+        }
+        let values = fields
+            .map(str::parse::<f32>)
+            .collect::<Result<Vec<_>, _>>()
+            .ok()
+            .filter(|values| values.len() == 17 && values.iter().all(|value| value.is_finite()))
+            .ok_or_else(invalid_track)?;
+
+        let mut map = Self::new();
+        map.sin_consts.copy_from_slice(&values[0..8]);
+        map.cos_consts.copy_from_slice(&values[8..16]);
+        map.track_base_radius = values[16];
+        map.fit_width_to_curvature();
+        map.validate()?;
+        Ok(map)
+    }
+}
+
+impl Default for Map {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Saves the course on a single line, so it can be put in a file or
+/// alongside other settings
+impl fmt::Display for Map {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "track")?;
+        for value in self.sin_consts.iter().chain(self.cos_consts.iter()) {
+            write!(f, " {}", value)?;
+        }
+        write!(f, " {}", self.track_base_radius)
+    }
+}
+
+fn sample_angle(sample: usize) -> f32 {
+    sample as f32 / WIDTH_SAMPLES as f32 * std::f32::consts::PI * 2.0
+}
+
+fn dot(a: Vec2, b: Vec2) -> f32 {
+    a.0 * b.0 + a.1 * b.1
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::f64::consts::PI;
+
+    const SEEDS: [u64; 4] = [1, 7, 42, 1234];
+    /// Points per lap for the brute force versions
+    const DENSE_SAMPLES: usize = 1 << 16;
+
+    /// A winding track with constants of its own for each seed, so the
+    /// tests don't depend on how tracks are generated
+    fn seeded_map(seed: u64) -> Map {
+        let mut state = seed;
+        let mut next = || {
+            state = state
+                .wrapping_mul(6_364_136_223_846_793_005)
+                .wrapping_add(1_442_695_040_888_963_407);
+            (state >> 40) as f32 / (1 << 24) as f32 * 2.0 - 1.0
+        };
+        let mut sin_consts = [0.0; 8];
+        let mut cos_consts = [0.0; 8];
+        for i in 0..8 {
+            let amplitude = 3.0 / f32::powf((i + 1) as f32, 1.3);
+            sin_consts[i] = next() * amplitude;
+            cos_consts[i] = next() * amplitude;
+        }
+        let mut map = Map {
+            sin_consts,
+            cos_consts,
+            track_base_radius: 8.0,
+            width_sin_consts: [0.0; 4],
+            width_cos_consts: [0.0; 4],
+            track_base_width: 0.0,
+        };
+        map.fit_width_to_curvature();
+        map
+    }
+
+    /// The centerline straight from the fourier series, in double precision
+    fn centerline(map: &Map, angle: f64) -> (f64, f64) {
+        let mut radius = map.track_base_radius as f64;
+        for i in 0..8 {
+            let omega = (i + 1) as f64;
+            radius += f64::sin(angle * omega) * map.sin_consts[i] as f64;
+            radius += f64::cos(angle * omega) * map.cos_consts[i] as f64;
+        }
+        (f64::cos(angle) * radius, f64::sin(angle) * radius)
+    }
+
+    fn distance(a: (f64, f64), b: (f64, f64)) -> f64 {
+        f64::hypot(a.0 - b.0, a.1 - b.1)
+    }
+
+    /// Length of the polyline through densely sampled centerline points
+    fn dense_arc_length(map: &Map, from_angle: f64, to_angle: f64) -> f64 {
+        let steps = usize::max(
+            (f64::abs(to_angle - from_angle) / (PI * 2.0) * DENSE_SAMPLES as f64).ceil() as usize,
+            1,
+        );
+        let step = (to_angle - from_angle) / steps as f64;
+        let mut previous = centerline(map, from_angle);
+        let mut total = 0.0;
+        for i in 1..=steps {
+            let point = centerline(map, from_angle + step * i as f64);
+            total += distance(previous, point);
+            previous = point;
+        }
+        total
+    }
+
+    /// The angle of the nearest of the densely sampled centerline points,
+    /// along with how far away it is
+    fn dense_closest(map: &Map, position: Vec2) -> (f64, f64) {
+        let position = (position.0 as f64, position.1 as f64);
+        (0..DENSE_SAMPLES)
+            .map(|sample| sample as f64 / DENSE_SAMPLES as f64 * PI * 2.0)
+            .map(|angle| (angle, distance(centerline(map, angle), position)))
+            .fold((0.0, f64::INFINITY), |best, here| {
+                if here.1 < best.1 {
+                    here
+                } else {
+                    best
+                }
+            })
+    }
+
+    /// Curvature of the circle through three nearby centerline points
+    fn dense_curvature(map: &Map, angle: f64) -> f64 {
+        const STEP: f64 = 1e-3;
+        let a = centerline(map, angle - STEP);
+        let b = centerline(map, angle);
+        let c = centerline(map, angle + STEP);
+        let cross = (b.0 - a.0) * (c.1 - a.1) - (b.1 - a.1) * (c.0 - a.0);
+        2.0 * f64::abs(cross) / (distance(a, b) * distance(b, c) * distance(a, c))
+    }
+
+    /// Positions spread around the lap on both sides of the centerline,
+    /// well inside the walls
+    fn track_positions(map: &Map) -> Vec<Vec2> {
+        (0..20)
+            .map(|i| {
+                let angle = (i as f32 + 0.5) / 20.0 * std::f32::consts::PI * 2.0;
+                let side = (i * 7 % 9) as f32 / 4.0 - 1.0;
+                let centerline = map.centerline_position(angle);
+                let direction = map.centerline_direction(angle);
+                let offset = side * map.track_width(angle) * 0.5;
+                (
+                    centerline.0 - direction.1 * offset,
+                    centerline.1 + direction.0 * offset,
+                )
+            })
+            .collect()
+    }
+
+    /// Progress from the start line to a centerline angle, going the
+    /// direction of racing
+    fn dense_progress(map: &Map, angle: f64) -> f64 {
+        let start_angle = map.get_start_position().angle as f64;
+        let swept = (start_angle - angle).rem_euclid(PI * 2.0);
+        dense_arc_length(map, start_angle - swept, start_angle)
+    }
+
+    #[test]
+    fn closest_point_matches_dense_sampling() {
+        for seed in SEEDS.iter() {
+            let map = seeded_map(*seed);
+            for position in track_positions(&map) {
+                let found = map.closest_point(position);
+                let found_distance = distance(
+                    (found.position.0 as f64, found.position.1 as f64),
+                    (position.0 as f64, position.1 as f64),
+                );
+                let (_, expected_distance) = dense_closest(&map, position);
+                assert!(
+                    f64::abs(found_distance - expected_distance) < 1e-3,
+                    "seed {} position {:?}: closest point {} away, not {}",
+                    seed,
+                    position,
+                    found_distance,
+                    expected_distance
+                );
+
+                let left = (-found.direction.1, found.direction.0);
+                let delta = (position.0 - found.position.0, position.1 - found.position.1);
+                assert!(f32::abs(f32::abs(found.lateral_offset) - found_distance as f32) < 1e-3);
+                assert!(f32::abs(found.lateral_offset - dot(delta, left)) < 1e-4);
+            }
+        }
+    }
+
+    #[test]
+    fn lateral_offset_is_positive_to_the_left() {
+        let map = seeded_map(1);
+        for sample in 0..WIDTH_SAMPLES {
+            let angle = sample_angle(sample);
+            let centerline = map.centerline_position(angle);
+            let direction = map.centerline_direction(angle);
+            let offset = map.track_width(angle) * 0.25;
+            let left = (
+                centerline.0 - direction.1 * offset,
+                centerline.1 + direction.0 * offset,
+            );
+            let right = (
+                centerline.0 + direction.1 * offset,
+                centerline.1 - direction.0 * offset,
+            );
+            assert!(map.closest_point(left).lateral_offset > 0.0);
+            assert!(map.closest_point(right).lateral_offset < 0.0);
+        }
+    }
+
+    #[test]
+    fn arc_length_matches_dense_sampling() {
+        for seed in SEEDS.iter() {
+            let map = seeded_map(*seed);
+            let spans = [
+                (0.0, std::f32::consts::PI * 2.0),
+                (0.3, 1.1),
+                (1.1, 0.3),
+                (-1.0, 0.5),
+                (2.0, 2.0 + std::f32::consts::PI * 3.0),
+            ];
+            for (from, to) in spans.iter() {
+                let found = map.arc_length(*from, *to) as f64;
+                let expected = dense_arc_length(&map, *from as f64, *to as f64);
+                assert!(
+                    f64::abs(found - expected) < 1e-3 * f64::max(expected, 1.0),
+                    "seed {} from {} to {}: {} not {}",
+                    seed,
+                    from,
+                    to,
+                    found,
+                    expected
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn track_progress_matches_dense_sampling() {
+        for seed in SEEDS.iter() {
+            let map = seeded_map(*seed);
+            for position in track_positions(&map) {
+                let found = map.track_progress(position) as f64;
+                let (angle, _) = dense_closest(&map, position);
+                let expected = dense_progress(&map, angle);
+                // Either side of the start line is close to both ends
+                let lap_length = map.lap_length() as f64;
+                let error = f64::abs(found - expected);
+                let error = f64::min(error, lap_length - error);
+                assert!(
+                    error < 0.01,
+                    "seed {} position {:?}: progress {} not {}",
+                    seed,
+                    position,
+                    found,
+                    expected
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn track_progress_wraps_at_the_start_line() {
+        for seed in SEEDS.iter() {
+            let map = seeded_map(*seed);
+            let start_angle = map.get_start_position().angle;
+            let lap_length = map.lap_length();
+            for offset in [-0.5, 0.0, 0.5].iter() {
+                let place = |angle: f32| {
+                    let centerline = map.centerline_position(angle);
+                    let direction = map.centerline_direction(angle);
+                    (
+                        centerline.0 - direction.1 * offset,
+                        centerline.1 + direction.0 * offset,
+                    )
+                };
+                // Ships race clockwise, so just past the line is a smaller
+                // angle
+                let after = map.track_progress(place(start_angle - 0.01)) as f64;
+                let before = map.track_progress(place(start_angle + 0.01)) as f64;
+                let expected =
+                    dense_arc_length(&map, start_angle as f64 - 0.01, start_angle as f64);
+                assert!(
+                    f64::abs(after - expected) < 0.01,
+                    "seed {}: {} past the line, not {}",
+                    seed,
+                    after,
+                    expected
+                );
+                let expected = lap_length as f64
+                    - dense_arc_length(&map, start_angle as f64, start_angle as f64 + 0.01);
+                assert!(
+                    f64::abs(before - expected) < 0.01,
+                    "seed {}: {} before the line, not {}",
+                    seed,
+                    before,
+                    expected
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn curvature_matches_dense_sampling() {
+        for seed in SEEDS.iter() {
+            let map = seeded_map(*seed);
+            for position in track_positions(&map) {
+                let found = map.curvature_at(position) as f64;
+                let (angle, _) = dense_closest(&map, position);
+                let expected = dense_curvature(&map, angle);
+                assert!(
+                    f64::abs(found - expected) < 1e-3 + expected * 0.01,
+                    "seed {} position {:?}: curvature {} not {}",
+                    seed,
+                    position,
+                    found,
+                    expected
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn saved_tracks_read_back() {
+        for seed in SEEDS.iter() {
+            let map = seeded_map(*seed);
+            let read = Map::from_str(&map.to_string()).expect("saved track reads back");
+            assert_eq!(read.sin_consts, map.sin_consts);
+            assert_eq!(read.cos_consts, map.cos_consts);
+            assert_eq!(read.track_base_radius, map.track_base_radius);
+        }
+    }
+
+    #[test]
+    fn rejects_non_finite_tracks() {
+        let saved = seeded_map(1).to_string();
+        let mut fields: Vec<&str> = saved.split_whitespace().collect();
+        for value in ["NaN", "inf", "-inf"].iter() {
+            fields[3] = value;
+            assert!(matches!(
+                Map::from_str(&fields.join(" ")),
+                Err(MapError::InvalidTrack(_))
+            ));
+        }
+
+        let mut map = seeded_map(1);
+        map.cos_consts[2] = f32::NAN;
+        assert!(matches!(map.validate(), Err(MapError::NotFinite)));
+        let mut map = seeded_map(1);
+        map.track_base_width = f32::INFINITY;
+        assert!(matches!(map.validate(), Err(MapError::NotFinite)));
+    }
+
+    const CIRCLE_RADIUS: f32 = 8.0;
+    const CIRCLE_HALF_WIDTH: f32 = 0.7;
+    const RAY_RANGE: f32 = 20.0;
+    /// How far from the wall a ray can stop
+    const RAY_POSITION_TOLERANCE: f32 = 0.002;
+    /// Rays that glance off a wall at a shallow angle stop short of it by
+    /// a lot more than RAY_POSITION_TOLERANCE, so distances are only
+    /// checked for rays at least this steep
+    const MIN_RAY_STEEPNESS: f32 = 0.2;
+    const RAY_DISTANCE_TOLERANCE: f32 = 0.01;
+    /// Smallest dot product between the found and exact normals
+    const RAY_NORMAL_TOLERANCE: f32 = 0.999;
+
+    /// A perfectly round track, where the walls are two circles and every
+    /// ray can be worked out exactly
+    fn circular_map() -> Map {
+        Map {
+            sin_consts: [0.0; 8],
+            cos_consts: [0.0; 8],
+            track_base_radius: CIRCLE_RADIUS,
+            width_sin_consts: [0.0; 4],
+            width_cos_consts: [0.0; 4],
+            track_base_width: CIRCLE_HALF_WIDTH,
+        }
+    }
+
+    /// Distance along a ray to where it first crosses a circle around the
+    /// origin, if it does
+    fn circle_crossing(origin: Vec2, direction: Vec2, radius: f32) -> Option<f32> {
+        // |origin + t * direction|^2 = radius^2, with direction a unit vector
+        let b = dot(origin, direction);
+        let c = dot(origin, origin) - radius * radius;
+        let discriminant = b * b - c;
+        if discriminant < 0.0 {
+            return None;
+        }
+        let root = f32::sqrt(discriminant);
+        [-b - root, -b + root]
+            .iter()
+            .copied()
+            .filter(|t| *t >= 0.0)
+            .fold(None, |nearest, t| Some(f32::min(nearest.unwrap_or(t), t)))
+    }
+
+    /// The exact hit distance, wall radius and normal for a ray starting
+    /// on the round track
+    fn exact_hit(origin: Vec2, direction: Vec2) -> (f32, f32, Vec2) {
+        let inner_radius = CIRCLE_RADIUS - CIRCLE_HALF_WIDTH;
+        let outer_radius = CIRCLE_RADIUS + CIRCLE_HALF_WIDTH;
+        let inner = circle_crossing(origin, direction, inner_radius);
+        let outer = circle_crossing(origin, direction, outer_radius)
+            .expect("rays always leave through the outer wall");
+
+        // The inner wall faces away from the center, the outer wall towards it
+        let (distance, wall_radius, facing) = match inner {
+            Some(inner) if inner < outer => (inner, inner_radius, 1.0),
+            _ => (outer, outer_radius, -1.0),
+        };
+        let position = (
+            origin.0 + direction.0 * distance,
+            origin.1 + direction.1 * distance,
+        );
+        let radius = length(&position);
+        (
+            distance,
+            wall_radius,
+            (position.0 / radius * facing, position.1 / radius * facing),
+        )
+    }
+
+    /// Checks a ray on the round track, returning the error in the hit
+    /// distance, or None for rays too shallow to check it
+    fn check_ray(map: &Map, origin: Vec2, direction: Vec2) -> Option<f32> {
+        let (distance, wall_radius, normal) = exact_hit(origin, direction);
+        let hit = map
+            .raycast(origin, direction, RAY_RANGE)
+            .unwrap_or_else(|| panic!("ray from {:?} along {:?} missed", origin, direction));
+
+        // A ray that only just misses one wall can count as touching it, so
+        // check the hit against whichever wall it ended up at
+        let hit_radius = length(&hit.position);
+        let (hit_wall_radius, facing) = if hit_radius < CIRCLE_RADIUS {
+            (CIRCLE_RADIUS - CIRCLE_HALF_WIDTH, 1.0)
+        } else {
+            (CIRCLE_RADIUS + CIRCLE_HALF_WIDTH, -1.0)
+        };
+        assert!(
+            f32::abs(hit_radius - hit_wall_radius) < RAY_POSITION_TOLERANCE,
+            "ray from {:?} along {:?} stopped {} from the wall",
+            origin,
+            direction,
+            f32::abs(hit_radius - hit_wall_radius)
+        );
+        assert!(
+            hit.distance < distance + RAY_POSITION_TOLERANCE,
+            "ray from {:?} along {:?} went through the wall",
+            origin,
+            direction
+        );
+
+        let hit_normal = (
+            hit.position.0 / hit_radius * facing,
+            hit.position.1 / hit_radius * facing,
+        );
+        assert!(
+            dot(hit.normal, hit_normal) > RAY_NORMAL_TOLERANCE,
+            "ray from {:?} along {:?} had normal {:?} not {:?}",
+            origin,
+            direction,
+            hit.normal,
+            hit_normal
+        );
+
+        let steepness = -dot(direction, normal);
+        if hit_wall_radius != wall_radius || steepness < MIN_RAY_STEEPNESS {
+            return None;
+        }
+        let error = f32::abs(hit.distance - distance);
+        assert!(
+            error < RAY_DISTANCE_TOLERANCE,
+            "ray from {:?} along {:?} hit at {} not {}",
+            origin,
+            direction,
+            hit.distance,
+            distance
+        );
+        Some(error)
+    }
+
+    #[test]
+    fn raycast_known_rays() {
+        let map = circular_map();
+        // Straight out to the outer wall and straight in to the inner one
+        check_ray(&map, (CIRCLE_RADIUS, 0.0), (1.0, 0.0)).expect("steep enough to check");
+        check_ray(&map, (CIRCLE_RADIUS, 0.0), (-1.0, 0.0)).expect("steep enough to check");
+        // Along the track until the outer wall curves round in front
+        check_ray(&map, (CIRCLE_RADIUS, 0.0), (0.0, 1.0));
+
+        let short = map.raycast((CIRCLE_RADIUS, 0.0), (1.0, 0.0), CIRCLE_HALF_WIDTH * 0.5);
+        assert!(short.is_none(), "ray hit a wall beyond its range");
+
+        let outside = map
+            .raycast((CIRCLE_RADIUS + 2.0, 0.0), (0.0, 1.0), RAY_RANGE)
+            .expect("rays starting in a wall hit it");
+        assert!(outside.distance == 0.0, "ray starting in a wall moved");
+    }
+
+    #[test]
+    fn raycast_random_rays() {
+        let map = circular_map();
+        let mut rng = Rng::new(1);
+        let mut checked = 0;
+        for _ in 0..2000 {
+            let angle = rng.next_f32() * std::f32::consts::PI * 2.0;
+            // Stay a ship's width or so away from the walls
+            let radius = CIRCLE_RADIUS + (rng.next_f32() * 2.0 - 1.0) * (CIRCLE_HALF_WIDTH - 0.05);
+            let origin = (f32::cos(angle) * radius, f32::sin(angle) * radius);
+            let heading = rng.next_f32() * std::f32::consts::PI * 2.0;
+            let direction = (f32::cos(heading), f32::sin(heading));
+            if check_ray(&map, origin, direction).is_some() {
+                checked += 1;
+            }
+        }
+        // Most rays are steep enough to have their distance checked
+        assert!(checked > 1000, "only {} rays were checked", checked);
+    }
 }
@@ -15,7 +15,9 @@ pub struct MapSprite {
     uniform_sin_consts: Option<WebGlUniformLocation>,
     uniform_cos_consts: Option<WebGlUniformLocation>,
     uniform_track_base_radius: Option<WebGlUniformLocation>,
-    uniform_track_width: Option<WebGlUniformLocation>,
+    uniform_width_sin_consts: Option<WebGlUniformLocation>,
+    uniform_width_cos_consts: Option<WebGlUniformLocation>,
+    uniform_track_base_width: Option<WebGlUniformLocation>,
 
     uniform_start_line_position: Option<WebGlUniformLocation>,
     uniform_start_line_tangent: Option<WebGlUniformLocation>,
@@ -50,7 +52,9 @@ impl MapSprite {
         let uniform_sin_consts = gl.get_uniform_location(&program, "sin_consts");
         let uniform_cos_consts = gl.get_uniform_location(&program, "cos_consts");
         let uniform_track_base_radius = gl.get_uniform_location(&program, "track_base_radius");
-        let uniform_track_width = gl.get_uniform_location(&program, "track_width");
+        let uniform_width_sin_consts = gl.get_uniform_location(&program, "width_sin_consts");
+        let uniform_width_cos_consts = gl.get_uniform_location(&program, "width_cos_consts");
+        let uniform_track_base_width = gl.get_uniform_location(&program, "track_base_width");
         let uniform_start_line_tangent = gl.get_uniform_location(&program, "start_line_tangent");
         let uniform_start_line_position = gl.get_uniform_location(&program, "start_line_position");
 
@@ -66,7 +70,9 @@ impl MapSprite {
             uniform_sin_consts,
             uniform_cos_consts,
             uniform_track_base_radius,
-            uniform_track_width,
+            uniform_width_sin_consts,
+            uniform_width_cos_consts,
+            uniform_track_base_width,
             uniform_start_line_tangent,
             uniform_start_line_position,
 
@@ -85,7 +91,18 @@ impl MapSprite {
             self.uniform_track_base_radius.as_ref(),
             map.track_base_radius,
         );
-        gl.uniform1f(self.uniform_track_width.as_ref(), map.track_width);
+        gl.uniform4fv_with_f32_array(
+            self.uniform_width_sin_consts.as_ref(),
+            &map.width_sin_consts,
+        );
+        gl.uniform4fv_with_f32_array(
+            self.uniform_width_cos_consts.as_ref(),
+            &map.width_cos_consts,
+        );
+        gl.uniform1f(
+            self.uniform_track_base_width.as_ref(),
+            map.track_base_width,
+        );
 
         let start_position = map.get_start_position();
         let start_angle = map.get_track_direction(start_position.angle);
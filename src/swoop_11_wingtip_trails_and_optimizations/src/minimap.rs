@@ -0,0 +1,115 @@
+use super::map::Map;
+use super::ship::Ship;
+
+/// How big the minimap is unless the options say otherwise, in CSS pixels
+pub const DEFAULT_SIZE: u32 = 160;
+/// Gap between the minimap and the edges of the canvas, in CSS pixels
+const MARGIN: f32 = 16.0;
+/// Room left around the track so the walls don't touch the edge
+const VIEW_MARGIN: f32 = 1.1;
+
+/// Which corner of the canvas the minimap sits in
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum MinimapCorner {
+    TopLeft,
+    TopRight,
+    BottomLeft,
+    BottomRight,
+}
+
+impl MinimapCorner {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "top-left" => Some(MinimapCorner::TopLeft),
+            "top-right" => Some(MinimapCorner::TopRight),
+            "bottom-left" => Some(MinimapCorner::BottomLeft),
+            "bottom-right" => Some(MinimapCorner::BottomRight),
+            _ => None,
+        }
+    }
+}
+
+/// Which way up the minimap is drawn
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum MinimapRotation {
+    /// The track is always drawn the same way round
+    NorthUp,
+    /// The track turns so that the followed ship always points up
+    PlayerUp,
+}
+
+impl MinimapRotation {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "north" => Some(MinimapRotation::NorthUp),
+            "player" => Some(MinimapRotation::PlayerUp),
+            _ => None,
+        }
+    }
+}
+
+/// A map of the whole circuit in a corner of the screen, so the corners
+/// coming up can be seen however far the camera has zoomed in. It is
+/// always scaled to fit the whole track.
+#[derive(Clone, Copy, Debug)]
+pub struct Minimap {
+    pub corner: MinimapCorner,
+    /// Width and height, in CSS pixels
+    pub size: u32,
+    pub rotation: MinimapRotation,
+}
+
+impl Minimap {
+    pub fn new() -> Self {
+        Self {
+            corner: MinimapCorner::BottomRight,
+            size: DEFAULT_SIZE,
+            rotation: MinimapRotation::NorthUp,
+        }
+    }
+
+    /// Where the minimap goes on a canvas of this resolution in device
+    /// pixels, as the left and top edges and the length of a side, all in
+    /// canvas pixels from the top left corner. It stays the same size on
+    /// screen whatever the device pixel ratio, but shrinks to fit small
+    /// canvases.
+    pub fn rect(&self, resolution: (u32, u32), pixel_ratio: f32) -> (f32, f32, f32) {
+        let (width, height) = (resolution.0 as f32, resolution.1 as f32);
+        let margin = MARGIN * pixel_ratio;
+        let size = f32::min(
+            self.size as f32 * pixel_ratio,
+            f32::min(width, height) / 2.0 - margin,
+        );
+        let size = f32::max(size, 0.0).floor();
+        let left = match self.corner {
+            MinimapCorner::TopLeft | MinimapCorner::BottomLeft => margin,
+            MinimapCorner::TopRight | MinimapCorner::BottomRight => width - margin - size,
+        };
+        let top = match self.corner {
+            MinimapCorner::TopLeft | MinimapCorner::TopRight => margin,
+            MinimapCorner::BottomLeft | MinimapCorner::BottomRight => height - margin - size,
+        };
+        (left.floor(), top.floor(), size)
+    }
+
+    /// How far the track is turned on the minimap, in radians, when
+    /// following a ship
+    pub fn view_rotation(&self, ship: &Ship) -> f32 {
+        match self.rotation {
+            MinimapRotation::NorthUp => 0.0,
+            MinimapRotation::PlayerUp => ship.position.rot,
+        }
+    }
+
+    /// How much of the world fits between the middle of the minimap and
+    /// its edge
+    pub fn view_radius(map: &Map) -> f32 {
+        map.bounding_radius() * VIEW_MARGIN
+    }
+}
+
+impl Default for Minimap {
+    fn default() -> Self {
+        Self::new()
+    }
+}
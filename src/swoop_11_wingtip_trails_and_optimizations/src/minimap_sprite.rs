@@ -0,0 +1,189 @@
+use web_sys::{WebGl2RenderingContext, WebGlBuffer, WebGlProgram, WebGlUniformLocation};
+
+use super::game_options::MAX_SHIPS;
+use super::map::Map;
+use super::shader::{init_shader_program, upload_array_f32, ShaderError};
+use super::ship::Ship;
+
+/// Draws the whole track in a circle in screen space, with a dot for each
+/// ship. The track comes from the same uniforms as the map sprite.
+pub struct MinimapSprite {
+    position_buffer: WebGlBuffer,
+    program: WebGlProgram,
+    attrib_vertex_positions: u32,
+
+    uniform_minimap_rect: Option<WebGlUniformLocation>,
+    uniform_view_radius: Option<WebGlUniformLocation>,
+    uniform_view_rotation: Option<WebGlUniformLocation>,
+
+    uniform_sin_consts: Option<WebGlUniformLocation>,
+    uniform_cos_consts: Option<WebGlUniformLocation>,
+    uniform_track_base_radius: Option<WebGlUniformLocation>,
+    uniform_width_sin_consts: Option<WebGlUniformLocation>,
+    uniform_width_cos_consts: Option<WebGlUniformLocation>,
+    uniform_track_base_width: Option<WebGlUniformLocation>,
+
+    uniform_ship_positions: Option<WebGlUniformLocation>,
+    uniform_ship_colors: Option<WebGlUniformLocation>,
+    uniform_ship_count: Option<WebGlUniformLocation>,
+    uniform_followed_ship: Option<WebGlUniformLocation>,
+
+    /// Where the minimap is on the canvas: left, top and side length in
+    /// canvas pixels from the top left corner
+    pub rect: (f32, f32, f32),
+    pub canvas_resolution: (u32, u32),
+    /// World distance from the middle of the minimap to its edge
+    pub view_radius: f32,
+    /// How far the track is turned, in radians
+    pub view_rotation: f32,
+}
+
+impl MinimapSprite {
+    pub fn new(gl: &WebGl2RenderingContext) -> Result<Self, ShaderError> {
+        let position_buffer =
+            upload_array_f32(gl, vec![-1.0, 1.0, 1.0, 1.0, -1.0, -1.0, 1.0, -1.0])?;
+
+        let program = init_shader_program(
+            gl,
+            include_str!("resources/minimap.vert"),
+            include_str!("resources/minimap.frag"),
+        )?;
+
+        let attrib_vertex_positions = gl.get_attrib_location(&program, "aVertexPosition") as u32;
+
+        let uniform_minimap_rect = gl.get_uniform_location(&program, "minimap_rect");
+        let uniform_view_radius = gl.get_uniform_location(&program, "view_radius");
+        let uniform_view_rotation = gl.get_uniform_location(&program, "view_rotation");
+
+        let uniform_sin_consts = gl.get_uniform_location(&program, "sin_consts");
+        let uniform_cos_consts = gl.get_uniform_location(&program, "cos_consts");
+        let uniform_track_base_radius = gl.get_uniform_location(&program, "track_base_radius");
+        let uniform_width_sin_consts = gl.get_uniform_location(&program, "width_sin_consts");
+        let uniform_width_cos_consts = gl.get_uniform_location(&program, "width_cos_consts");
+        let uniform_track_base_width = gl.get_uniform_location(&program, "track_base_width");
+
+        let uniform_ship_positions = gl.get_uniform_location(&program, "ship_positions");
+        let uniform_ship_colors = gl.get_uniform_location(&program, "ship_colors");
+        let uniform_ship_count = gl.get_uniform_location(&program, "ship_count");
+        let uniform_followed_ship = gl.get_uniform_location(&program, "followed_ship");
+
+        Ok(Self {
+            position_buffer,
+            program,
+            attrib_vertex_positions,
+
+            uniform_minimap_rect,
+            uniform_view_radius,
+            uniform_view_rotation,
+
+            uniform_sin_consts,
+            uniform_cos_consts,
+            uniform_track_base_radius,
+            uniform_width_sin_consts,
+            uniform_width_cos_consts,
+            uniform_track_base_width,
+
+            uniform_ship_positions,
+            uniform_ship_colors,
+            uniform_ship_count,
+            uniform_followed_ship,
+
+            rect: (0.0, 0.0, 0.0),
+            canvas_resolution: (1, 1),
+            view_radius: 1.0,
+            view_rotation: 0.0,
+        })
+    }
+
+    pub fn set_to_map(&mut self, gl: &WebGl2RenderingContext, map: &Map) {
+        gl.use_program(Some(&self.program));
+
+        gl.uniform4fv_with_f32_array(self.uniform_sin_consts.as_ref(), &map.sin_consts);
+        gl.uniform4fv_with_f32_array(self.uniform_cos_consts.as_ref(), &map.cos_consts);
+        gl.uniform1f(
+            self.uniform_track_base_radius.as_ref(),
+            map.track_base_radius,
+        );
+        gl.uniform4fv_with_f32_array(
+            self.uniform_width_sin_consts.as_ref(),
+            &map.width_sin_consts,
+        );
+        gl.uniform4fv_with_f32_array(
+            self.uniform_width_cos_consts.as_ref(),
+            &map.width_cos_consts,
+        );
+        gl.uniform1f(self.uniform_track_base_width.as_ref(), map.track_base_width);
+    }
+
+    /// Draws the minimap with a dot for each ship, the followed one
+    /// larger than the rest. Only the first MAX_SHIPS ships are shown.
+    pub fn render(
+        &mut self,
+        gl: &WebGl2RenderingContext,
+        ships: &[&Ship],
+        followed_ship: Option<usize>,
+    ) {
+        if self.rect.2 <= 0.0 {
+            return;
+        }
+        gl.use_program(Some(&self.program));
+        gl.blend_func(
+            WebGl2RenderingContext::SRC_ALPHA,
+            WebGl2RenderingContext::ONE_MINUS_SRC_ALPHA,
+        );
+
+        // From canvas pixels to clip space, where y points up
+        let (left, top, size) = self.rect;
+        let width = self.canvas_resolution.0 as f32;
+        let height = self.canvas_resolution.1 as f32;
+        gl.uniform4f(
+            self.uniform_minimap_rect.as_ref(),
+            (left + size / 2.0) / width * 2.0 - 1.0,
+            1.0 - (top + size / 2.0) / height * 2.0,
+            size / width,
+            size / height,
+        );
+        gl.uniform1f(self.uniform_view_radius.as_ref(), self.view_radius);
+        gl.uniform1f(self.uniform_view_rotation.as_ref(), self.view_rotation);
+
+        let ship_count = usize::min(ships.len(), MAX_SHIPS);
+        let mut positions = [0.0; MAX_SHIPS * 2];
+        let mut colors = [0.0; MAX_SHIPS * 4];
+        for (i, ship) in ships.iter().take(ship_count).enumerate() {
+            positions[i * 2] = ship.position.x;
+            positions[i * 2 + 1] = ship.position.y;
+            colors[i * 4] = ship.color.0;
+            colors[i * 4 + 1] = ship.color.1;
+            colors[i * 4 + 2] = ship.color.2;
+            colors[i * 4 + 3] = ship.color.3;
+        }
+        gl.uniform2fv_with_f32_array(self.uniform_ship_positions.as_ref(), &positions);
+        gl.uniform4fv_with_f32_array(self.uniform_ship_colors.as_ref(), &colors);
+        gl.uniform1i(self.uniform_ship_count.as_ref(), ship_count as i32);
+        gl.uniform1i(
+            self.uniform_followed_ship.as_ref(),
+            followed_ship.map_or(-1, |ship| ship as i32),
+        );
+
+        gl.bind_buffer(
+            WebGl2RenderingContext::ARRAY_BUFFER,
+            Some(&self.position_buffer),
+        );
+
+        gl.vertex_attrib_pointer_with_i32(
+            self.attrib_vertex_positions,
+            2, // num components
+            WebGl2RenderingContext::FLOAT,
+            false, // normalize
+            0,     // stride
+            0,     // offset
+        );
+        gl.enable_vertex_attrib_array(self.attrib_vertex_positions);
+
+        gl.draw_arrays(
+            WebGl2RenderingContext::TRIANGLE_STRIP,
+            0, // offset
+            4, // vertex count
+        );
+    }
+}
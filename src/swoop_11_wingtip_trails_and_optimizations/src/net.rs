@@ -0,0 +1,86 @@
+use std::cell::{Cell, RefCell};
+use std::rc::Rc;
+
+use js_sys::{ArrayBuffer, Uint8Array};
+use wasm_bindgen::prelude::Closure;
+use wasm_bindgen::{JsCast, JsValue};
+use web_sys::{BinaryType, Event, MessageEvent, WebSocket};
+
+use super::protocol::{ClientMessage, ProtocolError, ServerMessage};
+
+/// A WebSocket to a relay server. Messages arrive between frames and are
+/// kept until the game asks for them.
+pub struct Connection {
+    socket: WebSocket,
+    received: Rc<RefCell<Vec<Vec<u8>>>>,
+    closed: Rc<Cell<bool>>,
+    // Kept alive for as long as the socket might call them
+    _on_message: Closure<dyn FnMut(MessageEvent)>,
+    _on_close: Closure<dyn FnMut(Event)>,
+}
+
+impl Connection {
+    /// Starts connecting to a server at a ws:// or wss:// address. Nothing
+    /// can be sent until it is open.
+    pub fn open(url: &str) -> Result<Self, JsValue> {
+        let socket = WebSocket::new(url)?;
+        socket.set_binary_type(BinaryType::Arraybuffer);
+
+        let received = Rc::new(RefCell::new(vec![]));
+        let on_message_received = received.clone();
+        let on_message = Closure::wrap(Box::new(move |event: MessageEvent| {
+            // Text messages aren't part of the protocol
+            if let Ok(buffer) = event.data().dyn_into::<ArrayBuffer>() {
+                on_message_received
+                    .borrow_mut()
+                    .push(Uint8Array::new(&buffer).to_vec());
+            }
+        }) as Box<dyn FnMut(_)>);
+        socket.set_onmessage(Some(on_message.as_ref().unchecked_ref()));
+
+        let closed = Rc::new(Cell::new(false));
+        let on_close_closed = closed.clone();
+        let on_close = Closure::wrap(Box::new(move |_event: Event| {
+            on_close_closed.set(true);
+        }) as Box<dyn FnMut(_)>);
+        socket.set_onclose(Some(on_close.as_ref().unchecked_ref()));
+
+        Ok(Self {
+            socket,
+            received,
+            closed,
+            _on_message: on_message,
+            _on_close: on_close,
+        })
+    }
+
+    pub fn is_open(&self) -> bool {
+        self.socket.ready_state() == WebSocket::OPEN
+    }
+
+    /// Whether the connection has gone, or never got going
+    pub fn is_closed(&self) -> bool {
+        self.closed.get()
+    }
+
+    pub fn send(&self, message: &ClientMessage) -> Result<(), JsValue> {
+        self.socket.send_with_u8_array(&message.to_bytes())
+    }
+
+    /// Everything that has arrived since this was last called, in order
+    pub fn receive(&self) -> Vec<Result<ServerMessage, ProtocolError>> {
+        self.received
+            .borrow_mut()
+            .drain(..)
+            .map(|bytes| ServerMessage::from_bytes(&bytes))
+            .collect()
+    }
+}
+
+impl Drop for Connection {
+    fn drop(&mut self) {
+        self.socket.set_onmessage(None);
+        self.socket.set_onclose(None);
+        let _ = self.socket.close();
+    }
+}
@@ -7,7 +7,7 @@ use super::map::Map;
 use super::ship::Ship;
 use super::transform::{length, normalize, vect_between, Vec2};
 
-const SHIP_RADIUS: f32 = 0.05;
+pub const SHIP_RADIUS: f32 = 0.05;
 const GROUND_FRICTION: f32 = 5.0;
 
 #[wasm_bindgen]
@@ -16,13 +16,31 @@ extern "C" {
     fn log(s: &str);
 }
 
-pub fn calc_ship_physics(all_ships: &mut Vec<Ship>, map: &Map, dt: f32) {
+pub fn calc_ship_physics(all_ships: &mut [Ship], map: &Map, dt: f32) {
+    calc_physics(all_ships.iter_mut().collect(), map, dt);
+}
+
+/// Like `calc_ship_physics`, but only for the ships still in the race.
+/// The others are left where they are and nothing can run into them.
+pub fn calc_racing_ship_physics(all_ships: &mut [Ship], racing: &[bool], map: &Map, dt: f32) {
+    let ships = all_ships
+        .iter_mut()
+        .zip(racing.iter())
+        .filter(|(_, racing)| **racing)
+        .map(|(ship, _)| ship)
+        .collect();
+    calc_physics(ships, map, dt);
+}
+
+fn calc_physics(mut all_ships: Vec<&mut Ship>, map: &Map, dt: f32) {
     // Motion
     for ship in all_ships.iter_mut() {
         ship.update(dt as f32);
     }
 
-    let ship_refs = all_ships.iter_mut().map(|x| Rc::new(RefCell::new(x)));
+    let ship_refs = all_ships
+        .iter_mut()
+        .map(|x| Rc::new(RefCell::new(&mut **x)));
     let all_pairs = ship_refs.permutations(2);
     let collisions = all_pairs.filter_map(|ships: Vec<Rc<RefCell<&mut Ship>>>| {
         let ship1 = ships[0].clone();
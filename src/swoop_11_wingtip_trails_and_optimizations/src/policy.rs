@@ -0,0 +1,236 @@
+use std::fmt;
+use std::str::FromStr;
+
+use super::controller::{ControllerError, Observation, ShipControl, ShipController};
+use super::gym::{observe, OBSERVATION_SIZE};
+use super::rng::Rng;
+
+/// Steering, thrust and boost
+pub const ACTION_SIZE: usize = 3;
+
+/// A policy trained with the native trainer, small enough to ship with the
+/// game
+pub const BUILT_IN_POLICY: &str = include_str!("policies/racer.policy");
+
+/// An error to represent a policy file that couldn't be loaded
+#[derive(Debug)]
+pub enum PolicyError {
+    /// Layers start with a `layer INPUTS OUTPUTS` line
+    InvalidLayer(String),
+    InvalidNumber(String),
+    /// Each row is one weight per input followed by a bias
+    WrongRowLength {
+        expected: usize,
+        got: usize,
+    },
+    /// The file ended partway through a layer
+    MissingRows,
+    /// A layer's inputs don't match the size of whatever feeds it
+    SizeMismatch {
+        expected: usize,
+        got: usize,
+    },
+    NoLayers,
+}
+
+/// One fully connected layer
+#[derive(Clone, Debug)]
+struct Layer {
+    inputs: usize,
+    outputs: usize,
+    /// One row of `inputs` weights per output
+    weights: Vec<f32>,
+    biases: Vec<f32>,
+}
+
+impl Layer {
+    fn evaluate(&self, input: &[f32]) -> Vec<f32> {
+        self.weights
+            .chunks(self.inputs)
+            .zip(self.biases.iter())
+            .map(|(row, bias)| {
+                let sum: f32 = row.iter().zip(input.iter()).map(|(w, x)| w * x).sum();
+                f32::tanh(sum + bias)
+            })
+            .collect()
+    }
+}
+
+/// A small neural network that maps an observation from `gym::observe` to
+/// a ship control. Every layer uses tanh, so with no hidden layers it is
+/// just a squashed linear policy.
+///
+/// Policies are stored as plain text so they can be pasted into a page.
+/// Lines starting with # are comments, and each layer is written as:
+///     layer INPUTS OUTPUTS
+///     w w w ... bias      (one row per output)
+#[derive(Clone, Debug)]
+pub struct Policy {
+    layers: Vec<Layer>,
+}
+
+impl Policy {
+    /// A policy with small random weights. `hidden` lists the sizes of the
+    /// hidden layers, which can be empty.
+    pub fn random(hidden: &[usize], scale: f32, rng: &mut Rng) -> Self {
+        let mut sizes = vec![OBSERVATION_SIZE];
+        sizes.extend_from_slice(hidden);
+        sizes.push(ACTION_SIZE);
+
+        let layers = sizes
+            .windows(2)
+            .map(|size| Layer {
+                inputs: size[0],
+                outputs: size[1],
+                weights: (0..size[0] * size[1])
+                    .map(|_| (rng.next_f32() * 2.0 - 1.0) * scale)
+                    .collect(),
+                biases: vec![0.0; size[1]],
+            })
+            .collect();
+        Self { layers }
+    }
+
+    /// Runs the network, giving steering, thrust and boost from -1 to 1
+    pub fn evaluate(&self, observation: &[f32]) -> Vec<f32> {
+        self.layers
+            .iter()
+            .fold(observation.to_vec(), |values, layer| {
+                layer.evaluate(&values)
+            })
+    }
+
+    /// Every weight and bias in order, for training
+    pub fn parameters(&self) -> Vec<f32> {
+        self.layers
+            .iter()
+            .flat_map(|layer| layer.weights.iter().chain(layer.biases.iter()))
+            .copied()
+            .collect()
+    }
+
+    /// The reverse of `parameters`
+    pub fn set_parameters(&mut self, parameters: &[f32]) {
+        let mut parameters = parameters.iter();
+        for layer in self.layers.iter_mut() {
+            for value in layer.weights.iter_mut().chain(layer.biases.iter_mut()) {
+                *value = *parameters.next().expect("not enough parameters");
+            }
+        }
+    }
+
+    pub fn to_control(&self, observation: &[f32]) -> ShipControl {
+        let output = self.evaluate(observation);
+        ShipControl {
+            steering: output[0],
+            thrust: output[1],
+            boost: output[2] > 0.0,
+        }
+    }
+}
+
+impl FromStr for Policy {
+    type Err = PolicyError;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        let mut lines = text
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'));
+
+        let mut layers: Vec<Layer> = vec![];
+        while let Some(header) = lines.next() {
+            let sizes: Vec<usize> = header
+                .strip_prefix("layer")
+                .map(|sizes| sizes.split_whitespace().map(str::parse).collect())
+                .and_then(Result::ok)
+                .filter(|sizes: &Vec<usize>| sizes.len() == 2)
+                .ok_or_else(|| PolicyError::InvalidLayer(header.to_string()))?;
+            let (inputs, outputs) = (sizes[0], sizes[1]);
+
+            let expected_inputs = layers
+                .last()
+                .map_or(OBSERVATION_SIZE, |layer| layer.outputs);
+            if inputs != expected_inputs {
+                return Err(PolicyError::SizeMismatch {
+                    expected: expected_inputs,
+                    got: inputs,
+                });
+            }
+
+            let mut weights = Vec::with_capacity(inputs * outputs);
+            let mut biases = Vec::with_capacity(outputs);
+            for _ in 0..outputs {
+                let row = lines
+                    .next()
+                    .ok_or(PolicyError::MissingRows)?
+                    .split_whitespace()
+                    .map(|value| {
+                        value
+                            .parse::<f32>()
+                            .map_err(|_| PolicyError::InvalidNumber(value.to_string()))
+                    })
+                    .collect::<Result<Vec<_>, _>>()?;
+                if row.len() != inputs + 1 {
+                    return Err(PolicyError::WrongRowLength {
+                        expected: inputs + 1,
+                        got: row.len(),
+                    });
+                }
+                weights.extend_from_slice(&row[..inputs]);
+                biases.push(row[inputs]);
+            }
+
+            layers.push(Layer {
+                inputs,
+                outputs,
+                weights,
+                biases,
+            });
+        }
+
+        match layers.last() {
+            None => Err(PolicyError::NoLayers),
+            Some(layer) if layer.outputs != ACTION_SIZE => Err(PolicyError::SizeMismatch {
+                expected: ACTION_SIZE,
+                got: layer.outputs,
+            }),
+            Some(_) => Ok(Self { layers }),
+        }
+    }
+}
+
+impl fmt::Display for Policy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for layer in self.layers.iter() {
+            writeln!(f, "layer {} {}", layer.inputs, layer.outputs)?;
+            for (row, bias) in layer.weights.chunks(layer.inputs).zip(layer.biases.iter()) {
+                for weight in row {
+                    write!(f, "{} ", weight)?;
+                }
+                writeln!(f, "{}", bias)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Flies a ship with a trained policy
+pub struct PolicyController {
+    policy: Policy,
+}
+
+impl PolicyController {
+    pub fn new(policy: Policy) -> Self {
+        Self { policy }
+    }
+}
+
+impl ShipController for PolicyController {
+    fn control(&mut self, observation: &Observation) -> Result<ShipControl, ControllerError> {
+        let mut control = self.policy.to_control(&observe(observation));
+        // Policies get the same limits as the built in AI
+        control.thrust = f32::min(control.thrust, observation.catch_up.thrust_scale);
+        Ok(control)
+    }
+}
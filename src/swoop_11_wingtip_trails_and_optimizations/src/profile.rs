@@ -0,0 +1,576 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::str::FromStr;
+
+use super::championship::{ChampionshipError, Track};
+use super::ghost::{Ghost, GHOST_STORAGE_PREFIX};
+use super::map::Map;
+use super::physics::SHIP_RADIUS;
+use super::ship::{Ship, SHIP_COLORS};
+use super::storage::{KeyValueStore, StorageError};
+
+/// The profile is kept in storage under this
+pub const PROFILE_KEY: &str = "swoop-profile";
+/// What players are called until they pick a name
+pub const DEFAULT_NAME: &str = "player";
+/// Race times kept for each track and number of laps
+pub const LEADERBOARD_SIZE: usize = 5;
+const VERSION: u32 = 1;
+/// A ship this close to a wall is against it. Physics pushes ships back
+/// to exactly touching, so without a margin they would seem to bounce off
+/// and hit it again every tick.
+const WALL_MARGIN: f32 = 0.01;
+
+/// Brings a profile saved by an older version up to the next one. Each is
+/// given the saved text and the rest of the storage, and gives the text
+/// as the next version would have saved it.
+type Migration = fn(&str, &dyn KeyValueStore) -> String;
+
+/// One migration from each version to the next, starting with version 0
+/// for before there were profiles
+const MIGRATIONS: [Migration; VERSION as usize] = [from_ghosts];
+
+/// An error to represent a saved profile that couldn't be read
+#[derive(Debug)]
+pub enum ProfileError {
+    /// Profiles start with a `profile VERSION` line
+    InvalidHeader(String),
+    /// Saved by a newer version of the game than this one
+    UnsupportedVersion(u32),
+    /// Other lines start with name, color, stats, lap or race
+    UnknownLine(String),
+    /// Colors are the name of one of SHIP_COLORS
+    UnknownColor(String),
+    /// Stats are written as `stats RACES WINS DISTANCE WALL_HITS`
+    InvalidStats(String),
+    /// Times are written as `lap TIME TRACK` or `race LAPS TIME TRACK`
+    InvalidTime(String),
+    InvalidTrack(ChampionshipError),
+}
+
+/// Totals over every race the player has flown
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct ProfileStats {
+    pub races: u32,
+    pub wins: u32,
+    /// In world units
+    pub distance: f32,
+    pub wall_hits: u32,
+}
+
+/// The player's best times on one track
+#[derive(Clone, Debug)]
+pub struct TrackRecord {
+    pub track: Track,
+    pub best_lap: Option<f32>,
+    /// The fastest races by number of laps, quickest first, with at most
+    /// LEADERBOARD_SIZE of each
+    pub races: BTreeMap<u32, Vec<f32>>,
+}
+
+impl TrackRecord {
+    fn new(track: Track) -> Self {
+        Self {
+            track,
+            best_lap: None,
+            races: BTreeMap::new(),
+        }
+    }
+
+    /// Adds a lap time, giving true if it is the new best
+    fn add_lap(&mut self, time: f32) -> bool {
+        let best = self.best_lap.is_none_or(|best| time < best);
+        if best {
+            self.best_lap = Some(time);
+        }
+        best
+    }
+
+    /// Adds a race time to the leaderboard for its number of laps, giving
+    /// where it placed if it made it on
+    fn add_race(&mut self, laps: u32, time: f32) -> Option<usize> {
+        let times = self.races.entry(laps).or_default();
+        let place = times
+            .iter()
+            .position(|other| time < *other)
+            .unwrap_or(times.len());
+        times.insert(place, time);
+        times.truncate(LEADERBOARD_SIZE);
+        match place < LEADERBOARD_SIZE {
+            true => Some(place),
+            false => None,
+        }
+    }
+}
+
+/// Counts up how far one ship flies over a race and how often it runs
+/// into the walls
+#[derive(Clone, Debug, Default)]
+pub struct RaceTally {
+    pub distance: f32,
+    pub wall_hits: u32,
+    last_position: Option<(f32, f32)>,
+    /// Set while against a wall, so that scraping along one is only one
+    /// hit
+    on_wall: bool,
+}
+
+impl RaceTally {
+    pub fn update(&mut self, ship: &Ship, map: &Map) {
+        let position = (ship.position.x, ship.position.y);
+        if let Some((x, y)) = self.last_position {
+            self.distance += ((position.0 - x).powi(2) + (position.1 - y).powi(2)).sqrt();
+        }
+        self.last_position = Some(position);
+
+        let on_wall = map.distance_field(position) > -(SHIP_RADIUS + WALL_MARGIN);
+        if on_wall && !self.on_wall {
+            self.wall_hits += 1;
+        }
+        self.on_wall = on_wall;
+    }
+}
+
+/// How one race went for the player
+#[derive(Clone, Debug)]
+pub struct RaceOutcome {
+    pub track: Track,
+    pub laps: u32,
+    pub won: bool,
+    /// None if the player didn't finish, or the race doesn't count for
+    /// times
+    pub race_time: Option<f32>,
+    pub best_lap: Option<f32>,
+    pub tally: RaceTally,
+}
+
+/// Which of the player's records a race beat
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct NewRecords {
+    pub best_lap: bool,
+    /// Where the race time placed on the leaderboard, if it made it on
+    pub race_place: Option<usize>,
+}
+
+/// Everything about the player that is kept between visits: what they
+/// are called, the color they fly, their best times on every track they
+/// have raced and totals over all their races.
+///
+/// The profile saves as plain text:
+///     profile VERSION
+///     name NAME
+///     color COLOR                (left out to go by the starting order)
+///     stats RACES WINS DISTANCE WALL_HITS
+///     lap TIME TRACK             (best lap, one per track)
+///     race LAPS TIME TRACK       (one per time on each leaderboard)
+/// where TRACK is a seed or a saved track.
+#[derive(Clone, Debug)]
+pub struct Profile {
+    pub name: String,
+    /// Index into SHIP_COLORS of the color the player's ship is painted
+    pub color: Option<usize>,
+    pub records: Vec<TrackRecord>,
+    pub stats: ProfileStats,
+}
+
+impl Profile {
+    pub fn new() -> Self {
+        Self {
+            name: DEFAULT_NAME.to_string(),
+            color: None,
+            records: vec![],
+            stats: ProfileStats::default(),
+        }
+    }
+
+    /// Reads the profile from storage, bringing it up to date if an
+    /// older version saved it. With nothing saved yet, a new profile is
+    /// made from whatever older versions left lying around.
+    pub fn load(store: &dyn KeyValueStore) -> Result<Self, ProfileError> {
+        let mut text = store.get(PROFILE_KEY).unwrap_or_default();
+        let version = match first_line(&text) {
+            Some(header) => read_header(header)?,
+            None => 0,
+        };
+        if version > VERSION {
+            return Err(ProfileError::UnsupportedVersion(version));
+        }
+        for migration in MIGRATIONS[version as usize..].iter() {
+            text = migration(&text, store);
+        }
+        Self::from_str(&text)
+    }
+
+    pub fn save(&self, store: &mut dyn KeyValueStore) -> Result<(), StorageError> {
+        store.set(PROFILE_KEY, &self.to_string())
+    }
+
+    /// Picks a color by name, ignoring case. Gives false if there is no
+    /// such color.
+    pub fn set_color(&mut self, name: &str) -> bool {
+        match color_index(name) {
+            Some(color) => {
+                self.color = Some(color);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Names can't span lines, as the profile is saved a line at a time
+    pub fn set_name(&mut self, name: &str) {
+        self.name = name.lines().next().unwrap_or("").trim().to_string();
+        if self.name.is_empty() {
+            self.name = DEFAULT_NAME.to_string();
+        }
+    }
+
+    pub fn record(&self, track: &Track) -> Option<&TrackRecord> {
+        let key = track.to_string();
+        self.records
+            .iter()
+            .find(|record| record.track.to_string() == key)
+    }
+
+    fn record_mut(&mut self, track: &Track) -> &mut TrackRecord {
+        let key = track.to_string();
+        match self
+            .records
+            .iter()
+            .position(|record| record.track.to_string() == key)
+        {
+            Some(index) => &mut self.records[index],
+            None => {
+                self.records.push(TrackRecord::new(track.clone()));
+                self.records.last_mut().unwrap()
+            }
+        }
+    }
+
+    /// Adds a race to the stats and its times to the records for its track
+    pub fn record_race(&mut self, outcome: &RaceOutcome) -> NewRecords {
+        self.stats.races += 1;
+        if outcome.won {
+            self.stats.wins += 1;
+        }
+        self.stats.distance += outcome.tally.distance;
+        self.stats.wall_hits += outcome.tally.wall_hits;
+
+        let record = self.record_mut(&outcome.track);
+        NewRecords {
+            best_lap: outcome.best_lap.is_some_and(|lap| record.add_lap(lap)),
+            race_place: outcome
+                .race_time
+                .and_then(|time| record.add_race(outcome.laps, time)),
+        }
+    }
+}
+
+impl Default for Profile {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl FromStr for Profile {
+    type Err = ProfileError;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        let mut lines = text
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'));
+
+        let header = lines
+            .next()
+            .ok_or_else(|| ProfileError::InvalidHeader(String::new()))?;
+        let version = read_header(header)?;
+        if version != VERSION {
+            return Err(ProfileError::UnsupportedVersion(version));
+        }
+
+        let mut profile = Self::new();
+        for line in lines {
+            let mut parts = line.splitn(2, char::is_whitespace);
+            let kind = parts.next().unwrap_or("");
+            let value = parts.next().unwrap_or("").trim();
+            match kind {
+                "name" => profile.set_name(value),
+                "color" => {
+                    if !profile.set_color(value) {
+                        return Err(ProfileError::UnknownColor(value.to_string()));
+                    }
+                }
+                "stats" => profile.stats = read_stats(line)?,
+                "lap" => {
+                    let invalid_time = || ProfileError::InvalidTime(line.to_string());
+                    let mut parts = value.splitn(2, char::is_whitespace);
+                    let time = parts
+                        .next()
+                        .and_then(|time| time.parse().ok())
+                        .ok_or_else(invalid_time)?;
+                    let track = read_track(parts.next().ok_or_else(invalid_time)?)?;
+                    profile.record_mut(&track).add_lap(time);
+                }
+                "race" => {
+                    let invalid_time = || ProfileError::InvalidTime(line.to_string());
+                    let mut parts = value.splitn(3, char::is_whitespace);
+                    let laps = parts
+                        .next()
+                        .and_then(|laps| laps.parse().ok())
+                        .filter(|laps| *laps > 0)
+                        .ok_or_else(invalid_time)?;
+                    let time = parts
+                        .next()
+                        .and_then(|time| time.parse().ok())
+                        .ok_or_else(invalid_time)?;
+                    let track = read_track(parts.next().ok_or_else(invalid_time)?)?;
+                    profile.record_mut(&track).add_race(laps, time);
+                }
+                _ => return Err(ProfileError::UnknownLine(line.to_string())),
+            }
+        }
+        Ok(profile)
+    }
+}
+
+impl fmt::Display for Profile {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "profile {}", VERSION)?;
+        writeln!(f, "name {}", self.name)?;
+        if let Some((name, _)) = self.color.and_then(|color| SHIP_COLORS.get(color)) {
+            writeln!(f, "color {}", name)?;
+        }
+        let stats = &self.stats;
+        writeln!(
+            f,
+            "stats {} {} {} {}",
+            stats.races, stats.wins, stats.distance, stats.wall_hits
+        )?;
+        for record in self.records.iter() {
+            if let Some(lap) = record.best_lap {
+                writeln!(f, "lap {} {}", lap, record.track)?;
+            }
+            for (laps, times) in record.races.iter() {
+                for time in times.iter() {
+                    writeln!(f, "race {} {} {}", laps, time, record.track)?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+fn color_index(name: &str) -> Option<usize> {
+    SHIP_COLORS
+        .iter()
+        .position(|(color, _)| color.eq_ignore_ascii_case(name.trim()))
+}
+
+fn first_line(text: &str) -> Option<&str> {
+    text.lines()
+        .map(str::trim)
+        .find(|line| !line.is_empty() && !line.starts_with('#'))
+}
+
+fn read_header(header: &str) -> Result<u32, ProfileError> {
+    let invalid_header = || ProfileError::InvalidHeader(header.to_string());
+    let fields: Vec<&str> = header.split_whitespace().collect();
+    if fields.len() != 2 || fields[0] != "profile" {
+        return Err(invalid_header());
+    }
+    fields[1].parse().map_err(|_| invalid_header())
+}
+
+fn read_stats(line: &str) -> Result<ProfileStats, ProfileError> {
+    let invalid_stats = || ProfileError::InvalidStats(line.to_string());
+    let fields: Vec<&str> = line.split_whitespace().skip(1).collect();
+    if fields.len() != 4 {
+        return Err(invalid_stats());
+    }
+    Ok(ProfileStats {
+        races: fields[0].parse().map_err(|_| invalid_stats())?,
+        wins: fields[1].parse().map_err(|_| invalid_stats())?,
+        distance: fields[2].parse().map_err(|_| invalid_stats())?,
+        wall_hits: fields[3].parse().map_err(|_| invalid_stats())?,
+    })
+}
+
+fn read_track(text: &str) -> Result<Track, ProfileError> {
+    text.parse::<Track>().map_err(ProfileError::InvalidTrack)
+}
+
+/// Before profiles, the only thing kept between visits was the ghost of
+/// the best time trial lap on each track. Their lap times are the best
+/// laps the new profile starts with.
+fn from_ghosts(_text: &str, store: &dyn KeyValueStore) -> String {
+    let mut text = "profile 1\n".to_string();
+    for key in store.keys() {
+        if !key.starts_with(GHOST_STORAGE_PREFIX) {
+            continue;
+        }
+        // A ghost that can't be read was never going to be raced against
+        // either, so it is left out
+        let ghost = store
+            .get(&key)
+            .and_then(|ghost| ghost.parse::<Ghost>().ok());
+        if let Some(ghost) = ghost {
+            text.push_str(&format!("lap {} {}\n", ghost.lap_time, ghost.seed));
+        }
+    }
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ghost::GhostRecorder;
+    use crate::storage::MemoryStore;
+    use crate::transform::Transform2d;
+
+    fn save_ghost(store: &mut MemoryStore, seed: u64, lap_time: f32) {
+        let mut recorder = GhostRecorder::new(seed);
+        let position = Transform2d::new(0.0, 0.0, 0.0, 1.0);
+        recorder.sample(0.0, &position);
+        let ghost = recorder.finish(lap_time, &position);
+        let key = format!("{}{}", GHOST_STORAGE_PREFIX, seed);
+        store.set(&key, &ghost.to_string()).unwrap();
+    }
+
+    fn outcome(seed: u64, won: bool, race_time: Option<f32>, best_lap: Option<f32>) -> RaceOutcome {
+        RaceOutcome {
+            track: Track::Seed(seed),
+            laps: 3,
+            won,
+            race_time,
+            best_lap,
+            tally: RaceTally::default(),
+        }
+    }
+
+    #[test]
+    fn migrates_ghosts_from_before_profiles() {
+        let mut store = MemoryStore::new();
+        save_ghost(&mut store, 7, 12.5);
+        save_ghost(&mut store, 42, 20.25);
+        store
+            .set(&format!("{}{}", GHOST_STORAGE_PREFIX, 99), "not a ghost")
+            .unwrap();
+        store.set("something-else", "lap 1.0 5").unwrap();
+
+        let profile = Profile::load(&store).unwrap();
+        assert_eq!(profile.name, DEFAULT_NAME);
+        assert_eq!(profile.records.len(), 2);
+        assert_eq!(
+            profile.record(&Track::Seed(7)).unwrap().best_lap,
+            Some(12.5)
+        );
+        assert_eq!(
+            profile.record(&Track::Seed(42)).unwrap().best_lap,
+            Some(20.25)
+        );
+        assert!(profile.record(&Track::Seed(99)).is_none());
+        assert!(profile.record(&Track::Seed(5)).is_none());
+
+        // Once saved it is read back as it is, without migrating again
+        profile.save(&mut store).unwrap();
+        save_ghost(&mut store, 1, 30.0);
+        let reloaded = Profile::load(&store).unwrap();
+        assert_eq!(reloaded.to_string(), profile.to_string());
+    }
+
+    #[test]
+    fn rejects_profiles_from_a_newer_version() {
+        let mut store = MemoryStore::new();
+        store
+            .set(
+                PROFILE_KEY,
+                &format!("profile {}\nname someone\n", VERSION + 1),
+            )
+            .unwrap();
+        match Profile::load(&store) {
+            Err(ProfileError::UnsupportedVersion(version)) => assert_eq!(version, VERSION + 1),
+            other => panic!("expected an unsupported version, got {:?}", other),
+        }
+        assert!(matches!(
+            "profile 0\n".parse::<Profile>(),
+            Err(ProfileError::UnsupportedVersion(0))
+        ));
+    }
+
+    #[test]
+    fn keeps_best_times_for_each_seed() {
+        let mut profile = Profile::new();
+        let first = profile.record_race(&outcome(1, false, Some(60.0), Some(19.0)));
+        assert_eq!(
+            first,
+            NewRecords {
+                best_lap: true,
+                race_place: Some(0),
+            }
+        );
+        let slower = profile.record_race(&outcome(1, false, Some(61.0), Some(19.5)));
+        assert_eq!(
+            slower,
+            NewRecords {
+                best_lap: false,
+                race_place: Some(1),
+            }
+        );
+        let other_seed = profile.record_race(&outcome(2, false, Some(90.0), Some(29.0)));
+        assert_eq!(
+            other_seed,
+            NewRecords {
+                best_lap: true,
+                race_place: Some(0),
+            }
+        );
+        for time in [58.0, 62.0, 63.0, 64.0].iter() {
+            profile.record_race(&outcome(1, false, Some(*time), None));
+        }
+        let too_slow = profile.record_race(&outcome(1, false, Some(65.0), None));
+        assert_eq!(too_slow.race_place, None);
+
+        let seed_1 = profile.record(&Track::Seed(1)).unwrap();
+        assert_eq!(seed_1.best_lap, Some(19.0));
+        assert_eq!(seed_1.races[&3], vec![58.0, 60.0, 61.0, 62.0, 63.0]);
+        let seed_2 = profile.record(&Track::Seed(2)).unwrap();
+        assert_eq!(seed_2.best_lap, Some(29.0));
+        assert_eq!(seed_2.races[&3], vec![90.0]);
+
+        // And they are all still there once saved and read back
+        let mut store = MemoryStore::new();
+        profile.save(&mut store).unwrap();
+        let reloaded = Profile::load(&store).unwrap();
+        assert_eq!(reloaded.to_string(), profile.to_string());
+        assert_eq!(
+            reloaded.record(&Track::Seed(1)).unwrap().races[&3],
+            seed_1.races[&3]
+        );
+    }
+
+    #[test]
+    fn adds_up_stats_over_races() {
+        let mut profile = Profile::new();
+        let results = [(true, 100.0, 2), (false, 80.5, 0), (true, 120.25, 5)];
+        for (won, distance, wall_hits) in results.iter() {
+            let mut outcome = outcome(1, *won, None, None);
+            outcome.tally.distance = *distance;
+            outcome.tally.wall_hits = *wall_hits;
+            profile.record_race(&outcome);
+        }
+        assert_eq!(
+            profile.stats,
+            ProfileStats {
+                races: 3,
+                wins: 2,
+                distance: 300.75,
+                wall_hits: 7,
+            }
+        );
+
+        let mut store = MemoryStore::new();
+        profile.save(&mut store).unwrap();
+        assert_eq!(Profile::load(&store).unwrap().stats, profile.stats);
+    }
+}
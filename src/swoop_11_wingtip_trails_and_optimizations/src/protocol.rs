@@ -0,0 +1,680 @@
+use super::controller::ShipControl;
+use super::replay::{decode_control, encode_control};
+use super::ship::Ship;
+
+/// Every message starts with this, so that clients and servers built
+/// from different versions can tell rather than misreading each other.
+/// Bump it whenever the layout of any message changes.
+pub const PROTOCOL_VERSION: u8 = 3;
+
+/// Lobby and player names are cut down to this many bytes
+const MAX_NAME_LENGTH: usize = 32;
+/// Longer text, such as why the server turned a client away, is cut down
+/// to this many bytes
+const MAX_TEXT_LENGTH: usize = 1024;
+
+// Message kinds, the byte after the version
+const JOIN: u8 = 1;
+const READY: u8 = 2;
+const INPUT: u8 = 3;
+const LEAVE: u8 = 4;
+
+const WELCOME: u8 = 1;
+const LOBBY: u8 = 2;
+const START: u8 = 3;
+const SNAPSHOT: u8 = 4;
+const RESULTS: u8 = 5;
+const ERROR: u8 = 6;
+const RELAYED_INPUT: u8 = 7;
+
+/// An error to represent a message that couldn't be read
+#[derive(Debug, PartialEq)]
+pub enum ProtocolError {
+    /// The message was sent by a different version of the game
+    UnsupportedVersion(u8),
+    UnknownMessage(u8),
+    /// The message ended before everything was read
+    Truncated,
+    /// There was more in the message than there should have been
+    TrailingBytes,
+    /// A name or other text wasn't valid UTF-8
+    InvalidName,
+}
+
+/// What clients send to the server
+#[derive(Clone, Debug, PartialEq)]
+pub enum ClientMessage {
+    /// Asks to join a lobby, which is made if nobody is in it yet
+    Join {
+        lobby: String,
+        name: String,
+    },
+    /// Ready for the next race to start
+    Ready,
+    /// How the player flies their ship on a run of ticks ending with
+    /// `tick`, oldest first. The same ticks are sent more than once so
+    /// that a lost message is made up for by the next.
+    Input {
+        tick: u32,
+        controls: Vec<ShipControl>,
+    },
+    Leave,
+}
+
+/// Somebody in a lobby
+#[derive(Clone, Debug, PartialEq)]
+pub struct LobbyPlayer {
+    pub name: String,
+    pub ready: bool,
+}
+
+/// Everything about a ship that the physics moves on, along with the
+/// control it is being flown with
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ShipState {
+    pub position: (f32, f32, f32),
+    pub velocity: (f32, f32, f32),
+    pub boost: f32,
+    pub control: ShipControl,
+}
+
+impl ShipState {
+    pub fn from_ship(ship: &Ship, control: ShipControl) -> Self {
+        Self {
+            position: (ship.position.x, ship.position.y, ship.position.rot),
+            velocity: (ship.velocity.x, ship.velocity.y, ship.velocity.rot),
+            boost: ship.boost,
+            control,
+        }
+    }
+
+    /// Moves a ship to exactly this state, with the control applied
+    pub fn apply(&self, ship: &mut Ship) {
+        ship.position.x = self.position.0;
+        ship.position.y = self.position.1;
+        ship.position.rot = self.position.2;
+        ship.velocity.x = self.velocity.0;
+        ship.velocity.y = self.velocity.1;
+        ship.velocity.rot = self.velocity.2;
+        ship.boost = self.boost;
+        self.control.apply(ship);
+    }
+}
+
+/// What the server sends to clients
+#[derive(Clone, Debug, PartialEq)]
+pub enum ServerMessage {
+    /// Sent on joining, with where in the lobby the client is
+    Welcome {
+        player: u8,
+    },
+    /// Who is in the lobby, sent whenever that changes
+    Lobby {
+        players: Vec<LobbyPlayer>,
+    },
+    /// A race is about to start. The track is made from the seed, the
+    /// same as everywhere else. Ship is the one the client flies.
+    Start {
+        seed: u64,
+        laps: u32,
+        drivers: Vec<String>,
+        ship: u8,
+        /// Ticks the ships wait on the grid before the first tick is run
+        countdown: u32,
+    },
+    /// Every ship as of the start of `tick`, along with the controls it
+    /// was flown with on the tick before
+    Snapshot {
+        tick: u32,
+        ships: Vec<ShipState>,
+    },
+    /// Controls another player sent, passed on as they arrive so that
+    /// everyone can fix up their guesses at how that ship was flown
+    Input {
+        ship: u8,
+        tick: u32,
+        controls: Vec<ShipControl>,
+    },
+    /// The finishing order once the race is over
+    Results {
+        standings: Vec<u8>,
+    },
+    Error {
+        reason: String,
+    },
+}
+
+impl ClientMessage {
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut writer = Writer::new();
+        match self {
+            ClientMessage::Join { lobby, name } => {
+                writer.u8(JOIN);
+                writer.name(lobby);
+                writer.name(name);
+            }
+            ClientMessage::Ready => writer.u8(READY),
+            ClientMessage::Input { tick, controls } => {
+                writer.u8(INPUT);
+                writer.u32(*tick);
+                writer.controls(controls);
+            }
+            ClientMessage::Leave => writer.u8(LEAVE),
+        }
+        writer.bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, ProtocolError> {
+        let mut reader = Reader::new(bytes)?;
+        let message = match reader.u8()? {
+            JOIN => ClientMessage::Join {
+                lobby: reader.name()?,
+                name: reader.name()?,
+            },
+            READY => ClientMessage::Ready,
+            INPUT => ClientMessage::Input {
+                tick: reader.u32()?,
+                controls: reader.controls()?,
+            },
+            LEAVE => ClientMessage::Leave,
+            kind => return Err(ProtocolError::UnknownMessage(kind)),
+        };
+        reader.finish()?;
+        Ok(message)
+    }
+}
+
+impl ServerMessage {
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut writer = Writer::new();
+        match self {
+            ServerMessage::Welcome { player } => {
+                writer.u8(WELCOME);
+                writer.u8(*player);
+            }
+            ServerMessage::Lobby { players } => {
+                writer.u8(LOBBY);
+                writer.u8(players.len() as u8);
+                for player in players.iter() {
+                    writer.name(&player.name);
+                    writer.u8(player.ready as u8);
+                }
+            }
+            ServerMessage::Start {
+                seed,
+                laps,
+                drivers,
+                ship,
+                countdown,
+            } => {
+                writer.u8(START);
+                writer.u64(*seed);
+                writer.u32(*laps);
+                writer.u8(drivers.len() as u8);
+                for driver in drivers.iter() {
+                    writer.name(driver);
+                }
+                writer.u8(*ship);
+                writer.u32(*countdown);
+            }
+            ServerMessage::Snapshot { tick, ships } => {
+                writer.u8(SNAPSHOT);
+                writer.u32(*tick);
+                writer.u8(ships.len() as u8);
+                for ship in ships.iter() {
+                    writer.ship(ship);
+                }
+            }
+            ServerMessage::Input {
+                ship,
+                tick,
+                controls,
+            } => {
+                writer.u8(RELAYED_INPUT);
+                writer.u8(*ship);
+                writer.u32(*tick);
+                writer.controls(controls);
+            }
+            ServerMessage::Results { standings } => {
+                writer.u8(RESULTS);
+                writer.u8(standings.len() as u8);
+                writer.bytes.extend_from_slice(standings);
+            }
+            ServerMessage::Error { reason } => {
+                writer.u8(ERROR);
+                writer.text(reason);
+            }
+        }
+        writer.bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, ProtocolError> {
+        let mut reader = Reader::new(bytes)?;
+        let message = match reader.u8()? {
+            WELCOME => ServerMessage::Welcome {
+                player: reader.u8()?,
+            },
+            LOBBY => {
+                let count = reader.u8()?;
+                let players = (0..count)
+                    .map(|_| {
+                        Ok(LobbyPlayer {
+                            name: reader.name()?,
+                            ready: reader.u8()? != 0,
+                        })
+                    })
+                    .collect::<Result<Vec<_>, _>>()?;
+                ServerMessage::Lobby { players }
+            }
+            START => {
+                let seed = reader.u64()?;
+                let laps = reader.u32()?;
+                let count = reader.u8()?;
+                let drivers = (0..count)
+                    .map(|_| reader.name())
+                    .collect::<Result<Vec<_>, _>>()?;
+                ServerMessage::Start {
+                    seed,
+                    laps,
+                    drivers,
+                    ship: reader.u8()?,
+                    countdown: reader.u32()?,
+                }
+            }
+            SNAPSHOT => {
+                let tick = reader.u32()?;
+                let count = reader.u8()?;
+                let ships = (0..count)
+                    .map(|_| reader.ship())
+                    .collect::<Result<Vec<_>, _>>()?;
+                ServerMessage::Snapshot { tick, ships }
+            }
+            RELAYED_INPUT => ServerMessage::Input {
+                ship: reader.u8()?,
+                tick: reader.u32()?,
+                controls: reader.controls()?,
+            },
+            RESULTS => {
+                let count = reader.u8()? as usize;
+                ServerMessage::Results {
+                    standings: reader.take(count)?.to_vec(),
+                }
+            }
+            ERROR => ServerMessage::Error {
+                reason: reader.text()?,
+            },
+            kind => return Err(ProtocolError::UnknownMessage(kind)),
+        };
+        reader.finish()?;
+        Ok(message)
+    }
+}
+
+/// Builds up a message in little endian, starting with the version
+struct Writer {
+    bytes: Vec<u8>,
+}
+
+impl Writer {
+    fn new() -> Self {
+        Self {
+            bytes: vec![PROTOCOL_VERSION],
+        }
+    }
+
+    fn u8(&mut self, value: u8) {
+        self.bytes.push(value);
+    }
+
+    fn u16(&mut self, value: u16) {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+    }
+
+    fn u32(&mut self, value: u32) {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+    }
+
+    fn u64(&mut self, value: u64) {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+    }
+
+    fn f32(&mut self, value: f32) {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+    }
+
+    /// Names are cut short rather than refused, on a character boundary
+    fn name(&mut self, name: &str) {
+        let name = cut_short(name, MAX_NAME_LENGTH);
+        self.u8(name.len() as u8);
+        self.bytes.extend_from_slice(name.as_bytes());
+    }
+
+    /// Like a name, but with room for a sentence or two
+    fn text(&mut self, text: &str) {
+        let text = cut_short(text, MAX_TEXT_LENGTH);
+        self.u16(text.len() as u16);
+        self.bytes.extend_from_slice(text.as_bytes());
+    }
+
+    fn control(&mut self, control: ShipControl) {
+        self.bytes.extend_from_slice(&encode_control(control));
+    }
+
+    /// Runs of controls are only ever a few ticks long, so only the last
+    /// 255 are sent, keeping the run ending on the same tick
+    fn controls(&mut self, controls: &[ShipControl]) {
+        let count = usize::min(controls.len(), u8::MAX as usize);
+        self.u8(count as u8);
+        for control in controls[controls.len() - count..].iter() {
+            self.control(*control);
+        }
+    }
+
+    /// Ship states are sent at full precision, so that everyone's
+    /// physics carries on from exactly the same place
+    fn ship(&mut self, ship: &ShipState) {
+        self.f32(ship.position.0);
+        self.f32(ship.position.1);
+        self.f32(ship.position.2);
+        self.f32(ship.velocity.0);
+        self.f32(ship.velocity.1);
+        self.f32(ship.velocity.2);
+        self.f32(ship.boost);
+        self.control(ship.control);
+    }
+}
+
+/// Reads little endian values from the front of a message, after
+/// checking its version
+struct Reader<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn new(bytes: &'a [u8]) -> Result<Self, ProtocolError> {
+        let mut reader = Self { bytes, position: 0 };
+        let version = reader.u8()?;
+        if version != PROTOCOL_VERSION {
+            return Err(ProtocolError::UnsupportedVersion(version));
+        }
+        Ok(reader)
+    }
+
+    fn finish(&self) -> Result<(), ProtocolError> {
+        match self.position == self.bytes.len() {
+            true => Ok(()),
+            false => Err(ProtocolError::TrailingBytes),
+        }
+    }
+
+    fn take(&mut self, length: usize) -> Result<&'a [u8], ProtocolError> {
+        let end = self.position + length;
+        let bytes = self
+            .bytes
+            .get(self.position..end)
+            .ok_or(ProtocolError::Truncated)?;
+        self.position = end;
+        Ok(bytes)
+    }
+
+    fn u8(&mut self) -> Result<u8, ProtocolError> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, ProtocolError> {
+        let mut bytes = [0; 2];
+        bytes.copy_from_slice(self.take(2)?);
+        Ok(u16::from_le_bytes(bytes))
+    }
+
+    fn u32(&mut self) -> Result<u32, ProtocolError> {
+        let mut bytes = [0; 4];
+        bytes.copy_from_slice(self.take(4)?);
+        Ok(u32::from_le_bytes(bytes))
+    }
+
+    fn u64(&mut self) -> Result<u64, ProtocolError> {
+        let mut bytes = [0; 8];
+        bytes.copy_from_slice(self.take(8)?);
+        Ok(u64::from_le_bytes(bytes))
+    }
+
+    fn f32(&mut self) -> Result<f32, ProtocolError> {
+        let mut bytes = [0; 4];
+        bytes.copy_from_slice(self.take(4)?);
+        Ok(f32::from_le_bytes(bytes))
+    }
+
+    fn name(&mut self) -> Result<String, ProtocolError> {
+        let length = self.u8()? as usize;
+        self.string(length)
+    }
+
+    fn text(&mut self) -> Result<String, ProtocolError> {
+        let length = self.u16()? as usize;
+        self.string(length)
+    }
+
+    fn string(&mut self, length: usize) -> Result<String, ProtocolError> {
+        String::from_utf8(self.take(length)?.to_vec()).map_err(|_| ProtocolError::InvalidName)
+    }
+
+    fn control(&mut self) -> Result<ShipControl, ProtocolError> {
+        let mut encoded = [0; 4];
+        encoded.copy_from_slice(self.take(4)?);
+        Ok(decode_control(encoded))
+    }
+
+    fn controls(&mut self) -> Result<Vec<ShipControl>, ProtocolError> {
+        let count = self.u8()?;
+        (0..count).map(|_| self.control()).collect()
+    }
+
+    fn ship(&mut self) -> Result<ShipState, ProtocolError> {
+        Ok(ShipState {
+            position: (self.f32()?, self.f32()?, self.f32()?),
+            velocity: (self.f32()?, self.f32()?, self.f32()?),
+            boost: self.f32()?,
+            control: self.control()?,
+        })
+    }
+}
+
+/// The longest start of some text that fits in `max_length` bytes without
+/// splitting a character
+fn cut_short(text: &str, max_length: usize) -> &str {
+    let mut length = usize::min(text.len(), max_length);
+    while !text.is_char_boundary(length) {
+        length -= 1;
+    }
+    &text[..length]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::replay::quantize;
+
+    fn controls() -> Vec<ShipControl> {
+        (0..5)
+            .map(|tick| {
+                quantize(ShipControl {
+                    steering: tick as f32 * 0.3 - 0.6,
+                    thrust: tick as f32 * 0.5,
+                    boost: tick % 2 == 0,
+                })
+            })
+            .collect()
+    }
+
+    fn client_messages() -> Vec<ClientMessage> {
+        vec![
+            ClientMessage::Join {
+                lobby: "friday".to_string(),
+                name: "sam".to_string(),
+            },
+            ClientMessage::Ready,
+            ClientMessage::Input {
+                tick: 1234,
+                controls: controls(),
+            },
+            ClientMessage::Leave,
+        ]
+    }
+
+    fn server_messages() -> Vec<ServerMessage> {
+        vec![
+            ServerMessage::Welcome { player: 2 },
+            ServerMessage::Lobby {
+                players: vec![
+                    LobbyPlayer {
+                        name: "sam".to_string(),
+                        ready: true,
+                    },
+                    LobbyPlayer {
+                        name: "ålex".to_string(),
+                        ready: false,
+                    },
+                ],
+            },
+            ServerMessage::Start {
+                seed: u64::MAX - 7,
+                laps: 3,
+                drivers: vec!["sam".to_string(), "champion".to_string()],
+                ship: 1,
+                countdown: 180,
+            },
+            ServerMessage::Snapshot {
+                tick: 99,
+                ships: controls()
+                    .into_iter()
+                    .enumerate()
+                    .map(|(id, control)| ShipState {
+                        position: (id as f32 * 1.5, -2.25, 0.1),
+                        velocity: (0.3, id as f32, -1.0),
+                        boost: 0.75,
+                        control,
+                    })
+                    .collect(),
+            },
+            ServerMessage::Input {
+                ship: 3,
+                tick: 4321,
+                controls: controls(),
+            },
+            ServerMessage::Results {
+                standings: vec![2, 0, 1],
+            },
+            ServerMessage::Error {
+                reason: "Lobby friday is full, try again once a race has finished".to_string(),
+            },
+        ]
+    }
+
+    #[test]
+    fn client_messages_read_back() {
+        for message in client_messages() {
+            let bytes = message.to_bytes();
+            assert_eq!(bytes[0], PROTOCOL_VERSION);
+            assert_eq!(ClientMessage::from_bytes(&bytes), Ok(message));
+        }
+    }
+
+    #[test]
+    fn server_messages_read_back() {
+        for message in server_messages() {
+            let bytes = message.to_bytes();
+            assert_eq!(bytes[0], PROTOCOL_VERSION);
+            assert_eq!(ServerMessage::from_bytes(&bytes), Ok(message));
+        }
+    }
+
+    #[test]
+    fn other_versions_are_rejected() {
+        for message in client_messages() {
+            let mut bytes = message.to_bytes();
+            bytes[0] = PROTOCOL_VERSION - 1;
+            assert_eq!(
+                ClientMessage::from_bytes(&bytes),
+                Err(ProtocolError::UnsupportedVersion(PROTOCOL_VERSION - 1))
+            );
+        }
+        for message in server_messages() {
+            let mut bytes = message.to_bytes();
+            bytes[0] = PROTOCOL_VERSION + 1;
+            assert_eq!(
+                ServerMessage::from_bytes(&bytes),
+                Err(ProtocolError::UnsupportedVersion(PROTOCOL_VERSION + 1))
+            );
+        }
+    }
+
+    #[test]
+    fn broken_messages_are_rejected() {
+        assert_eq!(
+            ClientMessage::from_bytes(&[]),
+            Err(ProtocolError::Truncated)
+        );
+        assert_eq!(
+            ServerMessage::from_bytes(&[PROTOCOL_VERSION, 99]),
+            Err(ProtocolError::UnknownMessage(99))
+        );
+        for message in server_messages() {
+            let bytes = message.to_bytes();
+            assert_eq!(
+                ServerMessage::from_bytes(&bytes[..bytes.len() - 1]),
+                Err(ProtocolError::Truncated)
+            );
+            let mut longer = bytes.clone();
+            longer.push(0);
+            assert_eq!(
+                ServerMessage::from_bytes(&longer),
+                Err(ProtocolError::TrailingBytes)
+            );
+        }
+        let mut bytes = ClientMessage::Join {
+            lobby: "ab".to_string(),
+            name: "cd".to_string(),
+        }
+        .to_bytes();
+        bytes[3] = 0xFF;
+        assert_eq!(
+            ClientMessage::from_bytes(&bytes),
+            Err(ProtocolError::InvalidName)
+        );
+    }
+
+    #[test]
+    fn long_text_is_cut_short_between_characters() {
+        let name = "é".repeat(MAX_NAME_LENGTH);
+        let bytes = ClientMessage::Join {
+            lobby: name.clone(),
+            name: "sam".to_string(),
+        }
+        .to_bytes();
+        match ClientMessage::from_bytes(&bytes) {
+            Ok(ClientMessage::Join { lobby, .. }) => {
+                assert_eq!(lobby, "é".repeat(MAX_NAME_LENGTH / 2))
+            }
+            read => panic!("read back as {:?}", read),
+        }
+
+        // Error reasons have room for more than a name
+        let reason = "The server is shutting down for the night. ".repeat(3);
+        let message = ServerMessage::Error {
+            reason: reason.clone(),
+        };
+        assert!(reason.len() > MAX_NAME_LENGTH);
+        assert_eq!(ServerMessage::from_bytes(&message.to_bytes()), Ok(message));
+
+        let reason = "é".repeat(MAX_TEXT_LENGTH);
+        let bytes = ServerMessage::Error { reason }.to_bytes();
+        assert_eq!(
+            ServerMessage::from_bytes(&bytes),
+            Ok(ServerMessage::Error {
+                reason: "é".repeat(MAX_TEXT_LENGTH / 2)
+            })
+        );
+    }
+}
@@ -0,0 +1,136 @@
+use super::map::Map;
+use super::ship::Ship;
+use super::timing::LapTimer;
+
+/// How far a single ship has got through the race
+#[derive(Clone, Debug)]
+pub struct RaceProgress {
+    /// Number of times the ship has crossed the start line. Ships start
+    /// just behind the line so this begins at -1.
+    pub laps: i32,
+    /// Distance along the track from the start line on the current lap
+    pub lap_progress: f32,
+    /// Race time when the ship completed the final lap
+    pub finish_time: Option<f32>,
+}
+
+/// Keeps track of laps and finishing positions for every ship
+#[derive(Clone)]
+pub struct Race {
+    pub laps_to_win: u32,
+    pub time: f32,
+    lap_length: f32,
+    pub progress: Vec<RaceProgress>,
+    /// Lap and sector times for every ship
+    pub timing: Vec<LapTimer>,
+}
+
+impl Race {
+    pub fn new(ships: &[Ship], map: &Map, laps_to_win: u32) -> Self {
+        let lap_length = map.lap_length();
+        let progress = ships
+            .iter()
+            .map(|ship| {
+                let lap_progress = map.track_progress((ship.position.x, ship.position.y));
+                RaceProgress {
+                    laps: if lap_progress > lap_length * 0.5 { -1 } else { 0 },
+                    lap_progress,
+                    finish_time: None,
+                }
+            })
+            .collect();
+        let timing = ships.iter().map(|_| LapTimer::new(lap_length)).collect();
+
+        Self {
+            laps_to_win,
+            time: 0.0,
+            lap_length,
+            progress,
+            timing,
+        }
+    }
+
+    pub fn update(&mut self, ships: &[Ship], map: &Map, dt: f32) {
+        self.time += dt;
+
+        let ships = ships.iter().zip(self.progress.iter_mut());
+        for ((ship, progress), timer) in ships.zip(self.timing.iter_mut()) {
+            let lap_progress = map.track_progress((ship.position.x, ship.position.y));
+            let line_time = timer.update(progress.lap_progress, lap_progress, self.time, dt);
+
+            // A big jump in progress means the ship went over the start
+            // line, forwards or backwards
+            let delta = lap_progress - progress.lap_progress;
+            if delta < -self.lap_length * 0.5 {
+                progress.laps += 1;
+            } else if delta > self.lap_length * 0.5 {
+                progress.laps -= 1;
+            }
+            progress.lap_progress = lap_progress;
+
+            if progress.finish_time.is_none() && progress.laps >= self.laps_to_win as i32 {
+                progress.finish_time = Some(line_time.unwrap_or(self.time));
+            }
+        }
+    }
+
+    /// Total distance covered since the start of the race
+    pub fn distance(&self, ship_id: usize) -> f32 {
+        let progress = &self.progress[ship_id];
+        progress.laps as f32 * self.lap_length + progress.lap_progress
+    }
+
+    /// Ship indices in race order, leader first. Finished ships are
+    /// ordered by when they finished.
+    pub fn standings(&self) -> Vec<usize> {
+        let mut order: Vec<usize> = (0..self.progress.len()).collect();
+        order.sort_by(|a, b| {
+            match (self.progress[*a].finish_time, self.progress[*b].finish_time) {
+                (Some(time_a), Some(time_b)) => time_a.partial_cmp(&time_b),
+                (Some(_), None) => Some(std::cmp::Ordering::Less),
+                (None, Some(_)) => Some(std::cmp::Ordering::Greater),
+                (None, None) => self.distance(*b).partial_cmp(&self.distance(*a)),
+            }
+            .unwrap_or(std::cmp::Ordering::Equal)
+        });
+        order
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.progress
+            .iter()
+            .all(|progress| progress.finish_time.is_some())
+    }
+}
+
+/// Lines the ships up side by side just behind the start line, facing
+/// along the track and at rest
+pub fn place_on_grid(ships: &mut [Ship], map: &Map) {
+    const SHIP_SPACING: f32 = 0.12;
+    let start_position = map.get_start_position();
+    let startline_angle = map.get_track_direction(start_position.angle);
+
+    let startline_tangent = (f32::cos(startline_angle), f32::sin(startline_angle));
+    let startline_normal = (-f32::sin(startline_angle), f32::cos(startline_angle));
+
+    let num_ships = ships.len();
+
+    for (id, ship) in ships.iter_mut().enumerate() {
+        let offset = (id as f32) - ((num_ships - 1) as f32) * 0.5;
+
+        let offset_vec = (
+            (startline_tangent.0 * offset - startline_normal.0) * SHIP_SPACING,
+            (startline_tangent.1 * offset - startline_normal.1) * SHIP_SPACING,
+        );
+
+        let ship_start_position = start_position.to_cartesian();
+        ship.position.x = ship_start_position.0 + offset_vec.0;
+        ship.position.y = ship_start_position.1 + offset_vec.1;
+        ship.position.rot = startline_angle;
+
+        ship.velocity.x = 0.0;
+        ship.velocity.y = 0.0;
+        ship.velocity.rot = 0.0;
+        ship.boost = 1.0;
+    }
+}
@@ -0,0 +1,266 @@
+use super::map::Map;
+use super::transform::{length, Vec2};
+
+/// Number of points around the lap that the racing line is solved at
+const NUM_STATIONS: usize = 256;
+/// The line is first solved with this few stations and then refined by
+/// doubling up to NUM_STATIONS. Smoothing at full resolution alone takes
+/// far too many passes for the line to move out to the apexes.
+const COARSEST_STATIONS: usize = 16;
+/// How many smoothing passes the solver makes at each resolution
+const SMOOTHING_ITERATIONS: usize = 200;
+/// How close the racing line is allowed to get to the walls
+const WALL_MARGIN: f32 = 0.15;
+
+// Rough limits of what a ship can do, used to build the speed profile
+/// The speed on the straights, where nothing else limits it
+pub const MAX_SPEED: f32 = 5.0;
+const MAX_LATERAL_ACCELERATION: f32 = 6.0;
+pub const MAX_ACCELERATION: f32 = 4.0;
+/// How quickly a ship can slow down with full reverse thrust
+pub const MAX_BRAKING: f32 = 8.0;
+
+pub struct RacingLinePoint {
+    /// Polar angle of the station on the centerline
+    pub angle: f32,
+    /// Distance from the centerline. Positive is to the left when facing
+    /// the direction of racing, the same as TrackPoint::lateral_offset
+    pub offset: f32,
+    pub position: Vec2,
+    pub curvature: f32,
+    /// The fastest the line can be taken here on grip alone, without
+    /// slowing down for the corners ahead
+    pub corner_speed: f32,
+    /// The fastest a ship can go here and still make the upcoming corners
+    pub speed: f32,
+}
+
+/// The racing line interpolated at some point on the track
+pub struct RacingLineSample {
+    pub offset: f32,
+    pub corner_speed: f32,
+    pub speed: f32,
+}
+
+/// A path around the track that cuts the corners, along with how fast a
+/// ship can follow it. This is solved once per map.
+pub struct RacingLine {
+    points: Vec<RacingLinePoint>,
+}
+
+impl RacingLine {
+    /// Solves for the minimum curvature line inside the track. This is
+    /// fully deterministic so the same map always gives the same line.
+    pub fn new(map: &Map) -> Self {
+        let mut offsets = vec![0.0; COARSEST_STATIONS];
+        let mut num_stations = COARSEST_STATIONS;
+        loop {
+            let stations = Stations::new(map, num_stations);
+            offsets = stations.smooth(offsets);
+            if num_stations >= NUM_STATIONS {
+                let positions = stations.positions(&offsets);
+                return Self::from_solved(stations, offsets, positions);
+            }
+
+            // Refine: every old station is kept and new ones are placed
+            // half way between them
+            num_stations *= 2;
+            offsets = (0..num_stations)
+                .map(|i| {
+                    let before = offsets[(i / 2) % offsets.len()];
+                    let after = offsets[(i / 2 + 1) % offsets.len()];
+                    if i % 2 == 0 {
+                        before
+                    } else {
+                        (before + after) * 0.5
+                    }
+                })
+                .collect();
+        }
+    }
+
+    fn from_solved(stations: Stations, offsets: Vec<f32>, positions: Vec<Vec2>) -> Self {
+        let curvatures: Vec<f32> = (0..NUM_STATIONS)
+            .map(|i| {
+                three_point_curvature(
+                    positions[(i + NUM_STATIONS - 1) % NUM_STATIONS],
+                    positions[i],
+                    positions[(i + 1) % NUM_STATIONS],
+                )
+            })
+            .collect();
+
+        let corner_speeds: Vec<f32> = curvatures
+            .iter()
+            .map(|curvature| {
+                f32::min(
+                    MAX_SPEED,
+                    f32::sqrt(MAX_LATERAL_ACCELERATION / f32::max(*curvature, 0.0001)),
+                )
+            })
+            .collect();
+        let speeds = calc_speed_profile(&positions, &corner_speeds);
+
+        let points = (0..NUM_STATIONS)
+            .map(|i| RacingLinePoint {
+                angle: stations.angles[i],
+                offset: offsets[i],
+                position: positions[i],
+                curvature: curvatures[i],
+                corner_speed: corner_speeds[i],
+                speed: speeds[i],
+            })
+            .collect();
+
+        Self { points }
+    }
+
+    pub fn points(&self) -> &Vec<RacingLinePoint> {
+        &self.points
+    }
+
+    /// Interpolates the racing line at a polar angle around the track
+    pub fn sample(&self, angle: f32) -> RacingLineSample {
+        let step = std::f32::consts::PI * 2.0 / NUM_STATIONS as f32;
+        let station = angle.rem_euclid(std::f32::consts::PI * 2.0) / step;
+        let first = &self.points[station as usize % NUM_STATIONS];
+        let second = &self.points[(station as usize + 1) % NUM_STATIONS];
+        let blend = station.fract();
+
+        RacingLineSample {
+            offset: first.offset + (second.offset - first.offset) * blend,
+            corner_speed: first.corner_speed + (second.corner_speed - first.corner_speed) * blend,
+            speed: first.speed + (second.speed - first.speed) * blend,
+        }
+    }
+}
+
+/// Evenly spaced (by angle) points on the centerline that the racing line
+/// can slide sideways from
+struct Stations {
+    angles: Vec<f32>,
+    centers: Vec<Vec2>,
+    normals: Vec<Vec2>,
+    limits: Vec<f32>,
+}
+
+impl Stations {
+    fn new(map: &Map, num_stations: usize) -> Self {
+        let step = std::f32::consts::PI * 2.0 / num_stations as f32;
+        let angles: Vec<f32> = (0..num_stations).map(|i| i as f32 * step).collect();
+        let centers = angles
+            .iter()
+            .map(|angle| map.centerline_position(*angle))
+            .collect();
+        let normals = angles
+            .iter()
+            .map(|angle| {
+                let direction = map.centerline_direction(*angle);
+                (-direction.1, direction.0)
+            })
+            .collect();
+        let limits = angles
+            .iter()
+            .map(|angle| f32::max(map.wall_distance(*angle) - WALL_MARGIN, 0.0))
+            .collect();
+
+        Self {
+            angles,
+            centers,
+            normals,
+            limits,
+        }
+    }
+
+    fn position(&self, offsets: &[f32], i: usize) -> Vec2 {
+        let i = i % self.angles.len();
+        (
+            self.centers[i].0 + self.normals[i].0 * offsets[i],
+            self.centers[i].1 + self.normals[i].1 * offsets[i],
+        )
+    }
+
+    fn positions(&self, offsets: &[f32]) -> Vec<Vec2> {
+        (0..self.angles.len())
+            .map(|i| self.position(offsets, i))
+            .collect()
+    }
+
+    /// Repeatedly slides each station along its normal to where it
+    /// minimizes the bend with its neighbours, staying inside the walls
+    fn smooth(&self, mut offsets: Vec<f32>) -> Vec<f32> {
+        let num_stations = self.angles.len();
+        for _ in 0..SMOOTHING_ITERATIONS {
+            for i in 0..num_stations {
+                // The point that zeroes the second difference at this
+                // station and its neighbours in a least squares sense
+                let before2 = self.position(&offsets, i + num_stations - 2);
+                let before1 = self.position(&offsets, i + num_stations - 1);
+                let after1 = self.position(&offsets, i + 1);
+                let after2 = self.position(&offsets, i + 2);
+                let here = self.position(&offsets, i);
+
+                let ideal = (
+                    (4.0 * (before1.0 + after1.0) - (before2.0 + after2.0)) / 6.0,
+                    (4.0 * (before1.1 + after1.1) - (before2.1 + after2.1)) / 6.0,
+                );
+                let normal = self.normals[i];
+                let shift = (ideal.0 - here.0) * normal.0 + (ideal.1 - here.1) * normal.1;
+
+                let limit = self.limits[i];
+                offsets[i] = f32::max(f32::min(offsets[i] + shift, limit), -limit);
+            }
+        }
+        offsets
+    }
+}
+
+/// Works out how fast a ship can go at each station. Corners limit the
+/// speed directly, and then the limits are spread forwards (a ship can
+/// only accelerate so fast) and backwards (a ship has to brake in time).
+/// Ships race clockwise, which is towards lower station indices.
+fn calc_speed_profile(positions: &[Vec2], corner_speeds: &[f32]) -> Vec<f32> {
+    let num_stations = positions.len();
+    let mut speeds = corner_speeds.to_vec();
+
+    let racing_order: Vec<usize> = (0..num_stations + 1)
+        .map(|i| (num_stations - i) % num_stations)
+        .collect();
+
+    // Go around twice so the limits wrap past the start line
+    for _ in 0..2 {
+        for pair in racing_order.windows(2) {
+            let (here, next) = (pair[0], pair[1]);
+            let distance = distance_between(positions[here], positions[next]);
+            let reachable = f32::sqrt(speeds[here].powi(2) + 2.0 * MAX_ACCELERATION * distance);
+            speeds[next] = f32::min(speeds[next], reachable);
+        }
+    }
+    for _ in 0..2 {
+        for pair in racing_order.windows(2).rev() {
+            let (here, next) = (pair[0], pair[1]);
+            let distance = distance_between(positions[here], positions[next]);
+            let stoppable = f32::sqrt(speeds[next].powi(2) + 2.0 * MAX_BRAKING * distance);
+            speeds[here] = f32::min(speeds[here], stoppable);
+        }
+    }
+
+    speeds
+}
+
+/// One over the radius of the circle through three points
+fn three_point_curvature(a: Vec2, b: Vec2, c: Vec2) -> f32 {
+    let ab = (b.0 - a.0, b.1 - a.1);
+    let bc = (c.0 - b.0, c.1 - b.1);
+    let cross = ab.0 * bc.1 - ab.1 * bc.0;
+    let denominator = length(&ab) * length(&bc) * distance_between(a, c);
+    if denominator == 0.0 {
+        0.0
+    } else {
+        2.0 * f32::abs(cross) / denominator
+    }
+}
+
+fn distance_between(a: Vec2, b: Vec2) -> f32 {
+    length(&(b.0 - a.0, b.1 - a.1))
+}
@@ -0,0 +1,567 @@
+use super::controller::ShipControl;
+use super::map::Map;
+use super::physics::calc_ship_physics;
+use super::race::{place_on_grid, Race};
+use super::ship::Ship;
+use super::simulation::TICK;
+use super::transform::Transform2d;
+
+const MAGIC: &[u8; 4] = b"SWRP";
+const VERSION: u8 = 1;
+/// Ticks between checksums of the ship states
+const CHECKSUM_INTERVAL: u32 = 30;
+
+/// Controls are stored as 16 bit fixed point. Thrust gives up its lowest
+/// bit to hold the boost flag.
+const STEERING_SCALE: f32 = 32767.0;
+const THRUST_SCALE: f32 = 8192.0;
+
+/// An error to represent a replay file that couldn't be read
+#[derive(Debug)]
+pub enum ReplayError {
+    /// Replay files start with SWRP
+    NotAReplay,
+    UnsupportedVersion(u8),
+    /// The file ended before everything was read
+    Truncated,
+    /// A driver name wasn't valid UTF-8
+    InvalidName,
+    /// A ship's controls don't cover every tick of the race
+    WrongControlCount {
+        ship: usize,
+        expected: u32,
+        got: u32,
+    },
+    /// A ship's controls add up to more ticks than a replay can hold
+    TooManyControls(usize),
+    /// Only a replay without any ticks can have no ships in it
+    NoShips,
+}
+
+/// Everything needed to re-run a race: the map seed, the options and the
+/// controls every ship used on every tick. Checksums of the ship states
+/// are kept along the way so playback can tell if it has drifted from
+/// what was recorded.
+#[derive(Clone, Debug)]
+pub struct Replay {
+    pub seed: u64,
+    pub laps: u32,
+    /// Name of whoever flew each ship, in starting order
+    pub drivers: Vec<String>,
+    /// One entry per tick, holding the control for every ship
+    pub controls: Vec<Vec<ShipControl>>,
+    /// Checksum after every CHECKSUM_INTERVAL ticks
+    pub checksums: Vec<u32>,
+}
+
+impl Replay {
+    pub fn ticks(&self) -> u32 {
+        self.controls.len() as u32
+    }
+
+    pub fn duration(&self) -> f32 {
+        self.ticks() as f32 * TICK
+    }
+
+    /// Packs the replay into its binary format. Each ship's controls are
+    /// run length encoded, as players and idle ships hold the same
+    /// control for many ticks at a time.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = MAGIC.to_vec();
+        bytes.push(VERSION);
+        bytes.extend_from_slice(&self.seed.to_le_bytes());
+        bytes.extend_from_slice(&self.laps.to_le_bytes());
+        bytes.extend_from_slice(&self.ticks().to_le_bytes());
+
+        bytes.push(self.drivers.len() as u8);
+        for driver in self.drivers.iter() {
+            let name = &driver.as_bytes()[..usize::min(driver.len(), u8::MAX as usize)];
+            bytes.push(name.len() as u8);
+            bytes.extend_from_slice(name);
+        }
+
+        bytes.extend_from_slice(&(self.checksums.len() as u32).to_le_bytes());
+        for checksum in self.checksums.iter() {
+            bytes.extend_from_slice(&checksum.to_le_bytes());
+        }
+
+        for ship_id in 0..self.drivers.len() {
+            let mut runs: Vec<(u16, [u8; 4])> = vec![];
+            for tick in self.controls.iter() {
+                let encoded = encode_control(tick[ship_id]);
+                match runs.last_mut() {
+                    Some((length, control)) if *control == encoded && *length < u16::MAX => {
+                        *length += 1
+                    }
+                    _ => runs.push((1, encoded)),
+                }
+            }
+            bytes.extend_from_slice(&(runs.len() as u32).to_le_bytes());
+            for (length, control) in runs {
+                bytes.extend_from_slice(&length.to_le_bytes());
+                bytes.extend_from_slice(&control);
+            }
+        }
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, ReplayError> {
+        let mut reader = Reader { bytes, position: 0 };
+        if reader.take(MAGIC.len())? != MAGIC {
+            return Err(ReplayError::NotAReplay);
+        }
+        let version = reader.u8()?;
+        if version != VERSION {
+            return Err(ReplayError::UnsupportedVersion(version));
+        }
+        let seed = reader.u64()?;
+        let laps = reader.u32()?;
+        let ticks = reader.u32()?;
+
+        let ship_count = reader.u8()? as usize;
+        // Ticks without ships take up no room in the file but would still
+        // each need memory
+        if ship_count == 0 && ticks != 0 {
+            return Err(ReplayError::NoShips);
+        }
+        let mut drivers = Vec::with_capacity(ship_count);
+        for _ in 0..ship_count {
+            let length = reader.u8()? as usize;
+            let name = String::from_utf8(reader.take(length)?.to_vec())
+                .map_err(|_| ReplayError::InvalidName)?;
+            drivers.push(name);
+        }
+
+        let checksum_count = reader.u32()?;
+        let checksums = (0..checksum_count)
+            .map(|_| reader.u32())
+            .collect::<Result<Vec<_>, _>>()?;
+
+        // Check every ship's runs add up before expanding them, so a bad
+        // tick count can't ask for a huge amount of memory
+        let mut ship_runs = Vec::with_capacity(ship_count);
+        for ship in 0..ship_count {
+            let run_count = reader.u32()?;
+            let mut runs = vec![];
+            let mut total: u32 = 0;
+            for _ in 0..run_count {
+                let length = reader.u16()? as u32;
+                let mut encoded = [0; 4];
+                encoded.copy_from_slice(reader.take(4)?);
+                runs.push((length, decode_control(encoded)));
+                total =
+                    u32::checked_add(total, length).ok_or(ReplayError::TooManyControls(ship))?;
+            }
+            if total != ticks {
+                return Err(ReplayError::WrongControlCount {
+                    ship,
+                    expected: ticks,
+                    got: total,
+                });
+            }
+            ship_runs.push(runs);
+        }
+
+        let mut controls = vec![Vec::with_capacity(ship_count); ticks as usize];
+        for runs in ship_runs.iter() {
+            let mut tick = 0;
+            for (length, control) in runs.iter() {
+                for _ in 0..*length {
+                    controls[tick].push(*control);
+                    tick += 1;
+                }
+            }
+        }
+
+        Ok(Self {
+            seed,
+            laps,
+            drivers,
+            controls,
+            checksums,
+        })
+    }
+}
+
+/// Reads little endian values from the front of a byte slice
+struct Reader<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, length: usize) -> Result<&'a [u8], ReplayError> {
+        let end = self.position + length;
+        let bytes = self
+            .bytes
+            .get(self.position..end)
+            .ok_or(ReplayError::Truncated)?;
+        self.position = end;
+        Ok(bytes)
+    }
+
+    fn u8(&mut self) -> Result<u8, ReplayError> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, ReplayError> {
+        let mut bytes = [0; 2];
+        bytes.copy_from_slice(self.take(2)?);
+        Ok(u16::from_le_bytes(bytes))
+    }
+
+    fn u32(&mut self) -> Result<u32, ReplayError> {
+        let mut bytes = [0; 4];
+        bytes.copy_from_slice(self.take(4)?);
+        Ok(u32::from_le_bytes(bytes))
+    }
+
+    fn u64(&mut self) -> Result<u64, ReplayError> {
+        let mut bytes = [0; 8];
+        bytes.copy_from_slice(self.take(8)?);
+        Ok(u64::from_le_bytes(bytes))
+    }
+}
+
+/// Packs a control into four bytes, rounding it to what can be stored
+pub fn encode_control(control: ShipControl) -> [u8; 4] {
+    let steering = control.steering.clamp(-1.0, 1.0);
+    let thrust = control.thrust.clamp(-1.0, 3.0);
+    let steering = f32::round(steering * STEERING_SCALE) as i16;
+    let thrust = (f32::round(thrust * THRUST_SCALE) as i16 & !1) | control.boost as i16;
+
+    let mut encoded = [0; 4];
+    encoded[..2].copy_from_slice(&steering.to_le_bytes());
+    encoded[2..].copy_from_slice(&thrust.to_le_bytes());
+    encoded
+}
+
+pub fn decode_control(encoded: [u8; 4]) -> ShipControl {
+    let steering = i16::from_le_bytes([encoded[0], encoded[1]]);
+    let thrust = i16::from_le_bytes([encoded[2], encoded[3]]);
+    ShipControl {
+        steering: steering as f32 / STEERING_SCALE,
+        thrust: (thrust & !1) as f32 / THRUST_SCALE,
+        boost: thrust & 1 == 1,
+    }
+}
+
+/// Rounds a control to what a replay can store. Recorded races have to
+/// fly with the rounded controls or playback would drift.
+pub fn quantize(control: ShipControl) -> ShipControl {
+    decode_control(encode_control(control))
+}
+
+/// Hashes the parts of every ship that the physics moves on (FNV-1a)
+pub fn checksum(ships: &[Ship]) -> u32 {
+    let mut hash: u32 = 0x811c_9dc5;
+    for ship in ships {
+        let values = [
+            ship.position.x,
+            ship.position.y,
+            ship.position.rot,
+            ship.velocity.x,
+            ship.velocity.y,
+            ship.velocity.rot,
+            ship.boost,
+        ];
+        for value in values.iter() {
+            for byte in value.to_bits().to_le_bytes().iter() {
+                hash ^= *byte as u32;
+                hash = hash.wrapping_mul(0x0100_0193);
+            }
+        }
+    }
+    hash
+}
+
+/// Builds up a replay one tick at a time while a race is run
+pub struct Recorder {
+    replay: Replay,
+}
+
+impl Recorder {
+    pub fn new(seed: u64, laps: u32, drivers: Vec<String>) -> Self {
+        Self {
+            replay: Replay {
+                seed,
+                laps,
+                drivers,
+                controls: vec![],
+                checksums: vec![],
+            },
+        }
+    }
+
+    /// Stores the controls used for a tick, along with the ships once the
+    /// tick has been simulated. The controls should have gone through
+    /// `quantize`.
+    pub fn record(&mut self, controls: &[ShipControl], ships: &[Ship]) {
+        self.replay.controls.push(controls.to_vec());
+        if self.replay.ticks().is_multiple_of(CHECKSUM_INTERVAL) {
+            self.replay.checksums.push(checksum(ships));
+        }
+    }
+
+    pub fn replay(&self) -> &Replay {
+        &self.replay
+    }
+
+    pub fn finish(self) -> Replay {
+        self.replay
+    }
+}
+
+/// Re-runs a recorded race through the physics, checking against the
+/// recorded checksums as it goes
+pub struct Playback {
+    pub replay: Replay,
+    pub map: Map,
+    pub ships: Vec<Ship>,
+    pub race: Race,
+    tick: u32,
+    /// The first tick where the ships didn't match the recording
+    pub desync: Option<u32>,
+}
+
+impl Playback {
+    pub fn new(replay: Replay) -> Self {
+        let mut map = Map::new();
+        map.randomize(replay.seed);
+        let mut ships: Vec<Ship> = replay
+            .drivers
+            .iter()
+            .map(|_| Ship::new((1.0, 1.0, 1.0, 1.0), Transform2d::new(0.0, 0.0, 0.0, 0.1)))
+            .collect();
+        place_on_grid(&mut ships, &map);
+        let race = Race::new(&ships, &map, replay.laps);
+
+        Self {
+            replay,
+            map,
+            ships,
+            race,
+            tick: 0,
+            desync: None,
+        }
+    }
+
+    /// Goes back to the start of the race. Ship colors are kept.
+    pub fn restart(&mut self) {
+        place_on_grid(&mut self.ships, &self.map);
+        self.race = Race::new(&self.ships, &self.map, self.replay.laps);
+        self.tick = 0;
+    }
+
+    pub fn tick(&self) -> u32 {
+        self.tick
+    }
+
+    pub fn time(&self) -> f32 {
+        self.tick as f32 * TICK
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.tick >= self.replay.ticks()
+    }
+
+    /// Simulates one recorded tick. Returns false once the replay has
+    /// run out.
+    pub fn step(&mut self) -> bool {
+        let controls = match self.replay.controls.get(self.tick as usize) {
+            Some(controls) => controls,
+            None => return false,
+        };
+        for (ship, control) in self.ships.iter_mut().zip(controls.iter()) {
+            control.apply(ship);
+        }
+        calc_ship_physics(&mut self.ships, &self.map, TICK);
+        self.race.update(&self.ships, &self.map, TICK);
+        self.tick += 1;
+
+        if self.tick.is_multiple_of(CHECKSUM_INTERVAL) && self.desync.is_none() {
+            let recorded = self
+                .replay
+                .checksums
+                .get((self.tick / CHECKSUM_INTERVAL - 1) as usize);
+            if recorded != Some(&checksum(&self.ships)) {
+                self.desync = Some(self.tick);
+            }
+        }
+        true
+    }
+
+    /// Jumps to a tick. Going backwards re-simulates from the start, as
+    /// only the controls are stored.
+    pub fn seek(&mut self, tick: u32) {
+        let tick = u32::min(tick, self.replay.ticks());
+        if tick < self.tick {
+            self.restart();
+        }
+        while self.tick < tick {
+            self.step();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Controls that wander about, held for a while at a time like a
+    /// player's would be
+    fn scripted_controls(ships: usize, ticks: u32) -> Vec<Vec<ShipControl>> {
+        (0..ticks)
+            .map(|tick| {
+                (0..ships)
+                    .map(|ship| {
+                        let phase = (tick / 20) as f32 + ship as f32;
+                        quantize(ShipControl {
+                            steering: f32::sin(phase),
+                            thrust: 1.0 + f32::cos(phase * 0.7),
+                            boost: (tick / 50 + ship as u32).is_multiple_of(3),
+                        })
+                    })
+                    .collect()
+            })
+            .collect()
+    }
+
+    /// Records a replay of the given controls the way the game does
+    fn recorded(ships: usize, ticks: u32) -> Replay {
+        let drivers = (0..ships).map(|ship| format!("ship {}", ship)).collect();
+        let controls = scripted_controls(ships, ticks);
+        let mut playback = Playback::new(Replay {
+            seed: 7,
+            laps: 1,
+            drivers,
+            controls: controls.clone(),
+            checksums: vec![],
+        });
+        let mut recorder = Recorder::new(7, 1, playback.replay.drivers.clone());
+        for tick in controls.iter() {
+            playback.step();
+            recorder.record(tick, &playback.ships);
+        }
+        recorder.finish()
+    }
+
+    fn header(ship_count: u8, ticks: u32) -> Vec<u8> {
+        let mut bytes = MAGIC.to_vec();
+        bytes.push(VERSION);
+        bytes.extend_from_slice(&7u64.to_le_bytes());
+        bytes.extend_from_slice(&1u32.to_le_bytes());
+        bytes.extend_from_slice(&ticks.to_le_bytes());
+        bytes.push(ship_count);
+        // Every driver has an empty name
+        bytes.resize(bytes.len() + ship_count as usize, 0);
+        bytes.extend_from_slice(&0u32.to_le_bytes());
+        bytes
+    }
+
+    #[test]
+    fn replays_read_back() {
+        let replay = recorded(3, 200);
+        let read = Replay::from_bytes(&replay.to_bytes()).expect("replay reads back");
+        assert_eq!(read.seed, replay.seed);
+        assert_eq!(read.laps, replay.laps);
+        assert_eq!(read.drivers, replay.drivers);
+        assert_eq!(read.controls, replay.controls);
+        assert_eq!(read.checksums, replay.checksums);
+    }
+
+    #[test]
+    fn long_runs_are_split() {
+        let ticks = u16::MAX as u32 * 2 + 10;
+        let replay = Replay {
+            seed: 1,
+            laps: 1,
+            drivers: vec!["idle".to_string()],
+            controls: vec![vec![ShipControl::default()]; ticks as usize],
+            checksums: vec![],
+        };
+        let read = Replay::from_bytes(&replay.to_bytes()).expect("replay reads back");
+        assert_eq!(read.ticks(), ticks);
+    }
+
+    #[test]
+    fn quantized_controls_stay_put() {
+        for control in scripted_controls(2, 300).iter().flatten() {
+            assert_eq!(quantize(*control), *control);
+        }
+        let control = ShipControl {
+            steering: 0.123_456,
+            thrust: 2.5,
+            boost: true,
+        };
+        let rounded = quantize(control);
+        assert!(f32::abs(rounded.steering - control.steering) <= 0.5 / STEERING_SCALE);
+        assert!(f32::abs(rounded.thrust - control.thrust) <= 2.0 / THRUST_SCALE);
+        assert!(rounded.boost);
+
+        let out_of_range = quantize(ShipControl {
+            steering: -5.0,
+            thrust: 10.0,
+            boost: false,
+        });
+        assert_eq!(out_of_range.steering, -1.0);
+        assert!(f32::abs(out_of_range.thrust - 3.0) <= 2.0 / THRUST_SCALE);
+    }
+
+    #[test]
+    fn changed_controls_fail_the_checksum() {
+        let replay = recorded(2, 120);
+        let mut playback = Playback::new(replay.clone());
+        while playback.step() {}
+        assert_eq!(playback.desync, None);
+
+        let mut changed = replay;
+        changed.controls[40][1].steering = -changed.controls[40][1].steering;
+        let mut playback = Playback::new(changed);
+        while playback.step() {}
+        assert_eq!(playback.desync, Some(60));
+    }
+
+    #[test]
+    fn rejects_broken_files() {
+        let bytes = recorded(2, 60).to_bytes();
+        assert!(matches!(
+            Replay::from_bytes(b"NOPE"),
+            Err(ReplayError::NotAReplay)
+        ));
+        let mut newer = bytes.clone();
+        newer[MAGIC.len()] = VERSION + 1;
+        assert!(matches!(
+            Replay::from_bytes(&newer),
+            Err(ReplayError::UnsupportedVersion(_))
+        ));
+        for length in 0..bytes.len() {
+            assert!(Replay::from_bytes(&bytes[..length]).is_err());
+        }
+    }
+
+    #[test]
+    fn rejects_ticks_without_ships() {
+        assert!(matches!(
+            Replay::from_bytes(&header(0, 1_000_000)),
+            Err(ReplayError::NoShips)
+        ));
+        let empty = Replay::from_bytes(&header(0, 0)).expect("an empty replay is fine");
+        assert_eq!(empty.ticks(), 0);
+    }
+
+    #[test]
+    fn rejects_runs_adding_up_past_the_limit() {
+        let mut bytes = header(1, 10);
+        let run_count = (u32::MAX / u16::MAX as u32) + 2;
+        bytes.extend_from_slice(&run_count.to_le_bytes());
+        for _ in 0..run_count {
+            bytes.extend_from_slice(&u16::MAX.to_le_bytes());
+            bytes.extend_from_slice(&[0; 4]);
+        }
+        assert!(matches!(
+            Replay::from_bytes(&bytes),
+            Err(ReplayError::TooManyControls(0))
+        ));
+    }
+}
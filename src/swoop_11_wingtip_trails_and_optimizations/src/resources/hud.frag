@@ -0,0 +1,13 @@
+#version 300 es
+
+precision mediump float;
+in vec2 uv;
+in vec4 color;
+out vec4 FragColor;
+
+uniform sampler2D font_texture;
+
+void main() {
+    // The font is white, so only its alpha matters
+    FragColor = color * vec4(1.0, 1.0, 1.0, texture(font_texture, uv).a);
+}
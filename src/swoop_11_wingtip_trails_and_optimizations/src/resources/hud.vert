@@ -0,0 +1,22 @@
+#version 300 es
+/*
+ * Draws HUD quads given in canvas pixels from the top left corner. The
+ * camera isn't involved, so the HUD stays put on the screen.
+ */
+precision highp float;
+in vec2 aVertexPosition;
+in vec2 aTextureCoord;
+in vec4 aVertexColor;
+
+uniform vec2 screen_size;
+
+out vec2 uv;
+out vec4 color;
+
+void main() {
+    vec2 pos = aVertexPosition / screen_size * 2.0 - 1.0;
+
+    uv = aTextureCoord;
+    color = aVertexColor;
+    gl_Position = vec4(pos.x, -pos.y, 0.0, 1.0);
+}
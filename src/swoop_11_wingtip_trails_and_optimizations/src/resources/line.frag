@@ -0,0 +1,10 @@
+#version 300 es
+
+precision mediump float;
+out vec4 FragColor;
+
+uniform vec4 line_color;
+
+void main() {
+    FragColor = line_color;
+}
@@ -0,0 +1,18 @@
+#version 300 es
+/*
+ * Draws plain lines given in world space, for debug overlays.
+ */
+precision highp float;
+in vec2 aVertexPosition;
+
+uniform mat3 world_to_camera;
+uniform mat3 camera_to_clipspace; // Includes canvas resolution/aspect ratio
+
+void main() {
+    mat3 camera_to_world = inverse(world_to_camera);
+    mat3 clipspace_to_camera = inverse(camera_to_clipspace);
+    mat3 world_to_clipspace = clipspace_to_camera * camera_to_world;
+
+    vec2 pos = (world_to_clipspace * vec3(aVertexPosition, 1.0)).xy;
+    gl_Position = vec4(pos, 0.0, 1.0);
+}
@@ -11,10 +11,13 @@ const float track_edge_line_width = 0.5;
 
 
 uniform float track_base_radius;
-uniform float track_width;
 uniform vec4 sin_consts[2];
 uniform vec4 cos_consts[2];
 
+uniform float track_base_width;
+uniform vec4 width_sin_consts;
+uniform vec4 width_cos_consts;
+
 uniform vec2 start_line_tangent;
 uniform vec2 start_line_position;
 
@@ -37,6 +40,10 @@ float map_function(vec2 position) {
     track_radius += dot(cos(angles_1), cos_consts_1);
     track_radius += dot(cos(angles_2), cos_consts_2);
 
+    float track_width = track_base_width;
+    track_width += dot(sin(angles_1), width_sin_consts);
+    track_width += dot(cos(angles_1), width_cos_consts);
+
     float track_sdf = course - track_radius;
     track_sdf = abs(track_sdf) - track_width;
     return track_sdf;
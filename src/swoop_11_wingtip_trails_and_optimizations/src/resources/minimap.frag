@@ -0,0 +1,102 @@
+#version 300 es
+
+precision highp float;
+in vec2 uv;
+out vec4 FragColor;
+
+const int MAX_SHIPS = 5;
+/// Size of the ship dots, as a fraction of the minimap's radius
+const float ship_dot_radius = 0.05;
+const float edge_line_pixels = 1.5;
+const vec4 background_color = vec4(0.0, 0.0, 0.0, 0.6);
+const vec4 track_color = vec4(0.9, 0.9, 0.9, 0.15);
+const vec4 edge_color = vec4(0.9, 0.9, 0.9, 1.0);
+
+// The same track as map.frag, from the same uniforms
+uniform float track_base_radius;
+uniform vec4 sin_consts[2];
+uniform vec4 cos_consts[2];
+
+uniform float track_base_width;
+uniform vec4 width_sin_consts;
+uniform vec4 width_cos_consts;
+
+/// World distance from the middle of the minimap to its edge
+uniform float view_radius;
+/// How far the track is turned, in radians
+uniform float view_rotation;
+
+uniform vec2 ship_positions[MAX_SHIPS];
+uniform vec4 ship_colors[MAX_SHIPS];
+uniform int ship_count;
+/// Drawn larger than the others
+uniform int followed_ship;
+
+
+float map_function(vec2 position) {
+    float course = length(position - vec2(0.0, 0.0));
+    float angle = atan(position.y, position.x);
+    
+    vec4 angles_1 = vec4(angle, angle*2.0, angle*3.0, angle*4.0);
+    vec4 angles_2 = vec4(angle*5.0, angle*6.0, angle*7.0, angle*8.0);
+    
+    vec4 sin_consts_1 = sin_consts[0];
+    vec4 sin_consts_2 = sin_consts[1];
+    vec4 cos_consts_1 = cos_consts[0];
+    vec4 cos_consts_2 = cos_consts[1];
+
+    float track_radius = track_base_radius;
+    track_radius += dot(sin(angles_1), sin_consts_1);
+    track_radius += dot(sin(angles_2), sin_consts_2);
+    track_radius += dot(cos(angles_1), cos_consts_1);
+    track_radius += dot(cos(angles_2), cos_consts_2);
+
+    float track_width = track_base_width;
+    track_width += dot(sin(angles_1), width_sin_consts);
+    track_width += dot(cos(angles_1), width_cos_consts);
+
+    float track_sdf = course - track_radius;
+    track_sdf = abs(track_sdf) - track_width;
+    return track_sdf;
+}
+
+vec4 over(vec4 top, vec4 bottom) {
+    return mix(bottom, vec4(top.rgb, 1.0), top.a);
+}
+
+
+void main() {
+    float from_center = length(uv);
+    // Pixels across one unit of uv, so lines stay the same width on screen
+    float pixel = fwidth(from_center);
+    if (from_center > 1.0 + pixel) {
+        discard;
+    }
+
+    float c = cos(view_rotation);
+    float s = sin(view_rotation);
+    vec2 world = mat2(c, s, -s, c) * uv * view_radius;
+
+    float track = map_function(world) / view_radius;
+    float edge = clamp(edge_line_pixels - abs(track) / pixel, 0.0, 1.0);
+
+    vec4 color = background_color;
+    if (track < 0.0) {
+        color = over(track_color, color);
+    }
+    color = over(vec4(edge_color.rgb, edge), color);
+
+    for (int i = 0; i < MAX_SHIPS; i++) {
+        if (i >= ship_count) {
+            break;
+        }
+        float radius = ship_dot_radius * (i == followed_ship ? 1.5 : 1.0);
+        float distance = length(world - ship_positions[i]) / view_radius;
+        float dot_alpha = clamp((radius - distance) / pixel, 0.0, 1.0);
+        color = over(vec4(ship_colors[i].rgb, dot_alpha), color);
+    }
+
+    // Soften the round edge
+    color.a *= clamp((1.0 - from_center) / pixel + 1.0, 0.0, 1.0);
+    FragColor = color;
+}
@@ -0,0 +1,17 @@
+#version 300 es
+/*
+ * Draws the minimap as a square in screen space. The square goes from -1
+ * to 1 in both directions, and minimap_rect places it on the screen as a
+ * center and half size in clip space.
+ */
+precision highp float;
+in vec2 aVertexPosition;
+
+uniform vec4 minimap_rect;
+
+out vec2 uv;
+
+void main() {
+    uv = aVertexPosition;
+    gl_Position = vec4(minimap_rect.xy + aVertexPosition * minimap_rect.zw, 0.0, 1.0);
+}
@@ -26,5 +26,8 @@ void main() {
 	
 	vec4 engine_color = ship_engine * ship_color;
 	FragColor += neon(1.0 - raw_sprite.b, engine_color, 1.0) * ship_engine;
+
+	// Translucent ships such as ghosts are drawn dimmer
+	FragColor *= ship_color.a;
 }
 
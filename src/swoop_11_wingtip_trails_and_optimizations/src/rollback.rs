@@ -0,0 +1,463 @@
+use std::collections::{BTreeMap, VecDeque};
+
+use super::controller::ShipControl;
+use super::map::Map;
+use super::physics::calc_ship_physics;
+use super::protocol::ShipState;
+use super::race::{place_on_grid, Race};
+use super::replay::quantize;
+use super::ship::Ship;
+use super::simulation::TICK;
+use super::transform::Transform2d;
+
+/// How many ticks back the race can be wound to fix up a late input or a
+/// correction from the server. Anything older than this is too late to
+/// change anything.
+pub const MAX_ROLLBACK: u32 = 60;
+/// Ticks of controls sent in each input, so that one going missing on
+/// the way doesn't matter
+pub const INPUT_REDUNDANCY: u32 = 8;
+/// Ticks a client keeps ahead of the last snapshot, so that its inputs
+/// reach the server before they are needed
+pub const INPUT_LEAD: u32 = 3;
+/// If a client gets this far ahead of the last snapshot it holds on for
+/// the server instead of guessing any further
+pub const MAX_LEAD: u32 = 20;
+/// Most ticks run in one go when catching up with the server
+const MAX_CATCH_UP: u32 = 10;
+
+/// The race as of the start of a tick, along with the controls the tick
+/// was run with, whether they were known or only predicted
+struct SavedTick {
+    ships: Vec<Ship>,
+    race: Race,
+    controls: Vec<ShipControl>,
+}
+
+/// How much winding back has gone on
+#[derive(Clone, Copy, Debug, Default)]
+pub struct RollbackStats {
+    /// Times the race was wound back and run again
+    pub rollbacks: u32,
+    /// Ticks run again over all the rollbacks
+    pub resimulated: u32,
+    /// Furthest back a single rollback went, in ticks
+    pub deepest: u32,
+    /// Inputs and corrections that came in too late to be used
+    pub too_late: u32,
+}
+
+/// A race that runs on ahead of what it has heard from everyone else, so
+/// that a player's own ship answers the controls straight away. Other
+/// ships are flown with a guess at their controls, the last ones heard
+/// from them. When the real controls turn up late, or the server says
+/// where the ships really were, the race is wound back to that tick and
+/// run forwards again to catch up. This relies on the physics coming out
+/// exactly the same every time it is run.
+pub struct Rollback {
+    pub map: Map,
+    pub ships: Vec<Ship>,
+    pub race: Race,
+    /// The next tick to be run, counting from the start
+    tick: u32,
+    /// Controls that are known for sure, by ship and then tick
+    inputs: Vec<BTreeMap<u32, ShipControl>>,
+    /// The last MAX_ROLLBACK ticks that were run, oldest first
+    history: VecDeque<SavedTick>,
+    /// The earliest tick that has to be run again before carrying on
+    resimulate_from: Option<u32>,
+    /// Ticks before this have been corrected by the server, so late
+    /// inputs for them no longer change anything
+    authoritative: u32,
+    pub stats: RollbackStats,
+}
+
+impl Rollback {
+    /// Lines up the ships on the grid, the same way as `Simulation`
+    pub fn new(map: Map, ships: usize, laps: u32) -> Self {
+        let mut ships: Vec<Ship> = (0..ships)
+            .map(|_| Ship::new((1.0, 1.0, 1.0, 1.0), Transform2d::new(0.0, 0.0, 0.0, 0.1)))
+            .collect();
+        place_on_grid(&mut ships, &map);
+        let race = Race::new(&ships, &map, laps);
+
+        Self {
+            map,
+            inputs: ships.iter().map(|_| BTreeMap::new()).collect(),
+            ships,
+            race,
+            tick: 0,
+            history: VecDeque::new(),
+            resimulate_from: None,
+            authoritative: 0,
+            stats: RollbackStats::default(),
+        }
+    }
+
+    pub fn tick(&self) -> u32 {
+        self.tick
+    }
+
+    /// The control a ship is flown with on a tick: the one it sent for
+    /// that tick if it has arrived, or else the last one before it
+    pub fn control(&self, ship_id: usize, tick: u32) -> ShipControl {
+        self.inputs[ship_id]
+            .range(..=tick)
+            .next_back()
+            .map_or_else(ShipControl::default, |(_, control)| *control)
+    }
+
+    /// How many ticks to run this frame to stay the right distance ahead
+    /// of the last snapshot from the server
+    pub fn ticks_to_run(&self, server_tick: u32) -> u32 {
+        let lead = server_tick + INPUT_LEAD;
+        if self.tick > server_tick + MAX_LEAD {
+            0
+        } else if self.tick < lead {
+            u32::min(lead - self.tick, MAX_CATCH_UP)
+        } else {
+            1
+        }
+    }
+
+    /// Controls for a run of ticks ending with `tick`, oldest first. Sending
+    /// a few at a time means one lost on the way is made up for by the next.
+    pub fn recent_controls(&self, ship_id: usize, tick: u32, count: u32) -> Vec<ShipControl> {
+        let first = (tick + 1).saturating_sub(count);
+        (first..=tick)
+            .map(|tick| self.control(ship_id, tick))
+            .collect()
+    }
+
+    /// Records the control a ship is flown with on a tick. If that tick
+    /// has already been run with something else, the race will be run
+    /// again from there on the next `advance`.
+    pub fn add_input(&mut self, ship_id: usize, tick: u32, control: ShipControl) {
+        let control = quantize(control);
+        let known = match self.inputs.get_mut(ship_id) {
+            Some(inputs) => inputs.insert(tick, control),
+            None => return,
+        };
+        // Inputs are sent more than once, so most have been seen already
+        if known == Some(control) {
+            return;
+        }
+
+        // Ticks the server has already corrected are left as they are,
+        // but the guesses made after them may have changed
+        let from = u32::max(tick, self.authoritative);
+        if from >= self.tick {
+            return;
+        }
+        let saved = match self.saved(from) {
+            Some(saved) => saved,
+            None => {
+                self.stats.too_late += 1;
+                return;
+            }
+        };
+        if saved.controls[ship_id] != self.control(ship_id, from) {
+            self.resimulate_from = Some(self.resimulate_from.map_or(from, |f| u32::min(f, from)));
+        }
+    }
+
+    /// Puts the ships where the server says they were at the start of a
+    /// tick. Anything run since is run again from there, and late inputs
+    /// for before it are no longer looked at.
+    pub fn correct(&mut self, tick: u32, states: &[ShipState]) {
+        // Running behind the server, so catch up to it first
+        if self.tick < tick {
+            self.settle();
+            while self.tick < tick {
+                self.step();
+            }
+        }
+        let oldest = self.oldest_tick();
+        if tick == self.tick {
+            for (ship, state) in self.ships.iter_mut().zip(states.iter()) {
+                state.apply(ship);
+            }
+            // Whatever was going to be run again is now out of date
+            self.resimulate_from = None;
+        } else if tick >= oldest {
+            let saved = &mut self.history[(tick - oldest) as usize];
+            for (ship, state) in saved.ships.iter_mut().zip(states.iter()) {
+                state.apply(ship);
+            }
+            self.resimulate_from = Some(tick);
+        } else {
+            self.stats.too_late += 1;
+            return;
+        }
+        self.authoritative = u32::max(self.authoritative, tick);
+    }
+
+    /// Runs the next tick, after running again anything that late inputs
+    /// or corrections have changed
+    pub fn advance(&mut self) {
+        self.settle();
+        self.step();
+    }
+
+    /// The ships as they were at the start of a tick, if it was recent
+    /// enough to still be kept
+    pub fn ships_at(&self, tick: u32) -> Option<&[Ship]> {
+        match tick == self.tick {
+            true => Some(&self.ships),
+            false => self.saved(tick).map(|saved| &saved.ships[..]),
+        }
+    }
+
+    /// Winds back to the earliest tick that has changed and runs forwards
+    /// again to where the race had got to
+    pub fn settle(&mut self) {
+        let from = match self.resimulate_from.take() {
+            Some(from) => from,
+            None => return,
+        };
+        let now = self.tick;
+        let index = (from - self.oldest_tick()) as usize;
+        let saved = match self.history.drain(index..).next() {
+            Some(saved) => saved,
+            None => return,
+        };
+        self.ships = saved.ships;
+        self.race = saved.race;
+        self.tick = from;
+        while self.tick < now {
+            self.step();
+        }
+
+        self.stats.rollbacks += 1;
+        self.stats.resimulated += now - from;
+        self.stats.deepest = u32::max(self.stats.deepest, now - from);
+    }
+
+    fn step(&mut self) {
+        let controls: Vec<ShipControl> = (0..self.ships.len())
+            .map(|ship_id| self.control(ship_id, self.tick))
+            .collect();
+        self.history.push_back(SavedTick {
+            ships: self.ships.clone(),
+            race: self.race.clone(),
+            controls: controls.clone(),
+        });
+        if self.history.len() > MAX_ROLLBACK as usize {
+            self.history.pop_front();
+        }
+
+        for (ship, control) in self.ships.iter_mut().zip(controls.iter()) {
+            control.apply(ship);
+        }
+        calc_ship_physics(&mut self.ships, &self.map, TICK);
+        self.race.update(&self.ships, &self.map, TICK);
+        self.tick += 1;
+
+        // Inputs from before the history can't change anything, apart
+        // from the last one which the guesses are still made from
+        let oldest = self.oldest_tick();
+        for inputs in self.inputs.iter_mut() {
+            if let Some(keep) = inputs.range(..oldest).next_back().map(|(tick, _)| *tick) {
+                *inputs = inputs.split_off(&keep);
+            }
+        }
+    }
+
+    fn oldest_tick(&self) -> u32 {
+        self.tick - self.history.len() as u32
+    }
+
+    fn saved(&self, tick: u32) -> Option<&SavedTick> {
+        let oldest = self.oldest_tick();
+        match tick >= oldest {
+            true => self.history.get((tick - oldest) as usize),
+            false => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ai::{AiDriver, ALL_PROFILES};
+    use crate::controller::ShipController;
+    use crate::protocol::ClientMessage;
+    use crate::replay::checksum;
+    use crate::simulation::Simulation;
+    use crate::transport::{LinkConditions, SimulatedLink};
+
+    const SEEDS: [u64; 2] = [1, 7];
+    const PEERS: usize = 4;
+    /// Long enough for the pack to have spread out and be passing each
+    /// other
+    const TICKS: u32 = 600;
+    /// Ticks of controls in every message. Peers never get more than
+    /// MAX_LEAD ticks apart, so this covers everything another peer might
+    /// be missing.
+    const REDUNDANCY: u32 = MAX_ROLLBACK / 2;
+    /// Gives up if the peers are still waiting on each other after this
+    /// many ticks past the end of the race
+    const DRAIN_LIMIT: u32 = 10_000;
+    const BAD_LINK: LinkConditions = LinkConditions {
+        latency: 6,
+        jitter: 4,
+        loss: 0.1,
+    };
+
+    /// Races the AI with nothing in the way, giving every tick's controls
+    /// and the checksum of the ships at the end
+    fn reference_race(map: &Map, seed: u64) -> (Vec<Vec<ShipControl>>, u32) {
+        let controllers = ALL_PROFILES[..PEERS]
+            .iter()
+            .enumerate()
+            .map(|(id, profile)| {
+                Box::new(AiDriver::new(*profile, seed + id as u64)) as Box<dyn ShipController>
+            })
+            .collect();
+        let mut simulation = Simulation::new(map.clone(), controllers, 1);
+
+        let mut ticks = vec![];
+        for _ in 0..TICKS {
+            let controls: Vec<ShipControl> = simulation
+                .calc_controls(TICK)
+                .expect("the built in AI doesn't fail")
+                .into_iter()
+                .map(quantize)
+                .collect();
+            simulation.advance(&controls, TICK);
+            ticks.push(controls);
+        }
+        (ticks, checksum(&simulation.ships))
+    }
+
+    /// One player's view of the race. Its own ship is flown with the
+    /// reference controls, and everyone else's with whatever has come in.
+    struct Peer {
+        ship: usize,
+        rollback: Rollback,
+        /// For each ship, the first tick whose input hasn't been heard
+        /// yet, counting only unbroken runs from the start
+        heard_until: Vec<u32>,
+    }
+
+    impl Peer {
+        /// Ticks the other ships have all been heard up to
+        fn confirmed(&self) -> u32 {
+            self.heard_until
+                .iter()
+                .enumerate()
+                .filter(|(ship, _)| *ship != self.ship)
+                .map(|(_, heard)| *heard)
+                .min()
+                .unwrap_or(0)
+        }
+
+        fn receive(&mut self, sender: usize, bytes: &[u8]) {
+            let (tick, controls) = match ClientMessage::from_bytes(bytes) {
+                Ok(ClientMessage::Input { tick, controls }) => (tick, controls),
+                other => panic!("unexpected message {:?}", other),
+            };
+            let first = (tick + 1).saturating_sub(controls.len() as u32);
+            if first <= self.heard_until[sender] {
+                self.heard_until[sender] = u32::max(self.heard_until[sender], tick + 1);
+            }
+            for (input_tick, control) in (first..=tick).zip(controls) {
+                self.rollback.add_input(sender, input_tick, control);
+            }
+        }
+
+        /// The latest run of inputs for this peer's own ship, if it has
+        /// any
+        fn latest_input(&self) -> Option<Vec<u8>> {
+            let tick = self.rollback.tick().checked_sub(1)?;
+            let controls = self.rollback.recent_controls(self.ship, tick, REDUNDANCY);
+            Some(ClientMessage::Input { tick, controls }.to_bytes())
+        }
+    }
+
+    /// Runs the reference race again with each ship on its own peer, only
+    /// hearing about the others over links with these conditions. Gives
+    /// each peer once they have all heard everything, along with the
+    /// reference checksum.
+    fn race_peers(seed: u64, conditions: LinkConditions) -> (Vec<Peer>, u32) {
+        let mut map = Map::new();
+        map.randomize(seed);
+        let (reference, expected) = reference_race(&map, seed);
+
+        let mut peers: Vec<Peer> = (0..PEERS)
+            .map(|ship| Peer {
+                ship,
+                rollback: Rollback::new(map.clone(), PEERS, 1),
+                heard_until: vec![0; PEERS],
+            })
+            .collect();
+        // A link each way between every pair of peers
+        let mut links: Vec<Vec<SimulatedLink<Vec<u8>>>> = (0..PEERS)
+            .map(|from| {
+                (0..PEERS)
+                    .map(|to| {
+                        SimulatedLink::new(conditions, seed * 1000 + (from * PEERS + to) as u64)
+                    })
+                    .collect()
+            })
+            .collect();
+
+        let done = |peers: &[Peer]| {
+            peers
+                .iter()
+                .all(|peer| peer.rollback.tick() == TICKS && peer.confirmed() >= TICKS)
+        };
+        let mut now = 0;
+        while !done(&peers) {
+            assert!(now < TICKS + DRAIN_LIMIT, "the peers never caught up");
+            for (to, peer) in peers.iter_mut().enumerate() {
+                for (from, from_links) in links.iter_mut().enumerate() {
+                    for bytes in from_links[to].receive(now) {
+                        peer.receive(from, &bytes);
+                    }
+                }
+            }
+            for (from, peer) in peers.iter_mut().enumerate() {
+                let tick = peer.rollback.tick();
+                if tick < TICKS && tick < peer.confirmed() + MAX_LEAD {
+                    let control = reference[tick as usize][peer.ship];
+                    peer.rollback.add_input(peer.ship, tick, control);
+                    peer.rollback.advance();
+                }
+                if let Some(bytes) = peer.latest_input() {
+                    for (to, link) in links[from].iter_mut().enumerate() {
+                        if to != from {
+                            link.send(now, bytes.clone());
+                        }
+                    }
+                }
+            }
+            now += 1;
+        }
+        for peer in peers.iter_mut() {
+            peer.rollback.settle();
+        }
+        (peers, expected)
+    }
+
+    #[test]
+    fn peers_match_the_reference_over_a_perfect_link() {
+        for seed in SEEDS.iter() {
+            let (peers, expected) = race_peers(*seed, LinkConditions::PERFECT);
+            for peer in peers.iter() {
+                assert_eq!(checksum(&peer.rollback.ships), expected, "seed {}", seed);
+            }
+        }
+    }
+
+    #[test]
+    fn peers_match_the_reference_over_a_bad_link() {
+        for seed in SEEDS.iter() {
+            let (peers, expected) = race_peers(*seed, BAD_LINK);
+            for peer in peers.iter() {
+                assert_eq!(checksum(&peer.rollback.ships), expected, "seed {}", seed);
+                assert!(peer.rollback.stats.rollbacks > 0, "seed {}", seed);
+            }
+        }
+    }
+}
@@ -0,0 +1,243 @@
+use rhai::module_resolvers::DummyModuleResolver;
+use rhai::{Array, CallFnOptions, Dynamic, Engine, Map as ScriptMap, Scope, AST};
+
+use super::controller::{ControllerError, Observation, ShipControl, ShipController};
+use super::map::Map;
+
+// Limits so that a buggy or hostile script can't hang the game or eat
+// all the memory
+const MAX_OPERATIONS: u64 = 100_000;
+const MAX_CALL_LEVELS: usize = 32;
+const MAX_EXPRESSION_DEPTH: usize = 64;
+const MAX_FUNCTION_EXPRESSION_DEPTH: usize = 32;
+const MAX_STRING_SIZE: usize = 1024;
+const MAX_ARRAY_SIZE: usize = 1024;
+const MAX_MAP_SIZE: usize = 256;
+
+/// The function every script has to define. It is called once per frame
+/// as `control(observation, track)` and returns an object map with
+/// `steering`, `thrust` and `boost` in it. Anything the script wants to
+/// remember between frames can be stored on `this`.
+const CONTROL_FUNCTION: &str = "control";
+
+/// An error to represent a script that can't be used as a controller
+#[derive(Debug)]
+pub enum ScriptError {
+    /// The script doesn't parse
+    Compile(String),
+    /// Running the top level of the script failed
+    Init(String),
+    /// Scripts have to define `fn control(observation, track)`
+    MissingControlFunction,
+}
+
+/// Flies a ship using a Rhai script, so bot behaviour can be changed
+/// without rebuilding. Scripts are sandboxed: they can't load modules or
+/// touch anything outside of what they are given, and they are cut off if
+/// they run for too long.
+pub struct ScriptController {
+    engine: Engine,
+    ast: AST,
+    scope: Scope<'static>,
+    /// Bound to `this` inside the control function
+    memory: Dynamic,
+}
+
+impl ScriptController {
+    pub fn new(source: &str) -> Result<Self, ScriptError> {
+        let mut engine = Engine::new();
+        engine.set_module_resolver(DummyModuleResolver::new());
+        engine.set_max_operations(MAX_OPERATIONS);
+        engine.set_max_call_levels(MAX_CALL_LEVELS);
+        engine.set_max_expr_depths(MAX_EXPRESSION_DEPTH, MAX_FUNCTION_EXPRESSION_DEPTH);
+        engine.set_max_string_size(MAX_STRING_SIZE);
+        engine.set_max_array_size(MAX_ARRAY_SIZE);
+        engine.set_max_map_size(MAX_MAP_SIZE);
+        register_track(&mut engine);
+
+        let ast = engine
+            .compile(source)
+            .map_err(|err| ScriptError::Compile(err.to_string()))?;
+
+        let has_control_function = ast
+            .iter_functions()
+            .any(|function| function.name == CONTROL_FUNCTION && function.params.len() == 2);
+        if !has_control_function {
+            return Err(ScriptError::MissingControlFunction);
+        }
+
+        let mut scope = Scope::new();
+        engine
+            .run_ast_with_scope(&mut scope, &ast)
+            .map_err(|err| ScriptError::Init(err.to_string()))?;
+
+        Ok(Self {
+            engine,
+            ast,
+            scope,
+            memory: Dynamic::from_map(ScriptMap::new()),
+        })
+    }
+}
+
+impl ShipController for ScriptController {
+    fn control(&mut self, observation: &Observation) -> Result<ShipControl, ControllerError> {
+        let track = ScriptTrack {
+            map: observation.map.clone(),
+        };
+        let options = CallFnOptions::new()
+            .eval_ast(false)
+            .bind_this_ptr(&mut self.memory);
+
+        let result: Dynamic = self
+            .engine
+            .call_fn_with_options(
+                options,
+                &mut self.scope,
+                &self.ast,
+                CONTROL_FUNCTION,
+                (build_observation(observation), track),
+            )
+            .map_err(|err| ControllerError::Script(err.to_string()))?;
+
+        let result = result.try_cast::<ScriptMap>().ok_or_else(|| {
+            ControllerError::Script("control must return an object map".to_string())
+        })?;
+
+        // Scripts get the same limits as the built in AI
+        let max_thrust = observation.catch_up.thrust_scale;
+        Ok(ShipControl {
+            steering: get_number(&result, "steering")?.clamp(-1.0, 1.0),
+            thrust: get_number(&result, "thrust")?.clamp(-1.0, max_thrust),
+            boost: get_bool(&result, "boost")?,
+        })
+    }
+}
+
+/// Converts what the controller can see into an object map for the script
+fn build_observation(observation: &Observation) -> ScriptMap {
+    let ship = observation.ship;
+    let position = (ship.position.x, ship.position.y);
+    let track_point = observation.map.closest_point(position);
+    let line = observation.racing_line.sample(track_point.angle);
+
+    let mut map = ScriptMap::new();
+    let mut set = |key: &str, value: Dynamic| {
+        map.insert(key.into(), value);
+    };
+    set("x", ship.position.x.into());
+    set("y", ship.position.y.into());
+    set("rotation", ship.position.rot.into());
+    set("velocity_x", ship.velocity.x.into());
+    set("velocity_y", ship.velocity.y.into());
+    set("angular_velocity", ship.velocity.rot.into());
+    set("boost", ship.boost.into());
+    set("max_thrust", observation.catch_up.thrust_scale.into());
+    set("dt", observation.dt.into());
+
+    set("track_angle", track_point.angle.into());
+    set(
+        "track_direction",
+        observation
+            .map
+            .get_track_direction(track_point.angle)
+            .into(),
+    );
+    set("lateral_offset", track_point.lateral_offset.into());
+    set("line_offset", line.offset.into());
+    set("line_speed", line.speed.into());
+
+    let others: Array = observation
+        .others
+        .iter()
+        .map(|other| {
+            let mut other_map = ScriptMap::new();
+            other_map.insert("x".into(), other.position.0.into());
+            other_map.insert("y".into(), other.position.1.into());
+            other_map.insert("velocity_x".into(), other.velocity.0.into());
+            other_map.insert("velocity_y".into(), other.velocity.1.into());
+            Dynamic::from_map(other_map)
+        })
+        .collect();
+    set("others", others.into());
+
+    map
+}
+
+/// Missing values are treated as zero so scripts only have to return the
+/// controls they care about
+fn get_number(result: &ScriptMap, key: &str) -> Result<f32, ControllerError> {
+    match result.get(key) {
+        None => Ok(0.0),
+        Some(value) => value
+            .as_float()
+            .or_else(|_| value.as_int().map(|int| int as f32))
+            .map_err(|type_name| {
+                ControllerError::Script(format!("{} must be a number, not {}", key, type_name))
+            })
+            // Clamping leaves NaN as it is, and it would spread through the
+            // physics to the whole ship
+            .and_then(|number| match number.is_finite() {
+                true => Ok(number),
+                false => Err(ControllerError::Script(format!("{} must be finite", key))),
+            }),
+    }
+}
+
+fn get_bool(result: &ScriptMap, key: &str) -> Result<bool, ControllerError> {
+    match result.get(key) {
+        None => Ok(false),
+        Some(value) => value.as_bool().map_err(|type_name| {
+            ControllerError::Script(format!("{} must be true or false, not {}", key, type_name))
+        }),
+    }
+}
+
+/// Track queries a script can make, as methods on the `track` argument
+#[derive(Clone)]
+struct ScriptTrack {
+    map: Map,
+}
+
+fn register_track(engine: &mut Engine) {
+    engine
+        .register_type_with_name::<ScriptTrack>("Track")
+        .register_fn(
+            "closest_point",
+            |track: &mut ScriptTrack, x: f32, y: f32| {
+                let point = track.map.closest_point((x, y));
+                let mut map = ScriptMap::new();
+                map.insert("angle".into(), point.angle.into());
+                map.insert("x".into(), point.position.0.into());
+                map.insert("y".into(), point.position.1.into());
+                map.insert(
+                    "direction".into(),
+                    track.map.get_track_direction(point.angle).into(),
+                );
+                map.insert("lateral_offset".into(), point.lateral_offset.into());
+                map
+            },
+        )
+        .register_fn("position", |track: &mut ScriptTrack, angle: f32| {
+            let position = track.map.centerline_position(angle);
+            let mut map = ScriptMap::new();
+            map.insert("x".into(), position.0.into());
+            map.insert("y".into(), position.1.into());
+            map
+        })
+        .register_fn("direction", |track: &mut ScriptTrack, angle: f32| {
+            track.map.get_track_direction(angle)
+        })
+        .register_fn("wall_distance", |track: &mut ScriptTrack, angle: f32| {
+            track.map.wall_distance(angle)
+        })
+        .register_fn("curvature", |track: &mut ScriptTrack, angle: f32| {
+            track.map.track_curvature(angle)
+        })
+        .register_fn("progress", |track: &mut ScriptTrack, x: f32, y: f32| {
+            track.map.track_progress((x, y))
+        })
+        .register_fn("lap_length", |track: &mut ScriptTrack| {
+            track.map.lap_length()
+        });
+}
@@ -0,0 +1,151 @@
+use super::map::{Map, RayHit};
+use super::ship::Ship;
+use super::transform::Vec2;
+
+/// What one ray of a sensor fan saw
+#[derive(Clone, Copy, Debug)]
+pub struct SensorReading {
+    /// Unit vector the ray was cast along
+    pub direction: Vec2,
+    /// Distance to the wall, or the range of the fan if nothing was hit
+    pub distance: f32,
+    pub hit: Option<RayHit>,
+}
+
+/// A set of rays spread out evenly either side of where a ship is facing,
+/// for feeling out the walls around it
+#[derive(Clone, Copy, Debug)]
+pub struct SensorFan {
+    pub rays: usize,
+    /// Angle from straight ahead to the outermost rays
+    pub spread: f32,
+    /// How far the rays reach
+    pub range: f32,
+}
+
+impl SensorFan {
+    /// Casts every ray from the ship's position. Readings go from the
+    /// rightmost ray round to the leftmost.
+    pub fn sense(&self, ship: &Ship, map: &Map) -> Vec<SensorReading> {
+        let origin = (ship.position.x, ship.position.y);
+        (0..self.rays)
+            .map(|ray| {
+                let direction = self.ray_direction(ship.position.rot, ray);
+                let hit = map.raycast(origin, direction, self.range);
+                SensorReading {
+                    direction,
+                    distance: hit.map_or(self.range, |hit| hit.distance),
+                    hit,
+                }
+            })
+            .collect()
+    }
+
+    /// Direction of one ray for a ship with rotation `rotation`
+    pub fn ray_direction(&self, rotation: f32, ray: usize) -> Vec2 {
+        // A single ray points straight ahead
+        let fraction = if self.rays > 1 {
+            ray as f32 / (self.rays - 1) as f32 * 2.0 - 1.0
+        } else {
+            0.0
+        };
+        let angle = rotation + fraction * self.spread;
+        (-f32::sin(angle), f32::cos(angle))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transform::Transform2d;
+
+    const RADIUS: f32 = 8.0;
+    const HALF_WIDTH: f32 = 0.7;
+    const DISTANCE_TOLERANCE: f32 = 0.01;
+
+    /// A perfectly round track, so what the sensors should see can be
+    /// worked out exactly
+    fn circular_map() -> Map {
+        Map {
+            sin_consts: [0.0; 8],
+            cos_consts: [0.0; 8],
+            track_base_radius: RADIUS,
+            width_sin_consts: [0.0; 4],
+            width_cos_consts: [0.0; 4],
+            track_base_width: HALF_WIDTH,
+        }
+    }
+
+    /// A ship on the centerline facing up the screen, which is
+    /// anticlockwise at this point
+    fn ship() -> Ship {
+        Ship::new(
+            (1.0, 1.0, 1.0, 1.0),
+            Transform2d::new(RADIUS, 0.0, 0.0, 1.0),
+        )
+    }
+
+    /// A ship on the centerline sees the walls to either side of it and
+    /// the outer wall curving round ahead
+    #[test]
+    fn sees_the_walls_around_it() {
+        let fan = SensorFan {
+            rays: 3,
+            spread: std::f32::consts::PI / 2.0,
+            range: 20.0,
+        };
+        let readings = fan.sense(&ship(), &circular_map());
+        assert_eq!(readings.len(), 3);
+
+        let outer = RADIUS + HALF_WIDTH;
+        let expected = [
+            // Right, towards the outer wall
+            ((1.0, 0.0), HALF_WIDTH),
+            // Straight ahead, up to where the outer wall crosses
+            ((0.0, 1.0), f32::sqrt(outer * outer - RADIUS * RADIUS)),
+            // Left, towards the inner wall
+            ((-1.0, 0.0), HALF_WIDTH),
+        ];
+        for (reading, (direction, distance)) in readings.iter().zip(expected.iter()) {
+            assert!(
+                f32::abs(reading.direction.0 - direction.0) < 1e-5
+                    && f32::abs(reading.direction.1 - direction.1) < 1e-5,
+                "ray pointed along {:?} not {:?}",
+                reading.direction,
+                direction
+            );
+            assert!(
+                f32::abs(reading.distance - distance) < DISTANCE_TOLERANCE,
+                "sensor read {} not {}",
+                reading.distance,
+                distance
+            );
+            assert!(reading.hit.is_some());
+        }
+    }
+
+    #[test]
+    fn reads_its_range_when_nothing_is_hit() {
+        let fan = SensorFan {
+            rays: 5,
+            spread: 1.0,
+            range: 0.1,
+        };
+        for reading in fan.sense(&ship(), &circular_map()) {
+            assert!(reading.hit.is_none());
+            assert_eq!(reading.distance, fan.range);
+        }
+    }
+
+    #[test]
+    fn single_ray_points_straight_ahead() {
+        let fan = SensorFan {
+            rays: 1,
+            spread: 1.0,
+            range: 1.0,
+        };
+        let direction = fan.ray_direction(0.3, 0);
+        assert!(f32::abs(direction.0 + f32::sin(0.3)) < 1e-6);
+        assert!(f32::abs(direction.1 - f32::cos(0.3)) < 1e-6);
+    }
+}
@@ -0,0 +1,424 @@
+use std::collections::BTreeMap;
+
+use super::ai::{AiDriver, ALL_PROFILES};
+use super::controller::{ControllerError, Observation, ShipControl, ShipController};
+use super::game_options::MAX_SHIPS;
+use super::map::Map;
+use super::protocol::{ClientMessage, LobbyPlayer, ServerMessage, ShipState};
+use super::replay::quantize;
+use super::rng::Rng;
+use super::simulation::{Simulation, TICK};
+
+/// Ticks the ships wait on the grid before a race, the same three seconds
+/// as the countdown offline
+pub const COUNTDOWN_TICKS: u32 = 180;
+/// Ticks between snapshots of the race being sent out
+const SNAPSHOT_INTERVAL: u32 = 3;
+/// Races are made up to at least this many ships with AI
+const MIN_GRID: usize = 4;
+/// Races are called off after this long, in case somebody stops flying
+const TIME_LIMIT: f32 = 600.0;
+/// Inputs for further ahead than this many ticks are ignored, so that
+/// nobody can fill up the server with them
+const MAX_INPUT_AHEAD: u32 = 120;
+
+/// Tells apart the connections to the server
+pub type ClientId = u32;
+
+/// Stands in for a player's ship in the simulation. Their control comes
+/// from whatever they last sent instead.
+struct Remote;
+
+impl ShipController for Remote {
+    fn control(&mut self, _observation: &Observation) -> Result<ShipControl, ControllerError> {
+        Ok(ShipControl::default())
+    }
+}
+
+/// Somebody connected to a lobby
+struct Member {
+    client: ClientId,
+    name: String,
+    ready: bool,
+    /// The ship they fly in the race under way, if they are in it
+    ship: Option<usize>,
+}
+
+/// A race being run on the server, which has the final say on where
+/// every ship is
+struct ServerRace {
+    simulation: Simulation,
+    /// The control every ship used on the last tick
+    controls: Vec<ShipControl>,
+    /// Controls players have sent for ticks that haven't been run yet.
+    /// Anything that turns up too late is left out, and their ship keeps
+    /// flying with what it had.
+    inputs: Vec<BTreeMap<u32, ShipControl>>,
+    /// Ticks left on the grid before the race starts
+    countdown: u32,
+    /// Ticks run since the start
+    tick: u32,
+    /// Players have the first ships on the grid, with the AI after them
+    players: usize,
+}
+
+/// Players who race each other, one race at a time. Anybody joining
+/// during a race waits for the next one.
+struct Lobby {
+    name: String,
+    members: Vec<Member>,
+    race: Option<ServerRace>,
+}
+
+/// Hosts lobbies and runs their races, with no networking of its own.
+/// Whatever owns the connections hands over messages as they come in,
+/// calls `tick` once every simulation tick, and sends out whatever
+/// `take_outgoing` gives back.
+pub struct Server {
+    lobbies: Vec<Lobby>,
+    laps: u32,
+    rng: Rng,
+    outgoing: Vec<(ClientId, ServerMessage)>,
+}
+
+impl Server {
+    pub fn new(seed: u64, laps: u32) -> Self {
+        Self {
+            lobbies: vec![],
+            laps,
+            rng: Rng::new(seed),
+            outgoing: vec![],
+        }
+    }
+
+    /// Handles a message from a client. Gives false if it couldn't be
+    /// read, in which case the client has been told why and should be
+    /// disconnected.
+    pub fn receive(&mut self, client: ClientId, bytes: &[u8]) -> bool {
+        let message = match ClientMessage::from_bytes(bytes) {
+            Ok(message) => message,
+            Err(err) => {
+                self.send(
+                    client,
+                    ServerMessage::Error {
+                        reason: format!("{:?}", err),
+                    },
+                );
+                return false;
+            }
+        };
+        match message {
+            ClientMessage::Join { lobby, name } => self.join(client, lobby, name),
+            ClientMessage::Ready => {
+                if let Some(index) = self.lobby_of(client) {
+                    let lobby = &mut self.lobbies[index];
+                    for member in lobby.members.iter_mut() {
+                        if member.client == client {
+                            member.ready = true;
+                        }
+                    }
+                    self.send_lobby(index);
+                    self.start_race_if_ready(index);
+                }
+            }
+            ClientMessage::Input { tick, controls } => {
+                if let Some(index) = self.lobby_of(client) {
+                    self.receive_input(index, client, tick, controls);
+                }
+            }
+            ClientMessage::Leave => self.disconnect(client),
+        }
+        true
+    }
+
+    /// Takes a client out of its lobby. Its ship coasts for the rest of
+    /// the race.
+    pub fn disconnect(&mut self, client: ClientId) {
+        let index = match self.lobby_of(client) {
+            Some(index) => index,
+            None => return,
+        };
+        let lobby = &mut self.lobbies[index];
+        if let Some(position) = lobby.members.iter().position(|m| m.client == client) {
+            let member = lobby.members.remove(position);
+            if let (Some(race), Some(ship)) = (&mut lobby.race, member.ship) {
+                race.controls[ship] = ShipControl::default();
+                race.inputs[ship].clear();
+            }
+        }
+        if lobby.members.is_empty() {
+            self.lobbies.remove(index);
+        } else {
+            self.send_lobby(index);
+            self.start_race_if_ready(index);
+        }
+    }
+
+    /// Runs one tick of every race, sending out snapshots every few ticks
+    /// and the results when a race is over
+    pub fn tick(&mut self) {
+        for index in 0..self.lobbies.len() {
+            let race = match &mut self.lobbies[index].race {
+                Some(race) => race,
+                None => continue,
+            };
+            if race.countdown > 0 {
+                race.countdown -= 1;
+                continue;
+            }
+
+            // Players fly with what they sent for this tick, if it got
+            // here in time
+            for ship in 0..race.players {
+                if let Some(control) = race.inputs[ship].remove(&race.tick) {
+                    race.controls[ship] = control;
+                }
+            }
+            // The AI fly themselves
+            let computed = race
+                .simulation
+                .calc_controls(TICK)
+                .unwrap_or_else(|_| vec![ShipControl::default(); race.controls.len()]);
+            for (ship, control) in computed.into_iter().enumerate().skip(race.players) {
+                race.controls[ship] = quantize(control);
+            }
+            race.simulation.advance(&race.controls, TICK);
+            race.tick += 1;
+
+            let finished =
+                race.simulation.race.is_finished() || race.tick as f32 * TICK > TIME_LIMIT;
+            if race.tick % SNAPSHOT_INTERVAL == 0 || finished {
+                let snapshot = ServerMessage::Snapshot {
+                    tick: race.tick,
+                    ships: race
+                        .simulation
+                        .ships
+                        .iter()
+                        .zip(race.controls.iter())
+                        .map(|(ship, control)| ShipState::from_ship(ship, *control))
+                        .collect(),
+                };
+                self.send_racers(index, snapshot);
+            }
+            if finished {
+                self.finish_race(index);
+            }
+        }
+    }
+
+    /// Messages waiting to go out, encoded and ready to send
+    pub fn take_outgoing(&mut self) -> Vec<(ClientId, Vec<u8>)> {
+        self.outgoing
+            .drain(..)
+            .map(|(client, message)| (client, message.to_bytes()))
+            .collect()
+    }
+
+    fn join(&mut self, client: ClientId, lobby: String, name: String) {
+        // Joining somewhere else means leaving where they were
+        self.disconnect(client);
+        let index = match self.lobbies.iter().position(|l| l.name == lobby) {
+            Some(index) => index,
+            None => {
+                self.lobbies.push(Lobby {
+                    name: lobby,
+                    members: vec![],
+                    race: None,
+                });
+                self.lobbies.len() - 1
+            }
+        };
+        if self.lobbies[index].members.len() >= MAX_SHIPS {
+            self.send(
+                client,
+                ServerMessage::Error {
+                    reason: format!("Lobby {} is full", self.lobbies[index].name),
+                },
+            );
+            return;
+        }
+        self.lobbies[index].members.push(Member {
+            client,
+            name,
+            ready: false,
+            ship: None,
+        });
+        let player = self.lobbies[index].members.len() as u8 - 1;
+        self.send(client, ServerMessage::Welcome { player });
+        self.send_lobby(index);
+    }
+
+    /// Starts a race once everybody in the lobby is ready and there
+    /// isn't one already going
+    fn start_race_if_ready(&mut self, index: usize) {
+        let lobby = &self.lobbies[index];
+        if lobby.race.is_some() || !lobby.members.iter().all(|member| member.ready) {
+            return;
+        }
+        let seed = (self.rng.next_u32() as u64) << 32 | self.rng.next_u32() as u64;
+        let ai_count = MIN_GRID.saturating_sub(lobby.members.len());
+        let profiles = ALL_PROFILES.iter().cycle().take(ai_count);
+
+        let mut drivers: Vec<String> = lobby.members.iter().map(|m| m.name.clone()).collect();
+        let mut controllers: Vec<Box<dyn ShipController>> = lobby
+            .members
+            .iter()
+            .map(|_| Box::new(Remote) as _)
+            .collect();
+        for (id, profile) in profiles.enumerate() {
+            drivers.push(profile.name.to_string());
+            controllers.push(Box::new(AiDriver::new(*profile, seed + id as u64)));
+        }
+
+        let mut map = Map::new();
+        map.randomize(seed);
+        let ship_count = controllers.len();
+        let simulation = Simulation::new(map, controllers, self.laps);
+
+        let laps = self.laps;
+        let lobby = &mut self.lobbies[index];
+        lobby.race = Some(ServerRace {
+            simulation,
+            controls: vec![ShipControl::default(); ship_count],
+            inputs: vec![BTreeMap::new(); ship_count],
+            countdown: COUNTDOWN_TICKS,
+            tick: 0,
+            players: lobby.members.len(),
+        });
+        for (ship, member) in lobby.members.iter_mut().enumerate() {
+            member.ready = false;
+            member.ship = Some(ship);
+        }
+        let starts: Vec<(ClientId, ServerMessage)> = lobby
+            .members
+            .iter()
+            .enumerate()
+            .map(|(ship, member)| {
+                let start = ServerMessage::Start {
+                    seed,
+                    laps,
+                    drivers: drivers.clone(),
+                    ship: ship as u8,
+                    countdown: COUNTDOWN_TICKS,
+                };
+                (member.client, start)
+            })
+            .collect();
+        self.outgoing.extend(starts);
+        self.send_lobby(index);
+    }
+
+    fn finish_race(&mut self, index: usize) {
+        let lobby = &mut self.lobbies[index];
+        let race = match lobby.race.take() {
+            Some(race) => race,
+            None => return,
+        };
+        let results = ServerMessage::Results {
+            standings: race
+                .simulation
+                .race
+                .standings()
+                .iter()
+                .map(|ship| *ship as u8)
+                .collect(),
+        };
+        let racers: Vec<ClientId> = lobby
+            .members
+            .iter()
+            .filter(|member| member.ship.is_some())
+            .map(|member| member.client)
+            .collect();
+        for member in lobby.members.iter_mut() {
+            member.ship = None;
+        }
+        for client in racers {
+            self.send(client, results.clone());
+        }
+        self.send_lobby(index);
+    }
+
+    /// Keeps the controls a player sent for the ticks still to come, and
+    /// passes them on to everyone else in the race
+    fn receive_input(
+        &mut self,
+        index: usize,
+        client: ClientId,
+        tick: u32,
+        controls: Vec<ShipControl>,
+    ) {
+        let lobby = &mut self.lobbies[index];
+        let ship = lobby
+            .members
+            .iter()
+            .find(|member| member.client == client)
+            .and_then(|member| member.ship);
+        let (race, ship) = match (&mut lobby.race, ship) {
+            (Some(race), Some(ship)) => (race, ship),
+            _ => return,
+        };
+        let first = (tick + 1).saturating_sub(controls.len() as u32);
+        for (input_tick, control) in (first..=tick).zip(controls.iter()) {
+            if input_tick >= race.tick && input_tick <= race.tick + MAX_INPUT_AHEAD {
+                race.inputs[ship].insert(input_tick, quantize(*control));
+            }
+        }
+
+        let relayed = ServerMessage::Input {
+            ship: ship as u8,
+            tick,
+            controls,
+        };
+        let others: Vec<ClientId> = lobby
+            .members
+            .iter()
+            .filter(|member| member.ship.is_some() && member.client != client)
+            .map(|member| member.client)
+            .collect();
+        for other in others {
+            self.send(other, relayed.clone());
+        }
+    }
+
+    fn lobby_of(&self, client: ClientId) -> Option<usize> {
+        self.lobbies
+            .iter()
+            .position(|lobby| lobby.members.iter().any(|m| m.client == client))
+    }
+
+    fn send(&mut self, client: ClientId, message: ServerMessage) {
+        self.outgoing.push((client, message));
+    }
+
+    /// Tells everybody in a lobby who is in it
+    fn send_lobby(&mut self, index: usize) {
+        let lobby = &self.lobbies[index];
+        let message = ServerMessage::Lobby {
+            players: lobby
+                .members
+                .iter()
+                .map(|member| LobbyPlayer {
+                    name: member.name.clone(),
+                    ready: member.ready,
+                })
+                .collect(),
+        };
+        let clients: Vec<ClientId> = lobby.members.iter().map(|m| m.client).collect();
+        for client in clients {
+            self.send(client, message.clone());
+        }
+    }
+
+    /// Sends a message to everybody in the race under way in a lobby
+    fn send_racers(&mut self, index: usize, message: ServerMessage) {
+        let clients: Vec<ClientId> = self.lobbies[index]
+            .members
+            .iter()
+            .filter(|member| member.ship.is_some())
+            .map(|member| member.client)
+            .collect();
+        for client in clients {
+            self.send(client, message.clone());
+        }
+    }
+}
@@ -4,13 +4,42 @@ const ENGINE_THRUST: f32 = 10.0;
 const TURNING_THRUST: f32 = 40.0;
 const LINEAR_DAMPING: f32 = 2.0;
 const ANGULAR_DEAMPING: f32 = 8.0;
-
-#[derive(Debug)]
+/// Multiplier on the engine thrust while boosting
+const BOOST_THRUST: f32 = 1.5;
+/// How quickly a full boost meter empties while boosting, per second
+const BOOST_DRAIN: f32 = 0.5;
+/// How quickly the boost meter refills while not boosting, per second
+const BOOST_RECHARGE: f32 = 0.1;
+
+/// Red, green, blue and alpha, each from zero to one
+pub type Color = (f32, f32, f32, f32);
+
+const CYAN_SHIP: (f32, f32, f32, f32) = (0.0, 0.5, 1.0, 1.0);
+const YELLOW_SHIP: (f32, f32, f32, f32) = (1.0, 0.5, 0.0, 1.0);
+const PINK_SHIP: (f32, f32, f32, f32) = (1.0, 0.0, 0.5, 1.0);
+const PURPLE_SHIP: (f32, f32, f32, f32) = (0.5, 0.0, 1.0, 1.0);
+const WHITE_SHIP: (f32, f32, f32, f32) = (1.0, 1.0, 1.0, 1.0);
+
+/// What ships are painted, in starting order unless a player has picked
+/// one for themselves
+pub const SHIP_COLORS: [(&str, Color); 5] = [
+    ("Cyan", CYAN_SHIP),
+    ("Yellow", YELLOW_SHIP),
+    ("Pink", PINK_SHIP),
+    ("Purple", PURPLE_SHIP),
+    ("White", WHITE_SHIP),
+];
+
+#[derive(Debug, Clone)]
 pub struct Ship {
     pub position: Transform2d,
     pub velocity: Transform2d,
     pub linear_thrust: f32,
     pub angular_thrust: f32,
+    /// Set by whoever is controlling the ship to ask for more thrust
+    pub boosting: bool,
+    /// How much boost is left, from zero to one
+    pub boost: f32,
     pub color: (f32, f32, f32, f32),
 }
 
@@ -21,10 +50,18 @@ impl Ship {
             velocity: Transform2d::new(0.0, 0.0, 0.0, 0.0),
             linear_thrust: 0.0,
             angular_thrust: 0.0,
+            boosting: false,
+            boost: 1.0,
             color: color,
         }
     }
 
+    /// The linear thrust needed to exactly balance the damping when
+    /// flying straight at a particular speed
+    pub fn thrust_to_hold_speed(speed: f32) -> f32 {
+        speed * LINEAR_DAMPING / ENGINE_THRUST
+    }
+
     pub fn update(&mut self, dt: f32) {
         let angle: f32 = self.position.rot;
 
@@ -33,9 +70,17 @@ impl Ship {
 
         let forwards = (-s, c);
 
+        let mut engine_thrust = ENGINE_THRUST;
+        if self.boosting && self.boost > 0.0 && self.linear_thrust > 0.0 {
+            engine_thrust *= BOOST_THRUST;
+            self.boost = f32::max(self.boost - BOOST_DRAIN * dt, 0.0);
+        } else {
+            self.boost = f32::min(self.boost + BOOST_RECHARGE * dt, 1.0);
+        }
+
         let mut acceleration = (0.0, 0.0, 0.0);
-        acceleration.0 += forwards.0 * self.linear_thrust * ENGINE_THRUST;
-        acceleration.1 += forwards.1 * self.linear_thrust * ENGINE_THRUST;
+        acceleration.0 += forwards.0 * self.linear_thrust * engine_thrust;
+        acceleration.1 += forwards.1 * self.linear_thrust * engine_thrust;
         acceleration.2 += self.angular_thrust * TURNING_THRUST;
 
         acceleration.0 -= self.velocity.x * LINEAR_DAMPING;
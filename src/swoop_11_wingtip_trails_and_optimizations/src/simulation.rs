@@ -0,0 +1,83 @@
+use super::catch_up::{calc_catch_up, CatchUpStrength};
+use super::controller::{ControllerError, NearbyShip, Observation, ShipControl, ShipController};
+use super::map::Map;
+use super::physics::calc_ship_physics;
+use super::race::{place_on_grid, Race};
+use super::racing_line::RacingLine;
+use super::ship::Ship;
+use super::transform::Transform2d;
+
+/// Length of one simulation step. Races always move on by this much at a
+/// time so that they can be replayed exactly.
+pub const TICK: f32 = 1.0 / 60.0;
+
+/// A race with no rendering or keyboard, so that controllers can be run
+/// natively, for example to pit bots against each other
+pub struct Simulation {
+    pub map: Map,
+    pub racing_line: RacingLine,
+    pub ships: Vec<Ship>,
+    pub controllers: Vec<Box<dyn ShipController>>,
+    pub race: Race,
+    pub catch_up: CatchUpStrength,
+}
+
+impl Simulation {
+    /// Lines up one ship per controller on the grid, in order
+    pub fn new(map: Map, controllers: Vec<Box<dyn ShipController>>, laps: u32) -> Self {
+        let racing_line = RacingLine::new(&map);
+        let mut ships: Vec<Ship> = controllers
+            .iter()
+            .map(|_| Ship::new((1.0, 1.0, 1.0, 1.0), Transform2d::new(0.0, 0.0, 0.0, 0.1)))
+            .collect();
+        place_on_grid(&mut ships, &map);
+        let race = Race::new(&ships, &map, laps);
+
+        Self {
+            map,
+            racing_line,
+            ships,
+            controllers,
+            race,
+            catch_up: CatchUpStrength::None,
+        }
+    }
+
+    /// Moves the race on by one frame. If a controller fails then the
+    /// index of its ship is returned along with the error.
+    pub fn step(&mut self, dt: f32) -> Result<(), (usize, ControllerError)> {
+        let controls = self.calc_controls(dt)?;
+        self.advance(&controls, dt);
+        Ok(())
+    }
+
+    /// Asks every controller what it wants to do, without moving anything
+    pub fn calc_controls(&mut self, dt: f32) -> Result<Vec<ShipControl>, (usize, ControllerError)> {
+        let catch_up = calc_catch_up(&self.race, self.catch_up);
+
+        let mut controls = Vec::with_capacity(self.ships.len());
+        for (id, controller) in self.controllers.iter_mut().enumerate() {
+            let others = NearbyShip::others(&self.ships, id);
+            let observation = Observation {
+                ship: &self.ships[id],
+                others: &others,
+                map: &self.map,
+                racing_line: &self.racing_line,
+                catch_up: catch_up[id],
+                dt,
+            };
+            controls.push(controller.control(&observation).map_err(|err| (id, err))?);
+        }
+        Ok(controls)
+    }
+
+    /// Applies one control per ship and moves the race on by `dt`
+    pub fn advance(&mut self, controls: &[ShipControl], dt: f32) {
+        for (ship, control) in self.ships.iter_mut().zip(controls.iter()) {
+            control.apply(ship);
+        }
+
+        calc_ship_physics(&mut self.ships, &self.map, dt);
+        self.race.update(&self.ships, &self.map, dt);
+    }
+}
@@ -0,0 +1,46 @@
+use std::collections::BTreeMap;
+
+/// An error to represent something that couldn't be stored
+#[derive(Debug)]
+pub enum StorageError {
+    /// The backend wouldn't take it, usually because it is full or the
+    /// browser doesn't allow storage for the page
+    Refused(String),
+}
+
+/// Somewhere to keep text between sessions, by key. In the browser this
+/// is localStorage, which only ever holds strings.
+pub trait KeyValueStore {
+    fn get(&self, key: &str) -> Option<String>;
+    fn set(&mut self, key: &str, value: &str) -> Result<(), StorageError>;
+    /// Every key there is something stored under
+    fn keys(&self) -> Vec<String>;
+}
+
+/// Keeps everything in memory, for running natively where there is no
+/// localStorage
+#[derive(Clone, Debug, Default)]
+pub struct MemoryStore {
+    items: BTreeMap<String, String>,
+}
+
+impl MemoryStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl KeyValueStore for MemoryStore {
+    fn get(&self, key: &str) -> Option<String> {
+        self.items.get(key).cloned()
+    }
+
+    fn set(&mut self, key: &str, value: &str) -> Result<(), StorageError> {
+        self.items.insert(key.to_string(), value.to_string());
+        Ok(())
+    }
+
+    fn keys(&self) -> Vec<String> {
+        self.items.keys().cloned().collect()
+    }
+}
@@ -25,8 +25,8 @@ fn load_image_bytes_to_image_element(
     let arr = Array::new();
     arr.set(0, raw_arr.dyn_into().unwrap());
 
-    let mut blob_options = web_sys::BlobPropertyBag::new();
-    blob_options.type_("image/png");
+    let blob_options = web_sys::BlobPropertyBag::new();
+    blob_options.set_type("image/png");
 
     let blob: Blob = Blob::new_with_u8_array_sequence_and_options(&arr, &blob_options)?;
 
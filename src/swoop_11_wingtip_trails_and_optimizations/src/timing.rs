@@ -0,0 +1,328 @@
+/// Laps are split into this many sectors of equal length along the track
+pub const SECTORS: usize = 3;
+/// Evenly spaced points round the lap that are timed as they are passed.
+/// These give the live delta and make sure a lap went all the way round.
+/// The last one is the start line.
+const CHECKPOINTS: usize = SECTORS * 20;
+
+/// Times for one complete lap
+#[derive(Clone, Debug)]
+pub struct LapTime {
+    pub total: f32,
+    pub sectors: [f32; SECTORS],
+    /// Time into the lap each checkpoint was reached, ending with the
+    /// total
+    checkpoints: Vec<f32>,
+}
+
+impl LapTime {
+    fn from_checkpoints(checkpoints: Vec<f32>) -> Self {
+        let mut sectors = [0.0; SECTORS];
+        let mut sector_start = 0.0;
+        for (sector, time) in sectors.iter_mut().enumerate() {
+            let sector_end = checkpoints[(sector + 1) * CHECKPOINTS / SECTORS - 1];
+            *time = sector_end - sector_start;
+            sector_start = sector_end;
+        }
+        Self {
+            total: sector_start,
+            sectors,
+            checkpoints,
+        }
+    }
+}
+
+/// Times one ship's laps from how far round the track it is. Crossings
+/// are interpolated between ticks, so times are more precise than the
+/// tick length and come out the same whenever a race is replayed.
+#[derive(Clone, Debug)]
+pub struct LapTimer {
+    lap_length: f32,
+    /// Race time the current lap started. None before the ship first
+    /// crosses the line, or if it has gone back over the line since.
+    pub lap_start: Option<f32>,
+    /// Time into the current lap each checkpoint so far was reached
+    splits: Vec<f32>,
+    pub laps_completed: u32,
+    pub last_lap: Option<LapTime>,
+    pub best_lap: Option<LapTime>,
+    /// Fastest time through each sector, which can come from different
+    /// laps
+    pub best_sectors: [Option<f32>; SECTORS],
+}
+
+impl LapTimer {
+    pub fn new(lap_length: f32) -> Self {
+        Self {
+            lap_length,
+            lap_start: None,
+            splits: vec![],
+            laps_completed: 0,
+            last_lap: None,
+            best_lap: None,
+            best_sectors: [None; SECTORS],
+        }
+    }
+
+    fn checkpoint_distance(&self, checkpoint: usize) -> f32 {
+        self.lap_length * (checkpoint + 1) as f32 / CHECKPOINTS as f32
+    }
+
+    /// Moves the timer on by a tick ending at `time`, in which the ship
+    /// went from `previous_progress` to `progress` round the lap. Gives
+    /// the time the ship crossed the start line if it went over it
+    /// forwards.
+    pub fn update(
+        &mut self,
+        previous_progress: f32,
+        progress: f32,
+        time: f32,
+        dt: f32,
+    ) -> Option<f32> {
+        let tick_start = time - dt;
+        let delta = progress - previous_progress;
+
+        if delta < -self.lap_length * 0.5 {
+            // Forwards over the line
+            let to_line = self.lap_length - previous_progress;
+            let travelled = to_line + progress;
+            let time_at = |distance: f32| tick_start + dt * distance / travelled;
+
+            self.pass_checkpoints(previous_progress, self.lap_length, |distance| {
+                time_at(distance - previous_progress)
+            });
+            let line_time = time_at(to_line);
+            self.complete_lap(line_time);
+            self.pass_checkpoints(0.0, progress, |distance| time_at(to_line + distance));
+            Some(line_time)
+        } else if delta > self.lap_length * 0.5 {
+            // Backwards over the line, so this lap doesn't count
+            self.lap_start = None;
+            self.splits.clear();
+            None
+        } else {
+            if delta > 0.0 {
+                self.pass_checkpoints(previous_progress, progress, |distance| {
+                    tick_start + dt * (distance - previous_progress) / delta
+                });
+            }
+            // Going backwards past a checkpoint means it has to be passed
+            // again
+            while !self.splits.is_empty()
+                && progress < self.checkpoint_distance(self.splits.len() - 1)
+            {
+                self.splits.pop();
+            }
+            None
+        }
+    }
+
+    /// Times any checkpoints between two distances round the lap, apart
+    /// from the start line
+    fn pass_checkpoints(&mut self, from: f32, to: f32, time_at: impl Fn(f32) -> f32) {
+        let lap_start = match self.lap_start {
+            Some(lap_start) => lap_start,
+            None => return,
+        };
+        while self.splits.len() < CHECKPOINTS - 1 {
+            let distance = self.checkpoint_distance(self.splits.len());
+            if distance <= from || distance > to {
+                break;
+            }
+            self.splits.push(time_at(distance) - lap_start);
+        }
+    }
+
+    fn complete_lap(&mut self, line_time: f32) {
+        if let (Some(lap_start), true) = (self.lap_start, self.splits.len() == CHECKPOINTS - 1) {
+            let mut checkpoints = std::mem::take(&mut self.splits);
+            checkpoints.push(line_time - lap_start);
+            let lap = LapTime::from_checkpoints(checkpoints);
+
+            for (best, sector) in self.best_sectors.iter_mut().zip(lap.sectors.iter()) {
+                if best.is_none_or(|best| *sector < best) {
+                    *best = Some(*sector);
+                }
+            }
+            if self
+                .best_lap
+                .as_ref()
+                .is_none_or(|best| lap.total < best.total)
+            {
+                self.best_lap = Some(lap.clone());
+            }
+            self.last_lap = Some(lap);
+            self.laps_completed += 1;
+        }
+        self.lap_start = Some(line_time);
+        self.splits.clear();
+    }
+
+    /// How long the current lap has taken so far
+    pub fn lap_time(&self, time: f32) -> Option<f32> {
+        self.lap_start.map(|lap_start| time - lap_start)
+    }
+
+    /// How far ahead (negative) or behind (positive) the current lap is
+    /// compared with the best lap at the same point round the track
+    pub fn delta(&self, progress: f32, time: f32) -> Option<f32> {
+        let best = self.best_lap.as_ref()?;
+        let lap_time = self.lap_time(time)?;
+
+        // Interpolate the best lap between the checkpoints either side
+        let spacing = self.lap_length / CHECKPOINTS as f32;
+        let position = f32::max(f32::min(progress / spacing, CHECKPOINTS as f32), 0.0);
+        let checkpoint = usize::min(position as usize, CHECKPOINTS - 1);
+        let before = match checkpoint {
+            0 => 0.0,
+            _ => best.checkpoints[checkpoint - 1],
+        };
+        let after = best.checkpoints[checkpoint];
+        let best_time = before + (after - before) * (position - checkpoint as f32);
+        Some(lap_time - best_time)
+    }
+
+    /// Current lap times for each sector done so far this lap
+    pub fn current_sectors(&self) -> Vec<f32> {
+        let mut sectors = vec![];
+        let mut sector_start = 0.0;
+        for sector in 0..SECTORS - 1 {
+            match self.splits.get((sector + 1) * CHECKPOINTS / SECTORS - 1) {
+                Some(sector_end) => {
+                    sectors.push(sector_end - sector_start);
+                    sector_start = *sector_end;
+                }
+                None => break,
+            }
+        }
+        sectors
+    }
+}
+
+/// Formats a time as minutes, seconds and thousandths
+pub fn format_time(time: f32) -> String {
+    let thousandths = f32::round(f32::abs(time) * 1000.0) as u32;
+    format!(
+        "{}{}:{:02}.{:03}",
+        if time < 0.0 { "-" } else { "" },
+        thousandths / 60_000,
+        thousandths / 1000 % 60,
+        thousandths % 1000
+    )
+}
+
+/// Formats a difference in time with its sign, such as +0.123
+pub fn format_delta(delta: f32) -> String {
+    format!(
+        "{}{:.3}",
+        if delta < 0.0 { "-" } else { "+" },
+        f32::abs(delta)
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LAP_LENGTH: f32 = 60.0;
+
+    /// Runs a timer for a ship whose distance travelled goes as `distance`
+    /// over time, counting from just before the start line. Gives every
+    /// time the line was crossed.
+    fn drive(timer: &mut LapTimer, distance: impl Fn(f32) -> f32, dt: f32, ticks: u32) -> Vec<f32> {
+        let progress = |time: f32| (distance(time) - 1.0).rem_euclid(LAP_LENGTH);
+        (1..=ticks)
+            .filter_map(|tick| {
+                let time = tick as f32 * dt;
+                timer.update(progress(time - dt), progress(time), time, dt)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn crossing_part_way_through_a_tick() {
+        let mut timer = LapTimer::new(LAP_LENGTH);
+        // A quarter of the way to the line, then three quarters past it
+        assert_eq!(timer.update(59.5, 1.5, 2.0, 0.5), Some(1.625));
+        assert_eq!(timer.lap_start, Some(1.625));
+
+        // At a steady 12 units a second the line is first crossed at 1/12
+        // of a second, part way through the sixth tick, then every 5s
+        let mut timer = LapTimer::new(LAP_LENGTH);
+        let crossings = drive(&mut timer, |time| time * 12.0, 1.0 / 60.0, 60 * 11);
+        assert_eq!(crossings.len(), 3);
+        for (crossing, expected) in crossings.iter().zip([1.0 / 12.0, 5.0833, 10.0833].iter()) {
+            assert!(
+                f32::abs(crossing - expected) < 1e-3,
+                "{} not {}",
+                crossing,
+                expected
+            );
+        }
+        assert_eq!(timer.laps_completed, 2);
+        let lap = timer.best_lap.as_ref().unwrap();
+        assert!(f32::abs(lap.total - 5.0) < 1e-3, "lap took {}", lap.total);
+        for sector in lap.sectors.iter() {
+            assert!(f32::abs(sector - 5.0 / SECTORS as f32) < 1e-3);
+        }
+    }
+
+    #[test]
+    fn reverse_crossing_does_not_count() {
+        let mut timer = LapTimer::new(LAP_LENGTH);
+        timer.update(59.0, 1.0, 1.0, 0.1);
+        assert!(timer.lap_start.is_some());
+
+        // Backing over the line and going forwards again starts the lap
+        // over rather than finishing one
+        assert_eq!(timer.update(1.0, 59.0, 1.1, 0.1), None);
+        assert_eq!(timer.lap_start, None);
+        assert_eq!(timer.update(59.0, 1.0, 1.2, 0.1), Some(1.15));
+        assert_eq!(timer.laps_completed, 0);
+        assert_eq!(timer.lap_start, Some(1.15));
+
+        // Backing over the line after finishing a lap and coming back
+        // round doesn't finish another one
+        let mut timer = LapTimer::new(LAP_LENGTH);
+        let there_and_back = |time: f32| {
+            if time < 5.5 {
+                time * 12.0
+            } else if time < 6.0 {
+                66.0 - (time - 5.5) * 12.0
+            } else {
+                60.0 + (time - 6.0) * 12.0
+            }
+        };
+        let crossings = drive(&mut timer, there_and_back, 0.1, 70);
+        assert_eq!(crossings.len(), 3);
+        assert_eq!(timer.laps_completed, 1);
+        let restart = timer.lap_start.expect("the lap starts again");
+        assert!(
+            f32::abs(restart - 6.0833) < 1e-3,
+            "restarted at {}",
+            restart
+        );
+    }
+
+    #[test]
+    fn times_are_the_same_every_run() {
+        let uneven = |time: f32| time * 10.0 + f32::sin(time * 3.0) * 4.0;
+        let run = || {
+            let mut timer = LapTimer::new(LAP_LENGTH);
+            let crossings = drive(&mut timer, uneven, 1.0 / 60.0, 60 * 20);
+            (crossings, timer.best_lap.unwrap())
+        };
+        let (first_crossings, first_lap) = run();
+        let (second_crossings, second_lap) = run();
+        assert_eq!(first_crossings, second_crossings);
+        assert_eq!(first_lap.total.to_bits(), second_lap.total.to_bits());
+        assert_eq!(first_lap.sectors, second_lap.sectors);
+        assert_eq!(first_lap.checkpoints, second_lap.checkpoints);
+
+        // A different tick length only changes the times by rounding
+        let mut timer = LapTimer::new(LAP_LENGTH);
+        drive(&mut timer, uneven, 1.0 / 30.0, 30 * 20);
+        let coarse_lap = timer.best_lap.unwrap();
+        assert!(f32::abs(coarse_lap.total - first_lap.total) < 0.01);
+    }
+}
@@ -2,7 +2,7 @@ pub type Vec2 = (f32, f32);
 
 /// A non-generic transform in 2D. Only supports rotations translations
 /// and a uniform scaling.
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Transform2d {
     pub x: f32,
     pub y: f32,
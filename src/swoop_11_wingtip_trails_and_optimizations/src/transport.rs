@@ -0,0 +1,83 @@
+use super::rng::Rng;
+
+/// How bad a simulated connection is. Times are in ticks, as that is what
+/// everything on either end of it counts in.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct LinkConditions {
+    /// Ticks every message takes to arrive
+    pub latency: u32,
+    /// Up to this many ticks more, picked at random for each message, so
+    /// messages can overtake each other
+    pub jitter: u32,
+    /// Chance of a message never arriving, from 0 to 1
+    pub loss: f32,
+}
+
+impl LinkConditions {
+    /// Every message arrives straight away
+    pub const PERFECT: LinkConditions = LinkConditions {
+        latency: 0,
+        jitter: 0,
+        loss: 0.0,
+    };
+}
+
+/// One direction of a made up network connection, for trying out netcode
+/// natively. Messages go in with the tick they were sent on and come out
+/// once enough ticks have gone by, if they aren't lost on the way. The
+/// same seed always loses and delays the same messages.
+pub struct SimulatedLink<T> {
+    pub conditions: LinkConditions,
+    rng: Rng,
+    /// Messages on their way, along with the tick they arrive on
+    in_flight: Vec<(u32, T)>,
+    pub sent: u32,
+    pub lost: u32,
+}
+
+impl<T> SimulatedLink<T> {
+    pub fn new(conditions: LinkConditions, seed: u64) -> Self {
+        Self {
+            conditions,
+            rng: Rng::new(seed),
+            in_flight: vec![],
+            sent: 0,
+            lost: 0,
+        }
+    }
+
+    pub fn send(&mut self, now: u32, message: T) {
+        self.sent += 1;
+        if self.rng.next_f32() < self.conditions.loss {
+            self.lost += 1;
+            return;
+        }
+        let jitter = match self.conditions.jitter {
+            0 => 0,
+            jitter => self.rng.next_u32() % (jitter + 1),
+        };
+        let arrival = now + self.conditions.latency + jitter;
+        self.in_flight.push((arrival, message));
+    }
+
+    /// Messages that have arrived by this tick, in the order they arrived
+    pub fn receive(&mut self, now: u32) -> Vec<T> {
+        // Stable, so messages arriving on the same tick stay in the order
+        // they were sent
+        self.in_flight.sort_by_key(|(arrival, _)| *arrival);
+        let arrived = self
+            .in_flight
+            .iter()
+            .take_while(|(arrival, _)| *arrival <= now)
+            .count();
+        self.in_flight
+            .drain(..arrived)
+            .map(|(_, message)| message)
+            .collect()
+    }
+
+    /// Whether anything is still on its way
+    pub fn is_empty(&self) -> bool {
+        self.in_flight.is_empty()
+    }
+}
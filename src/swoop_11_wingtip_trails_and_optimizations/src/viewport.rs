@@ -0,0 +1,119 @@
+use super::game_options::MAX_PLAYERS;
+
+/// Gap left between split screen views, in CSS pixels
+const DIVIDER: f32 = 2.0;
+
+/// Part of the canvas that one camera draws into. Positions and sizes are
+/// in canvas pixels from the top left corner, like the rest of the screen
+/// space code.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Viewport {
+    pub left: u32,
+    pub top: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl Viewport {
+    /// The whole of a canvas of this resolution
+    pub fn full(resolution: (u32, u32)) -> Self {
+        Self {
+            left: 0,
+            top: 0,
+            width: resolution.0,
+            height: resolution.1,
+        }
+    }
+
+    /// Width of the view over its height
+    pub fn aspect_ratio(&self) -> f32 {
+        match self.height {
+            0 => 1.0,
+            height => self.width as f32 / height as f32,
+        }
+    }
+
+    /// Squashes camera space so that a circle stays round in this view,
+    /// whatever shape it is
+    pub fn camera_to_clipspace(&self) -> [f32; 9] {
+        [
+            1.0,
+            0.0,
+            0.0,
+            0.0,
+            1.0 / self.aspect_ratio(),
+            0.0,
+            0.0,
+            0.0,
+            1.0,
+        ]
+    }
+
+    /// The rectangle as WebGL wants it for the viewport and scissor box:
+    /// x, y, width and height with y counting up from the bottom of the
+    /// canvas
+    pub fn gl_rect(&self, canvas_height: u32) -> (i32, i32, i32, i32) {
+        let bottom = canvas_height.saturating_sub(self.top + self.height);
+        (
+            self.left as i32,
+            bottom as i32,
+            self.width as i32,
+            self.height as i32,
+        )
+    }
+
+    /// Whether a point in canvas pixels from the top left is in the view
+    pub fn contains(&self, point: (f32, f32)) -> bool {
+        let (x, y) = point;
+        x >= self.left as f32
+            && x < (self.left + self.width) as f32
+            && y >= self.top as f32
+            && y < (self.top + self.height) as f32
+    }
+}
+
+/// Splits a canvas of this resolution in device pixels between a number
+/// of views. Two views go side by side on wide canvases and one above the
+/// other on tall ones. Three or four share a two by two grid, with the
+/// last corner left empty for three. There is always at least one view,
+/// and never more than MAX_PLAYERS.
+pub fn split(resolution: (u32, u32), views: usize, pixel_ratio: f32) -> Vec<Viewport> {
+    let (width, height) = resolution;
+    let (columns, rows) = match views {
+        0 | 1 => return vec![Viewport::full(resolution)],
+        2 if width >= height => (2, 1),
+        2 => (1, 2),
+        _ => (2, 2),
+    };
+    let divider = (DIVIDER * pixel_ratio).round() as u32;
+    // The divider is shared between the views either side of it, and any
+    // odd pixel left over goes to the right or bottom view
+    let edges = |length: u32, count: u32, index: u32| {
+        let start = length * index / count;
+        let end = length * (index + 1) / count;
+        let start = if index > 0 {
+            start + divider / 2
+        } else {
+            start
+        };
+        let end = if index + 1 < count {
+            end.saturating_sub(divider - divider / 2)
+        } else {
+            end
+        };
+        (start, end.saturating_sub(start))
+    };
+
+    (0..usize::min(views, MAX_PLAYERS) as u32)
+        .map(|index| {
+            let (left, width) = edges(width, columns, index % columns);
+            let (top, height) = edges(height, rows, index / columns);
+            Viewport {
+                left,
+                top,
+                width,
+                height,
+            }
+        })
+        .collect()
+}
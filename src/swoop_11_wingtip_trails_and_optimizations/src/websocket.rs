@@ -0,0 +1,593 @@
+use std::io::{self, Read, Write};
+
+/// Mixed into the client's key to make the server's accept key
+const GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+/// Biggest message either end will take. Snapshots of every ship are only
+/// a couple of hundred bytes.
+const MAX_MESSAGE_SIZE: usize = 1 << 16;
+/// Biggest handshake either end will read before giving up
+const MAX_HANDSHAKE_SIZE: usize = 8192;
+
+/// An error to represent a connection that can't carry on
+#[derive(Debug)]
+pub enum WebSocketError {
+    Io(io::Error),
+    /// The other end didn't ask for, or agree to, a WebSocket
+    BadHandshake(String),
+    /// A frame broke the protocol, such as a client not masking
+    BadFrame(String),
+    /// A message was bigger than MAX_MESSAGE_SIZE
+    MessageTooLarge(usize),
+}
+
+impl From<io::Error> for WebSocketError {
+    fn from(err: io::Error) -> Self {
+        WebSocketError::Io(err)
+    }
+}
+
+/// What a frame holds
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Opcode {
+    Continuation,
+    Text,
+    Binary,
+    Close,
+    Ping,
+    Pong,
+}
+
+impl Opcode {
+    fn from_bits(bits: u8) -> Option<Self> {
+        match bits {
+            0x0 => Some(Opcode::Continuation),
+            0x1 => Some(Opcode::Text),
+            0x2 => Some(Opcode::Binary),
+            0x8 => Some(Opcode::Close),
+            0x9 => Some(Opcode::Ping),
+            0xA => Some(Opcode::Pong),
+            _ => None,
+        }
+    }
+
+    fn bits(&self) -> u8 {
+        match self {
+            Opcode::Continuation => 0x0,
+            Opcode::Text => 0x1,
+            Opcode::Binary => 0x2,
+            Opcode::Close => 0x8,
+            Opcode::Ping => 0x9,
+            Opcode::Pong => 0xA,
+        }
+    }
+}
+
+/// A whole message, put back together from however many frames it came
+/// in
+#[derive(Clone, Debug, PartialEq)]
+pub enum Message {
+    Binary(Vec<u8>),
+    Text(String),
+    /// Should be answered with a pong holding the same bytes
+    Ping(Vec<u8>),
+    Pong(Vec<u8>),
+    /// The other end is going away
+    Close,
+}
+
+/// The key a server answers a client's key with
+pub fn accept_key(client_key: &str) -> String {
+    let mut input = client_key.trim().as_bytes().to_vec();
+    input.extend_from_slice(GUID.as_bytes());
+    base64(&sha1(&input))
+}
+
+/// Reads a browser's request to open a WebSocket (RFC 6455) from a
+/// freshly accepted stream and agrees to it. Gives back the path that was
+/// asked for and anything the client sent after the handshake.
+pub fn accept<S: Read + Write>(stream: &mut S) -> Result<(String, Vec<u8>), WebSocketError> {
+    let (head, rest) = read_head(stream)?;
+    let mut lines = head.lines();
+    let request = lines.next().unwrap_or("");
+    let path = match request.split_whitespace().collect::<Vec<_>>()[..] {
+        ["GET", path, _] => path.to_string(),
+        _ => return Err(bad_handshake("Not a GET request")),
+    };
+    let headers: Vec<(String, String)> = lines.filter_map(parse_header).collect();
+    let header = |name: &str| {
+        headers
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    };
+    let upgrade = header("Upgrade").is_some_and(|value| value.eq_ignore_ascii_case("websocket"));
+    if !upgrade {
+        return Err(bad_handshake("Not a WebSocket upgrade"));
+    }
+    let key = header("Sec-WebSocket-Key").ok_or_else(|| bad_handshake("No key"))?;
+
+    let response = format!(
+        "HTTP/1.1 101 Switching Protocols\r\n\
+         Upgrade: websocket\r\n\
+         Connection: Upgrade\r\n\
+         Sec-WebSocket-Accept: {}\r\n\r\n",
+        accept_key(key)
+    );
+    stream.write_all(response.as_bytes())?;
+    Ok((path, rest))
+}
+
+/// Asks a server to open a WebSocket on a freshly connected stream. The
+/// key only has to be different each time, not secret. Gives back
+/// anything the server sent after the handshake.
+pub fn connect<S: Read + Write>(
+    stream: &mut S,
+    host: &str,
+    path: &str,
+    key: [u8; 16],
+) -> Result<Vec<u8>, WebSocketError> {
+    let key = base64(&key);
+    let request = format!(
+        "GET {} HTTP/1.1\r\n\
+         Host: {}\r\n\
+         Upgrade: websocket\r\n\
+         Connection: Upgrade\r\n\
+         Sec-WebSocket-Key: {}\r\n\
+         Sec-WebSocket-Version: 13\r\n\r\n",
+        path, host, key
+    );
+    stream.write_all(request.as_bytes())?;
+
+    let (head, rest) = read_head(stream)?;
+    let mut lines = head.lines();
+    let status = lines.next().unwrap_or("");
+    if status.split_whitespace().nth(1) != Some("101") {
+        return Err(bad_handshake(status));
+    }
+    let expected = accept_key(&key);
+    let accepted = lines.filter_map(parse_header).any(|(name, value)| {
+        name.eq_ignore_ascii_case("Sec-WebSocket-Accept") && value == expected
+    });
+    if !accepted {
+        return Err(bad_handshake("Wrong accept key"));
+    }
+    Ok(rest)
+}
+
+/// Packs a payload into a single frame. Clients have to mask every frame
+/// they send, and servers must not.
+pub fn encode_frame(opcode: Opcode, payload: &[u8], mask: Option<[u8; 4]>) -> Vec<u8> {
+    let mut frame = Vec::with_capacity(payload.len() + 14);
+    frame.push(0x80 | opcode.bits());
+    let mask_bit = if mask.is_some() { 0x80 } else { 0 };
+    match payload.len() {
+        length if length < 126 => frame.push(mask_bit | length as u8),
+        length if length <= u16::MAX as usize => {
+            frame.push(mask_bit | 126);
+            frame.extend_from_slice(&(length as u16).to_be_bytes());
+        }
+        length => {
+            frame.push(mask_bit | 127);
+            frame.extend_from_slice(&(length as u64).to_be_bytes());
+        }
+    }
+    match mask {
+        Some(mask) => {
+            frame.extend_from_slice(&mask);
+            frame.extend(
+                payload
+                    .iter()
+                    .enumerate()
+                    .map(|(index, byte)| byte ^ mask[index % 4]),
+            );
+        }
+        None => frame.extend_from_slice(payload),
+    }
+    frame
+}
+
+/// Puts messages back together from bytes as they arrive. Reading is
+/// kept apart from writing, which is just `encode_frame`, so the two can
+/// happen on different threads with cloned streams.
+pub struct FrameReader {
+    buffer: Vec<u8>,
+    /// Frames of a message that hasn't finished arriving
+    fragments: Vec<u8>,
+    fragment_opcode: Option<Opcode>,
+    /// Servers only take masked frames, and clients only unmasked ones
+    masked: bool,
+}
+
+impl FrameReader {
+    /// A reader for the server end of a connection, which only takes
+    /// masked frames
+    pub fn server(received: Vec<u8>) -> Self {
+        Self::new(received, true)
+    }
+
+    /// A reader for the client end of a connection
+    pub fn client(received: Vec<u8>) -> Self {
+        Self::new(received, false)
+    }
+
+    fn new(received: Vec<u8>, masked: bool) -> Self {
+        Self {
+            buffer: received,
+            fragments: vec![],
+            fragment_opcode: None,
+            masked,
+        }
+    }
+
+    pub fn push(&mut self, bytes: &[u8]) {
+        self.buffer.extend_from_slice(bytes);
+    }
+
+    /// The next whole message, if enough has arrived for one
+    pub fn next_message(&mut self) -> Result<Option<Message>, WebSocketError> {
+        loop {
+            let (fin, opcode, payload) = match self.next_frame()? {
+                Some(frame) => frame,
+                None => return Ok(None),
+            };
+            match opcode {
+                Opcode::Close => return Ok(Some(Message::Close)),
+                Opcode::Ping => return Ok(Some(Message::Ping(payload))),
+                Opcode::Pong => return Ok(Some(Message::Pong(payload))),
+                Opcode::Continuation if self.fragment_opcode.is_none() => {
+                    return Err(bad_frame("Continuation without a message"))
+                }
+                Opcode::Text | Opcode::Binary if self.fragment_opcode.is_some() => {
+                    return Err(bad_frame("New message before the last one finished"))
+                }
+                Opcode::Text | Opcode::Binary => self.fragment_opcode = Some(opcode),
+                Opcode::Continuation => (),
+            }
+            if self.fragments.len() + payload.len() > MAX_MESSAGE_SIZE {
+                return Err(WebSocketError::MessageTooLarge(
+                    self.fragments.len() + payload.len(),
+                ));
+            }
+            self.fragments.extend_from_slice(&payload);
+            if fin {
+                let payload = std::mem::take(&mut self.fragments);
+                return match self.fragment_opcode.take() {
+                    Some(Opcode::Text) => String::from_utf8(payload)
+                        .map(|text| Some(Message::Text(text)))
+                        .map_err(|_| bad_frame("Text that isn't UTF-8")),
+                    _ => Ok(Some(Message::Binary(payload))),
+                };
+            }
+        }
+    }
+
+    /// Takes one whole frame off the front of the buffer, unmasked
+    fn next_frame(&mut self) -> Result<Option<(bool, Opcode, Vec<u8>)>, WebSocketError> {
+        if self.buffer.len() < 2 {
+            return Ok(None);
+        }
+        let fin = self.buffer[0] & 0x80 != 0;
+        let opcode =
+            Opcode::from_bits(self.buffer[0] & 0x0F).ok_or_else(|| bad_frame("Unknown opcode"))?;
+        let masked = self.buffer[1] & 0x80 != 0;
+        if masked != self.masked {
+            return Err(bad_frame(match masked {
+                true => "Masked frame from the server",
+                false => "Unmasked frame from a client",
+            }));
+        }
+
+        let (length, mut header) = match self.buffer[1] & 0x7F {
+            126 if self.buffer.len() >= 4 => (
+                u16::from_be_bytes([self.buffer[2], self.buffer[3]]) as u64,
+                4,
+            ),
+            127 if self.buffer.len() >= 10 => {
+                let mut bytes = [0; 8];
+                bytes.copy_from_slice(&self.buffer[2..10]);
+                (u64::from_be_bytes(bytes), 10)
+            }
+            126 | 127 => return Ok(None),
+            length => (length as u64, 2),
+        };
+        if length > MAX_MESSAGE_SIZE as u64 {
+            return Err(WebSocketError::MessageTooLarge(length as usize));
+        }
+        let length = length as usize;
+        let mask = match masked {
+            true if self.buffer.len() >= header + 4 => {
+                let mut mask = [0; 4];
+                mask.copy_from_slice(&self.buffer[header..header + 4]);
+                header += 4;
+                Some(mask)
+            }
+            true => return Ok(None),
+            false => None,
+        };
+        if self.buffer.len() < header + length {
+            return Ok(None);
+        }
+
+        let mut payload: Vec<u8> = self.buffer.drain(..header + length).skip(header).collect();
+        if let Some(mask) = mask {
+            for (index, byte) in payload.iter_mut().enumerate() {
+                *byte ^= mask[index % 4];
+            }
+        }
+        Ok(Some((fin, opcode, payload)))
+    }
+}
+
+/// Blocks until the next whole message arrives. Gives None once the
+/// stream has closed.
+pub fn read_message<R: Read>(
+    stream: &mut R,
+    reader: &mut FrameReader,
+) -> Result<Option<Message>, WebSocketError> {
+    let mut chunk = [0; 4096];
+    loop {
+        if let Some(message) = reader.next_message()? {
+            return Ok(Some(message));
+        }
+        match stream.read(&mut chunk)? {
+            0 => return Ok(None),
+            read => reader.push(&chunk[..read]),
+        }
+    }
+}
+
+/// Reads up to the blank line that ends an HTTP head, giving back the
+/// head and anything read past it
+fn read_head<R: Read>(stream: &mut R) -> Result<(String, Vec<u8>), WebSocketError> {
+    let mut received = vec![];
+    let mut chunk = [0; 1024];
+    loop {
+        if let Some(end) = find(&received, b"\r\n\r\n") {
+            let rest = received.split_off(end + 4);
+            let head = String::from_utf8(received)
+                .map_err(|_| bad_handshake("Handshake that isn't UTF-8"))?;
+            return Ok((head, rest));
+        }
+        if received.len() > MAX_HANDSHAKE_SIZE {
+            return Err(bad_handshake("Handshake too long"));
+        }
+        match stream.read(&mut chunk)? {
+            0 => return Err(bad_handshake("Closed during the handshake")),
+            read => received.extend_from_slice(&chunk[..read]),
+        }
+    }
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack
+        .windows(needle.len())
+        .position(|window| window == needle)
+}
+
+fn parse_header(line: &str) -> Option<(String, String)> {
+    let mut parts = line.splitn(2, ':');
+    let name = parts.next()?.trim();
+    let value = parts.next()?.trim();
+    Some((name.to_string(), value.to_string()))
+}
+
+fn bad_handshake(reason: &str) -> WebSocketError {
+    WebSocketError::BadHandshake(reason.to_string())
+}
+
+fn bad_frame(reason: &str) -> WebSocketError {
+    WebSocketError::BadFrame(reason.to_string())
+}
+
+fn sha1(input: &[u8]) -> [u8; 20] {
+    let mut state: [u32; 5] = [
+        0x6745_2301,
+        0xEFCD_AB89,
+        0x98BA_DCFE,
+        0x1032_5476,
+        0xC3D2_E1F0,
+    ];
+
+    let mut message = input.to_vec();
+    message.push(0x80);
+    while message.len() % 64 != 56 {
+        message.push(0);
+    }
+    message.extend_from_slice(&(input.len() as u64 * 8).to_be_bytes());
+
+    for block in message.chunks(64) {
+        let mut words = [0u32; 80];
+        for (index, word) in block.chunks(4).enumerate() {
+            words[index] = u32::from_be_bytes([word[0], word[1], word[2], word[3]]);
+        }
+        for index in 16..80 {
+            words[index] =
+                (words[index - 3] ^ words[index - 8] ^ words[index - 14] ^ words[index - 16])
+                    .rotate_left(1);
+        }
+
+        let [mut a, mut b, mut c, mut d, mut e] = state;
+        for (index, word) in words.iter().enumerate() {
+            let (f, k) = match index {
+                0..=19 => ((b & c) | (!b & d), 0x5A82_7999),
+                20..=39 => (b ^ c ^ d, 0x6ED9_EBA1),
+                40..=59 => ((b & c) | (b & d) | (c & d), 0x8F1B_BCDC),
+                _ => (b ^ c ^ d, 0xCA62_C1D6),
+            };
+            let temp = a
+                .rotate_left(5)
+                .wrapping_add(f)
+                .wrapping_add(e)
+                .wrapping_add(k)
+                .wrapping_add(*word);
+            e = d;
+            d = c;
+            c = b.rotate_left(30);
+            b = a;
+            a = temp;
+        }
+        for (value, added) in state.iter_mut().zip([a, b, c, d, e].iter()) {
+            *value = value.wrapping_add(*added);
+        }
+    }
+
+    let mut digest = [0; 20];
+    for (bytes, value) in digest.chunks_mut(4).zip(state.iter()) {
+        bytes.copy_from_slice(&value.to_be_bytes());
+    }
+    digest
+}
+
+fn base64(input: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut output = String::with_capacity(input.len().div_ceil(3) * 4);
+    for chunk in input.chunks(3) {
+        let bytes = [
+            chunk[0],
+            chunk.get(1).copied().unwrap_or(0),
+            chunk.get(2).copied().unwrap_or(0),
+        ];
+        let bits = (bytes[0] as u32) << 16 | (bytes[1] as u32) << 8 | bytes[2] as u32;
+        for index in 0..4 {
+            if index <= chunk.len() {
+                output.push(ALPHABET[(bits >> (18 - index * 6) & 0x3F) as usize] as char);
+            } else {
+                output.push('=');
+            }
+        }
+    }
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Example frames from RFC 6455 section 5.7, each holding "Hello"
+    const UNMASKED_HELLO: [u8; 7] = [0x81, 0x05, 0x48, 0x65, 0x6c, 0x6c, 0x6f];
+    const MASKED_HELLO: [u8; 11] = [
+        0x81, 0x85, 0x37, 0xfa, 0x21, 0x3d, 0x7f, 0x9f, 0x4d, 0x51, 0x58,
+    ];
+    const MASK: [u8; 4] = [0x37, 0xfa, 0x21, 0x3d];
+
+    fn hello() -> Option<Message> {
+        Some(Message::Text("Hello".to_string()))
+    }
+
+    #[test]
+    fn accept_key_matches_the_rfc() {
+        assert_eq!(
+            accept_key("dGhlIHNhbXBsZSBub25jZQ=="),
+            "s3pPLMBiTxaQ9kYGzzhZRbK+xOo="
+        );
+    }
+
+    #[test]
+    fn frames_match_the_rfc() {
+        assert_eq!(encode_frame(Opcode::Text, b"Hello", None), UNMASKED_HELLO);
+        assert_eq!(
+            encode_frame(Opcode::Text, b"Hello", Some(MASK)),
+            MASKED_HELLO
+        );
+
+        let mut client = FrameReader::client(UNMASKED_HELLO.to_vec());
+        assert_eq!(client.next_message().unwrap(), hello());
+        assert_eq!(client.next_message().unwrap(), None);
+        let mut server = FrameReader::server(MASKED_HELLO.to_vec());
+        assert_eq!(server.next_message().unwrap(), hello());
+
+        // Split over two frames
+        let mut client = FrameReader::client(vec![0x01, 0x03, 0x48, 0x65, 0x6c]);
+        assert_eq!(client.next_message().unwrap(), None);
+        client.push(&[0x80, 0x02, 0x6c, 0x6f]);
+        assert_eq!(client.next_message().unwrap(), hello());
+    }
+
+    #[test]
+    fn masking_has_to_match_the_end() {
+        let mut client = FrameReader::client(MASKED_HELLO.to_vec());
+        assert!(matches!(
+            client.next_message(),
+            Err(WebSocketError::BadFrame(_))
+        ));
+        let mut server = FrameReader::server(UNMASKED_HELLO.to_vec());
+        assert!(matches!(
+            server.next_message(),
+            Err(WebSocketError::BadFrame(_))
+        ));
+    }
+
+    #[test]
+    fn extended_lengths() {
+        for (length, header) in [
+            (125, 2),
+            (126, 4),
+            (u16::MAX as usize, 4),
+            (MAX_MESSAGE_SIZE, 10),
+        ]
+        .iter()
+        {
+            let payload: Vec<u8> = (0..*length).map(|index| index as u8).collect();
+            let frame = encode_frame(Opcode::Binary, &payload, None);
+            assert_eq!(frame.len(), header + length);
+            let mut client = FrameReader::client(frame);
+            assert_eq!(
+                client.next_message().unwrap(),
+                Some(Message::Binary(payload.clone()))
+            );
+
+            let frame = encode_frame(Opcode::Binary, &payload, Some(MASK));
+            assert_eq!(frame.len(), header + 4 + length);
+            let mut server = FrameReader::server(frame);
+            assert_eq!(
+                server.next_message().unwrap(),
+                Some(Message::Binary(payload))
+            );
+        }
+
+        let frame = encode_frame(Opcode::Binary, &vec![0; MAX_MESSAGE_SIZE + 1], None);
+        let mut client = FrameReader::client(frame);
+        assert!(matches!(
+            client.next_message(),
+            Err(WebSocketError::MessageTooLarge(_))
+        ));
+    }
+
+    #[test]
+    fn waits_for_the_rest_of_a_frame() {
+        for length in [5, 300, MAX_MESSAGE_SIZE].iter() {
+            let payload = vec![7; *length];
+            let frame = encode_frame(Opcode::Binary, &payload, Some(MASK));
+            let mut server = FrameReader::server(vec![]);
+            // Every way of cutting the header short, then the payload
+            let cuts = (0..16).chain(frame.len() - 2..frame.len());
+            for cut in cuts.filter(|cut| *cut < frame.len()) {
+                let mut server_so_far = FrameReader::server(frame[..cut].to_vec());
+                assert_eq!(server_so_far.next_message().unwrap(), None);
+            }
+            for chunk in frame.chunks(1000) {
+                assert_eq!(server.next_message().unwrap(), None);
+                server.push(chunk);
+            }
+            assert_eq!(
+                server.next_message().unwrap(),
+                Some(Message::Binary(payload))
+            );
+        }
+    }
+
+    #[test]
+    fn control_frames_come_between_fragments() {
+        let mut frames = vec![0x01, 0x03, 0x48, 0x65, 0x6c];
+        frames.extend(encode_frame(Opcode::Ping, b"hi", None));
+        frames.extend_from_slice(&[0x80, 0x02, 0x6c, 0x6f]);
+        frames.extend(encode_frame(Opcode::Close, &[], None));
+        let mut client = FrameReader::client(frames);
+        assert_eq!(
+            client.next_message().unwrap(),
+            Some(Message::Ping(b"hi".to_vec()))
+        );
+        assert_eq!(client.next_message().unwrap(), hello());
+        assert_eq!(client.next_message().unwrap(), Some(Message::Close));
+    }
+}